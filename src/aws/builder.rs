@@ -17,15 +17,19 @@
 
 use crate::aws::client::{S3Client, S3Config};
 use crate::aws::credential::{
-    EKSPodCredentialProvider, InstanceCredentialProvider, SessionProvider, TaskCredentialProvider,
-    WebIdentityProvider,
+    instance_region, AssumeRoleProvider, EKSPodCredentialProvider, ExpiryJitter,
+    InstanceCredentialProvider, Mfa, MfaTokenCallback, ProcessCredentialProvider,
+    ProfileCredentialProvider, SSOCredentialProvider, Sensitive, SessionProvider,
+    TaskCredentialProvider, WebIdentityProvider, DEFAULT_EXPIRY_JITTER, DEFAULT_IMDS_TOKEN_TTL,
+    IMDS_TOKEN_MIN_TTL, IMDS_TOKEN_TTL_RANGE,
 };
 use crate::aws::{
     AmazonS3, AwsCredential, AwsCredentialProvider, Checksum, S3ConditionalPut, S3CopyIfNotExists,
     STORE,
 };
+use crate::client::token::TokenCache;
 use crate::client::{http_connector, HttpConnector, TokenCredentialProvider};
-use crate::config::ConfigValue;
+use crate::config::{fmt_duration, ConfigValue};
 use crate::{ClientConfigKey, ClientOptions, Result, RetryConfig, StaticCredentialProvider};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
@@ -33,15 +37,80 @@ use itertools::Itertools;
 use md5::{Digest, Md5};
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::info;
+use tracing::{info, warn};
 use url::Url;
 
 /// Default metadata endpoint
 static DEFAULT_METADATA_ENDPOINT: &str = "http://169.254.169.254";
 
+/// Returns the configured IMDSv2 session token TTL, validating it is accepted by IMDS
+fn imds_token_ttl(ttl: Option<&ConfigValue<Duration>>) -> Result<Duration> {
+    let ttl = match ttl {
+        Some(ttl) => ttl.get()?,
+        None => return Ok(DEFAULT_IMDS_TOKEN_TTL),
+    };
+    match IMDS_TOKEN_TTL_RANGE.contains(&ttl.as_secs()) {
+        true => Ok(ttl),
+        false => Err(Error::InvalidImdsTokenTtl { ttl }.into()),
+    }
+}
+
+/// Returns the credential expiry jitter, validating it lies within `0..=1`
+fn expiry_jitter(fraction: Option<f64>) -> Result<f64> {
+    let fraction = fraction.unwrap_or(DEFAULT_EXPIRY_JITTER);
+    match (0. ..=1.).contains(&fraction) {
+        true => Ok(fraction),
+        false => Err(Error::InvalidExpiryJitter { fraction }.into()),
+    }
+}
+
+/// Returns the directory containing the AWS configuration, i.e. `~/.aws`
+fn aws_config_dir() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or(Error::MissingHomeDirectory)?;
+    Ok(PathBuf::from(home).join(".aws"))
+}
+
+/// Returns `profile` along with the shared credentials and config files to read it from,
+/// or `None` if it uses a credential source not supported by [`ProfileCredentialProvider`]
+fn supported_profile(
+    profile: Option<String>,
+    credentials_file: Option<String>,
+    config_file: Option<String>,
+) -> Result<Option<(String, PathBuf, PathBuf)>> {
+    let profile = match profile {
+        Some(profile) => profile,
+        None => return Ok(None),
+    };
+
+    let file = |path: Option<String>, name: &str| match path {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => aws_config_dir().map(|dir| dir.join(name)),
+    };
+    let credentials_file = file(credentials_file, "credentials")?;
+    let config_file = file(config_file, "config")?;
+
+    let supported =
+        ProfileCredentialProvider::is_supported(&profile, &credentials_file, &config_file)
+            .map_err(|source| crate::Error::Generic {
+                store: STORE,
+                source,
+            })?;
+    match supported {
+        true => Ok(Some((profile, credentials_file, config_file))),
+        false => {
+            info!("Profile '{profile}' uses an unsupported credential source, ignoring");
+            Ok(None)
+        }
+    }
+}
+
 /// A specialized `Error` for object store-related errors
 #[derive(Debug, thiserror::Error)]
 enum Error {
@@ -54,6 +123,21 @@ enum Error {
     #[error("Missing SecretAccessKey")]
     MissingSecretAccessKey,
 
+    #[error("Missing {key} for SSO credentials")]
+    MissingSSOConfig { key: &'static str },
+
+    #[error("Unable to determine home directory for the AWS configuration")]
+    MissingHomeDirectory,
+
+    #[error("MFA serial number configured without a token callback")]
+    MissingMfaTokenCallback,
+
+    #[error("IMDS token TTL must be between 1 and 21600 seconds, got {ttl:?}")]
+    InvalidImdsTokenTtl { ttl: Duration },
+
+    #[error("Credential expiry jitter must be between 0 and 1, got {fraction}")]
+    InvalidExpiryJitter { fraction: f64 },
+
     #[error("Unable parse source url. Url: {}, Error: {}", url, source)]
     UnableToParseUrl {
         source: url::ParseError,
@@ -125,7 +209,7 @@ pub struct AmazonS3Builder {
     /// Access key id
     access_key_id: Option<String>,
     /// Secret access_key
-    secret_access_key: Option<String>,
+    secret_access_key: Option<Sensitive<String>>,
     /// Region
     region: Option<String>,
     /// Bucket name
@@ -133,13 +217,19 @@ pub struct AmazonS3Builder {
     /// Endpoint for communicating with AWS S3
     endpoint: Option<String>,
     /// Token to use for requests
-    token: Option<String>,
+    token: Option<Sensitive<String>>,
     /// Url
     url: Option<String>,
     /// Retry config
     retry_config: RetryConfig,
     /// When set to true, fallback to IMDSv1
     imdsv1_fallback: ConfigValue<bool>,
+    /// TTL of IMDSv2 session tokens
+    imds_token_ttl: Option<ConfigValue<Duration>>,
+    /// Maximum fraction of a credential's TTL to randomly bring its refresh forward by
+    credential_expiry_jitter: Option<f64>,
+    /// How long before credentials would otherwise be refreshed to refresh them ahead
+    credential_refresh_ahead: Option<Duration>,
     /// When set to true, virtual hosted style request has to be used
     virtual_hosted_style_request: ConfigValue<bool>,
     /// When set to true, S3 express is used
@@ -156,6 +246,41 @@ pub struct AmazonS3Builder {
     container_credentials_full_uri: Option<String>,
     /// Container authorization token file, see <https://docs.aws.amazon.com/sdkref/latest/guide/feature-container-credentials.html>
     container_authorization_token_file: Option<String>,
+    /// Named profile in the shared credentials and config files
+    profile: Option<String>,
+    /// Whether `profile` was read from `AWS_PROFILE`, in which case errors reading it are
+    /// ignored in favour of the remaining credential providers
+    profile_from_env: bool,
+    /// Shared credentials file, defaults to `~/.aws/credentials`
+    shared_credentials_file: Option<String>,
+    /// Shared config file, defaults to `~/.aws/config`
+    config_file: Option<String>,
+    /// Command to source credentials from
+    credential_process: Option<String>,
+    /// Role to assume using the resolved credentials
+    assume_role_arn: Option<String>,
+    /// Session name for the assumed role
+    assume_role_session_name: Option<String>,
+    /// External ID required to assume the role
+    external_id: Option<String>,
+    /// Serial number of the MFA device used to assume the role
+    mfa_serial: Option<String>,
+    /// Callback returning the MFA token code
+    mfa_token_callback: Option<MfaTokenCallback>,
+    /// SSO start URL, see <https://docs.aws.amazon.com/sdkref/latest/guide/feature-sso-credentials.html>
+    sso_start_url: Option<String>,
+    /// SSO session name
+    sso_session_name: Option<String>,
+    /// SSO region
+    sso_region: Option<String>,
+    /// SSO account ID
+    sso_account_id: Option<String>,
+    /// SSO role name
+    sso_role_name: Option<String>,
+    /// SSO portal endpoint
+    sso_endpoint: Option<String>,
+    /// STS endpoint
+    sts_endpoint: Option<String>,
     /// Client options
     client_options: ClientOptions,
     /// Credentials
@@ -176,6 +301,8 @@ pub struct AmazonS3Builder {
     encryption_customer_key_base64: Option<String>,
     /// When set to true, charge requester for bucket operations
     request_payer: ConfigValue<bool>,
+    /// When set to true, send a `Content-MD5` header with object writes
+    content_md5: ConfigValue<bool>,
     /// The [`HttpConnector`] to use
     http_connector: Option<Arc<dyn HttpConnector>>,
 }
@@ -272,6 +399,15 @@ pub enum AmazonS3ConfigKey {
     /// - `imdsv1_fallback`
     ImdsV1Fallback,
 
+    /// The TTL of IMDSv2 session tokens
+    ///
+    /// See [`AmazonS3Builder::with_imds_token_ttl`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_imds_token_ttl`
+    /// - `imds_token_ttl`
+    ImdsTokenTtl,
+
     /// If virtual hosted style request has to be used
     ///
     /// See [`AmazonS3Builder::with_virtual_hosted_style_request`] for details.
@@ -319,6 +455,133 @@ pub enum AmazonS3ConfigKey {
     /// <https://docs.aws.amazon.com/sdkref/latest/guide/feature-container-credentials.html>
     ContainerAuthorizationTokenFile,
 
+    /// The named profile to source credentials from
+    ///
+    /// See [`AmazonS3Builder::with_profile`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_profile`
+    /// - `profile`
+    Profile,
+
+    /// The location of the shared credentials file
+    ///
+    /// See [`AmazonS3Builder::with_shared_credentials_file`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_shared_credentials_file`
+    /// - `shared_credentials_file`
+    SharedCredentialsFile,
+
+    /// The location of the shared config file
+    ///
+    /// See [`AmazonS3Builder::with_config_file`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_config_file`
+    /// - `config_file`
+    ConfigFile,
+
+    /// The ARN of a role to assume using the otherwise configured credentials
+    ///
+    /// See [`AmazonS3Builder::with_assume_role_arn`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_assume_role_arn`
+    /// - `assume_role_arn`
+    AssumeRoleArn,
+
+    /// The session name to use when assuming [`AmazonS3ConfigKey::AssumeRoleArn`]
+    ///
+    /// See [`AmazonS3Builder::with_assume_role_session_name`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_assume_role_session_name`
+    /// - `assume_role_session_name`
+    AssumeRoleSessionName,
+
+    /// The external ID required when assuming [`AmazonS3ConfigKey::AssumeRoleArn`]
+    ///
+    /// See [`AmazonS3Builder::with_external_id`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_external_id`
+    /// - `external_id`
+    ExternalId,
+
+    /// The serial number or ARN of the MFA device used when assuming
+    /// [`AmazonS3ConfigKey::AssumeRoleArn`]
+    ///
+    /// See [`AmazonS3Builder::with_mfa_serial`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_mfa_serial`
+    /// - `mfa_serial`
+    MfaSerial,
+
+    /// The start URL of the AWS access portal used for SSO credentials
+    ///
+    /// See [`AmazonS3Builder::with_sso_start_url`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sso_start_url`
+    /// - `sso_start_url`
+    SsoStartUrl,
+
+    /// The name of the `sso-session` used for SSO credentials
+    ///
+    /// See [`AmazonS3Builder::with_sso_session_name`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sso_session_name`
+    /// - `sso_session_name`
+    SsoSessionName,
+
+    /// The region of the AWS access portal used for SSO credentials
+    ///
+    /// See [`AmazonS3Builder::with_sso_region`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sso_region`
+    /// - `sso_region`
+    SsoRegion,
+
+    /// The account ID to fetch SSO credentials for
+    ///
+    /// See [`AmazonS3Builder::with_sso_account_id`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sso_account_id`
+    /// - `sso_account_id`
+    SsoAccountId,
+
+    /// The role name to fetch SSO credentials for
+    ///
+    /// See [`AmazonS3Builder::with_sso_role_name`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sso_role_name`
+    /// - `sso_role_name`
+    SsoRoleName,
+
+    /// The endpoint of the AWS access portal used for SSO credentials
+    ///
+    /// See [`AmazonS3Builder::with_sso_endpoint`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sso_endpoint`
+    /// - `sso_endpoint`
+    SsoEndpoint,
+
+    /// The endpoint of the AWS Security Token Service
+    ///
+    /// See [`AmazonS3Builder::with_sts_endpoint`] for details.
+    ///
+    /// Supported keys:
+    /// - `aws_sts_endpoint`
+    /// - `sts_endpoint`
+    StsEndpoint,
+
     /// Configure how to provide `copy_if_not_exists`
     ///
     /// See [`S3CopyIfNotExists`]
@@ -355,6 +618,13 @@ pub enum AmazonS3ConfigKey {
     /// - `request_payer`
     RequestPayer,
 
+    /// Send a `Content-MD5` header with requests whose payload is held in memory
+    ///
+    /// Supported keys:
+    /// - `aws_content_md5`
+    /// - `content_md5`
+    ContentMd5,
+
     /// Client options
     Client(ClientConfigKey),
 
@@ -372,6 +642,7 @@ impl AsRef<str> for AmazonS3ConfigKey {
             Self::Endpoint => "aws_endpoint",
             Self::Token => "aws_session_token",
             Self::ImdsV1Fallback => "aws_imdsv1_fallback",
+            Self::ImdsTokenTtl => "aws_imds_token_ttl",
             Self::VirtualHostedStyleRequest => "aws_virtual_hosted_style_request",
             Self::S3Express => "aws_s3_express",
             Self::DefaultRegion => "aws_default_region",
@@ -381,11 +652,26 @@ impl AsRef<str> for AmazonS3ConfigKey {
             Self::ContainerCredentialsRelativeUri => "aws_container_credentials_relative_uri",
             Self::ContainerCredentialsFullUri => "aws_container_credentials_full_uri",
             Self::ContainerAuthorizationTokenFile => "aws_container_authorization_token_file",
+            Self::Profile => "aws_profile",
+            Self::SharedCredentialsFile => "aws_shared_credentials_file",
+            Self::ConfigFile => "aws_config_file",
+            Self::AssumeRoleArn => "aws_assume_role_arn",
+            Self::AssumeRoleSessionName => "aws_assume_role_session_name",
+            Self::ExternalId => "aws_external_id",
+            Self::MfaSerial => "aws_mfa_serial",
+            Self::SsoStartUrl => "aws_sso_start_url",
+            Self::SsoSessionName => "aws_sso_session_name",
+            Self::SsoRegion => "aws_sso_region",
+            Self::SsoAccountId => "aws_sso_account_id",
+            Self::SsoRoleName => "aws_sso_role_name",
+            Self::SsoEndpoint => "aws_sso_endpoint",
+            Self::StsEndpoint => "aws_sts_endpoint",
             Self::SkipSignature => "aws_skip_signature",
             Self::CopyIfNotExists => "aws_copy_if_not_exists",
             Self::ConditionalPut => "aws_conditional_put",
             Self::DisableTagging => "aws_disable_tagging",
             Self::RequestPayer => "aws_request_payer",
+            Self::ContentMd5 => "aws_content_md5",
             Self::Client(opt) => opt.as_ref(),
             Self::Encryption(opt) => opt.as_ref(),
         }
@@ -409,17 +695,37 @@ impl FromStr for AmazonS3ConfigKey {
             }
             "aws_s3_express" | "s3_express" => Ok(Self::S3Express),
             "aws_imdsv1_fallback" | "imdsv1_fallback" => Ok(Self::ImdsV1Fallback),
+            "aws_imds_token_ttl" | "imds_token_ttl" => Ok(Self::ImdsTokenTtl),
             "aws_metadata_endpoint" | "metadata_endpoint" => Ok(Self::MetadataEndpoint),
             "aws_unsigned_payload" | "unsigned_payload" => Ok(Self::UnsignedPayload),
             "aws_checksum_algorithm" | "checksum_algorithm" => Ok(Self::Checksum),
             "aws_container_credentials_relative_uri" => Ok(Self::ContainerCredentialsRelativeUri),
             "aws_container_credentials_full_uri" => Ok(Self::ContainerCredentialsFullUri),
             "aws_container_authorization_token_file" => Ok(Self::ContainerAuthorizationTokenFile),
+            "aws_profile" | "profile" => Ok(Self::Profile),
+            "aws_shared_credentials_file" | "shared_credentials_file" => {
+                Ok(Self::SharedCredentialsFile)
+            }
+            "aws_config_file" | "config_file" => Ok(Self::ConfigFile),
+            "aws_assume_role_arn" | "assume_role_arn" => Ok(Self::AssumeRoleArn),
+            "aws_assume_role_session_name" | "assume_role_session_name" => {
+                Ok(Self::AssumeRoleSessionName)
+            }
+            "aws_external_id" | "external_id" => Ok(Self::ExternalId),
+            "aws_mfa_serial" | "mfa_serial" => Ok(Self::MfaSerial),
+            "aws_sso_start_url" | "sso_start_url" => Ok(Self::SsoStartUrl),
+            "aws_sso_session_name" | "sso_session_name" => Ok(Self::SsoSessionName),
+            "aws_sso_region" | "sso_region" => Ok(Self::SsoRegion),
+            "aws_sso_account_id" | "sso_account_id" => Ok(Self::SsoAccountId),
+            "aws_sso_role_name" | "sso_role_name" => Ok(Self::SsoRoleName),
+            "aws_sso_endpoint" | "sso_endpoint" => Ok(Self::SsoEndpoint),
+            "aws_sts_endpoint" | "sts_endpoint" => Ok(Self::StsEndpoint),
             "aws_skip_signature" | "skip_signature" => Ok(Self::SkipSignature),
             "aws_copy_if_not_exists" | "copy_if_not_exists" => Ok(Self::CopyIfNotExists),
             "aws_conditional_put" | "conditional_put" => Ok(Self::ConditionalPut),
             "aws_disable_tagging" | "disable_tagging" => Ok(Self::DisableTagging),
             "aws_request_payer" | "request_payer" => Ok(Self::RequestPayer),
+            "aws_content_md5" | "content_md5" => Ok(Self::ContentMd5),
             // Backwards compatibility
             "aws_allow_http" => Ok(Self::Client(ClientConfigKey::AllowHttp)),
             "aws_server_side_encryption" => Ok(Self::Encryption(
@@ -461,6 +767,9 @@ impl AmazonS3Builder {
     /// * `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` -> <https://docs.aws.amazon.com/AmazonECS/latest/developerguide/task-iam-roles.html>
     /// * `AWS_CONTAINER_CREDENTIALS_FULL_URI` -> <https://docs.aws.amazon.com/sdkref/latest/guide/feature-container-credentials.html>
     /// * `AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE` -> <https://docs.aws.amazon.com/sdkref/latest/guide/feature-container-credentials.html>
+    /// * `AWS_PROFILE` -> profile
+    /// * `AWS_SHARED_CREDENTIALS_FILE` -> shared_credentials_file
+    /// * `AWS_CONFIG_FILE` -> config_file
     /// * `AWS_ALLOW_HTTP` -> set to "true" to permit HTTP connections without TLS
     /// * `AWS_REQUEST_PAYER` -> set to "true" to permit operations on requester-pays buckets.
     /// # Example
@@ -472,9 +781,14 @@ impl AmazonS3Builder {
     ///     .build();
     /// ```
     pub fn from_env() -> Self {
+        Self::from_env_vars(std::env::vars_os())
+    }
+
+    /// Create a builder from the environment variables `vars`, see [`Self::from_env`]
+    fn from_env_vars(vars: impl IntoIterator<Item = (OsString, OsString)>) -> Self {
         let mut builder: Self = Default::default();
 
-        for (os_key, os_value) in std::env::vars_os() {
+        for (os_key, os_value) in vars {
             if let (Some(key), Some(value)) = (os_key.to_str(), os_value.to_str()) {
                 if key.starts_with("AWS_") {
                     if let Ok(config_key) = key.to_ascii_lowercase().parse() {
@@ -484,6 +798,7 @@ impl AmazonS3Builder {
             }
         }
 
+        builder.profile_from_env = builder.profile.is_some();
         builder
     }
 
@@ -516,12 +831,17 @@ impl AmazonS3Builder {
     pub fn with_config(mut self, key: AmazonS3ConfigKey, value: impl Into<String>) -> Self {
         match key {
             AmazonS3ConfigKey::AccessKeyId => self.access_key_id = Some(value.into()),
-            AmazonS3ConfigKey::SecretAccessKey => self.secret_access_key = Some(value.into()),
+            AmazonS3ConfigKey::SecretAccessKey => {
+                self.secret_access_key = Some(Sensitive(value.into()))
+            }
             AmazonS3ConfigKey::Region => self.region = Some(value.into()),
             AmazonS3ConfigKey::Bucket => self.bucket_name = Some(value.into()),
             AmazonS3ConfigKey::Endpoint => self.endpoint = Some(value.into()),
-            AmazonS3ConfigKey::Token => self.token = Some(value.into()),
+            AmazonS3ConfigKey::Token => self.token = Some(Sensitive(value.into())),
             AmazonS3ConfigKey::ImdsV1Fallback => self.imdsv1_fallback.parse(value),
+            AmazonS3ConfigKey::ImdsTokenTtl => {
+                self.imds_token_ttl = Some(ConfigValue::Deferred(value.into()))
+            }
             AmazonS3ConfigKey::VirtualHostedStyleRequest => {
                 self.virtual_hosted_style_request.parse(value)
             }
@@ -543,6 +863,27 @@ impl AmazonS3Builder {
             AmazonS3ConfigKey::ContainerAuthorizationTokenFile => {
                 self.container_authorization_token_file = Some(value.into());
             }
+            AmazonS3ConfigKey::Profile => {
+                self.profile = Some(value.into());
+                self.profile_from_env = false;
+            }
+            AmazonS3ConfigKey::SharedCredentialsFile => {
+                self.shared_credentials_file = Some(value.into())
+            }
+            AmazonS3ConfigKey::ConfigFile => self.config_file = Some(value.into()),
+            AmazonS3ConfigKey::AssumeRoleArn => self.assume_role_arn = Some(value.into()),
+            AmazonS3ConfigKey::AssumeRoleSessionName => {
+                self.assume_role_session_name = Some(value.into())
+            }
+            AmazonS3ConfigKey::ExternalId => self.external_id = Some(value.into()),
+            AmazonS3ConfigKey::MfaSerial => self.mfa_serial = Some(value.into()),
+            AmazonS3ConfigKey::SsoStartUrl => self.sso_start_url = Some(value.into()),
+            AmazonS3ConfigKey::SsoSessionName => self.sso_session_name = Some(value.into()),
+            AmazonS3ConfigKey::SsoRegion => self.sso_region = Some(value.into()),
+            AmazonS3ConfigKey::SsoAccountId => self.sso_account_id = Some(value.into()),
+            AmazonS3ConfigKey::SsoRoleName => self.sso_role_name = Some(value.into()),
+            AmazonS3ConfigKey::SsoEndpoint => self.sso_endpoint = Some(value.into()),
+            AmazonS3ConfigKey::StsEndpoint => self.sts_endpoint = Some(value.into()),
             AmazonS3ConfigKey::Client(key) => {
                 self.client_options = self.client_options.with_config(key, value)
             }
@@ -557,6 +898,7 @@ impl AmazonS3Builder {
             AmazonS3ConfigKey::RequestPayer => {
                 self.request_payer = ConfigValue::Deferred(value.into())
             }
+            AmazonS3ConfigKey::ContentMd5 => self.content_md5.parse(value),
             AmazonS3ConfigKey::Encryption(key) => match key {
                 S3EncryptionConfigKey::ServerSideEncryption => {
                     self.encryption_type = Some(ConfigValue::Deferred(value.into()))
@@ -587,12 +929,13 @@ impl AmazonS3Builder {
     pub fn get_config_value(&self, key: &AmazonS3ConfigKey) -> Option<String> {
         match key {
             AmazonS3ConfigKey::AccessKeyId => self.access_key_id.clone(),
-            AmazonS3ConfigKey::SecretAccessKey => self.secret_access_key.clone(),
+            AmazonS3ConfigKey::SecretAccessKey => self.secret_access_key.as_deref().cloned(),
             AmazonS3ConfigKey::Region | AmazonS3ConfigKey::DefaultRegion => self.region.clone(),
             AmazonS3ConfigKey::Bucket => self.bucket_name.clone(),
             AmazonS3ConfigKey::Endpoint => self.endpoint.clone(),
-            AmazonS3ConfigKey::Token => self.token.clone(),
+            AmazonS3ConfigKey::Token => self.token.as_deref().cloned(),
             AmazonS3ConfigKey::ImdsV1Fallback => Some(self.imdsv1_fallback.to_string()),
+            AmazonS3ConfigKey::ImdsTokenTtl => self.imds_token_ttl.as_ref().map(fmt_duration),
             AmazonS3ConfigKey::VirtualHostedStyleRequest => {
                 Some(self.virtual_hosted_style_request.to_string())
             }
@@ -612,6 +955,20 @@ impl AmazonS3Builder {
             AmazonS3ConfigKey::ContainerAuthorizationTokenFile => {
                 self.container_authorization_token_file.clone()
             }
+            AmazonS3ConfigKey::Profile => self.profile.clone(),
+            AmazonS3ConfigKey::SharedCredentialsFile => self.shared_credentials_file.clone(),
+            AmazonS3ConfigKey::ConfigFile => self.config_file.clone(),
+            AmazonS3ConfigKey::AssumeRoleArn => self.assume_role_arn.clone(),
+            AmazonS3ConfigKey::AssumeRoleSessionName => self.assume_role_session_name.clone(),
+            AmazonS3ConfigKey::ExternalId => self.external_id.clone(),
+            AmazonS3ConfigKey::MfaSerial => self.mfa_serial.clone(),
+            AmazonS3ConfigKey::SsoStartUrl => self.sso_start_url.clone(),
+            AmazonS3ConfigKey::SsoSessionName => self.sso_session_name.clone(),
+            AmazonS3ConfigKey::SsoRegion => self.sso_region.clone(),
+            AmazonS3ConfigKey::SsoAccountId => self.sso_account_id.clone(),
+            AmazonS3ConfigKey::SsoRoleName => self.sso_role_name.clone(),
+            AmazonS3ConfigKey::SsoEndpoint => self.sso_endpoint.clone(),
+            AmazonS3ConfigKey::StsEndpoint => self.sts_endpoint.clone(),
             AmazonS3ConfigKey::SkipSignature => Some(self.skip_signature.to_string()),
             AmazonS3ConfigKey::CopyIfNotExists => {
                 self.copy_if_not_exists.as_ref().map(ToString::to_string)
@@ -619,6 +976,7 @@ impl AmazonS3Builder {
             AmazonS3ConfigKey::ConditionalPut => Some(self.conditional_put.to_string()),
             AmazonS3ConfigKey::DisableTagging => Some(self.disable_tagging.to_string()),
             AmazonS3ConfigKey::RequestPayer => Some(self.request_payer.to_string()),
+            AmazonS3ConfigKey::ContentMd5 => Some(self.content_md5.to_string()),
             AmazonS3ConfigKey::Encryption(key) => match key {
                 S3EncryptionConfigKey::ServerSideEncryption => {
                     self.encryption_type.as_ref().map(ToString::to_string)
@@ -692,13 +1050,13 @@ impl AmazonS3Builder {
 
     /// Set the AWS Secret Access Key
     pub fn with_secret_access_key(mut self, secret_access_key: impl Into<String>) -> Self {
-        self.secret_access_key = Some(secret_access_key.into());
+        self.secret_access_key = Some(Sensitive(secret_access_key.into()));
         self
     }
 
     /// Set the AWS Session Token to use for requests
     pub fn with_token(mut self, token: impl Into<String>) -> Self {
-        self.token = Some(token.into());
+        self.token = Some(Sensitive(token.into()));
         self
     }
 
@@ -790,6 +1148,42 @@ impl AmazonS3Builder {
         self
     }
 
+    /// Sets the TTL requested for [IMDSv2] session tokens, defaults to 10 minutes
+    ///
+    /// Tokens are reused across credential refreshes until close to expiry. IMDS
+    /// accepts TTLs between 1 second and 6 hours, with [`Self::build`] returning an
+    /// error for values outside this range.
+    ///
+    /// [IMDSv2]: https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/configuring-instance-metadata-service.html
+    pub fn with_imds_token_ttl(mut self, ttl: Duration) -> Self {
+        self.imds_token_ttl = Some(ConfigValue::Parsed(ttl));
+        self
+    }
+
+    /// Sets the maximum fraction of a credential's lifetime by which to randomly bring
+    /// forward its refresh, defaults to `0.0`, i.e. disabled
+    ///
+    /// This avoids many processes that obtained credentials with the same lifetime all
+    /// refreshing them at once, and applies to instance, web identity, task,
+    /// EKS pod identity, profile, process, SSO and assumed role credentials.
+    /// Building fails if `max_fraction` does not lie within `0.0..=1.0`.
+    pub fn with_credential_expiry_jitter(mut self, max_fraction: f64) -> Self {
+        self.credential_expiry_jitter = Some(max_fraction);
+        self
+    }
+
+    /// Sets a window before credentials would otherwise be refreshed in which to refresh
+    /// them ahead of time, defaults to zero
+    ///
+    /// Within this window the credentials are refreshed by a background task, with all
+    /// requests served the still valid cached credentials meanwhile, rather than waiting
+    /// on the refresh once the credentials are close to expiry. This
+    /// applies to instance, task, EKS pod identity and S3 Express session credentials.
+    pub fn with_credential_refresh_ahead(mut self, window: Duration) -> Self {
+        self.credential_refresh_ahead = Some(window);
+        self
+    }
+
     /// Sets if unsigned payload option has to be used.
     /// See [unsigned payload option](https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html)
     /// * false (default): Signed payload option is used, where the checksum for the request body is computed and included when constructing a canonical request.
@@ -826,6 +1220,197 @@ impl AmazonS3Builder {
         self
     }
 
+    /// Source credentials from the named profile in the [shared credentials and config files]
+    ///
+    /// Profiles with `role_arn` and `source_profile` assume the role using the
+    /// credentials of the source profile.
+    ///
+    /// The profile is read when building the store, returning an error if it is not found.
+    /// A profile read from `AWS_PROFILE` by [`Self::from_env`] is instead ignored if it
+    /// cannot be read, as with other credentials configured by the environment.
+    /// Profiles using other credential sources, such as SSO, `web_identity_token_file` or
+    /// `credential_source`, are ignored in favour of the remaining credential providers.
+    ///
+    /// [shared credentials and config files]: https://docs.aws.amazon.com/sdkref/latest/guide/file-format.html
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self.profile_from_env = false;
+        self
+    }
+
+    /// Set the location of the shared credentials file, defaults to `~/.aws/credentials`
+    pub fn with_shared_credentials_file(mut self, path: impl Into<String>) -> Self {
+        self.shared_credentials_file = Some(path.into());
+        self
+    }
+
+    /// Set the location of the shared config file, defaults to `~/.aws/config`
+    pub fn with_config_file(mut self, path: impl Into<String>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Source credentials from the output of a [`credential_process`] command
+    ///
+    /// The command is run using the system shell, i.e. `sh -c` or `cmd /C` on Windows,
+    /// and is rerun when the returned credentials near their expiration.
+    /// Profiles in the shared config file may also set `credential_process`, see
+    /// [`Self::with_profile`].
+    ///
+    /// As this runs an arbitrary command, it has no corresponding [`AmazonS3ConfigKey`]
+    /// and is never read from the environment.
+    ///
+    /// [`credential_process`]: https://docs.aws.amazon.com/sdkref/latest/guide/feature-process-credentials.html
+    pub fn with_credential_process(mut self, command: impl Into<String>) -> Self {
+        self.credential_process = Some(command.into());
+        self
+    }
+
+    /// Assume the role with the given ARN using the otherwise configured credentials,
+    /// including those provided by [`Self::with_credentials`]
+    ///
+    /// <https://docs.aws.amazon.com/STS/latest/APIReference/API_AssumeRole.html>
+    pub fn with_assume_role_arn(mut self, role_arn: impl Into<String>) -> Self {
+        self.assume_role_arn = Some(role_arn.into());
+        self
+    }
+
+    /// Set the session name used by [`Self::with_assume_role_arn`],
+    /// defaults to `AssumeRoleSession`
+    pub fn with_assume_role_session_name(mut self, session_name: impl Into<String>) -> Self {
+        self.assume_role_session_name = Some(session_name.into());
+        self
+    }
+
+    /// Set the external ID required by the trust policy of [`Self::with_assume_role_arn`]
+    ///
+    /// <https://docs.aws.amazon.com/IAM/latest/UserGuide/id_roles_create_for-user_externalid.html>
+    pub fn with_external_id(mut self, external_id: impl Into<String>) -> Self {
+        self.external_id = Some(external_id.into());
+        self
+    }
+
+    /// Set the serial number, or ARN, of the MFA device required to assume
+    /// [`Self::with_assume_role_arn`]
+    ///
+    /// Requires [`Self::with_mfa_token_callback`]
+    pub fn with_mfa_serial(mut self, serial: impl Into<String>) -> Self {
+        self.mfa_serial = Some(serial.into());
+        self
+    }
+
+    /// Set the callback returning the one-time code of the device set by
+    /// [`Self::with_mfa_serial`], e.g. by prompting the user
+    ///
+    /// This is called immediately before each request to assume the role, including
+    /// those to refresh the credentials, on the blocking thread pool if running in a tokio
+    /// context. As each code may only be used once, these requests are not retried. If the
+    /// code is rejected, fetching credentials fails with
+    /// [`Error::Unauthenticated`](crate::Error::Unauthenticated)
+    ///
+    /// This is also used for roles requiring MFA in [`Self::with_profile`], i.e. those
+    /// setting `mfa_serial`
+    pub fn with_mfa_token_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.mfa_token_callback = Some(MfaTokenCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Set the start URL of the AWS access portal, used to fetch [SSO credentials]
+    ///
+    /// SSO credentials are used if both [`Self::with_sso_account_id`] and
+    /// [`Self::with_sso_role_name`] are set, and require a valid token to have been
+    /// cached by `aws sso login`
+    ///
+    /// [SSO credentials]: https://docs.aws.amazon.com/sdkref/latest/guide/feature-sso-credentials.html
+    pub fn with_sso_start_url(mut self, start_url: impl Into<String>) -> Self {
+        self.sso_start_url = Some(start_url.into());
+        self
+    }
+
+    /// Set the name of the `sso-session` used to fetch SSO credentials
+    ///
+    /// If set, the cached token is looked up by this name instead of [`Self::with_sso_start_url`]
+    pub fn with_sso_session_name(mut self, session_name: impl Into<String>) -> Self {
+        self.sso_session_name = Some(session_name.into());
+        self
+    }
+
+    /// Set the region of the AWS access portal, defaults to the bucket region
+    pub fn with_sso_region(mut self, region: impl Into<String>) -> Self {
+        self.sso_region = Some(region.into());
+        self
+    }
+
+    /// Set the account ID to fetch SSO credentials for
+    pub fn with_sso_account_id(mut self, account_id: impl Into<String>) -> Self {
+        self.sso_account_id = Some(account_id.into());
+        self
+    }
+
+    /// Set the role name to fetch SSO credentials for
+    pub fn with_sso_role_name(mut self, role_name: impl Into<String>) -> Self {
+        self.sso_role_name = Some(role_name.into());
+        self
+    }
+
+    /// Set the endpoint of the AWS access portal, defaults to
+    /// `https://portal.sso.{sso_region}.amazonaws.com`
+    pub fn with_sso_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.sso_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the endpoint of the AWS Security Token Service, used by web identity,
+    /// assumed role and profile credentials, defaults to the regional endpoint
+    /// `https://sts.{region}.amazonaws.com`
+    ///
+    /// This allows using, for example, GovCloud, FIPS or VPC endpoints, independently
+    /// of the S3 endpoint
+    ///
+    /// <https://docs.aws.amazon.com/general/latest/gr/sts.html>
+    pub fn with_sts_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.sts_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Sets the region to that of the EC2 instance, as reported by the
+    /// [instance metadata endpoint], if no region is otherwise configured
+    ///
+    /// This respects [`Self::with_metadata_endpoint`] and [`Self::with_imdsv1_fallback`]
+    ///
+    /// [instance metadata endpoint]: https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-retrieval.html
+    pub async fn with_instance_region(mut self) -> Result<Self> {
+        if self.region.is_some() {
+            return Ok(self);
+        }
+
+        let http = http_connector(self.http_connector.clone())?;
+        let client = http.connect(&self.client_options.metadata_options())?;
+        let endpoint = self
+            .metadata_endpoint
+            .as_deref()
+            .unwrap_or(DEFAULT_METADATA_ENDPOINT);
+
+        let region = instance_region(
+            &client,
+            &self.retry_config,
+            endpoint,
+            self.imdsv1_fallback.get()?,
+            imds_token_ttl(self.imds_token_ttl.as_ref())?,
+        )
+        .await
+        .map_err(|source| crate::Error::Generic {
+            store: STORE,
+            source,
+        })?;
+
+        self.region = Some(region);
+        Ok(self)
+    }
+
     /// Set the proxy_url to be used by the underlying client
     pub fn with_proxy_url(mut self, proxy_url: impl Into<String>) -> Self {
         self.client_options = self.client_options.with_proxy_url(proxy_url);
@@ -916,6 +1501,18 @@ impl AmazonS3Builder {
         self
     }
 
+    /// Set whether to send a `Content-MD5` header with requests whose payload is held in
+    /// memory, such as object writes, defaults to `false`
+    ///
+    /// Some S3-compatible stores, and bucket policies, require this header for integrity
+    /// checking. It is included in the request signature.
+    ///
+    /// <https://docs.aws.amazon.com/AmazonS3/latest/API/API_PutObject.html#API_PutObject_RequestSyntax>
+    pub fn with_content_md5(mut self, enabled: bool) -> Self {
+        self.content_md5 = ConfigValue::Parsed(enabled);
+        self
+    }
+
     /// The [`HttpConnector`] to use
     ///
     /// On non-WASM32 platforms uses [`reqwest`] by default, on WASM32 platforms must be provided
@@ -937,6 +1534,15 @@ impl AmazonS3Builder {
         let region = self.region.unwrap_or_else(|| "us-east-1".to_string());
         let checksum = self.checksum_algorithm.map(|x| x.get()).transpose()?;
         let copy_if_not_exists = self.copy_if_not_exists.map(|x| x.get()).transpose()?;
+        let imds_token_ttl = imds_token_ttl(self.imds_token_ttl.as_ref())?;
+        let sts_endpoint = self
+            .sts_endpoint
+            .take()
+            .unwrap_or_else(|| format!("https://sts.{region}.amazonaws.com"));
+
+        let expiry_jitter = expiry_jitter(self.credential_expiry_jitter)?;
+        let jitter = || ExpiryJitter::new(expiry_jitter);
+        let refresh_ahead = self.credential_refresh_ahead.unwrap_or_default();
 
         let credentials = if let Some(credentials) = self.credentials {
             credentials
@@ -946,8 +1552,8 @@ impl AmazonS3Builder {
                     info!("Using Static credential provider");
                     let credential = AwsCredential {
                         key_id,
-                        secret_key,
-                        token,
+                        secret_key: secret_key.0,
+                        token: token.map(|t| t.0),
                     };
                     Arc::new(StaticCredentialProvider::new(credential)) as _
                 }
@@ -955,6 +1561,67 @@ impl AmazonS3Builder {
                 (Some(_), None, _) => return Err(Error::MissingSecretAccessKey.into()),
                 (None, None, _) => unreachable!(),
             }
+        } else if let Some((profile, credentials_file, config_file)) =
+            match supported_profile(self.profile, self.shared_credentials_file, self.config_file) {
+                Err(e) if self.profile_from_env => {
+                    warn!("Ignoring profile from AWS_PROFILE: {e}");
+                    None
+                }
+                r => r?,
+            }
+        {
+            info!("Using Profile credential provider");
+
+            Arc::new(ProfileCredentialProvider {
+                profile,
+                credentials_file,
+                config_file,
+                region: region.clone(),
+                sts_endpoint: sts_endpoint.clone(),
+                mfa_token_callback: self.mfa_token_callback.clone(),
+                retry: self.retry_config.clone(),
+                client: http.connect(&self.client_options)?,
+                cache: Default::default(),
+                jitter: jitter(),
+            }) as _
+        } else if let Some(command) = self.credential_process {
+            info!("Using Process credential provider");
+
+            Arc::new(ProcessCredentialProvider {
+                command,
+                cache: Default::default(),
+                jitter: jitter(),
+            }) as _
+        } else if let (Some(account_id), Some(role_name)) =
+            (self.sso_account_id, self.sso_role_name)
+        {
+            info!("Using SSO credential provider");
+
+            let cache_key =
+                self.sso_session_name
+                    .or(self.sso_start_url)
+                    .ok_or(Error::MissingSSOConfig {
+                        key: "sso_start_url",
+                    })?;
+            let sso_region = self.sso_region.as_deref().unwrap_or(&region);
+            let endpoint = self
+                .sso_endpoint
+                .unwrap_or_else(|| format!("https://portal.sso.{sso_region}.amazonaws.com"));
+
+            let token = SSOCredentialProvider {
+                cache_dir: aws_config_dir()?.join("sso").join("cache"),
+                cache_key,
+                account_id,
+                role_name,
+                endpoint,
+                jitter: jitter(),
+            };
+
+            Arc::new(TokenCredentialProvider::new(
+                token,
+                http.connect(&self.client_options)?,
+                self.retry_config.clone(),
+            )) as _
         } else if let (Ok(token_path), Ok(role_arn)) = (
             std::env::var("AWS_WEB_IDENTITY_TOKEN_FILE"),
             std::env::var("AWS_ROLE_ARN"),
@@ -965,7 +1632,7 @@ impl AmazonS3Builder {
             let session_name = std::env::var("AWS_ROLE_SESSION_NAME")
                 .unwrap_or_else(|_| "WebIdentitySession".to_string());
 
-            let endpoint = format!("https://sts.{region}.amazonaws.com");
+            let endpoint = sts_endpoint.clone();
 
             // Disallow non-HTTPs requests
             let options = self.client_options.clone().with_allow_http(false);
@@ -975,6 +1642,7 @@ impl AmazonS3Builder {
                 session_name,
                 role_arn,
                 endpoint,
+                jitter: jitter(),
             };
 
             Arc::new(TokenCredentialProvider::new(
@@ -992,7 +1660,8 @@ impl AmazonS3Builder {
                 retry: self.retry_config.clone(),
                 // The instance metadata endpoint is access over HTTP
                 client: http.connect(&options)?,
-                cache: Default::default(),
+                cache: TokenCache::default().with_refresh_ahead(refresh_ahead),
+                jitter: jitter(),
             }) as _
         } else if let (Some(full_uri), Some(token_file)) = (
             self.container_credentials_full_uri,
@@ -1007,7 +1676,8 @@ impl AmazonS3Builder {
                 token_file,
                 retry: self.retry_config.clone(),
                 client: http.connect(&options)?,
-                cache: Default::default(),
+                cache: TokenCache::default().with_refresh_ahead(refresh_ahead),
+                jitter: jitter(),
             }) as _
         } else {
             info!("Using Instance credential provider");
@@ -1017,16 +1687,54 @@ impl AmazonS3Builder {
                 metadata_endpoint: self
                     .metadata_endpoint
                     .unwrap_or_else(|| DEFAULT_METADATA_ENDPOINT.into()),
+                token_ttl: imds_token_ttl,
+                token_cache: TokenCache::default()
+                    .with_min_ttl(IMDS_TOKEN_MIN_TTL.min(imds_token_ttl / 2)),
+                jitter: jitter(),
             };
 
-            Arc::new(TokenCredentialProvider::new(
-                token,
-                http.connect(&self.client_options.metadata_options())?,
-                self.retry_config.clone(),
-            )) as _
+            Arc::new(
+                TokenCredentialProvider::new(
+                    token,
+                    http.connect(&self.client_options.metadata_options())?,
+                    self.retry_config.clone(),
+                )
+                .with_refresh_ahead(refresh_ahead),
+            ) as _
         };
 
-        let (session_provider, zonal_endpoint) = match self.s3_express.get()? {
+        let credentials = match self.assume_role_arn {
+            Some(role_arn) => {
+                info!("Using AssumeRole credential provider");
+
+                let token = AssumeRoleProvider {
+                    base: credentials,
+                    role_arn,
+                    session_name: self
+                        .assume_role_session_name
+                        .unwrap_or_else(|| "AssumeRoleSession".to_string()),
+                    endpoint: sts_endpoint,
+                    region: region.clone(),
+                    external_id: self.external_id,
+                    mfa: match (self.mfa_serial, self.mfa_token_callback) {
+                        (Some(serial), Some(token_code)) => Some(Mfa { serial, token_code }),
+                        (Some(_), None) => return Err(Error::MissingMfaTokenCallback.into()),
+                        (None, _) => None,
+                    },
+                    jitter: jitter(),
+                };
+
+                Arc::new(TokenCredentialProvider::new(
+                    token,
+                    http.connect(&self.client_options)?,
+                    self.retry_config.clone(),
+                )) as _
+            }
+            None => credentials,
+        };
+
+        let s3_express = self.s3_express.get()?;
+        let zonal_endpoint = match s3_express {
             true => {
                 let zone = parse_bucket_az(&bucket).ok_or_else(|| {
                     let bucket = bucket.clone();
@@ -1034,23 +1742,11 @@ impl AmazonS3Builder {
                 })?;
 
                 // https://docs.aws.amazon.com/AmazonS3/latest/userguide/s3-express-Regions-and-Zones.html
-                let endpoint = format!("https://{bucket}.s3express-{zone}.{region}.amazonaws.com");
-
-                let session = Arc::new(
-                    TokenCredentialProvider::new(
-                        SessionProvider {
-                            endpoint: endpoint.clone(),
-                            region: region.clone(),
-                            credentials: Arc::clone(&credentials),
-                        },
-                        http.connect(&self.client_options)?,
-                        self.retry_config.clone(),
-                    )
-                    .with_min_ttl(Duration::from_secs(60)), // Credentials only valid for 5 minutes
-                );
-                (Some(session as _), Some(endpoint))
+                Some(format!(
+                    "https://{bucket}.s3express-{zone}.{region}.amazonaws.com"
+                ))
             }
-            false => (None, None),
+            false => None,
         };
 
         // If `endpoint` is provided it's assumed to be consistent with `virtual_hosted_style_request` or `s3_express`.
@@ -1064,6 +1760,28 @@ impl AmazonS3Builder {
             (None, None, false) => format!("https://s3.{region}.amazonaws.com/{bucket}"),
         };
 
+        let session_provider = match s3_express {
+            true => {
+                // Sessions are created against the directory bucket's endpoint,
+                // which may have been overridden by `endpoint`
+                let session = Arc::new(
+                    TokenCredentialProvider::new(
+                        SessionProvider {
+                            endpoint: bucket_endpoint.clone(),
+                            region: region.clone(),
+                            credentials: Arc::clone(&credentials),
+                        },
+                        http.connect(&self.client_options)?,
+                        self.retry_config.clone(),
+                    )
+                    .with_min_ttl(Duration::from_secs(60)) // Credentials only valid for 5 minutes
+                    .with_refresh_ahead(refresh_ahead),
+                );
+                Some(session as _)
+            }
+            false => None,
+        };
+
         let encryption_headers = if let Some(encryption_type) = self.encryption_type {
             S3EncryptionHeaders::try_new(
                 &encryption_type.get()?,
@@ -1093,6 +1811,7 @@ impl AmazonS3Builder {
             conditional_put: self.conditional_put.get()?,
             encryption_headers,
             request_payer: self.request_payer.get()?,
+            content_md5: self.content_md5.get()?,
         };
 
         let http_client = http.connect(&config.client_options)?;
@@ -1311,10 +2030,13 @@ mod tests {
             .with_config(AmazonS3ConfigKey::SecretAccessKey, "new-secret-key");
 
         assert_eq!(builder.access_key_id.unwrap(), aws_access_key_id.as_str());
-        assert_eq!(builder.secret_access_key.unwrap(), "new-secret-key");
+        assert_eq!(
+            builder.secret_access_key.unwrap().as_str(),
+            "new-secret-key"
+        );
         assert_eq!(builder.region.unwrap(), aws_default_region);
         assert_eq!(builder.endpoint.unwrap(), aws_endpoint);
-        assert_eq!(builder.token.unwrap(), aws_session_token);
+        assert_eq!(builder.token.unwrap().as_str(), aws_session_token);
         assert_eq!(
             builder.checksum_algorithm.unwrap().get().unwrap(),
             Checksum::SHA256
@@ -1563,6 +2285,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_debug_masks_secrets() {
+        let builder = AmazonS3Builder::new()
+            .with_access_key_id("AKIAXXX")
+            .with_secret_access_key("super_secret")
+            .with_token("temp_token");
+
+        let debug_output = format!("{builder:?}");
+        assert!(debug_output.contains("AKIAXXX"));
+        assert!(!debug_output.contains("super_secret"));
+        assert!(!debug_output.contains("temp_token"));
+
+        let secret = builder.get_config_value(&AmazonS3ConfigKey::SecretAccessKey);
+        assert_eq!(secret.as_deref(), Some("super_secret"));
+    }
+
+    #[test]
+    fn test_invalid_expiry_jitter() {
+        for fraction in [-0.1, 1.5, f64::NAN] {
+            let err = AmazonS3Builder::new()
+                .with_bucket_name("bucket")
+                .with_region("region")
+                .with_credential_expiry_jitter(fraction)
+                .build()
+                .unwrap_err()
+                .to_string();
+
+            assert!(
+                err.contains("Credential expiry jitter must be between 0 and 1"),
+                "{err}"
+            );
+        }
+
+        AmazonS3Builder::new()
+            .with_bucket_name("bucket")
+            .with_region("region")
+            .with_credential_expiry_jitter(1.)
+            .build()
+            .unwrap();
+    }
+
     #[test]
     fn test_parse_bucket_az() {
         let cases = [
@@ -1611,4 +2374,208 @@ mod tests {
             "expected EKS provider but got: {debug_str}"
         );
     }
+
+    #[test]
+    fn test_builder_credential_refresh_ahead() {
+        let s3 = AmazonS3Builder::new()
+            .with_bucket_name("some-bucket")
+            .with_config(
+                AmazonS3ConfigKey::ContainerCredentialsFullUri,
+                "https://127.0.0.1/eks-credentials",
+            )
+            .with_config(
+                AmazonS3ConfigKey::ContainerAuthorizationTokenFile,
+                "/tmp/fake-bearer-token",
+            )
+            .with_credential_refresh_ahead(Duration::from_secs(120))
+            .build()
+            .unwrap();
+
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("refresh_ahead: 120s"),
+            "expected refresh ahead window but got: {debug_str}"
+        );
+    }
+
+    #[test]
+    fn test_builder_sts_endpoint() {
+        let builder = AmazonS3Builder::new()
+            .with_bucket_name("some-bucket")
+            .with_region("us-gov-west-1")
+            .with_access_key_id("key")
+            .with_secret_access_key("secret")
+            .with_config(
+                "aws_assume_role_arn".parse().unwrap(),
+                "arn:aws-us-gov:iam::123456789012:role/Test",
+            );
+
+        // Defaults to the regional endpoint
+        let s3 = builder.clone().build().unwrap();
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("endpoint: \"https://sts.us-gov-west-1.amazonaws.com\""),
+            "{debug_str}"
+        );
+
+        let s3 = builder
+            .with_config(
+                AmazonS3ConfigKey::StsEndpoint,
+                "https://sts-fips.us-gov-west-1.amazonaws.com",
+            )
+            .build()
+            .unwrap();
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("endpoint: \"https://sts-fips.us-gov-west-1.amazonaws.com\""),
+            "{debug_str}"
+        );
+    }
+
+    #[test]
+    fn test_builder_sso_with_config() {
+        let builder = AmazonS3Builder::new()
+            .with_bucket_name("some-bucket")
+            .with_config(AmazonS3ConfigKey::SsoAccountId, "123456789012")
+            .with_config(AmazonS3ConfigKey::SsoRoleName, "TestRole");
+
+        let err = builder.clone().build().unwrap_err().to_string();
+        assert!(err.contains("Missing sso_start_url"), "{err}");
+
+        let s3 = builder
+            .with_config(
+                "sso_start_url".parse().unwrap(),
+                "https://my-sso-portal.awsapps.com/start",
+            )
+            .with_config(AmazonS3ConfigKey::SsoRegion, "eu-west-1")
+            .build()
+            .expect("should build successfully");
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("SSOCredentialProvider"),
+            "expected SSO provider but got: {debug_str}"
+        );
+        assert!(debug_str.contains("https://portal.sso.eu-west-1.amazonaws.com"));
+    }
+
+    #[test]
+    fn test_builder_profile_with_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let credentials = dir.path().join("credentials");
+        let config = dir.path().join("config");
+        std::fs::write(
+            &credentials,
+            "[dev]\naws_access_key_id = KEY\naws_secret_access_key = SECRET\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &config,
+            "[profile sso]\nsso_session = my-sso\n\n[profile web]\nrole_arn = arn:aws:iam::123456789012:role/Web\nweb_identity_token_file = /tmp/token\n",
+        )
+        .unwrap();
+
+        let builder = |profile: &str| {
+            AmazonS3Builder::new()
+                .with_bucket_name("some-bucket")
+                .with_config("aws_profile".parse().unwrap(), profile)
+                .with_config(
+                    "aws_shared_credentials_file".parse().unwrap(),
+                    credentials.to_str().unwrap(),
+                )
+                .with_config("aws_config_file".parse().unwrap(), config.to_str().unwrap())
+        };
+
+        let s3 = builder("dev").build().expect("should build successfully");
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("ProfileCredentialProvider"),
+            "expected profile provider but got: {debug_str}"
+        );
+        assert!(
+            debug_str.contains(&format!("{credentials:?}")),
+            "{debug_str}"
+        );
+        assert!(debug_str.contains(&format!("{config:?}")), "{debug_str}");
+
+        // Profiles using unsupported credential sources fall through to other providers
+        for profile in ["sso", "web"] {
+            let s3 = builder(profile)
+                .with_credential_process("echo")
+                .build()
+                .unwrap();
+            let debug_str = format!("{:?}", s3.client.config.credentials);
+            assert!(
+                debug_str.contains("ProcessCredentialProvider"),
+                "expected process provider but got: {debug_str}"
+            );
+        }
+
+        let err = builder("missing").build().unwrap_err().to_string();
+        assert!(err.contains("Profile 'missing' not found"), "{err}");
+
+        // A missing profile from the environment falls through to other providers
+        let vars = [
+            ("AWS_PROFILE", "missing"),
+            ("AWS_SHARED_CREDENTIALS_FILE", credentials.to_str().unwrap()),
+            ("AWS_CONFIG_FILE", config.to_str().unwrap()),
+        ];
+        let vars = vars.map(|(k, v)| (OsString::from(k), OsString::from(v)));
+        let s3 = AmazonS3Builder::from_env_vars(vars)
+            .with_bucket_name("some-bucket")
+            .with_credential_process("echo")
+            .build()
+            .unwrap();
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("ProcessCredentialProvider"),
+            "expected process provider but got: {debug_str}"
+        );
+
+        // Unless set explicitly
+        let vars = [("AWS_PROFILE", "env")].map(|(k, v)| (OsString::from(k), OsString::from(v)));
+        let err = AmazonS3Builder::from_env_vars(vars)
+            .with_bucket_name("some-bucket")
+            .with_profile("missing")
+            .with_shared_credentials_file(credentials.to_str().unwrap())
+            .with_config_file(config.to_str().unwrap())
+            .build()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Profile 'missing' not found"), "{err}");
+    }
+
+    #[test]
+    fn test_builder_assume_role_with_config() {
+        let builder = AmazonS3Builder::new()
+            .with_bucket_name("some-bucket")
+            .with_region("eu-west-1")
+            .with_access_key_id("BASE_KEY")
+            .with_secret_access_key("BASE_SECRET")
+            .with_config(
+                "aws_assume_role_arn".parse().unwrap(),
+                "arn:aws:iam::123456789012:role/Chained",
+            )
+            .with_config(AmazonS3ConfigKey::AssumeRoleSessionName, "chained");
+
+        let err = builder
+            .clone()
+            .with_mfa_serial("arn:aws:iam::123456789012:mfa/user")
+            .build()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("without a token callback"), "{err}");
+
+        let s3 = builder.build().expect("should build successfully");
+
+        let debug_str = format!("{:?}", s3.client.config.credentials);
+        assert!(
+            debug_str.contains("AssumeRoleProvider"),
+            "expected assume role provider but got: {debug_str}"
+        );
+        assert!(
+            debug_str.contains("StaticCredentialProvider"),
+            "{debug_str}"
+        );
+        assert!(debug_str.contains("https://sts.eu-west-1.amazonaws.com"));
+    }
 }
@@ -16,20 +16,86 @@
 // under the License.
 
 use crate::config::Parse;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use ring::digest::{self, Context};
 use std::str::FromStr;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 /// Enum representing checksum algorithm supported by S3.
 pub enum Checksum {
     /// SHA-256 algorithm.
     SHA256,
+    /// CRC-32C (Castagnoli) algorithm.
+    CRC32C,
+}
+
+impl Checksum {
+    /// The name of the header carrying this checksum, e.g. `x-amz-checksum-sha256`
+    pub(crate) fn header_name(&self) -> &'static str {
+        match self {
+            Self::SHA256 => "x-amz-checksum-sha256",
+            Self::CRC32C => "x-amz-checksum-crc32c",
+        }
+    }
+
+    /// The value of the `x-amz-checksum-algorithm` header for this checksum
+    pub(crate) fn algorithm(&self) -> &'static str {
+        match self {
+            Self::SHA256 => "SHA256",
+            Self::CRC32C => "CRC32C",
+        }
+    }
+
+    /// Computes the base64 encoded checksum of the provided chunks
+    pub(crate) fn compute<'a>(&self, chunks: impl IntoIterator<Item = &'a [u8]>) -> String {
+        match self {
+            Self::SHA256 => {
+                let mut sha256 = Context::new(&digest::SHA256);
+                chunks.into_iter().for_each(|x| sha256.update(x));
+                BASE64_STANDARD.encode(sha256.finish())
+            }
+            Self::CRC32C => {
+                let crc = chunks.into_iter().fold(!0, crc32c_update);
+                BASE64_STANDARD.encode((!crc).to_be_bytes())
+            }
+        }
+    }
+}
+
+/// Lookup table for the reflected CRC-32C polynomial
+static CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x82F6_3B78,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, b| {
+        CRC32C_TABLE[((crc ^ *b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
 }
 
 impl std::fmt::Display for Checksum {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             Self::SHA256 => write!(f, "sha256"),
+            Self::CRC32C => write!(f, "crc32c"),
         }
     }
 }
@@ -40,6 +106,7 @@ impl FromStr for Checksum {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "sha256" => Ok(Self::SHA256),
+            "crc32c" => Ok(Self::CRC32C),
             _ => Err(()),
         }
     }
@@ -61,3 +128,19 @@ impl Parse for Checksum {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute() {
+        let chunks: [&[u8]; 2] = [b"1234", b"56789"];
+        assert_eq!(Checksum::CRC32C.compute(chunks), "4waSgw==");
+        assert_eq!(Checksum::CRC32C.compute([]), "AAAAAA==");
+        assert_eq!(
+            Checksum::SHA256.compute(chunks),
+            "FeKw08M4keuw8e9gnsQZQgwg4yDOlMZfvIwzEkSOsiU="
+        );
+    }
+}
@@ -205,6 +205,7 @@ pub(crate) struct S3Config {
     pub copy_if_not_exists: Option<S3CopyIfNotExists>,
     pub conditional_put: S3ConditionalPut,
     pub request_payer: bool,
+    pub content_md5: bool,
     pub(super) encryption_headers: S3EncryptionHeaders,
 }
 
@@ -253,7 +254,8 @@ impl SessionCredential<'_> {
         let mut authorizer =
             AwsAuthorizer::new(self.credential.as_deref()?, "s3", &self.config.region)
                 .with_sign_payload(self.config.sign_payload)
-                .with_request_payer(self.config.request_payer);
+                .with_request_payer(self.config.request_payer)
+                .with_content_md5(self.config.content_md5);
 
         if self.session_token {
             let token = HeaderName::from_static("x-amz-s3session-token");
@@ -395,7 +397,7 @@ impl Request<'_> {
 
     pub(crate) fn with_payload(mut self, payload: PutPayload) -> Self {
         if (!self.config.skip_signature && self.config.sign_payload)
-            || self.config.checksum.is_some()
+            || self.config.checksum == Some(Checksum::SHA256)
         {
             let mut sha256 = Context::new(&digest::SHA256);
             payload.iter().for_each(|x| sha256.update(x));
@@ -409,6 +411,18 @@ impl Request<'_> {
             self.payload_sha256 = Some(payload_sha256);
         }
 
+        if let Some(checksum @ Checksum::CRC32C) = self.config.checksum {
+            let value = checksum.compute(payload.iter().map(|x| x.as_ref()));
+            self.builder = self.builder.header(checksum.header_name(), value);
+        }
+
+        if self.config.content_md5 {
+            let mut hasher = Md5::new();
+            payload.iter().for_each(|x| hasher.update(x));
+            let md5 = BASE64_STANDARD.encode(hasher.finalize());
+            self.builder = self.builder.header("Content-MD5", md5);
+        }
+
         let content_length = payload.content_length();
         self.builder = self.builder.header(CONTENT_LENGTH, content_length);
         self.payload = Some(payload);
@@ -640,11 +654,7 @@ impl S3Client {
 
         let mut request = self.request(Method::POST, location);
         if let Some(algorithm) = self.config.checksum {
-            match algorithm {
-                Checksum::SHA256 => {
-                    request = request.header(ALGORITHM, "SHA256");
-                }
-            }
+            request = request.header(ALGORITHM, algorithm.algorithm());
         }
         let response = request
             .query(&[("uploads", "")])
@@ -699,11 +709,15 @@ impl S3Client {
             request = request.with_encryption_headers();
         }
         let (parts, body) = request.send().await?.into_parts();
-        let checksum_sha256 = parts
-            .headers
-            .get(SHA256_CHECKSUM)
-            .and_then(|v| v.to_str().ok())
-            .map(|v| v.to_string());
+        let checksum = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let checksum_sha256 = checksum(SHA256_CHECKSUM);
+        let checksum_crc32c = checksum(Checksum::CRC32C.header_name());
 
         let e_tag = match is_copy {
             false => get_etag(&parts.headers).map_err(|source| Error::Metadata { source })?,
@@ -718,10 +732,11 @@ impl S3Client {
             }
         };
 
-        let content_id = if self.config.checksum == Some(Checksum::SHA256) {
+        let content_id = if self.config.checksum.is_some() {
             let meta = PartMetadata {
                 e_tag,
                 checksum_sha256,
+                checksum_crc32c,
             };
             quick_xml::se::to_string(&meta).unwrap()
         } else {
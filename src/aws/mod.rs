@@ -77,7 +77,9 @@ const STORE: &str = "S3";
 pub type AwsCredentialProvider = Arc<dyn CredentialProvider<Credential = AwsCredential>>;
 use crate::client::parts::Parts;
 use crate::list::{PaginatedListOptions, PaginatedListResult, PaginatedListStore};
-pub use credential::{AwsAuthorizer, AwsCredential};
+pub use credential::{
+    AwsAuthorizer, AwsAuthorizerV4A, AwsCredential, PostPolicyCondition, StreamingSigner,
+};
 
 /// Interface for [Amazon S3](https://aws.amazon.com/s3/).
 #[derive(Debug, Clone)]
@@ -97,6 +99,47 @@ impl AmazonS3 {
         &self.client.config.credentials
     }
 
+    /// Create a URL that authorizes uploading part `part_number` of the multipart upload
+    /// `upload_id` to `path` via a `PUT` request, valid for the duration specified in
+    /// `expires_in`
+    ///
+    /// This allows parts to be uploaded directly by other clients, such as browsers, with
+    /// the upload created and completed by the holder of the credentials. Part numbers
+    /// start at 1. See [`AwsAuthorizer::sign_upload_part`].
+    pub async fn signed_upload_part_url(
+        &self,
+        path: &Path,
+        upload_id: &MultipartId,
+        part_number: usize,
+        expires_in: Duration,
+    ) -> Result<Url> {
+        self.signed_path_url(path, |authorizer, url| {
+            authorizer.sign_upload_part(url, upload_id, part_number, expires_in)
+        })
+        .await
+    }
+
+    /// Returns the URL of `path`, signed by `sign` with the current credentials
+    async fn signed_path_url(
+        &self,
+        path: &Path,
+        sign: impl FnOnce(&AwsAuthorizer<'_>, &mut Url) + Send,
+    ) -> Result<Url> {
+        let credential = self.credentials().get_credential().await?;
+        let authorizer = AwsAuthorizer::new(&credential, "s3", &self.client.config.region)
+            .with_request_payer(self.client.config.request_payer);
+
+        let path_url = self.path_url(path);
+        let mut url = path_url.parse().map_err(|e| Error::Generic {
+            store: STORE,
+            source: format!("Unable to parse url {path_url}: {e}").into(),
+        })?;
+
+        sign(&authorizer, &mut url);
+
+        Ok(url)
+    }
+
     /// Create a full URL to the resource specified by `path` with this instance's configuration.
     fn path_url(&self, path: &Path) -> String {
         self.client.config.path_url(path)
@@ -138,19 +181,10 @@ impl Signer for AmazonS3 {
     /// # }
     /// ```
     async fn signed_url(&self, method: Method, path: &Path, expires_in: Duration) -> Result<Url> {
-        let credential = self.credentials().get_credential().await?;
-        let authorizer = AwsAuthorizer::new(&credential, "s3", &self.client.config.region)
-            .with_request_payer(self.client.config.request_payer);
-
-        let path_url = self.path_url(path);
-        let mut url = path_url.parse().map_err(|e| Error::Generic {
-            store: STORE,
-            source: format!("Unable to parse url {path_url}: {e}").into(),
-        })?;
-
-        authorizer.sign(method, &mut url, expires_in);
-
-        Ok(url)
+        self.signed_path_url(path, |authorizer, url| {
+            authorizer.sign(method, url, expires_in)
+        })
+        .await
     }
 }
 
@@ -1060,6 +1060,10 @@ impl CredentialProvider for AzureCliCredential {
     async fn get_credential(&self) -> crate::Result<Arc<Self::Credential>> {
         Ok(self.cache.get_or_insert_with(|| self.fetch_token()).await?)
     }
+
+    fn next_refresh(&self) -> Option<Instant> {
+        self.cache.next_refresh()
+    }
 }
 
 #[cfg(test)]
@@ -88,7 +88,7 @@ impl ObjectStore for ChunkedStore {
         let stream = match r.payload {
             #[cfg(all(feature = "fs", not(target_arch = "wasm32")))]
             GetResultPayload::File(file, path) => {
-                crate::local::chunked_stream(file, path, r.range.clone(), self.chunk_size)
+                crate::local::chunked_stream(file, path, r.range.clone(), self.chunk_size, None)
             }
             GetResultPayload::Stream(stream) => {
                 let buffer = BytesMut::new();
@@ -59,7 +59,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[cfg(not(target_arch = "wasm32"))]
 use reqwest::{NoProxy, Proxy};
@@ -786,6 +786,14 @@ pub trait CredentialProvider: std::fmt::Debug + Send + Sync {
 
     /// Return a credential
     async fn get_credential(&self) -> Result<Arc<Self::Credential>>;
+
+    /// Returns the instant at which the cached credential will next be refreshed, if known
+    ///
+    /// This is intended for introspection, e.g. reporting credential expiry in metrics,
+    /// and returns `None` by default
+    fn next_refresh(&self) -> Option<Instant> {
+        None
+    }
 }
 
 /// A static set of credentials
@@ -824,7 +832,7 @@ mod cloud {
     /// A [`CredentialProvider`] that uses [`HttpClient`] to fetch temporary tokens
     #[derive(Debug)]
     pub(crate) struct TokenCredentialProvider<T: TokenProvider> {
-        inner: T,
+        inner: Arc<T>,
         client: HttpClient,
         retry: RetryConfig,
         cache: TokenCache<Arc<T::Credential>>,
@@ -833,7 +841,7 @@ mod cloud {
     impl<T: TokenProvider> TokenCredentialProvider<T> {
         pub(crate) fn new(inner: T, client: HttpClient, retry: RetryConfig) -> Self {
             Self {
-                inner,
+                inner: Arc::new(inner),
                 client,
                 retry,
                 cache: Default::default(),
@@ -846,17 +854,30 @@ mod cloud {
             self.cache = self.cache.with_min_ttl(min_ttl);
             self
         }
+
+        /// Refresh cached tokens ahead of the minimum remaining TTL, see [`TokenCache::with_refresh_ahead`]
+        #[cfg(feature = "aws")]
+        pub(crate) fn with_refresh_ahead(mut self, refresh_ahead: Duration) -> Self {
+            self.cache = self.cache.with_refresh_ahead(refresh_ahead);
+            self
+        }
     }
 
     #[async_trait]
-    impl<T: TokenProvider> CredentialProvider for TokenCredentialProvider<T> {
+    impl<T: TokenProvider + 'static> CredentialProvider for TokenCredentialProvider<T> {
         type Credential = T::Credential;
 
         async fn get_credential(&self) -> Result<Arc<Self::Credential>> {
+            let inner = Arc::clone(&self.inner);
+            let (client, retry) = (self.client.clone(), self.retry.clone());
             self.cache
-                .get_or_insert_with(|| self.inner.fetch_token(&self.client, &self.retry))
+                .get_or_refresh_with(|| async move { inner.fetch_token(&client, &retry).await })
                 .await
         }
+
+        fn next_refresh(&self) -> Option<Instant> {
+            self.cache.next_refresh()
+        }
     }
 
     #[async_trait]
@@ -111,6 +111,8 @@ pub(crate) struct PartMetadata {
     pub e_tag: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub checksum_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_crc32c: Option<String>,
 }
 
 impl From<Vec<PartId>> for CompleteMultipartUpload {
@@ -125,12 +127,14 @@ impl From<Vec<PartId>> for CompleteMultipartUpload {
                     Err(_) => PartMetadata {
                         e_tag: part.content_id.clone(),
                         checksum_sha256: None,
+                        checksum_crc32c: None,
                     },
                 };
                 MultipartPart {
                     e_tag: md.e_tag,
                     part_number: part_idx + 1,
                     checksum_sha256: md.checksum_sha256,
+                    checksum_crc32c: md.checksum_crc32c,
                 }
             })
             .collect();
@@ -147,6 +151,9 @@ pub(crate) struct MultipartPart {
     #[serde(rename = "ChecksumSHA256")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub checksum_sha256: Option<String>,
+    #[serde(rename = "ChecksumCRC32C")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_crc32c: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -16,6 +16,8 @@
 // under the License.
 
 use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
@@ -33,9 +35,23 @@ pub(crate) struct TemporaryToken<T> {
 /// [`TemporaryToken`] based on its expiry
 #[derive(Debug)]
 pub(crate) struct TokenCache<T> {
-    cache: Mutex<Option<(TemporaryToken<T>, Instant)>>,
+    cache: Arc<Mutex<Option<CachedToken<T>>>>,
     min_ttl: Duration,
     fetch_backoff: Duration,
+    refresh_ahead: Duration,
+    refreshing: Arc<AtomicBool>,
+}
+
+/// A cached token along with the instant it was fetched
+type CachedToken<T> = (TemporaryToken<T>, Instant);
+
+/// Clears [`TokenCache::refreshing`] once a refresh completes, or is cancelled
+struct RefreshGuard(Arc<AtomicBool>);
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
 }
 
 impl<T> Default for TokenCache<T> {
@@ -46,10 +62,17 @@ impl<T> Default for TokenCache<T> {
             // How long to wait before re-attempting a token fetch after receiving one that
             // is still within the min-ttl
             fetch_backoff: Duration::from_millis(100),
+            refresh_ahead: Duration::ZERO,
+            refreshing: Default::default(),
         }
     }
 }
 
+/// Returns the current instant, which is paused along with the tokio clock in tests
+fn instant_now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
 impl<T: Clone + Send> TokenCache<T> {
     /// Override the minimum remaining TTL for a cached token to be used
     #[cfg(any(feature = "aws", feature = "gcp"))]
@@ -57,21 +80,36 @@ impl<T: Clone + Send> TokenCache<T> {
         Self { min_ttl, ..self }
     }
 
+    /// Refresh cached tokens in the background once their remaining TTL falls within
+    /// `refresh_ahead` of the minimum TTL, defaults to zero
+    ///
+    /// Within this window, [`Self::get_or_refresh_with`] spawns a single task to refresh
+    /// the token, with every caller served the still valid cached token meanwhile, avoiding
+    /// both stalling requests on an expiring token and stampeding the provider
+    #[cfg(any(feature = "aws", test))]
+    pub(crate) fn with_refresh_ahead(self, refresh_ahead: Duration) -> Self {
+        Self {
+            refresh_ahead,
+            ..self
+        }
+    }
+
     pub(crate) async fn get_or_insert_with<F, Fut, E>(&self, f: F) -> Result<T, E>
     where
         F: FnOnce() -> Fut + Send,
         Fut: Future<Output = Result<TemporaryToken<T>, E>> + Send,
     {
-        let now = Instant::now();
+        let now = instant_now();
         let mut locked = self.cache.lock().await;
 
         if let Some((cached, fetched_at)) = locked.as_ref() {
             match cached.expiry {
                 Some(ttl) => {
+                    let elapsed = now.checked_duration_since(*fetched_at).unwrap_or_default();
                     if ttl.checked_duration_since(now).unwrap_or_default() > self.min_ttl ||
                         // if we've recently attempted to fetch this token and it's not actually
                         // expired, we'll wait to re-fetch it and return the cached one
-                        (fetched_at.elapsed() < self.fetch_backoff && ttl.checked_duration_since(now).is_some())
+                        (elapsed < self.fetch_backoff && ttl.checked_duration_since(now).is_some())
                     {
                         return Ok(cached.token.clone());
                     }
@@ -82,15 +120,98 @@ impl<T: Clone + Send> TokenCache<T> {
 
         let cached = f().await?;
         let token = cached.token.clone();
-        *locked = Some((cached, Instant::now()));
+        *locked = Some((cached, instant_now()));
 
         Ok(token)
     }
+
+    /// Like [`Self::get_or_insert_with`], but refreshing the cached token in the background
+    /// within the window set by [`Self::with_refresh_ahead`]
+    ///
+    /// Errors refreshing the token in the background are not reported, as the cached token
+    /// remains valid, and are instead deferred to callers once it falls within the minimum
+    /// TTL. Outside a tokio runtime, tokens are not refreshed ahead of time.
+    pub(crate) async fn get_or_refresh_with<F, Fut, E>(&self, f: F) -> Result<T, E>
+    where
+        T: Sync + 'static,
+        E: Send + 'static,
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<TemporaryToken<T>, E>> + Send + 'static,
+    {
+        match self.refresh_in_background(f).await {
+            Ok(token) => Ok(token),
+            Err(f) => self.get_or_insert_with(f).await,
+        }
+    }
+
+    /// Spawns a task calling `f` to refresh the cached token if it is within the
+    /// refresh-ahead window, returning the cached token, or giving back `f` otherwise
+    async fn refresh_in_background<F, Fut, E>(&self, f: F) -> Result<T, F>
+    where
+        T: Sync + 'static,
+        E: Send + 'static,
+        Fut: Future<Output = Result<TemporaryToken<T>, E>> + Send + 'static,
+        F: FnOnce() -> Fut,
+    {
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return Err(f),
+        };
+
+        let now = instant_now();
+        let locked = self.cache.lock().await;
+        let (cached, fetched_at) = match locked.as_ref() {
+            Some((cached, fetched_at)) => (cached, *fetched_at),
+            None => return Err(f),
+        };
+        let remaining = match cached.expiry {
+            Some(ttl) => ttl.checked_duration_since(now).unwrap_or_default(),
+            None => return Err(f),
+        };
+        if remaining <= self.min_ttl || remaining > self.min_ttl + self.refresh_ahead {
+            return Err(f);
+        }
+
+        // Await the fetch backoff after a failed refresh before reattempting it, with only
+        // a single refresh in progress at a time
+        let elapsed = now.checked_duration_since(fetched_at).unwrap_or_default();
+        if elapsed >= self.fetch_backoff && !self.refreshing.swap(true, Ordering::AcqRel) {
+            let guard = RefreshGuard(Arc::clone(&self.refreshing));
+            let cache = Arc::clone(&self.cache);
+            let fut = f();
+            runtime.spawn(async move {
+                let _guard = guard;
+                let result = fut.await;
+                let mut locked = cache.lock().await;
+                match result {
+                    Ok(fetched) => *locked = Some((fetched, instant_now())),
+                    Err(_) => {
+                        if let Some((_, fetched_at)) = locked.as_mut() {
+                            *fetched_at = instant_now();
+                        }
+                    }
+                }
+            });
+        }
+        Ok(cached.token.clone())
+    }
+
+    /// Returns the instant at which the cached token will next be refreshed
+    ///
+    /// Returns `None` if no token is cached, the cached token does not expire,
+    /// or a refresh is currently in progress
+    pub(crate) fn next_refresh(&self) -> Option<Instant> {
+        let locked = self.cache.try_lock().ok()?;
+        let expiry = locked.as_ref()?.0.expiry?;
+        let window = self.min_ttl + self.refresh_ahead;
+        Some(expiry.checked_sub(window).unwrap_or(expiry))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::client::token::{TemporaryToken, TokenCache};
+    use crate::client::token::{instant_now, TemporaryToken, TokenCache};
+    use futures::future::join_all;
     use std::sync::atomic::{AtomicU32, Ordering};
     use std::time::{Duration, Instant};
 
@@ -129,6 +250,8 @@ mod test {
             cache: Default::default(),
             min_ttl: Duration::from_secs(1),
             fetch_backoff: Duration::from_millis(1),
+            refresh_ahead: Duration::ZERO,
+            refreshing: Default::default(),
         };
 
         static COUNTER: AtomicU32 = AtomicU32::new(0);
@@ -152,4 +275,97 @@ mod test {
         let _ = cache.get_or_insert_with(get_token).await.unwrap();
         assert_eq!(COUNTER.load(Ordering::SeqCst), 2);
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_ahead() {
+        let cache = TokenCache::default()
+            .with_min_ttl(Duration::ZERO)
+            .with_refresh_ahead(Duration::from_millis(900));
+
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+        async fn get_token() -> Result<TemporaryToken<String>, String> {
+            let count = COUNTER.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(TemporaryToken {
+                token: format!("token_{count}"),
+                expiry: Some(instant_now() + Duration::from_secs(1)),
+            })
+        }
+
+        // Initial fetch
+        let token = cache.get_or_refresh_with(get_token).await.unwrap();
+        assert_eq!(token, "token_0");
+
+        // Not yet within the refresh-ahead window
+        let token = cache.get_or_refresh_with(get_token).await.unwrap();
+        assert_eq!(token, "token_0");
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+
+        // Within the window the token is refreshed in the background, with every caller
+        // served the cached token without waiting on the refresh
+        let start = instant_now();
+        let tokens = join_all((0..5).map(|_| cache.get_or_refresh_with(get_token))).await;
+        assert_eq!(instant_now(), start);
+        assert!(tokens.into_iter().all(|x| x.unwrap() == "token_0"));
+
+        // A single refresh is spawned, after which the refreshed token is cached
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 2);
+        // The refresh took 50ms, with the new token refreshed 900ms ahead of its 1s expiry
+        assert_eq!(
+            cache.next_refresh(),
+            Some(start + Duration::from_millis(150))
+        );
+        let token = cache.get_or_refresh_with(get_token).await.unwrap();
+        assert_eq!(token, "token_1");
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_ahead_error() {
+        let cache = TokenCache::default()
+            .with_min_ttl(Duration::ZERO)
+            .with_refresh_ahead(Duration::from_secs(10));
+
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+        async fn get_token() -> Result<TemporaryToken<String>, String> {
+            match COUNTER.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(TemporaryToken {
+                    token: "test_token".to_string(),
+                    expiry: Some(instant_now() + Duration::from_secs(5)),
+                }),
+                _ => Err("refresh failed".to_string()),
+            }
+        }
+
+        let _ = cache.get_or_refresh_with(get_token).await.unwrap();
+        tokio::time::advance(Duration::from_millis(101)).await;
+
+        // A failed refresh ahead of expiry serves the still valid cached token
+        let token = cache.get_or_refresh_with(get_token).await.unwrap();
+        assert_eq!(token, "test_token");
+        tokio::task::yield_now().await;
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 2);
+
+        // And is not reattempted until the fetch backoff has elapsed
+        let token = cache.get_or_refresh_with(get_token).await.unwrap();
+        assert_eq!(token, "test_token");
+        tokio::task::yield_now().await;
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 2);
+
+        tokio::time::advance(Duration::from_millis(101)).await;
+        let token = cache.get_or_refresh_with(get_token).await.unwrap();
+        assert_eq!(token, "test_token");
+        tokio::task::yield_now().await;
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 3);
+
+        // Once expired, the error is returned to the caller
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let err = cache.get_or_refresh_with(get_token).await.unwrap_err();
+        assert_eq!(err, "refresh failed");
+    }
 }
@@ -482,6 +482,8 @@
 //! # }
 //! ```
 //!
+//! [`ObjectStore::update`] provides this loop, retrying a bounded number of times.
+//!
 //! [Optimistic Concurrency Control]: https://en.wikipedia.org/wiki/Optimistic_concurrency_control
 //! [Apache Iceberg]: https://iceberg.apache.org/
 //! [Delta Lake]: https://delta.io/
@@ -586,6 +588,9 @@ pub type DynObjectStore = dyn ObjectStore;
 /// Id type for multipart uploads.
 pub type MultipartId = String;
 
+/// The maximum number of attempts made by [`ObjectStore::update`]
+const UPDATE_MAX_ATTEMPTS: usize = 16;
+
 /// Universal API to multiple object store services.
 #[async_trait]
 pub trait ObjectStore: std::fmt::Display + Send + Sync + Debug + 'static {
@@ -607,6 +612,51 @@ pub trait ObjectStore: std::fmt::Display + Send + Sync + Debug + 'static {
         opts: PutOptions,
     ) -> Result<PutResult>;
 
+    /// Atomically replace the object at `location` with the result of applying `f` to
+    /// its current contents, or `None` if it does not exist
+    ///
+    /// This performs the read-modify-write loop described under [Conditional Put],
+    /// writing with [`PutMode::Update`], or [`PutMode::Create`] if the object does not
+    /// exist, and retrying with the latest contents if the object is concurrently modified,
+    /// created or deleted. `f` may therefore be called multiple times.
+    ///
+    /// Returns the error of the final attempt if the object is still being concurrently
+    /// modified after a bounded number of attempts, and [`Error::NotImplemented`] if the
+    /// store does not support conditional puts.
+    ///
+    /// [Conditional Put]: crate#conditional-put
+    async fn update(
+        &self,
+        location: &Path,
+        f: &(dyn Fn(Option<Bytes>) -> Bytes + Send + Sync),
+    ) -> Result<PutResult> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let (current, mode) = match self.get(location).await {
+                Ok(r) => {
+                    let version = UpdateVersion {
+                        e_tag: r.meta.e_tag.clone(),
+                        version: r.meta.version.clone(),
+                    };
+                    (Some(r.bytes().await?), PutMode::Update(version))
+                }
+                Err(Error::NotFound { .. }) => (None, PutMode::Create),
+                Err(e) => return Err(e),
+            };
+
+            let payload = f(current).into();
+            match self.put_opts(location, payload, mode.into()).await {
+                Err(
+                    Error::Precondition { .. }
+                    | Error::AlreadyExists { .. }
+                    | Error::NotFound { .. },
+                ) if attempt < UPDATE_MAX_ATTEMPTS => continue,
+                r => return r,
+            }
+        }
+    }
+
     /// Perform a multipart upload
     ///
     /// Client should prefer [`ObjectStore::put`] for small payloads, as streaming uploads
@@ -1120,8 +1170,7 @@ impl GetResult {
         match self.payload {
             #[cfg(all(feature = "fs", not(target_arch = "wasm32")))]
             GetResultPayload::File(file, path) => {
-                const CHUNK_SIZE: usize = 8 * 1024;
-                local::chunked_stream(file, path, self.range, CHUNK_SIZE)
+                local::chunked_stream(file, path, self.range, local::CHUNK_SIZE, None)
             }
             GetResultPayload::Stream(s) => s,
         }
@@ -1253,7 +1302,8 @@ pub struct PutMultipartOptions {
     /// Implementation-specific extensions. Intended for use by [`ObjectStore`] implementations
     /// that need to pass context-specific information (like tracing spans) via trait methods.
     ///
-    /// These extensions are ignored entirely by backends offered through this crate.
+    /// These extensions are ignored by backends offered through this crate, with the
+    /// exception of `local::SizeHint`, which is used by `LocalFileSystem`.
     ///
     /// They are also eclused from [`PartialEq`] and [`Eq`].
     pub extensions: ::http::Extensions,
@@ -1331,6 +1381,15 @@ pub enum Error {
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
 
+    /// Error when the location refers to a directory, or prefix, rather than an object
+    #[error("Object at location {} is a directory: {}", path, source)]
+    IsADirectory {
+        /// The path to the directory
+        path: String,
+        /// The wrapped error
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
     /// Error for invalid path
     #[error("Encountered object with invalid path: {}", source)]
     InvalidPath {
@@ -1449,6 +1508,47 @@ mod tests {
     }
     pub(crate) use maybe_skip_integration;
 
+    #[tokio::test]
+    async fn test_update() {
+        // Delay requests so that concurrent updates interleave
+        let config = throttle::ThrottleConfig {
+            wait_get_per_call: std::time::Duration::from_millis(1),
+            wait_put_per_call: std::time::Duration::from_millis(1),
+            ..Default::default()
+        };
+        let store = Arc::new(throttle::ThrottledStore::new(
+            memory::InMemory::new(),
+            config,
+        ));
+        let path = Path::from("counter");
+
+        let append = |current: Option<Bytes>| {
+            let mut data = current.map(Vec::from).unwrap_or_default();
+            data.push(b'x');
+            Bytes::from(data)
+        };
+
+        // Concurrent updates, including concurrent creation of the object, are not lost
+        let tasks: Vec<_> = (0..UPDATE_MAX_ATTEMPTS)
+            .map(|_| {
+                let (store, path) = (Arc::clone(&store), path.clone());
+                tokio::spawn(async move { store.update(&path, &append).await.unwrap() })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let data = store.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data.len(), UPDATE_MAX_ATTEMPTS);
+
+        // Object deleted before the update
+        store.delete(&path).await.unwrap();
+        store.update(&path, &append).await.unwrap();
+        let data = store.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data.as_ref(), b"x");
+    }
+
     /// Test that the returned stream does not borrow the lifetime of Path
     fn list_store<'a>(
         store: &'a dyn ObjectStore,
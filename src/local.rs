@@ -16,12 +16,15 @@
 // under the License.
 
 //! An object store implementation for a local filesystem
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
 use std::fs::{metadata, symlink_metadata, File, Metadata, OpenOptions};
+use std::io;
 use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
-use std::{collections::BTreeSet, io};
+use std::time::{Duration, Instant, SystemTime};
 use std::{collections::VecDeque, path::PathBuf};
 
 use async_trait::async_trait;
@@ -29,17 +32,21 @@ use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use futures::{stream::BoxStream, StreamExt};
 use futures::{FutureExt, TryStreamExt};
+#[cfg(feature = "fs-s3-etag")]
+use md5::Md5;
 use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 use url::Url;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::{
     maybe_spawn_blocking,
-    path::{absolute_path_to_url, Path},
-    util::InvalidGetRange,
-    Attributes, GetOptions, GetResult, GetResultPayload, ListResult, MultipartUpload, ObjectMeta,
-    ObjectStore, PutMode, PutMultipartOptions, PutOptions, PutPayload, PutResult, Result,
-    UploadPart,
+    path::{absolute_path_to_url, InvalidPart, Path, DELIMITER},
+    util::{hex_encode, merge_ranges, split_ranges, InvalidGetRange},
+    Attribute, Attributes, GetOptions, GetRange, GetResult, GetResultPayload, ListResult,
+    MultipartUpload, ObjectMeta, ObjectStore, PutMode, PutMultipartOptions, PutOptions, PutPayload,
+    PutResult, Result, UpdateVersion, UploadPart,
 };
 
 /// A specialized `Error` for filesystem object store-related errors
@@ -95,6 +102,12 @@ pub(crate) enum Error {
     #[error("NotFound")]
     NotFound { path: PathBuf, source: io::Error },
 
+    #[error("Is a directory: {}", path.display())]
+    IsADirectory { path: PathBuf },
+
+    #[error("Not a directory: {}", path.display())]
+    NotADirectory { path: PathBuf },
+
     #[error("Error seeking file {}: {}", path.display(), source)]
     Seek { source: io::Error, path: PathBuf },
 
@@ -107,11 +120,109 @@ pub(crate) enum Error {
     #[error("Unable to canonicalize filesystem root: {}", path.display())]
     UnableToCanonicalize { path: PathBuf, source: io::Error },
 
-    #[error("Filenames containing trailing '/#\\d+/' are not supported: {}", path)]
+    #[error(
+        "Filenames containing trailing '/#\\d+/', '#attrs' or '#sha256' are not supported: {}",
+        path
+    )]
     InvalidPath { path: String },
 
+    #[error("Checksum mismatch for {}: expected {}, found {}", path.display(), expected, actual)]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
     #[error("Upload aborted")]
     Aborted,
+
+    #[error(
+        "Staging directory {} is not on the same filesystem as {}",
+        staging_dir.display(),
+        root.display()
+    )]
+    StagingDirFilesystem { staging_dir: PathBuf, root: PathBuf },
+
+    #[error("Unable to sync {} to disk: {}", path.display(), source)]
+    UnableToSync { source: io::Error, path: PathBuf },
+
+    #[error("Unable to lock file {}: {}", path.display(), source)]
+    UnableToLockFile { source: io::Error, path: PathBuf },
+
+    #[error("Upload staged at {} is in progress", path.display())]
+    UploadInProgress { path: PathBuf },
+
+    #[error("Unable to set extended attribute on {}: {}", path.display(), source)]
+    UnableToSetXattr { source: io::Error, path: PathBuf },
+
+    #[error("Both {} and {} would be copied to {}", first, second, to)]
+    CopyCollision {
+        first: String,
+        second: String,
+        to: String,
+    },
+
+    #[error("Counter at {} does not contain a valid integer: {}", path.display(), source)]
+    InvalidCounter {
+        path: PathBuf,
+        source: std::num::ParseIntError,
+    },
+
+    #[error("Incrementing counter at {} by {} would overflow", path.display(), by)]
+    CounterOverflow { path: PathBuf, by: u64 },
+
+    #[error("Line {} of {} is not valid UTF-8: {}", line, path.display(), source)]
+    InvalidUtf8 {
+        path: PathBuf,
+        line: usize,
+        source: std::string::FromUtf8Error,
+    },
+
+    #[error("Line {} of {} exceeds the maximum length of {} bytes", line, path.display(), max)]
+    LineTooLong {
+        path: PathBuf,
+        line: usize,
+        max: usize,
+    },
+
+    #[error("File {} was replaced or deleted whilst being read", path.display())]
+    Replaced { path: PathBuf },
+
+    #[error("ETag required for conditional update")]
+    MissingETag,
+
+    #[error("Object versions are not supported, got version {version}")]
+    UnsupportedVersion { version: String },
+
+    #[error("Read chunk size must be greater than zero")]
+    ZeroChunkSize,
+
+    #[error("Path {} is a symlink, which are not followed", path.display())]
+    Symlink { path: PathBuf },
+
+    #[error("Path {} resolves outside the root {}", path.display(), root.display())]
+    PathEscapesRoot { path: PathBuf, root: PathBuf },
+
+    #[error("Refusing to {} with an empty prefix", operation)]
+    EmptyPrefix { operation: &'static str },
+
+    #[error("Refusing to {} the filesystem root {}", operation, path.display())]
+    FilesystemRoot {
+        operation: &'static str,
+        path: PathBuf,
+    },
+
+    #[error(
+        "Part {} of {} bytes is smaller than the minimum of {} bytes for all but the last part",
+        part,
+        size,
+        min
+    )]
+    PartTooSmall {
+        part: usize,
+        size: usize,
+        min: usize,
+    },
 }
 
 impl From<Error> for super::Error {
@@ -125,6 +236,13 @@ impl From<Error> for super::Error {
                 path,
                 source: source.into(),
             },
+            Error::UnsupportedVersion { .. } => Self::NotSupported {
+                source: Box::new(source),
+            },
+            Error::IsADirectory { path } => Self::IsADirectory {
+                path: path.to_string_lossy().to_string(),
+                source: io::Error::new(ErrorKind::Other, "is directory").into(),
+            },
             _ => Self::Generic {
                 store: "LocalFileSystem",
                 source: Box::new(source),
@@ -133,6 +251,195 @@ impl From<Error> for super::Error {
     }
 }
 
+/// The [`Attribute::Metadata`] key under which [`LocalFileSystem`] reports the storage class
+/// of an object, see [`LocalFileSystem::with_storage_class`]
+pub const STORAGE_CLASS_ATTRIBUTE: &str = "storage-class";
+
+/// The [`Attribute::Metadata`] key under which [`LocalFileSystem`] reports the number of
+/// bytes allocated on disk for an object, see [`LocalFileSystem::with_allocated_size_attribute`]
+///
+/// This may differ from [`ObjectMeta::size`] for sparse or compressed files
+pub const ALLOCATED_SIZE_ATTRIBUTE: &str = "allocated-size";
+
+/// The [`Attribute::Metadata`] key under which [`LocalFileSystem`] reports the number of
+/// hard links to an object, see [`LocalFileSystem::with_link_count_attribute`]
+pub const LINK_COUNT_ATTRIBUTE: &str = "link-count";
+
+/// The [`Attribute::Metadata`] key under which [`LocalFileSystem`] reports the size of an
+/// object in bytes, see [`LocalFileSystem::with_derived_attributes`]
+pub const CONTENT_LENGTH_ATTRIBUTE: &str = "content-length";
+
+/// The [`Attribute::Metadata`] key under which [`LocalFileSystem`] reports the last modified
+/// time of an object as an HTTP date, see [`LocalFileSystem::with_derived_attributes`]
+pub const LAST_MODIFIED_ATTRIBUTE: &str = "last-modified";
+
+/// The content types reported by [`LocalFileSystem::with_content_type_from_extension`]
+/// for files with a given extension
+const CONTENT_TYPE_EXTENSIONS: [(&str, &str); 16] = [
+    ("avro", "application/avro"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("parquet", "application/vnd.apache.parquet"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+/// The extended attribute that overrides the storage class reported for a single object
+const STORAGE_CLASS_XATTR: &str = "user.object_store.storage_class";
+
+/// The extended attribute recording when an object written by [`LocalFileSystem::put_with_ttl`]
+/// expires, in milliseconds since the UNIX epoch
+const EXPIRES_AT_XATTR: &str = "user.object_store.expires_at";
+
+/// The size of the chunks in which files are streamed
+pub(crate) const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The number of locations inspected by each blocking task of batched operations
+const BATCH_SIZE: usize = 64;
+
+/// The maximum number of concurrent blocking tasks of batched operations
+const BATCH_CONCURRENCY: usize = 8;
+
+/// The content encodings served by [`LocalFileSystem::get_negotiated`], and the extension
+/// of the corresponding variants
+const CONTENT_ENCODING_EXTENSIONS: [(&str, &str); 3] =
+    [("gzip", "gz"), ("br", "br"), ("zstd", "zst")];
+
+/// The prefix of the extended attributes storing [`Attributes`], see
+/// [`LocalFileSystem::with_attributes`]
+const ATTRIBUTE_XATTR_PREFIX: &str = "user.object_store.attribute.";
+
+/// The suffix, following a `#`, of the file storing the attributes of an object, see
+/// [`AttributeStorage::Sidecar`]
+const SIDECAR_SUFFIX: &str = "attrs";
+
+/// The suffix, following a `#`, of the file storing the checksum of an object, see
+/// [`LocalFileSystem::with_checksums`]
+const CHECKSUM_SUFFIX: &str = "sha256";
+
+/// The extended attribute caching a content-derived ETag, see [`EtagStrategy`]
+const ETAG_XATTR: &str = "user.object_store.etag";
+
+#[cfg(feature = "fs-s3-etag")]
+/// The extended attribute recording the part sizes of objects written by multipart
+/// uploads, whose [`EtagStrategy::S3Compatible`] ETag is computed per part
+const MULTIPART_XATTR: &str = "user.object_store.multipart";
+
+/// The maximum number of times a read is retried after reopening a file whose handle
+/// has gone stale, see [`retry_stale`]
+const STALE_HANDLE_RETRIES: usize = 3;
+
+/// The maximum number of times creating parent directories is retried after they were
+/// removed by a concurrent delete with [`LocalFileSystem::with_automatic_cleanup`]
+const CREATE_DIR_RETRIES: usize = 3;
+
+/// The minimum size of all but the last part of an S3 multipart upload, see
+/// [`LocalFileSystem::with_enforce_s3_part_sizes`]
+const S3_MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// The scheme used by [`LocalFileSystem`] to compute the ETag of an object
+///
+/// See [`LocalFileSystem::with_etag_strategy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum EtagStrategy {
+    /// Derive the ETag from the inode, size and modification time of the file, as used
+    /// by many popular web servers
+    ///
+    /// On Windows, the file ID is used in place of the inode, which requires opening the
+    /// file when listing objects or retrieving their metadata.
+    #[default]
+    InodeSizeMtime,
+    /// Derive the ETag from the size and modification time of the file
+    ///
+    /// Unlike [`Self::InodeSizeMtime`], this yields the same ETag for a file copied to
+    /// another filesystem or machine, provided its modification time is preserved
+    SizeMtime,
+    /// Compute the ETag from the SHA-256 of the file contents, e.g. `"<sha256>"`
+    ///
+    /// This changes only if the contents of the file do, regardless of how the file
+    /// was written or copied. As with [`Self::S3Compatible`], the ETag is quoted, and
+    /// listings only return ETags that were previously computed.
+    ContentSha256,
+    /// Compute the ETag from the file contents, as S3 and S3-compatible stores such as
+    /// MinIO would for an object uploaded with the same parts
+    ///
+    /// Objects written with a single request have the MD5 of their contents as their ETag,
+    /// whereas objects written by multipart uploads have the MD5 of the concatenated MD5s
+    /// of each part, suffixed with the number of parts, e.g. `"<md5-of-md5s>-3"`. As with
+    /// S3, the ETag is quoted.
+    ///
+    /// The part sizes of multipart uploads written with this strategy are recorded in the
+    /// `user.object_store.multipart` extended attribute, and so where these are
+    /// unsupported, or for objects written with another strategy, all objects have the
+    /// ETag of an object written with a single request. As computing the ETag requires reading the
+    /// entire file, listings only return ETags that were previously computed, e.g. when
+    /// the object was written.
+    #[cfg(feature = "fs-s3-etag")]
+    S3Compatible,
+}
+
+/// Where [`LocalFileSystem`] persists the [`Attributes`] of objects
+///
+/// See [`LocalFileSystem::with_attribute_storage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum AttributeStorage {
+    /// Attributes are not persisted, and writes with attributes return
+    /// [`crate::Error::NotImplemented`]
+    #[default]
+    Disabled,
+    /// Store each attribute in an extended attribute of the file, which is not supported
+    /// by all filesystems, nor on Windows
+    ExtendedAttributes,
+    /// Store the attributes as a JSON object in a sidecar file next to the object, e.g.
+    /// `foo.parquet#attrs` for `foo.parquet`, which is ignored by listing operations
+    #[cfg(feature = "fs-sidecar")]
+    Sidecar,
+}
+
+/// The expected total size in bytes of a multipart upload to [`LocalFileSystem`]
+///
+/// When provided in [`PutMultipartOptions::extensions`], disk space for the upload is
+/// reserved up front on Linux, reducing fragmentation. This is purely a hint, and is
+/// ignored on other platforms or filesystems that do not support preallocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeHint(pub u64);
+
+/// The expiry of an object written by [`LocalFileSystem::put_with_ttl`], in milliseconds
+/// since the unix epoch, provided to [`ObjectStore::put_opts`] in [`PutOptions::extensions`]
+#[derive(Debug, Clone, Copy)]
+struct ExpiresAt(u128);
+
+/// How [`LocalFileSystem::copy`] and [`LocalFileSystem::copy_if_not_exists`] copy files
+///
+/// See [`LocalFileSystem::with_copy_strategy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum CopyStrategy {
+    /// Create a hard link to the source file, sharing its inode
+    #[default]
+    HardLink,
+    /// Clone the source file with a copy-on-write reflink, as supported by filesystems such
+    /// as Btrfs and XFS on Linux, falling back to [`CopyStrategy::HardLink`] where reflinks
+    /// are not supported
+    ///
+    /// Unlike a hard link, a reflink has its own inode, and so the copy is unaffected by
+    /// subsequent in-place modifications of the source. Extended attributes are not cloned,
+    /// see [`AttributeStorage::ExtendedAttributes`].
+    Reflink,
+}
+
 /// Local filesystem storage providing an [`ObjectStore`] interface to files on
 /// local disk. Can optionally be created with a directory prefix
 ///
@@ -165,7 +472,9 @@ impl From<Error> for super::Error {
 /// encountering such sequences.
 ///
 /// Finally, filenames matching the regex `/.*#\d+/`, e.g. `foo.parquet#123`, are not supported
-/// by [`LocalFileSystem`] as they are used to provide atomic writes. Such files will be ignored
+/// by [`LocalFileSystem`] as they are used to provide atomic writes, nor when using
+/// [`AttributeStorage::Sidecar`] are those ending with `#attrs`, or when using
+/// [`LocalFileSystem::with_checksums`] those ending with `#sha256`. Such files will be ignored
 /// for listing operations, and attempting to address such a file will error.
 ///
 /// # Tokio Compatibility
@@ -188,10 +497,31 @@ impl From<Error> for super::Error {
 /// * Mutating a file through one or more symlinks will mutate the underlying file
 /// * Deleting a path that resolves to a symlink will only delete the symlink
 ///
+/// See [`LocalFileSystem::with_follow_symlinks`] to instead ignore symlinks, or
+/// [`LocalFileSystem::with_sandbox`] to only follow those resolving within the root.
+///
+/// # Conditional Updates
+///
+/// [`PutMode::Update`] is implemented by comparing the ETag of the existing file whilst
+/// holding an advisory lock on it. This serializes concurrent conditional updates, including
+/// from other processes, but not unconditional writes, which may be lost if they race with
+/// a conditional update. As files are not versioned, an [`UpdateVersion`] with a `version`
+/// returns [`crate::Error::NotSupported`].
+///
 /// # Cross-Filesystem Copy
 ///
-/// [`LocalFileSystem::copy`] is implemented using [`std::fs::hard_link`], and therefore
-/// does not support copying across filesystem boundaries.
+/// [`LocalFileSystem::copy`] is implemented using [`std::fs::hard_link`], which is not
+/// supported across filesystem boundaries, e.g. where the destination is a different mount.
+/// In this case the contents are instead copied to a staging file, synced to disk, and then
+/// atomically renamed into place. Similarly [`LocalFileSystem::rename`] falls back to such
+/// a copy followed by deleting the source. Such copies have default permissions and the
+/// current modification time, unless [`LocalFileSystem::with_preserve_metadata`].
+///
+/// On Windows, filesystems such as ReFS and network drives may not support hard links at
+/// all, in which case files are instead copied with `CopyFileExW`, failing if the
+/// destination exists. This preserves the semantics of [`LocalFileSystem::copy_if_not_exists`]
+/// and [`PutMode::Create`], however, unlike a hard link, a concurrent reader may observe a
+/// partially copied file.
 ///
 #[derive(Debug)]
 pub struct LocalFileSystem {
@@ -200,11 +530,217 @@ pub struct LocalFileSystem {
     automatic_cleanup: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Config {
     root: Url,
+    storage_class: String,
+    replica: Option<Url>,
+    checksums: bool,
+    directory_error: bool,
+    normalize_backslashes: bool,
+    etag_strategy: EtagStrategy,
+    nfs_consistency: bool,
+    strict_path_segments: bool,
+    restrict_symlinks_to_root: bool,
+    detect_replacement: bool,
+    read_rate_limit: Option<Arc<RateLimiter>>,
+    enforce_s3_part_sizes: bool,
+    link_count_attribute: bool,
+    allocated_size_attribute: bool,
+    decode_percent_filenames: bool,
+    fd_cache: Option<Arc<FdCache>>,
+    skip_unreadable: bool,
+    /// The number of directories skipped by listing operations, see
+    /// [`LocalFileSystem::skipped_unreadable`]
+    skipped_unreadable: SkippedCounter,
+    attributes: AttributeStorage,
+    derived_attributes: bool,
+    copy_strategy: CopyStrategy,
+    durable_writes: bool,
+    /// The number of files and directories synced to disk, to verify
+    /// [`LocalFileSystem::with_durable_writes`]
+    #[cfg(test)]
+    syncs: Arc<AtomicUsize>,
+    staging_dir: Option<PathBuf>,
+    follow_symlinks: bool,
+    sandbox: bool,
+    mmap_threshold: Option<usize>,
+    read_chunk_size: usize,
+    preserve_metadata: bool,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    list_concurrency: usize,
+    content_type_from_extension: bool,
+    range_coalesce_gap: usize,
+    io_limiter: Option<Arc<IoLimiter>>,
+    directory_markers: bool,
+}
+
+/// A snapshot of the effective configuration of a [`LocalFileSystem`]
+///
+/// See [`LocalFileSystem::config`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LocalFileSystemConfig {
+    /// The root of the store, see [`LocalFileSystem::new_with_prefix`]
+    pub root: Url,
+    /// See [`LocalFileSystem::with_automatic_cleanup`]
+    pub automatic_cleanup: bool,
+    /// See [`LocalFileSystem::with_storage_class`]
+    pub storage_class: String,
+    /// See [`LocalFileSystem::with_replica`]
+    pub replica: Option<Url>,
+    /// See [`LocalFileSystem::with_checksums`]
+    pub checksums: bool,
+    /// See [`LocalFileSystem::with_directory_error`]
+    pub directory_error: bool,
+    /// See [`LocalFileSystem::with_normalize_backslashes`]
+    pub normalize_backslashes: bool,
+    /// See [`LocalFileSystem::with_etag_strategy`]
+    pub etag_strategy: EtagStrategy,
+    /// See [`LocalFileSystem::with_nfs_consistency`]
+    pub nfs_consistency: bool,
+    /// See [`LocalFileSystem::with_strict_path_segments`]
+    pub strict_path_segments: bool,
+    /// See [`LocalFileSystem::with_restrict_symlinks_to_root`]
+    pub restrict_symlinks_to_root: bool,
+    /// See [`LocalFileSystem::with_detect_replacement`]
+    pub detect_replacement: bool,
+    /// See [`LocalFileSystem::with_read_rate_limit`], in bytes per second
+    pub read_rate_limit: Option<u64>,
+    /// See [`LocalFileSystem::with_enforce_s3_part_sizes`]
+    pub enforce_s3_part_sizes: bool,
+    /// See [`LocalFileSystem::with_link_count_attribute`]
+    pub link_count_attribute: bool,
+    /// See [`LocalFileSystem::with_allocated_size_attribute`]
+    pub allocated_size_attribute: bool,
+    /// See [`LocalFileSystem::with_decode_percent_filenames`]
+    pub decode_percent_filenames: bool,
+    /// See [`LocalFileSystem::with_fd_cache`], the maximum number of open files
+    pub fd_cache: Option<usize>,
+    /// See [`LocalFileSystem::with_skip_unreadable`]
+    pub skip_unreadable: bool,
+    /// See [`LocalFileSystem::with_attribute_storage`]
+    pub attributes: AttributeStorage,
+    /// See [`LocalFileSystem::with_derived_attributes`]
+    pub derived_attributes: bool,
+    /// See [`LocalFileSystem::with_copy_strategy`]
+    pub copy_strategy: CopyStrategy,
+    /// See [`LocalFileSystem::with_durable_writes`]
+    pub durable_writes: bool,
+    /// See [`LocalFileSystem::with_staging_dir`]
+    pub staging_dir: Option<PathBuf>,
+    /// See [`LocalFileSystem::with_follow_symlinks`]
+    pub follow_symlinks: bool,
+    /// See [`LocalFileSystem::with_sandbox`]
+    pub sandbox: bool,
+    /// See [`LocalFileSystem::with_mmap_threshold`] and [`LocalFileSystem::with_mmap`]
+    pub mmap_threshold: Option<usize>,
+    /// See [`LocalFileSystem::with_read_chunk_size`]
+    pub read_chunk_size: usize,
+    /// See [`LocalFileSystem::with_preserve_metadata`]
+    pub preserve_metadata: bool,
+    /// See [`LocalFileSystem::with_file_mode`]
+    pub file_mode: Option<u32>,
+    /// See [`LocalFileSystem::with_dir_mode`]
+    pub dir_mode: Option<u32>,
+    /// See [`LocalFileSystem::with_list_concurrency`]
+    pub list_concurrency: usize,
+    /// See [`LocalFileSystem::with_content_type_from_extension`]
+    pub content_type_from_extension: bool,
+    /// See [`LocalFileSystem::with_range_coalesce_gap`]
+    pub range_coalesce_gap: usize,
+    /// See [`LocalFileSystem::with_io_concurrency_limit`]
+    pub io_concurrency_limit: Option<usize>,
+    /// See [`LocalFileSystem::with_directory_markers`]
+    pub directory_markers: bool,
 }
 
+const DEFAULT_STORAGE_CLASS: &str = "STANDARD";
+
+/// The anomalies found by [`LocalFileSystem::verify`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VerifyReport {
+    /// Non-empty staging files, which may have been left behind by interrupted uploads
+    pub staging_files: Vec<PathBuf>,
+    /// Empty staging files, which may have been left behind by interrupted uploads
+    pub empty_staging_files: Vec<PathBuf>,
+    /// Symlinks whose target does not exist
+    pub broken_symlinks: Vec<PathBuf>,
+    /// Paths that could not be read, along with the error encountered
+    pub unreadable: Vec<(PathBuf, String)>,
+    /// Files whose contents do not match their recorded checksum, see
+    /// [`LocalFileSystem::with_checksums`]
+    pub checksum_mismatches: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    /// Returns true if no anomalies were found
+    pub fn is_empty(&self) -> bool {
+        self.staging_files.is_empty()
+            && self.empty_staging_files.is_empty()
+            && self.broken_symlinks.is_empty()
+            && self.unreadable.is_empty()
+            && self.checksum_mismatches.is_empty()
+    }
+}
+
+/// The capacity of the filesystem containing a [`LocalFileSystem`], see
+/// [`LocalFileSystem::disk_usage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DiskUsage {
+    /// The total size of the filesystem in bytes
+    pub total: u64,
+    /// The number of bytes available to unprivileged users
+    pub available: u64,
+    /// The number of bytes in use
+    pub used: u64,
+}
+
+/// The order of the objects returned by [`LocalFileSystem::list_sorted_by_mtime`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtimeOrder {
+    /// Least recently modified objects first
+    OldestFirst,
+    /// Most recently modified objects first
+    NewestFirst,
+}
+
+/// An [`ObjectMeta`] ordered by its position in the output of
+/// [`LocalFileSystem::list_sorted_by_mtime`], with ties broken by location
+#[derive(Debug)]
+struct MtimeEntry {
+    order: MtimeOrder,
+    meta: ObjectMeta,
+}
+
+impl Ord for MtimeEntry {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        let (a, b) = (&self.meta, &other.meta);
+        let mtime = match self.order {
+            MtimeOrder::OldestFirst => a.last_modified.cmp(&b.last_modified),
+            MtimeOrder::NewestFirst => b.last_modified.cmp(&a.last_modified),
+        };
+        mtime.then_with(|| a.location.cmp(&b.location))
+    }
+}
+
+impl PartialOrd for MtimeEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MtimeEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for MtimeEntry {}
+
 impl std::fmt::Display for LocalFileSystem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "LocalFileSystem({})", self.config.root)
@@ -223,11 +759,60 @@ impl LocalFileSystem {
         Self {
             config: Arc::new(Config {
                 root: Url::parse("file:///").unwrap(),
+                storage_class: DEFAULT_STORAGE_CLASS.to_string(),
+                replica: None,
+                checksums: false,
+                directory_error: false,
+                normalize_backslashes: false,
+                etag_strategy: EtagStrategy::default(),
+                nfs_consistency: false,
+                strict_path_segments: false,
+                restrict_symlinks_to_root: false,
+                detect_replacement: false,
+                read_rate_limit: None,
+                enforce_s3_part_sizes: false,
+                link_count_attribute: false,
+                allocated_size_attribute: false,
+                decode_percent_filenames: false,
+                fd_cache: None,
+                skip_unreadable: false,
+                skipped_unreadable: Default::default(),
+                attributes: AttributeStorage::Disabled,
+                derived_attributes: false,
+                copy_strategy: CopyStrategy::HardLink,
+                durable_writes: false,
+                #[cfg(test)]
+                syncs: Default::default(),
+                staging_dir: None,
+                follow_symlinks: true,
+                sandbox: false,
+                mmap_threshold: None,
+                read_chunk_size: CHUNK_SIZE,
+                preserve_metadata: false,
+                file_mode: None,
+                dir_mode: None,
+                list_concurrency: 1,
+                content_type_from_extension: false,
+                range_coalesce_gap: 0,
+                io_limiter: None,
+                directory_markers: false,
             }),
             automatic_cleanup: false,
         }
     }
 
+    /// Create new filesystem storage with `prefix` applied to all paths, creating the
+    /// directory `prefix` and any missing ancestors if necessary
+    ///
+    /// Returns an error if the path exists but is not a directory
+    pub fn new_with_prefix_create(prefix: impl AsRef<std::path::Path>) -> Result<Self> {
+        std::fs::create_dir_all(&prefix).map_err(|source| {
+            let path = prefix.as_ref().into();
+            Error::UnableToCreateDir { source, path }
+        })?;
+        Self::new_with_prefix(prefix)
+    }
+
     /// Create new filesystem storage with `prefix` applied to all paths
     ///
     /// Returns an error if the path does not exist
@@ -238,23 +823,21 @@ impl LocalFileSystem {
             Error::UnableToCanonicalize { source, path }
         })?;
 
-        Ok(Self {
-            config: Arc::new(Config {
-                root: absolute_path_to_url(path)?,
-            }),
-            automatic_cleanup: false,
-        })
+        let mut store = Self::new();
+        Arc::make_mut(&mut store.config).root = absolute_path_to_url(path)?;
+        Ok(store)
     }
 
     /// Return an absolute filesystem path of the given file location
     pub fn path_to_filesystem(&self, location: &Path) -> Result<PathBuf> {
-        if !is_valid_file_path(location) {
+        if !self.config.is_valid_file_path(location) {
             let path = location.as_ref().into();
             let error = Error::InvalidPath { path };
             return Err(error.into());
         }
 
         let path = self.config.prefix_to_filesystem(location)?;
+        self.config.check_sandbox(&path)?;
 
         #[cfg(target_os = "windows")]
         let path = {
@@ -272,1406 +855,9316 @@ impl LocalFileSystem {
         Ok(path)
     }
 
+    /// Returns the effective configuration of this [`LocalFileSystem`]
+    ///
+    /// This is intended for diagnostics, such as logging the configuration of a store
+    pub fn config(&self) -> LocalFileSystemConfig {
+        let config = self.config.as_ref();
+        LocalFileSystemConfig {
+            root: config.root.clone(),
+            automatic_cleanup: self.automatic_cleanup,
+            storage_class: config.storage_class.clone(),
+            replica: config.replica.clone(),
+            checksums: config.checksums,
+            directory_error: config.directory_error,
+            normalize_backslashes: config.normalize_backslashes,
+            etag_strategy: config.etag_strategy,
+            nfs_consistency: config.nfs_consistency,
+            strict_path_segments: config.strict_path_segments,
+            restrict_symlinks_to_root: config.restrict_symlinks_to_root,
+            detect_replacement: config.detect_replacement,
+            read_rate_limit: config.read_rate_limit.as_ref().map(|l| l.bytes_per_sec),
+            enforce_s3_part_sizes: config.enforce_s3_part_sizes,
+            link_count_attribute: config.link_count_attribute,
+            allocated_size_attribute: config.allocated_size_attribute,
+            decode_percent_filenames: config.decode_percent_filenames,
+            fd_cache: config.fd_cache.as_ref().map(|c| c.capacity),
+            skip_unreadable: config.skip_unreadable,
+            attributes: config.attributes,
+            derived_attributes: config.derived_attributes,
+            copy_strategy: config.copy_strategy,
+            durable_writes: config.durable_writes,
+            staging_dir: config.staging_dir.clone(),
+            follow_symlinks: config.follow_symlinks,
+            sandbox: config.sandbox,
+            mmap_threshold: config.mmap_threshold,
+            read_chunk_size: config.read_chunk_size,
+            preserve_metadata: config.preserve_metadata,
+            file_mode: config.file_mode,
+            dir_mode: config.dir_mode,
+            list_concurrency: config.list_concurrency,
+            content_type_from_extension: config.content_type_from_extension,
+            range_coalesce_gap: config.range_coalesce_gap,
+            io_concurrency_limit: config.io_limiter.as_ref().map(|l| l.limit),
+            directory_markers: config.directory_markers,
+        }
+    }
+
     /// Enable automatic cleanup of empty directories when deleting files
+    ///
+    /// Cleanup stops at the first directory that cannot be removed, e.g. because a
+    /// concurrent write created a file within it, and writes retry creating parent
+    /// directories removed by a concurrent cleanup
     pub fn with_automatic_cleanup(mut self, automatic_cleanup: bool) -> Self {
         self.automatic_cleanup = automatic_cleanup;
         self
     }
-}
-
-impl Config {
-    /// Return an absolute filesystem path of the given location
-    fn prefix_to_filesystem(&self, location: &Path) -> Result<PathBuf> {
-        let mut url = self.root.clone();
-        url.path_segments_mut()
-            .expect("url path")
-            // technically not necessary as Path ignores empty segments
-            // but avoids creating paths with "//" which look odd in error messages.
-            .pop_if_empty()
-            .extend(location.parts());
 
-        url.to_file_path()
-            .map_err(|_| Error::InvalidUrl { url }.into())
+    /// Set the storage class reported for objects, defaults to `STANDARD`
+    ///
+    /// The storage class is returned in [`GetResult::attributes`] under the
+    /// [`STORAGE_CLASS_ATTRIBUTE`] key, allowing [`LocalFileSystem`] to stand in for a
+    /// tiered object store. On platforms supporting extended attributes, this can be
+    /// overridden for an individual object by setting `user.object_store.storage_class`
+    /// on the underlying file.
+    pub fn with_storage_class(mut self, storage_class: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.config).storage_class = storage_class.into();
+        self
     }
 
-    /// Resolves the provided absolute filesystem path to a [`Path`] prefix
-    fn filesystem_to_path(&self, location: &std::path::Path) -> Result<Path> {
-        Ok(Path::from_absolute_path_with_base(
-            location,
-            Some(&self.root),
-        )?)
+    /// Serve every non-empty range read by [`ObjectStore::get_range`] and
+    /// [`ObjectStore::get_ranges`] by memory mapping the requested region of the file,
+    /// equivalent to a [`Self::with_mmap_threshold`] of `0`
+    ///
+    /// The returned [`Bytes`] is a zero-copy view of the mapping, which is unmapped once
+    /// it, and any slices of it, are dropped. Ranges are validated as for buffered reads,
+    /// returning the same errors for invalid ranges.
+    ///
+    /// Files that cannot be mapped, such as FIFOs, fall back to buffered reads.
+    ///
+    /// Note: modifying a file in place whilst it is mapped results in the mapped bytes
+    /// changing, and should another process truncate the file, accessing the bytes beyond
+    /// its new length raises `SIGBUS`, terminating the process. Writes made through
+    /// [`LocalFileSystem`] replace files atomically and are therefore unaffected.
+    #[cfg(feature = "fs-mmap")]
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        Arc::make_mut(&mut self.config).mmap_threshold = mmap.then_some(0);
+        self
     }
-}
 
-fn is_valid_file_path(path: &Path) -> bool {
-    match path.filename() {
-        Some(p) => match p.split_once('#') {
-            Some((_, suffix)) if !suffix.is_empty() => {
-                // Valid if contains non-digits
-                !suffix.as_bytes().iter().all(|x| x.is_ascii_digit())
-            }
-            _ => true,
-        },
-        None => false,
+    /// Serve reads of ranges larger than `threshold` bytes by memory mapping them, see
+    /// [`Self::with_mmap`]
+    ///
+    /// This only maps the large ranges read by [`ObjectStore::get_range`] and
+    /// [`ObjectStore::get_ranges`], avoiding allocating and copying into a buffer of the
+    /// full size, whilst reading small ranges into a buffer. The caveats of
+    /// [`Self::with_mmap`] on files modified in place also apply.
+    #[cfg(feature = "fs-mmap")]
+    pub fn with_mmap_threshold(mut self, threshold: usize) -> Self {
+        Arc::make_mut(&mut self.config).mmap_threshold = Some(threshold);
+        self
     }
-}
-
-#[async_trait]
-impl ObjectStore for LocalFileSystem {
-    async fn put_opts(
-        &self,
-        location: &Path,
-        payload: PutPayload,
-        opts: PutOptions,
-    ) -> Result<PutResult> {
-        if matches!(opts.mode, PutMode::Update(_)) {
-            return Err(crate::Error::NotImplemented);
-        }
-
-        if !opts.attributes.is_empty() {
-            return Err(crate::Error::NotImplemented);
-        }
 
-        let path = self.path_to_filesystem(location)?;
-        maybe_spawn_blocking(move || {
-            let (mut file, staging_path) = new_staged_upload(&path)?;
-            let mut e_tag = None;
-
-            let err = match payload.iter().try_for_each(|x| file.write_all(x)) {
-                Ok(_) => {
-                    let metadata = file.metadata().map_err(|e| Error::Metadata {
-                        source: e.into(),
-                        path: path.to_string_lossy().to_string(),
-                    })?;
-                    e_tag = Some(get_etag(&metadata));
-                    match opts.mode {
-                        PutMode::Overwrite => {
-                            // For some fuse types of file systems, the file must be closed first
-                            // to trigger the upload operation, and then renamed, such as Blobfuse
-                            std::mem::drop(file);
-                            match std::fs::rename(&staging_path, &path) {
-                                Ok(_) => None,
-                                Err(source) => Some(Error::UnableToRenameFile { source }),
-                            }
-                        }
-                        PutMode::Create => match std::fs::hard_link(&staging_path, &path) {
-                            Ok(_) => {
-                                let _ = std::fs::remove_file(&staging_path); // Attempt to cleanup
-                                None
-                            }
-                            Err(source) => match source.kind() {
-                                ErrorKind::AlreadyExists => Some(Error::AlreadyExists {
-                                    path: path.to_str().unwrap().to_string(),
-                                    source,
-                                }),
-                                _ => Some(Error::UnableToRenameFile { source }),
-                            },
-                        },
-                        PutMode::Update(_) => unreachable!(),
-                    }
-                }
-                Err(source) => Some(Error::UnableToCopyDataToFile { source }),
-            };
+    /// Return [`crate::Error::IsADirectory`] when reading a location that is a directory
+    ///
+    /// By default such reads return [`crate::Error::NotFound`], consistent with object
+    /// stores where a directory is merely a common prefix of other objects. Enabling this
+    /// allows callers to distinguish a missing object from a prefix, e.g. to fall back
+    /// to listing it.
+    pub fn with_directory_error(mut self, directory_error: bool) -> Self {
+        Arc::make_mut(&mut self.config).directory_error = directory_error;
+        self
+    }
 
-            if let Some(err) = err {
-                let _ = std::fs::remove_file(&staging_path); // Attempt to cleanup
-                return Err(err.into());
-            }
+    /// Treat `\` in locations as a path delimiter, equivalent to `/`
+    ///
+    /// [`Path`] ordinarily treats `\` as a literal character, and so a location parsed from
+    /// a native Windows path, e.g. `a\b\c`, refers to a single file rather than `a/b/c`.
+    /// Enabling this maps such locations intuitively, at the cost of making objects with
+    /// a `\` in their name inaccessible.
+    pub fn with_normalize_backslashes(mut self, normalize_backslashes: bool) -> Self {
+        Arc::make_mut(&mut self.config).normalize_backslashes = normalize_backslashes;
+        self
+    }
 
-            Ok(PutResult {
-                e_tag,
-                version: None,
-            })
-        })
-        .await
+    /// Reject locations containing empty path segments, defaults to `false`
+    ///
+    /// Whilst [`Path::parse`] rejects empty segments, and [`Path::from`] removes them,
+    /// a [`Path`] constructed otherwise, e.g. with [`Path::child`] and an empty part, may
+    /// contain `a//b`. By default such locations are silently resolved to `a/b`, enabling
+    /// this instead returns [`Error::InvalidPath`](crate::Error::InvalidPath), helping
+    /// callers catch malformed keys.
+    pub fn with_strict_path_segments(mut self, strict_path_segments: bool) -> Self {
+        Arc::make_mut(&mut self.config).strict_path_segments = strict_path_segments;
+        self
     }
 
-    async fn put_multipart_opts(
-        &self,
-        location: &Path,
-        opts: PutMultipartOptions,
-    ) -> Result<Box<dyn MultipartUpload>> {
-        if !opts.attributes.is_empty() {
-            return Err(crate::Error::NotImplemented);
-        }
+    /// Refuse to write objects through symlinks that resolve outside the root, defaults
+    /// to `false`
+    ///
+    /// By default, writing `a/b` where `a` is a symlink to a directory outside the root
+    /// will write the object outside the root. Enabling this instead returns an error from
+    /// [`ObjectStore::put`], [`ObjectStore::put_multipart`] and [`LocalFileSystem::put_with_ttl`]
+    /// if the parent directory of the object does not resolve to within the root.
+    ///
+    /// This does not protect against symlinks created concurrently with the write.
+    pub fn with_restrict_symlinks_to_root(mut self, restrict_symlinks_to_root: bool) -> Self {
+        Arc::make_mut(&mut self.config).restrict_symlinks_to_root = restrict_symlinks_to_root;
+        self
+    }
 
-        let dest = self.path_to_filesystem(location)?;
-        let (file, src) = new_staged_upload(&dest)?;
-        Ok(Box::new(LocalUpload::new(src, dest, file)))
+    /// Follow symlinks when listing and reading objects, defaults to `true`
+    ///
+    /// By default symlinks are followed, including those resolving outside the root, see
+    /// [Symlinks](Self#symlinks). If disabled, symlinks are excluded from listings, neither
+    /// being returned as objects nor traversed as directories, and reading an object whose
+    /// path below the root contains a symlink returns an error.
+    ///
+    /// On unix, the object and each of its parent directories are opened with `O_NOFOLLOW`,
+    /// such that a symlink created concurrently with a read is never followed. On other
+    /// platforms only the final path component is checked, before opening it, and
+    /// symlinked directories are still followed. See
+    /// [`Self::with_restrict_symlinks_to_root`] to prevent writes through symlinks.
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        Arc::make_mut(&mut self.config).follow_symlinks = follow_symlinks;
+        self
     }
 
-    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
-        let location = location.clone();
-        let path = self.path_to_filesystem(&location)?;
-        maybe_spawn_blocking(move || {
-            let (file, metadata) = open_file(&path)?;
-            let meta = convert_metadata(metadata, location);
-            options.check_preconditions(&meta)?;
+    /// Reject paths that resolve outside the root, defaults to `false`
+    ///
+    /// If enabled, the path of every object accessed is canonicalized, following any
+    /// symlinks, and an error returned if it does not resolve to within the canonicalized
+    /// root. For objects that do not yet exist, their closest existing ancestor is checked
+    /// instead. Listing operations skip symlinks resolving outside the root, neither
+    /// returning them as objects nor traversing them as directories.
+    ///
+    /// This does not protect against symlinks created concurrently with an operation.
+    pub fn with_sandbox(mut self, sandbox: bool) -> Self {
+        Arc::make_mut(&mut self.config).sandbox = sandbox;
+        self
+    }
 
-            let range = match options.range {
-                Some(r) => r
-                    .as_range(meta.size)
-                    .map_err(|source| Error::InvalidRange { source })?,
-                None => 0..meta.size,
-            };
+    /// Remove staged uploads left behind by crashed processes, returning the number removed
+    ///
+    /// Staged uploads, files with a `#` followed by digits suffix, that were last modified
+    /// at least `max_age` ago are removed by a scan of the root. This is intended to be
+    /// awaited once on startup. `max_age` should comfortably exceed the duration of any
+    /// upload, as in-progress uploads would otherwise be removed.
+    ///
+    /// Returns an error if the root of this store is the filesystem root, as is the case
+    /// for a store created with [`Self::new`].
+    pub async fn cleanup_staging(&self, max_age: Duration) -> Result<usize> {
+        let path = self.config.prefix_to_filesystem(&Path::default())?;
+        if path.parent().is_none() {
+            let operation = "clean up staged uploads in";
+            return Err(Error::FilesystemRoot { operation, path }.into());
+        }
 
-            Ok(GetResult {
-                payload: GetResultPayload::File(file, path),
-                attributes: Attributes::default(),
-                range,
-                meta,
-            })
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            let removed = remove_stale_staging(&path, max_age);
+            info!(
+                "Removed {} stale staged uploads from {}",
+                removed,
+                path.display()
+            );
+            Ok(removed)
         })
         .await
     }
 
-    async fn get_range(&self, location: &Path, range: Range<u64>) -> Result<Bytes> {
-        let path = self.path_to_filesystem(location)?;
-        maybe_spawn_blocking(move || {
-            let (mut file, _) = open_file(&path)?;
-            read_range(&mut file, &path, range)
-        })
-        .await
+    /// Detect files replaced or deleted whilst being read by [`ObjectStore::get`], defaults
+    /// to `false`
+    ///
+    /// An opened file continues to be readable after it is replaced, and so by default a
+    /// read returns a consistent snapshot of the version that was opened, even if it has
+    /// since been superseded. Enabling this instead returns an error at the end of the
+    /// stream if the location no longer refers to the file that was read.
+    ///
+    /// When enabled, [`GetResult::payload`] is a [`GetResultPayload::Stream`] rather than
+    /// a [`GetResultPayload::File`].
+    pub fn with_detect_replacement(mut self, detect_replacement: bool) -> Self {
+        Arc::make_mut(&mut self.config).detect_replacement = detect_replacement;
+        self
     }
 
-    async fn get_ranges(&self, location: &Path, ranges: &[Range<u64>]) -> Result<Vec<Bytes>> {
-        let path = self.path_to_filesystem(location)?;
-        let ranges = ranges.to_vec();
-        maybe_spawn_blocking(move || {
-            // Vectored IO might be faster
-            let (mut file, _) = open_file(&path)?;
-            ranges
-                .into_iter()
-                .map(|r| read_range(&mut file, &path, r))
-                .collect()
-        })
-        .await
+    /// Set the size of the chunks in which objects are streamed, defaults to 8 KiB
+    ///
+    /// Applies to the [`GetResult`] of [`ObjectStore::get`], streamed with
+    /// [`GetResult::into_stream`], and to [`Self::lines`]. The final chunk of a stream
+    /// may be smaller. A `chunk_size` of `0` results in streams returning an error.
+    ///
+    /// When set to other than the default, [`GetResult::payload`] is a
+    /// [`GetResultPayload::Stream`] rather than a [`GetResultPayload::File`].
+    pub fn with_read_chunk_size(mut self, chunk_size: usize) -> Self {
+        Arc::make_mut(&mut self.config).read_chunk_size = chunk_size;
+        self
     }
 
-    async fn delete(&self, location: &Path) -> Result<()> {
-        let config = Arc::clone(&self.config);
-        let path = self.path_to_filesystem(location)?;
-        let automactic_cleanup = self.automatic_cleanup;
-        maybe_spawn_blocking(move || {
-            if let Err(e) = std::fs::remove_file(&path) {
-                Err(match e.kind() {
-                    ErrorKind::NotFound => Error::NotFound { path, source: e }.into(),
-                    _ => Error::UnableToDeleteFile { path, source: e }.into(),
-                })
-            } else if automactic_cleanup {
-                let root = &config.root;
-                let root = root
-                    .to_file_path()
-                    .map_err(|_| Error::InvalidUrl { url: root.clone() })?;
-
-                // here we will try to traverse up and delete an empty dir if possible until we reach the root or get an error
-                let mut parent = path.parent();
-
-                while let Some(loc) = parent {
-                    if loc != root && std::fs::remove_dir(loc).is_ok() {
-                        parent = loc.parent();
-                    } else {
-                        break;
-                    }
-                }
-
-                Ok(())
-            } else {
-                Ok(())
-            }
-        })
-        .await
+    /// Limit the rate at which data is read from files to `bytes_per_sec`, defaults to
+    /// unlimited
+    ///
+    /// The limit is shared by all reads from this store, including clones of it, and so
+    /// bounds their aggregate throughput. Streaming reads wait for each chunk to be within
+    /// the limit before reading it, whilst reads of byte ranges return once the data read
+    /// is within the limit. Either waits asynchronously, without occupying the blocking
+    /// thread pool. A `bytes_per_sec` of `0` is treated as `1`.
+    ///
+    /// When set, [`GetResult::payload`] is a [`GetResultPayload::Stream`] rather than
+    /// a [`GetResultPayload::File`].
+    pub fn with_read_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        let limiter = RateLimiter::new(bytes_per_sec.max(1));
+        Arc::make_mut(&mut self.config).read_rate_limit = Some(Arc::new(limiter));
+        self
     }
 
-    fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, Result<ObjectMeta>> {
-        self.list_with_maybe_offset(prefix, None)
+    /// Limit the number of blocking IO operations in progress at once, defaults to no limit
+    ///
+    /// Each operation runs on the tokio blocking thread pool, which a large number of
+    /// concurrent operations, such as a parallel delete or list, may otherwise saturate,
+    /// starving other users of the pool. Once `limit` operations are in progress, further
+    /// operations wait for one to complete. A `limit` of `0` is treated as `1`.
+    pub fn with_io_concurrency_limit(mut self, limit: usize) -> Self {
+        let limiter = IoLimiter::new(limit.max(1));
+        Arc::make_mut(&mut self.config).io_limiter = Some(Arc::new(limiter));
+        self
     }
 
-    fn list_with_offset(
-        &self,
-        prefix: Option<&Path>,
-        offset: &Path,
-    ) -> BoxStream<'static, Result<ObjectMeta>> {
-        self.list_with_maybe_offset(prefix, Some(offset))
+    /// Reject multipart uploads that S3 would refuse, defaults to `false`
+    ///
+    /// S3 requires all but the last part of a multipart upload to be at least 5 MiB,
+    /// whereas by default parts of any size are accepted. Enabling this instead returns an
+    /// error from [`MultipartUpload::put_part`] when a part follows one smaller than this,
+    /// allowing code targeting S3 to be tested against a [`LocalFileSystem`].
+    pub fn with_enforce_s3_part_sizes(mut self, enforce_s3_part_sizes: bool) -> Self {
+        Arc::make_mut(&mut self.config).enforce_s3_part_sizes = enforce_s3_part_sizes;
+        self
     }
 
-    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
-        let config = Arc::clone(&self.config);
-
-        let prefix = prefix.cloned().unwrap_or_default();
-        let resolved_prefix = config.prefix_to_filesystem(&prefix)?;
+    /// Report the number of hard links to an object under [`LINK_COUNT_ATTRIBUTE`],
+    /// defaults to `false`
+    ///
+    /// As [`LocalFileSystem::copy`] creates hard links, a count greater than one
+    /// indicates an object shares its contents with other locations. This is only
+    /// available on unix platforms.
+    pub fn with_link_count_attribute(mut self, link_count_attribute: bool) -> Self {
+        Arc::make_mut(&mut self.config).link_count_attribute = link_count_attribute;
+        self
+    }
 
-        maybe_spawn_blocking(move || {
-            let walkdir = WalkDir::new(&resolved_prefix)
-                .min_depth(1)
-                .max_depth(1)
-                .follow_links(true);
+    /// Report the number of bytes allocated on disk for an object under
+    /// [`ALLOCATED_SIZE_ATTRIBUTE`], defaults to `false`
+    ///
+    /// This may differ from [`ObjectMeta::size`] for sparse or compressed files, and is
+    /// only available on unix platforms.
+    pub fn with_allocated_size_attribute(mut self, allocated_size_attribute: bool) -> Self {
+        Arc::make_mut(&mut self.config).allocated_size_attribute = allocated_size_attribute;
+        self
+    }
 
-            let mut common_prefixes = BTreeSet::new();
-            let mut objects = Vec::new();
+    /// Treat filenames on disk as percent-encoded on all platforms, defaults to `false`
+    ///
+    /// On Windows, where filenames cannot contain `:`, a location such as `a:b` is stored
+    /// in a file named `a%3Ab`, whereas by default other platforms neither encode nor
+    /// decode filenames. A dataset written on Windows therefore lists as `a%3Ab` elsewhere.
+    ///
+    /// Enabling this stores a `:` in a location as `%3A`, as on Windows, and a `%` as `%25`,
+    /// decoding only these sequences when listing, so that `a%3Ab` lists as `a:b`. Such
+    /// datasets are therefore consistent across platforms, and every location written
+    /// lists unchanged. Other percent-encoded sequences are listed verbatim.
+    pub fn with_decode_percent_filenames(mut self, decode_percent_filenames: bool) -> Self {
+        Arc::make_mut(&mut self.config).decode_percent_filenames = decode_percent_filenames;
+        self
+    }
 
-            for entry_res in walkdir.into_iter().map(convert_walkdir_result) {
-                if let Some(entry) = entry_res? {
-                    let is_directory = entry.file_type().is_dir();
-                    let entry_location = config.filesystem_to_path(entry.path())?;
-                    if !is_directory && !is_valid_file_path(&entry_location) {
-                        continue;
-                    }
+    /// Keep up to `capacity` files open for reads of byte ranges, defaults to disabled
+    ///
+    /// Rather than opening a file for each call to [`ObjectStore::get_range`],
+    /// [`ObjectStore::get_ranges`] or [`LocalFileSystem::read_range_into`], the file is
+    /// kept open and reused by subsequent reads, using positioned reads so concurrent
+    /// reads do not interfere. Each read still checks the metadata of the file, reopening
+    /// it if it has since been replaced or modified. The least recently used file is
+    /// closed once `capacity` is reached, and a `capacity` of `0` is treated as `1`.
+    ///
+    /// This is intended for serving repeated reads of a small set of large files, and
+    /// does not apply to [`ObjectStore::get`].
+    ///
+    /// Note that an open file keeps its disk space allocated even once deleted or replaced,
+    /// until it is closed. The space of a cached file removed through this store or by
+    /// another process is therefore only released once a subsequent read of its path
+    /// reopens it, or it is evicted by reads of other files.
+    pub fn with_fd_cache(mut self, capacity: usize) -> Self {
+        let cache = FdCache::new(capacity.max(1));
+        Arc::make_mut(&mut self.config).fd_cache = Some(Arc::new(cache));
+        self
+    }
 
-                    let mut parts = match entry_location.prefix_match(&prefix) {
-                        Some(parts) => parts,
-                        None => continue,
-                    };
+    /// Walk up to `concurrency` directories in parallel when listing, defaults to `1`
+    ///
+    /// If greater than `1`, [`ObjectStore::list`], [`ObjectStore::list_with_offset`] and
+    /// [`Self::list_max_depth`] walk each directory directly within the listed prefix on
+    /// its own blocking task, with up to `concurrency` walks in progress at once. This
+    /// can considerably speed up listing large hierarchies, especially on network
+    /// filesystems, however, objects are then returned in no particular order.
+    pub fn with_list_concurrency(mut self, concurrency: usize) -> Self {
+        Arc::make_mut(&mut self.config).list_concurrency = concurrency;
+        self
+    }
 
-                    let common_prefix = match parts.next() {
-                        Some(p) => p,
-                        None => continue,
-                    };
+    /// Coalesce ranges separated by at most `gap` bytes into a single read in
+    /// [`ObjectStore::get_ranges`], defaults to `0`
+    ///
+    /// Adjacent and overlapping ranges are always read together. A larger gap trades
+    /// reading, and discarding, the bytes between ranges for fewer reads, which benefits
+    /// readers such as Parquet that request many nearby ranges.
+    pub fn with_range_coalesce_gap(mut self, gap: usize) -> Self {
+        Arc::make_mut(&mut self.config).range_coalesce_gap = gap;
+        self
+    }
 
-                    drop(parts);
+    /// Report empty directories as zero-byte objects when listing, defaults to `false`
+    ///
+    /// Some tools, such as Spark, create zero-byte marker objects to represent directories
+    /// in object stores. If enabled [`ObjectStore::list`] returns each empty directory as
+    /// such an object, located at the directory as a [`Path`] has no trailing delimiter.
+    /// These objects are also returned by [`ObjectStore::head`] and [`ObjectStore::get`],
+    /// and removed by [`ObjectStore::delete`], which removes only empty directories.
+    /// [`ObjectStore::list_with_delimiter`] reports empty directories as common prefixes
+    /// regardless. See [`Self::create_dir`] to create an empty directory.
+    pub fn with_directory_markers(mut self, directory_markers: bool) -> Self {
+        Arc::make_mut(&mut self.config).directory_markers = directory_markers;
+        self
+    }
 
-                    if is_directory {
-                        common_prefixes.insert(prefix.child(common_prefix));
-                    } else if let Some(metadata) = convert_entry(entry, entry_location)? {
-                        objects.push(metadata);
-                    }
-                }
-            }
+    /// Skip directories that cannot be read due to insufficient permissions when
+    /// listing, defaults to `false`
+    ///
+    /// By default such directories result in an error from listing operations. Enabling
+    /// this instead logs a warning and lists the remaining entries, with the number of
+    /// directories skipped available from [`Self::skipped_unreadable`].
+    pub fn with_skip_unreadable(mut self, skip_unreadable: bool) -> Self {
+        Arc::make_mut(&mut self.config).skip_unreadable = skip_unreadable;
+        self
+    }
 
-            Ok(ListResult {
-                common_prefixes: common_prefixes.into_iter().collect(),
-                objects,
-            })
+    /// Persist the [`Attributes`] of objects in extended attributes, defaults to `false`
+    ///
+    /// By default writes with [`PutOptions::attributes`] or
+    /// [`PutMultipartOptions::attributes`] return [`crate::Error::NotImplemented`].
+    /// Enabling this instead stores each attribute in an extended attribute of the file,
+    /// which are returned by [`ObjectStore::get_opts`]. Writes still return
+    /// [`crate::Error::NotImplemented`] if the filesystem does not support extended
+    /// attributes, as is the case on Windows.
+    ///
+    /// This is equivalent to [`Self::with_attribute_storage`] with
+    /// [`AttributeStorage::ExtendedAttributes`] if `true`, or
+    /// [`AttributeStorage::Disabled`] if `false`.
+    pub fn with_attributes(self, attributes: bool) -> Self {
+        self.with_attribute_storage(match attributes {
+            true => AttributeStorage::ExtendedAttributes,
+            false => AttributeStorage::Disabled,
         })
-        .await
     }
 
-    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
-        let from = self.path_to_filesystem(from)?;
-        let to = self.path_to_filesystem(to)?;
-        let mut id = 0;
-        // In order to make this atomic we:
-        //
-        // - hard link to a hidden temporary file
-        // - atomically rename this temporary file into place
-        //
-        // This is necessary because hard_link returns an error if the destination already exists
-        maybe_spawn_blocking(move || loop {
-            let staged = staged_upload_path(&to, &id.to_string());
-            match std::fs::hard_link(&from, &staged) {
-                Ok(_) => {
-                    return std::fs::rename(&staged, &to).map_err(|source| {
-                        let _ = std::fs::remove_file(&staged); // Attempt to clean up
-                        Error::UnableToCopyFile { from, to, source }.into()
-                    });
-                }
-                Err(source) => match source.kind() {
-                    ErrorKind::AlreadyExists => id += 1,
-                    ErrorKind::NotFound => match from.exists() {
-                        true => create_parent_dirs(&to, source)?,
-                        false => return Err(Error::NotFound { path: from, source }.into()),
-                    },
-                    _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
-                },
-            }
-        })
-        .await
+    /// Set where the [`Attributes`] of objects are persisted, defaults to
+    /// [`AttributeStorage::Disabled`]
+    ///
+    /// [`AttributeStorage::Sidecar`] works on any filesystem. The sidecar is replaced
+    /// before the object itself, and so a concurrent reader may briefly observe the
+    /// previous object with its new attributes. It is removed by [`ObjectStore::delete`],
+    /// and carried over by [`ObjectStore::copy`] and [`ObjectStore::rename`].
+    pub fn with_attribute_storage(mut self, storage: AttributeStorage) -> Self {
+        Arc::make_mut(&mut self.config).attributes = storage;
+        self
     }
 
-    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
-        let from = self.path_to_filesystem(from)?;
-        let to = self.path_to_filesystem(to)?;
-        maybe_spawn_blocking(move || loop {
-            match std::fs::rename(&from, &to) {
-                Ok(_) => return Ok(()),
-                Err(source) => match source.kind() {
-                    ErrorKind::NotFound => match from.exists() {
-                        true => create_parent_dirs(&to, source)?,
-                        false => return Err(Error::NotFound { path: from, source }.into()),
-                    },
-                    _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
-                },
-            }
-        })
-        .await
+    /// Report attributes derived from the file itself, defaults to `false`
+    ///
+    /// If enabled [`ObjectStore::get_opts`] returns the size of the object under
+    /// [`CONTENT_LENGTH_ATTRIBUTE`] and its last modified time, formatted as an HTTP date,
+    /// under [`LAST_MODIFIED_ATTRIBUTE`]. Additionally [`Attribute::ContentType`] is
+    /// inferred from the file extension for common formats, unless one is stored for the
+    /// object, see [`Self::with_attribute_storage`]. This provides a consistent view of an
+    /// object regardless of whether the filesystem can persist attributes.
+    pub fn with_derived_attributes(mut self, derived_attributes: bool) -> Self {
+        Arc::make_mut(&mut self.config).derived_attributes = derived_attributes;
+        self
     }
 
-    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
-        let from = self.path_to_filesystem(from)?;
-        let to = self.path_to_filesystem(to)?;
+    /// Infer [`Attribute::ContentType`] from the file extension, defaults to `false`
+    ///
+    /// If enabled [`ObjectStore::get_opts`] reports the content type of common formats,
+    /// such as `application/json` for `.json` files, unless one is stored for the object,
+    /// see [`Self::with_attribute_storage`]. This is implied by
+    /// [`Self::with_derived_attributes`].
+    pub fn with_content_type_from_extension(mut self, content_type_from_extension: bool) -> Self {
+        Arc::make_mut(&mut self.config).content_type_from_extension = content_type_from_extension;
+        self
+    }
 
-        maybe_spawn_blocking(move || loop {
-            match std::fs::hard_link(&from, &to) {
-                Ok(_) => return Ok(()),
-                Err(source) => match source.kind() {
-                    ErrorKind::AlreadyExists => {
-                        return Err(Error::AlreadyExists {
-                            path: to.to_str().unwrap().to_string(),
-                            source,
-                        }
-                        .into())
-                    }
-                    ErrorKind::NotFound => match from.exists() {
-                        true => create_parent_dirs(&to, source)?,
-                        false => return Err(Error::NotFound { path: from, source }.into()),
-                    },
-                    _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
-                },
-            }
-        })
-        .await
+    /// Set how files are copied, defaults to [`CopyStrategy::HardLink`]
+    ///
+    /// Regardless of strategy, files on different filesystems are copied byte by byte,
+    /// see [Cross-Filesystem Copy](Self#cross-filesystem-copy).
+    pub fn with_copy_strategy(mut self, copy_strategy: CopyStrategy) -> Self {
+        Arc::make_mut(&mut self.config).copy_strategy = copy_strategy;
+        self
     }
-}
 
-impl LocalFileSystem {
-    fn list_with_maybe_offset(
-        &self,
-        prefix: Option<&Path>,
-        maybe_offset: Option<&Path>,
-    ) -> BoxStream<'static, Result<ObjectMeta>> {
-        let config = Arc::clone(&self.config);
+    /// Preserve the permissions and modification time of the source of copies that
+    /// do not share its metadata, defaults to `false`
+    ///
+    /// Hard links share the metadata of their source, however, the byte by byte copies
+    /// made across filesystems, see [Cross-Filesystem Copy](Self#cross-filesystem-copy),
+    /// and reflinks, see [`CopyStrategy::Reflink`], otherwise have default permissions, or
+    /// those set by `with_file_mode`, and the current modification time. This applies to
+    /// [`ObjectStore::copy`], [`ObjectStore::copy_if_not_exists`] and [`ObjectStore::rename`].
+    pub fn with_preserve_metadata(mut self, preserve_metadata: bool) -> Self {
+        Arc::make_mut(&mut self.config).preserve_metadata = preserve_metadata;
+        self
+    }
 
-        let root_path = match prefix {
-            Some(prefix) => match config.prefix_to_filesystem(prefix) {
-                Ok(path) => path,
-                Err(e) => return futures::future::ready(Err(e)).into_stream().boxed(),
-            },
-            None => config.root.to_file_path().unwrap(),
+    /// Set the permissions of the files written by [`ObjectStore::put`] and
+    /// [`ObjectStore::put_multipart`], e.g. `0o640`, defaults to those given by the umask
+    ///
+    /// Unlike the umask, this is specific to this store, and is applied to the staged file
+    /// before it is moved into place, and so written objects always have `mode`.
+    #[cfg(unix)]
+    pub fn with_file_mode(mut self, mode: u32) -> Self {
+        Arc::make_mut(&mut self.config).file_mode = Some(mode);
+        self
+    }
+
+    /// Set the permissions of the directories created by writes, e.g. `0o750`, defaults to
+    /// those given by the umask
+    ///
+    /// This applies to the parent directories of objects created by [`ObjectStore::put`],
+    /// [`ObjectStore::put_multipart`], [`ObjectStore::copy`] and [`ObjectStore::rename`].
+    /// Existing directories are not modified.
+    #[cfg(unix)]
+    pub fn with_dir_mode(mut self, mode: u32) -> Self {
+        Arc::make_mut(&mut self.config).dir_mode = Some(mode);
+        self
+    }
+
+    /// Sync writes to disk before they are acknowledged, defaults to `false`
+    ///
+    /// By default a write may be lost on power failure, even after [`ObjectStore::put`] or
+    /// [`MultipartUpload::complete`] returns, as both its contents and the rename moving it
+    /// into place may only be held in the page cache. If enabled the staged file is synced
+    /// with [`File::sync_all`] before it is moved into place, and on unix platforms the
+    /// containing directory is synced after, making the write durable. Likewise copies and
+    /// renames sync the directories they modify. This comes at a significant cost to write
+    /// latency.
+    pub fn with_durable_writes(mut self, durable_writes: bool) -> Self {
+        Arc::make_mut(&mut self.config).durable_writes = durable_writes;
+        self
+    }
+
+    /// Returns the number of directories skipped by listing operations as they could
+    /// not be read, see [`Self::with_skip_unreadable`]
+    pub fn skipped_unreadable(&self) -> usize {
+        self.config.skipped_unreadable.0.load(Ordering::Relaxed)
+    }
+
+    /// Set the [`EtagStrategy`] used to compute the ETag of objects, defaults to
+    /// [`EtagStrategy::InodeSizeMtime`]
+    ///
+    /// Content-derived ETags require reading the entire file, and so are cached in the
+    /// `user.object_store.etag` extended attribute of the file where supported, and
+    /// recomputed if the file's inode, size or modification time change. Listings return
+    /// no ETag for objects whose ETag is not cached, rather than reading every file.
+    pub fn with_etag_strategy(mut self, etag_strategy: EtagStrategy) -> Self {
+        Arc::make_mut(&mut self.config).etag_strategy = etag_strategy;
+        self
+    }
+
+    /// Revalidate the size and modification time of objects when reading them, defaults
+    /// to `false`
+    ///
+    /// NFS clients cache file attributes, and so may report a stale size or modification
+    /// time for a file written by another client. Enabling this always opens the file to
+    /// obtain its metadata, relying on NFS close-to-open consistency to revalidate the
+    /// attribute cache, and reopens the file if it was replaced in the meantime.
+    ///
+    /// This adds latency to [`ObjectStore::head`] and [`ObjectStore::get`], and is only
+    /// necessary for NFS-backed deployments with multiple writers.
+    pub fn with_nfs_consistency(mut self, nfs_consistency: bool) -> Self {
+        Arc::make_mut(&mut self.config).nfs_consistency = nfs_consistency;
+        self
+    }
+
+    /// Record the SHA-256 checksum of objects in a sidecar file, defaults to `false`
+    ///
+    /// The checksum of each object written is stored in a file next to it, e.g.
+    /// `foo.parquet#sha256` for `foo.parquet`, which is ignored by listing operations, and
+    /// carried over by [`ObjectStore::copy`] and [`ObjectStore::rename`].
+    ///
+    /// Objects with a recorded checksum are verified when opened, requiring them to be
+    /// read in full, and an error is returned if they are corrupt, unless they can be
+    /// repaired from the mirror of [`Self::with_replica`], whose own checksum is verified
+    /// and carried over. Objects without a recorded checksum, such as those written with
+    /// this disabled, are not verified.
+    ///
+    /// Note: this verification applies to every read, so even a [`ObjectStore::get_range`]
+    /// of a few bytes reads the entire object, which is costly for large objects.
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        Arc::make_mut(&mut self.config).checksums = checksums;
+        self
+    }
+
+    /// Read-repair objects from a mirror of this store rooted at `mirror_root`
+    ///
+    /// A get of an object missing from this store, or corrupt as determined by
+    /// [`Self::with_checksums`], will be served from the same location within
+    /// `mirror_root`, if present, with the object first being restored to this store by
+    /// atomically writing a copy of the mirrored file.
+    ///
+    /// Returns an error if `mirror_root` does not exist
+    pub fn with_replica(mut self, mirror_root: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = std::fs::canonicalize(&mirror_root).map_err(|source| {
+            let path = mirror_root.as_ref().into();
+            Error::UnableToCanonicalize { source, path }
+        })?;
+        Arc::make_mut(&mut self.config).replica = Some(absolute_path_to_url(path)?);
+        Ok(self)
+    }
+
+    /// Stage writes within `staging_dir`, instead of alongside their destination
+    ///
+    /// This allows the contents of [`ObjectStore::put`] and [`ObjectStore::put_multipart`]
+    /// to be written to a faster local disk, before being atomically moved into place.
+    /// Staged files are named after the SHA-256 digest of the path of their destination,
+    /// such that uploads to different locations never collide, with the usual `#` suffix,
+    /// and are not removed by [`Self::cleanup_staging`] unless `staging_dir` is within
+    /// the root.
+    ///
+    /// As renames cannot cross filesystem boundaries, this returns an error if
+    /// `staging_dir` does not exist, or on unix platforms is not on the same filesystem as
+    /// the root of this store. Writes to objects beneath a mount point within the root
+    /// will fail.
+    pub fn with_staging_dir(mut self, staging_dir: impl AsRef<std::path::Path>) -> Result<Self> {
+        let staging_dir = std::fs::canonicalize(&staging_dir).map_err(|source| {
+            let path = staging_dir.as_ref().into();
+            Error::UnableToCanonicalize { source, path }
+        })?;
+
+        let root = self
+            .config
+            .root
+            .to_file_path()
+            .map_err(|_| Error::InvalidUrl {
+                url: self.config.root.clone(),
+            })?;
+        let device = |path: &std::path::Path| {
+            let metadata = std::fs::metadata(path).map_err(|source| {
+                let path = path.into();
+                Error::UnableToOpenFile { source, path }
+            })?;
+            Ok::<_, crate::Error>(device_id(&metadata))
         };
+        if device(&staging_dir)? != device(&root)? {
+            return Err(Error::StagingDirFilesystem { staging_dir, root }.into());
+        }
 
-        let walkdir = WalkDir::new(root_path)
-            // Don't include the root directory itself
-            .min_depth(1)
-            .follow_links(true);
+        Arc::make_mut(&mut self.config).staging_dir = Some(staging_dir);
+        Ok(self)
+    }
 
-        let maybe_offset = maybe_offset.cloned();
+    /// Returns the total number of bytes allocated on disk for the files under `prefix`
+    ///
+    /// Unlike summing [`ObjectMeta::size`] this accounts for sparse and compressed files,
+    /// and includes the staging files of in-progress uploads. On platforms where the
+    /// allocated size is unavailable, the logical size of each file is used instead.
+    pub async fn prefix_allocated_size(&self, prefix: Option<&Path>) -> Result<u64> {
+        let prefix = prefix.cloned().unwrap_or_default();
+        let root_path = self.config.prefix_to_filesystem(&prefix)?;
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            let walkdir = WalkDir::new(root_path).min_depth(1).follow_links(true);
 
-        let s = walkdir.into_iter().flat_map(move |result_dir_entry| {
-            // Apply offset filter before proceeding, to reduce statx file system calls
-            // This matters for NFS mounts
-            if let (Some(offset), Ok(entry)) = (maybe_offset.as_ref(), result_dir_entry.as_ref()) {
-                let location = config.filesystem_to_path(entry.path());
-                match location {
-                    Ok(path) if path <= *offset => return None,
-                    Err(e) => return Some(Err(e)),
-                    _ => {}
+            let mut total = 0;
+            for entry_res in walkdir.into_iter().map(convert_walkdir_result) {
+                if let Some(entry) = entry_res? {
+                    let metadata = entry.metadata().map_err(|e| Error::Metadata {
+                        source: e.into(),
+                        path: entry.path().to_string_lossy().to_string(),
+                    })?;
+                    if metadata.is_file() {
+                        total += allocated_size(&metadata).unwrap_or(metadata.len());
+                    }
                 }
             }
+            Ok(total)
+        })
+        .await
+    }
 
-            let entry = match convert_walkdir_result(result_dir_entry).transpose()? {
-                Ok(entry) => entry,
-                Err(e) => return Some(Err(e)),
-            };
+    /// Creates the directory for `prefix` and any missing ancestors
+    ///
+    /// The directory is reported as a common prefix by [`ObjectStore::list_with_delimiter`]
+    /// and, if [`Self::with_directory_markers`], as an object by [`ObjectStore::list`]
+    /// whilst empty. Succeeds if the directory already exists.
+    pub async fn create_dir(&self, prefix: &Path) -> Result<()> {
+        let path = self.config.prefix_to_filesystem(prefix)?;
+        self.config.check_sandbox(&path)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            create_dirs(&path, config.dir_mode)
+        })
+        .await
+    }
 
-            if !entry.path().is_file() {
-                return None;
-            }
+    /// Returns the total, available and used bytes of the filesystem containing the root
+    /// of this store
+    ///
+    /// The available bytes may be fewer than the total less those used, as filesystems
+    /// commonly reserve space for privileged users.
+    pub async fn disk_usage(&self) -> Result<DiskUsage> {
+        let root_path = self.config.prefix_to_filesystem(&Path::default())?;
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            disk_usage(&root_path).map_err(|e| {
+                Error::Metadata {
+                    source: e.into(),
+                    path: root_path.to_string_lossy().to_string(),
+                }
+                .into()
+            })
+        })
+        .await
+    }
 
-            match config.filesystem_to_path(entry.path()) {
-                Ok(path) => match is_valid_file_path(&path) {
-                    true => convert_entry(entry, path).transpose(),
-                    false => None,
-                },
-                Err(e) => Some(Err(e)),
+    /// Returns the objects with the given prefix sorted by last modified time in `order`,
+    /// with objects modified at the same time sorted by location
+    ///
+    /// If `limit` is provided only the first `limit` objects are returned. As at most
+    /// `limit` objects are retained whilst listing, this is considerably cheaper than
+    /// sorting the output of [`ObjectStore::list`] to find, for example, the few oldest
+    /// objects of a large hierarchy.
+    pub async fn list_sorted_by_mtime(
+        &self,
+        prefix: Option<&Path>,
+        order: MtimeOrder,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>> {
+        // A max-heap whose greatest entry is the first to be evicted once over `limit`
+        let mut heap = BinaryHeap::new();
+        let mut stream = self.list(prefix);
+        while let Some(meta) = stream.try_next().await? {
+            heap.push(MtimeEntry { order, meta });
+            if limit.map_or(false, |limit| heap.len() > limit) {
+                heap.pop();
             }
-        });
-
-        // If no tokio context, return iterator directly as no
-        // need to perform chunked spawn_blocking reads
-        if tokio::runtime::Handle::try_current().is_err() {
-            return futures::stream::iter(s).boxed();
         }
+        Ok(heap.into_sorted_vec().into_iter().map(|e| e.meta).collect())
+    }
 
-        // Otherwise list in batches of CHUNK_SIZE
-        const CHUNK_SIZE: usize = 1024;
+    /// Deletes all objects under `prefix`, removing the directory of `prefix` and all of
+    /// its contents
+    ///
+    /// Unlike deleting each object listed under `prefix`, this also removes the staging
+    /// files of uploads to locations under `prefix`, which will then fail to complete.
+    /// Uploads to other locations are unaffected, including those staged within
+    /// `prefix` by the [`Self::with_staging_dir`] of this store. If [`Self::with_automatic_cleanup`], any
+    /// parent directories left empty are also removed.
+    ///
+    /// Returns `Ok(())` if `prefix` does not exist, and an error if it is an object. To
+    /// guard against accidentally deleting the contents of the whole store, or of the
+    /// whole filesystem for a store created with [`Self::new`], `prefix` must not be
+    /// empty, nor resolve to the filesystem root.
+    pub async fn delete_prefix(&self, prefix: &Path) -> Result<()> {
+        let operation = "delete objects";
+        if prefix.as_ref().is_empty() {
+            return Err(Error::EmptyPrefix { operation }.into());
+        }
 
-        let buffer = VecDeque::with_capacity(CHUNK_SIZE);
-        futures::stream::try_unfold((s, buffer), |(mut s, mut buffer)| async move {
-            if buffer.is_empty() {
-                (s, buffer) = tokio::task::spawn_blocking(move || {
-                    for _ in 0..CHUNK_SIZE {
-                        match s.next() {
-                            Some(r) => buffer.push_back(r),
-                            None => break,
-                        }
+        let config = Arc::clone(&self.config);
+        let path = config.prefix_to_filesystem(prefix)?;
+        if path.parent().is_none() {
+            return Err(Error::FilesystemRoot { operation, path }.into());
+        }
+        let automatic_cleanup = self.automatic_cleanup;
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            config.check_sandbox(&path)?;
+
+            match symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => return Err(Error::NotADirectory { path }.into()),
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) => {
+                    return Err(Error::Metadata {
+                        source: e.into(),
+                        path: path.to_string_lossy().to_string(),
                     }
-                    (s, buffer)
-                })
-                .await?;
+                    .into())
+                }
             }
 
-            match buffer.pop_front() {
-                Some(Err(e)) => Err(e),
-                Some(Ok(meta)) => Ok(Some((meta, (s, buffer)))),
-                None => Ok(None),
+            let keep = config.staging_dir.as_deref();
+            remove_dir_contents(&path, keep).map_err(|source| {
+                let path = path.clone();
+                Error::UnableToDeleteFile { source, path }
+            })?;
+
+            if !keep.map_or(false, |keep| keep.starts_with(&path)) {
+                match std::fs::remove_dir(&path) {
+                    // Concurrently deleted
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        return Err(Error::UnableToDeleteFile { source: e, path }.into());
+                    }
+                    _ => {}
+                }
+                if automatic_cleanup {
+                    config.remove_empty_parents(&path)?;
+                }
             }
+            Ok(())
         })
-        .boxed()
+        .await
     }
-}
 
-/// Creates the parent directories of `path` or returns an error based on `source` if no parent
-fn create_parent_dirs(path: &std::path::Path, source: io::Error) -> Result<()> {
-    let parent = path.parent().ok_or_else(|| {
-        let path = path.to_path_buf();
-        Error::UnableToCreateFile { path, source }
-    })?;
+    /// Resumes the multipart upload to `location` with staging file `upload_id`, such as
+    /// one left behind by a process that crashed before completing it
+    ///
+    /// The `upload_id` is the suffix following the `#` of the staging file, e.g. `1` for
+    /// `{location}#1`, as can be found with [`Self::verify`]. Parts written to the returned
+    /// upload are appended to the existing contents of the staging file, and completing
+    /// it atomically moves the file into place as for [`ObjectStore::put_multipart`].
+    ///
+    /// Returns an error if the upload is still in progress, i.e. the staging file is held
+    /// open by an upload returned by [`ObjectStore::put_multipart`] or this method, which
+    /// each take an exclusive advisory lock on it.
+    ///
+    /// The staging file may end with a partially written part, and so callers should
+    /// verify its length, e.g. against the sizes of the parts they know to have been
+    /// written, before resuming. Attributes stored in a [`AttributeStorage::Sidecar`]
+    /// are only written on completion, and so are not recovered.
+    pub async fn resume_multipart(
+        &self,
+        location: &Path,
+        upload_id: &str,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        if upload_id.is_empty() || !upload_id.chars().all(|c| c.is_ascii_digit()) {
+            let path = format!("{location}#{upload_id}");
+            return Err(Error::InvalidPath { path }.into());
+        }
 
-    std::fs::create_dir_all(parent).map_err(|source| {
-        let path = parent.into();
-        Error::UnableToCreateDir { source, path }
-    })?;
-    Ok(())
-}
+        let dest = self.path_to_filesystem(location)?;
+        let src = self.config.staging_path(&dest, upload_id);
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&src)
+                .map_err(|source| match source.kind() {
+                    ErrorKind::NotFound => Error::NotFound {
+                        path: src.clone(),
+                        source,
+                    },
+                    _ => Error::UnableToOpenFile {
+                        path: src.clone(),
+                        source,
+                    },
+                })?;
+            lock_staged(&file, &src)?;
+            let metadata = file.metadata().map_err(|e| Error::Metadata {
+                source: e.into(),
+                path: src.to_string_lossy().to_string(),
+            })?;
 
-/// Generates a unique file path `{base}#{suffix}`, returning the opened `File` and `path`
-///
-/// Creates any directories if necessary
-fn new_staged_upload(base: &std::path::Path) -> Result<(File, PathBuf)> {
-    let mut multipart_id = 1;
-    loop {
-        let suffix = multipart_id.to_string();
-        let path = staged_upload_path(base, &suffix);
-        let mut options = OpenOptions::new();
-        match options.read(true).write(true).create_new(true).open(&path) {
-            Ok(f) => return Ok((f, path)),
-            Err(source) => match source.kind() {
-                ErrorKind::AlreadyExists => multipart_id += 1,
-                ErrorKind::NotFound => create_parent_dirs(&path, source)?,
-                _ => return Err(Error::UnableToOpenFile { source, path }.into()),
-            },
+            let mut upload = LocalUpload::new(config, src, dest, file, Attributes::new());
+            upload.offset = metadata.len();
+            Ok(Box::new(upload) as _)
+        })
+        .await
+    }
+
+    /// Walks the files under `prefix`, returning a [`VerifyReport`] of any anomalies found
+    ///
+    /// The store is not modified. Staging files are reported regardless of age, and so
+    /// include those of uploads that are still in progress. Symlinks are followed only if
+    /// [`Self::with_follow_symlinks`], and if [`Self::with_checksums`] the contents of
+    /// each file with a recorded checksum are read and verified.
+    pub async fn verify(&self, prefix: Option<&Path>) -> Result<VerifyReport> {
+        let prefix = prefix.cloned().unwrap_or_default();
+        let root_path = self.config.prefix_to_filesystem(&prefix)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            let walkdir = WalkDir::new(&root_path)
+                .min_depth(1)
+                .follow_links(config.follow_symlinks);
+
+            let mut report = VerifyReport::default();
+            for entry_res in walkdir {
+                let entry = match entry_res {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        let path = e.path().unwrap_or(&root_path).to_path_buf();
+                        let not_found = e.io_error().map(|e| e.kind()) == Some(ErrorKind::NotFound);
+                        match not_found {
+                            true if path.is_symlink() => report.broken_symlinks.push(path),
+                            // Removed whilst walking, or the prefix does not exist
+                            true => {}
+                            false => report.unreadable.push((path, e.to_string())),
+                        }
+                        continue;
+                    }
+                };
+
+                if entry.path_is_symlink() && !config.follow_symlinks {
+                    if !entry.path().exists() {
+                        report.broken_symlinks.push(entry.into_path());
+                    }
+                    continue;
+                }
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+
+                let name = entry.file_name().to_str();
+                if !name.map_or(false, is_staging_file_name) {
+                    let sidecar = name.map_or(false, |name| {
+                        config
+                            .sidecar_suffixes()
+                            .any(|suffix| is_sidecar_file_name(name, suffix))
+                    });
+                    if sidecar {
+                        continue;
+                    }
+                    match config.checksum_mismatch(entry.path()) {
+                        Ok(Some(_)) => report.checksum_mismatches.push(entry.into_path()),
+                        Ok(None) => {}
+                        Err(e) => report.unreadable.push((entry.into_path(), e.to_string())),
+                    }
+                    continue;
+                }
+                match entry.metadata() {
+                    Ok(metadata) if metadata.len() == 0 => {
+                        report.empty_staging_files.push(entry.into_path())
+                    }
+                    Ok(_) => report.staging_files.push(entry.into_path()),
+                    Err(e) => report.unreadable.push((entry.into_path(), e.to_string())),
+                }
+            }
+            Ok(report)
+        })
+        .await
+    }
+
+    /// Returns the [`ObjectMeta`] of each of `locations`, as would be returned by [`ObjectStore::head`]
+    ///
+    /// The files are inspected in batches across a bounded number of blocking tasks, with
+    /// the results returned in the same order as `locations`.
+    pub async fn head_many(&self, locations: Vec<Path>) -> Vec<Result<ObjectMeta>> {
+        let mut batches = vec![];
+        let mut iter = locations.into_iter().peekable();
+        while iter.peek().is_some() {
+            let batch: Vec<_> = iter
+                .by_ref()
+                .take(BATCH_SIZE)
+                .map(|location| {
+                    let path = self.path_to_filesystem(&location);
+                    (location, path)
+                })
+                .collect();
+            batches.push(batch);
         }
+
+        futures::stream::iter(batches)
+            .map(|batch| {
+                let config = Arc::clone(&self.config);
+                let len = batch.len();
+                spawn_blocking(self.config.io_limiter.clone(), move || {
+                    Ok(batch
+                        .into_iter()
+                        .map(|(location, path)| config.head(location, &path?))
+                        .collect::<Vec<_>>())
+                })
+                .map(move |r| match r {
+                    Ok(results) => results,
+                    Err(e) => {
+                        let e = e.to_string();
+                        (0..len)
+                            .map(|_| {
+                                Err(crate::Error::Generic {
+                                    store: "LocalFileSystem",
+                                    source: e.clone().into(),
+                                })
+                            })
+                            .collect()
+                    }
+                })
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .flat_map(futures::stream::iter)
+            .collect()
+            .await
     }
-}
 
-/// Returns the unique upload for the given path and suffix
-fn staged_upload_path(dest: &std::path::Path, suffix: &str) -> PathBuf {
-    let mut staging_path = dest.as_os_str().to_owned();
-    staging_path.push("#");
-    staging_path.push(suffix);
-    staging_path.into()
-}
+    /// Returns the variant of `location` best matching `accept_encoding`, mirroring HTTP
+    /// content negotiation
+    ///
+    /// `accept_encoding` lists the encodings accepted by the client, in order of preference,
+    /// e.g. `["br", "gzip"]`. For each of `gzip`, `br` and `zstd`, a sibling object with the
+    /// extension `.gz`, `.br` and `.zst` respectively, e.g. `file.gz` for `file`, is served
+    /// as is with [`Attribute::ContentEncoding`] set. Encodings with a quality of zero,
+    /// e.g. `gzip;q=0`, are not accepted. If no such variant exists, `location` is returned.
+    ///
+    /// The [`ObjectMeta`] of the returned [`GetResult`] describes the variant served.
+    pub async fn get_negotiated(
+        &self,
+        location: &Path,
+        accept_encoding: &[&str],
+    ) -> Result<GetResult> {
+        for accepted in accept_encoding {
+            let mut params = accepted.split(';').map(str::trim);
+            let encoding = params.next().unwrap_or_default().to_ascii_lowercase();
+            let rejected = params.any(|p| match p.split_once('=') {
+                Some((q, v)) if q.eq_ignore_ascii_case("q") => v.parse() == Ok(0_f32),
+                _ => false,
+            });
+
+            let extension = CONTENT_ENCODING_EXTENSIONS
+                .iter()
+                .find_map(|(e, ext)| (*e == encoding).then_some(*ext));
+            let extension = match extension {
+                Some(extension) if !rejected => extension,
+                _ => continue,
+            };
 
-#[derive(Debug)]
-struct LocalUpload {
-    /// The upload state
-    state: Arc<UploadState>,
-    /// The location of the temporary file
-    src: Option<PathBuf>,
-    /// The next offset to write into the file
-    offset: u64,
-}
+            let variant = Path::from(format!("{location}.{extension}"));
+            match self.get(&variant).await {
+                Ok(mut r) => {
+                    r.attributes
+                        .insert(Attribute::ContentEncoding, encoding.into());
+                    return Ok(r);
+                }
+                Err(crate::Error::NotFound { .. }) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        self.get(location).await
+    }
 
-#[derive(Debug)]
-struct UploadState {
-    dest: PathBuf,
-    file: Mutex<File>,
-}
+    /// Returns whether an object exists at `location`
+    ///
+    /// This is cheaper than [`ObjectStore::head`], performing a single `stat` without
+    /// constructing an [`ObjectMeta`]. Directories are not objects and so return `false`.
+    pub async fn exists(&self, location: &Path) -> Result<bool> {
+        // Validate the location, rejecting the names reserved for staged uploads
+        self.path_to_filesystem(location)?;
+        let location = location.clone();
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            config.exists(&location)
+        })
+        .await
+    }
 
-impl LocalUpload {
-    pub(crate) fn new(src: PathBuf, dest: PathBuf, file: File) -> Self {
-        Self {
-            state: Arc::new(UploadState {
-                dest,
-                file: Mutex::new(file),
-            }),
-            src: Some(src),
-            offset: 0,
+    /// Returns whether an object exists at each of `locations`, in the same order as `locations`
+    ///
+    /// This is cheaper than [`LocalFileSystem::head_many`], only inspecting the file type
+    /// of each location. Returns an error if the existence of any location could not be
+    /// determined, e.g. due to insufficient permissions.
+    pub async fn exists_many(&self, locations: &[Path]) -> Result<Vec<bool>> {
+        let batches = locations
+            .chunks(BATCH_SIZE)
+            .map(|batch| batch.to_vec())
+            .collect::<Vec<_>>();
+
+        let results: Vec<Vec<bool>> = futures::stream::iter(batches)
+            .map(|batch| {
+                let config = Arc::clone(&self.config);
+                spawn_blocking(self.config.io_limiter.clone(), move || {
+                    batch
+                        .iter()
+                        .map(|location| config.exists(location))
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .try_collect()
+            .await?;
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Returns the bytes of `location` within `range`, resolved against the length of the file
+    ///
+    /// Unlike [`ObjectStore::get_range`] this accepts suffix and open-ended ranges. A suffix
+    /// longer than the file returns the whole file, and an offset at the end of the file
+    /// returns no bytes rather than an error.
+    pub async fn get_range_opts(&self, location: &Path, range: GetRange) -> Result<Bytes> {
+        let location = location.clone();
+        let path = self.path_to_filesystem(&location)?;
+        let config = Arc::clone(&self.config);
+        let bytes = spawn_blocking(self.config.io_limiter.clone(), move || {
+            let (reader, metadata) = config.open_for_ranges(&location, &path)?;
+            let len = metadata.len();
+            let range = match range {
+                GetRange::Offset(offset) if offset == len => return Ok(Bytes::new()),
+                range => range
+                    .as_range(len)
+                    .map_err(|source| Error::InvalidRange { source })?,
+            };
+            if range.is_empty() {
+                return Ok(Bytes::new());
+            }
+            config.read_range(reader, &metadata, &path, range)
+        })
+        .await?;
+        self.config.throttle(bytes.len() as u64).await;
+        Ok(bytes)
+    }
+
+    /// Reads `range` of `location` into `buf`, returning the number of bytes read
+    ///
+    /// Unlike [`ObjectStore::get_range`] this allows a buffer to be reused across reads,
+    /// it is only grown if its capacity is insufficient for the range. If `append` is
+    /// `false` the existing contents of `buf` are first cleared, otherwise the range is
+    /// appended to them. On error no partially read data is left in `buf`.
+    pub async fn read_range_into(
+        &self,
+        location: &Path,
+        range: Range<u64>,
+        buf: &mut Vec<u8>,
+        append: bool,
+    ) -> Result<usize> {
+        let location = location.clone();
+        let path = self.path_to_filesystem(&location)?;
+        let config = Arc::clone(&self.config);
+
+        if !append {
+            buf.clear();
+        }
+        let mut owned = std::mem::take(buf);
+        let (owned, result) = spawn_blocking(self.config.io_limiter.clone(), move || {
+            let result = config
+                .open_for_ranges(&location, &path)
+                .and_then(|(mut reader, _)| reader.read_range_into(&path, range, &mut owned));
+            Ok((owned, result))
+        })
+        .await?;
+        *buf = owned;
+        if let Ok(read) = &result {
+            self.config.throttle(*read as u64).await;
         }
+        result
     }
-}
 
-#[async_trait]
-impl MultipartUpload for LocalUpload {
-    fn put_part(&mut self, data: PutPayload) -> UploadPart {
-        let offset = self.offset;
-        self.offset += data.content_length() as u64;
+    /// Returns the lines of the text object at `location`
+    ///
+    /// The file is read in chunks on the blocking thread pool, with each line returned
+    /// without its `\n` or `\r\n` terminator. The last line need not be terminated.
+    /// Returns an error if a line is not valid UTF-8.
+    pub fn lines(&self, location: &Path) -> BoxStream<'static, Result<String>> {
+        self.lines_impl(location, None)
+    }
 
-        let s = Arc::clone(&self.state);
-        maybe_spawn_blocking(move || {
-            let mut file = s.file.lock();
-            file.seek(SeekFrom::Start(offset)).map_err(|source| {
-                let path = s.dest.clone();
-                Error::Seek { source, path }
-            })?;
+    /// Returns the lines of the text object at `location`, see [`LocalFileSystem::lines`],
+    /// returning an error if a line exceeds `max_line_length` bytes
+    ///
+    /// This bounds the memory used when reading untrusted input
+    pub fn lines_with_max_length(
+        &self,
+        location: &Path,
+        max_line_length: usize,
+    ) -> BoxStream<'static, Result<String>> {
+        self.lines_impl(location, Some(max_line_length))
+    }
 
-            data.iter()
-                .try_for_each(|x| file.write_all(x))
-                .map_err(|source| Error::UnableToCopyDataToFile { source })?;
+    fn lines_impl(
+        &self,
+        location: &Path,
+        max: Option<usize>,
+    ) -> BoxStream<'static, Result<String>> {
+        let location = location.clone();
+        let config = Arc::clone(&self.config);
+        let path = self.path_to_filesystem(&location);
+
+        futures::stream::once(async move {
+            let path = path?;
+            let limiter = config.read_rate_limit.clone();
+            let chunk_size = config.read_chunk_size;
+            let (file, path, len) = spawn_blocking(config.io_limiter.clone(), move || {
+                let (file, metadata) = config.open(&location, &path)?;
+                Ok((file, path, metadata.len()))
+            })
+            .await?;
 
-            Ok(())
+            let chunks = chunked_stream(file, path.clone(), 0..len, chunk_size, limiter);
+            let mut splitter = LineSplitter {
+                path,
+                max,
+                pending: vec![],
+                line: 0,
+                failed: false,
+            };
+
+            // Append a marker for the end of the file, to yield any unterminated line
+            let lines = chunks
+                .map_ok(Some)
+                .chain(futures::stream::once(futures::future::ready(Ok(None))))
+                .map(move |chunk| match chunk {
+                    Ok(Some(bytes)) => splitter.push(&bytes),
+                    Ok(None) => splitter.finish(),
+                    Err(e) => vec![Err(e)],
+                })
+                .flat_map(futures::stream::iter);
+            Ok::<_, crate::Error>(lines)
         })
+        .try_flatten()
         .boxed()
     }
 
-    async fn complete(&mut self) -> Result<PutResult> {
-        let src = self.src.take().ok_or(Error::Aborted)?;
-        let s = Arc::clone(&self.state);
-        maybe_spawn_blocking(move || {
-            // Ensure no inflight writes
-            let file = s.file.lock();
-            std::fs::rename(&src, &s.dest)
-                .map_err(|source| Error::UnableToRenameFile { source })?;
-            let metadata = file.metadata().map_err(|e| Error::Metadata {
-                source: e.into(),
-                path: src.to_string_lossy().to_string(),
-            })?;
+    /// Copies the objects under `from_prefix` to the locations under `to_prefix` given by `map`
+    ///
+    /// `map` is called with the location of each object relative to `from_prefix`, and
+    /// returns its destination relative to `to_prefix`. For example, copying `src/2024/01/31/f`
+    /// to `dst/2024-01-31/f` requires mapping `2024/01/31/f` to `2024-01-31/f`.
+    ///
+    /// Returns an error without copying anything if two objects would be copied to the
+    /// same destination. The copies are otherwise performed concurrently, and are not
+    /// atomic as a whole.
+    pub async fn copy_prefix_with<F>(
+        &self,
+        from_prefix: &Path,
+        to_prefix: &Path,
+        map: F,
+    ) -> Result<()>
+    where
+        F: Fn(&Path) -> Path,
+    {
+        /// The maximum number of concurrent copies
+        const CONCURRENCY: usize = 8;
+
+        let objects: Vec<_> = self.list(Some(from_prefix)).try_collect().await?;
+
+        let mut destinations = HashMap::with_capacity(objects.len());
+        for object in objects {
+            let relative = match object.location.prefix_match(from_prefix) {
+                Some(parts) => Path::from_iter(parts),
+                None => continue,
+            };
+            let to = Path::from_iter(to_prefix.parts().chain(map(&relative).parts()));
+            if let Some(first) = destinations.insert(to.clone(), object.location.clone()) {
+                return Err(Error::CopyCollision {
+                    first: first.to_string(),
+                    second: object.location.to_string(),
+                    to: to.to_string(),
+                }
+                .into());
+            }
+        }
 
-            Ok(PutResult {
-                e_tag: Some(get_etag(&metadata)),
-                version: None,
-            })
+        futures::stream::iter(destinations)
+            .map(|(to, from)| async move { self.copy(&from, &to).await })
+            .buffer_unordered(CONCURRENCY)
+            .try_collect()
+            .await
+    }
+
+    /// Writes `payload` to `location`, with the object expiring after `ttl`
+    ///
+    /// The expiry is recorded in the `user.object_store.expires_at` extended attribute of
+    /// the file, which is set before the object is atomically moved into place. Expired
+    /// objects continue to be served until removed by [`Self::purge_expired`].
+    ///
+    /// Returns an error on platforms, or filesystems, without extended attribute support
+    pub async fn put_with_ttl(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        ttl: Duration,
+    ) -> Result<PutResult> {
+        let expires_at = (SystemTime::now() + ttl)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        let mut opts = PutOptions::default();
+        opts.extensions.insert(ExpiresAt(expires_at));
+        self.put_opts(location, payload, opts).await
+    }
+
+    /// Deletes the objects under `prefix` written by [`Self::put_with_ttl`] whose expiry
+    /// has passed, returning the number of objects removed
+    pub async fn purge_expired(&self, prefix: Option<&Path>) -> Result<usize> {
+        let config = Arc::clone(&self.config);
+        let prefix = prefix.cloned().unwrap_or_default();
+        let root_path = config.prefix_to_filesystem(&prefix)?;
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+
+            let walkdir = WalkDir::new(root_path).min_depth(1).follow_links(true);
+
+            let mut purged = 0;
+            for entry_res in walkdir.into_iter().map(convert_walkdir_result) {
+                let entry = match entry_res? {
+                    Some(entry) if entry.file_type().is_file() => entry,
+                    _ => continue,
+                };
+
+                if !config.is_valid_file_path(&config.filesystem_to_path(entry.path())?) {
+                    continue;
+                }
+
+                // The expiry is read from an open handle, such that the file removed is
+                // that found to have expired, rather than an object concurrently written
+                let file = match File::open(entry.path()) {
+                    Ok(file) => file,
+                    // Concurrently deleted
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(source) => {
+                        let path = entry.path().to_path_buf();
+                        return Err(Error::UnableToOpenFile { source, path }.into());
+                    }
+                };
+                let expires_at =
+                    read_file_xattr(&file, EXPIRES_AT_XATTR).and_then(|v| v.parse::<u128>().ok());
+
+                if matches!(expires_at, Some(expires_at) if expires_at <= now)
+                    && remove_if_unchanged(&config, entry.path(), &file)?
+                {
+                    purged += 1;
+                }
+            }
+            Ok(purged)
         })
         .await
     }
 
-    async fn abort(&mut self) -> Result<()> {
-        let src = self.src.take().ok_or(Error::Aborted)?;
-        maybe_spawn_blocking(move || {
-            std::fs::remove_file(&src)
-                .map_err(|source| Error::UnableToDeleteFile { source, path: src })?;
-            Ok(())
+    /// Atomically increments the counter stored at `location` by `by`, returning the new value
+    ///
+    /// The counter is stored as a decimal integer, and is initialized to `by` if absent.
+    /// Concurrent increments, including from other processes, are serialized by taking an
+    /// advisory lock on the file, with the new value written to a staging file and then
+    /// atomically renamed into place.
+    pub async fn increment(&self, location: &Path, by: u64) -> Result<u64> {
+        let path = self.path_to_filesystem(location)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            // The lock is held until the new value has been renamed into place
+            let mut file = open_locked(&path, true)?;
+
+            let mut current = String::new();
+            file.read_to_string(&mut current).map_err(|source| {
+                let path = path.clone();
+                Error::UnableToReadBytes { source, path }
+            })?;
+
+            let value = match current.trim() {
+                "" => by,
+                current => current
+                    .parse::<u64>()
+                    .map_err(|source| Error::InvalidCounter {
+                        path: path.clone(),
+                        source,
+                    })?
+                    .checked_add(by)
+                    .ok_or_else(|| Error::CounterOverflow {
+                        path: path.clone(),
+                        by,
+                    })?,
+            };
+
+            config.write_staged(
+                &path,
+                &path,
+                |staged| staged.write_all(value.to_string().as_bytes()),
+                |staging_path| config.commit_sidecars(&path, staging_path, &Attributes::new()),
+            )?;
+            Ok(value)
         })
         .await
     }
 }
 
-impl Drop for LocalUpload {
-    fn drop(&mut self) {
-        if let Some(src) = self.src.take() {
-            // Try to clean up intermediate file ignoring any error
-            match tokio::runtime::Handle::try_current() {
-                Ok(r) => drop(r.spawn_blocking(move || std::fs::remove_file(src))),
-                Err(_) => drop(std::fs::remove_file(src)),
-            };
+impl Config {
+    /// Return an absolute filesystem path of the given location
+    fn prefix_to_filesystem(&self, location: &Path) -> Result<PathBuf> {
+        if self.strict_path_segments && location.parts().any(|p| p.as_ref().is_empty()) {
+            let path = location.to_string();
+            let source = crate::path::Error::EmptySegment { path };
+            return Err(crate::Error::InvalidPath { source });
+        }
+        let normalized;
+        let location = match self.normalize_backslashes && location.as_ref().contains('\\') {
+            true => {
+                normalized = Path::parse(location.as_ref().replace('\\', "/"))
+                    .map_err(|source| crate::Error::InvalidPath { source })?;
+                &normalized
+            }
+            false => location,
+        };
+        let encoded;
+        let location = match self.decode_percent_filenames && location.as_ref().contains([':', '%'])
+        {
+            true => {
+                let location = location.as_ref().replace('%', "%25").replace(':', "%3A");
+                encoded =
+                    Path::parse(location).map_err(|source| crate::Error::InvalidPath { source })?;
+                &encoded
+            }
+            false => location,
+        };
+        if cfg!(windows) {
+            check_windows_segments(location)?;
+        }
+        join_url(&self.root, location)
+    }
+
+    /// Opens the file for `location` at `path`, restoring it from the replica if missing
+    fn open(&self, location: &Path, path: &PathBuf) -> Result<(File, Metadata)> {
+        match self.open_or_repair(location, path) {
+            Err(e) => Err(self.directory_not_found(e)),
+            Ok(opened) if self.nfs_consistency => {
+                revalidate(path, opened, |path| self.open_file(path))
+            }
+            Ok(opened) => Ok(opened),
+        }
+    }
+
+    /// Reports [`crate::Error::IsADirectory`] as not found, unless
+    /// [`LocalFileSystem::with_directory_error`]
+    fn directory_not_found(&self, e: crate::Error) -> crate::Error {
+        match e {
+            crate::Error::IsADirectory { path, source } if !self.directory_error => {
+                crate::Error::NotFound { path, source }
+            }
+            e => e,
         }
     }
-}
 
-pub(crate) fn chunked_stream(
-    mut file: File,
-    path: PathBuf,
-    range: Range<u64>,
-    chunk_size: usize,
-) -> BoxStream<'static, Result<Bytes, super::Error>> {
-    futures::stream::once(async move {
-        let (file, path) = maybe_spawn_blocking(move || {
-            file.seek(SeekFrom::Start(range.start as _))
-                .map_err(|source| Error::Seek {
-                    source,
-                    path: path.clone(),
-                })?;
-            Ok((file, path))
-        })
-        .await?;
+    /// Opens the file for `location` at `path`, restoring it from the replica if missing
+    /// or it does not match its checksum
+    fn open_or_repair(&self, location: &Path, path: &PathBuf) -> Result<(File, Metadata)> {
+        let err: crate::Error = match self.open_file(path) {
+            Ok(opened) => match self.checksum_mismatch(path)? {
+                Some(mismatch) => mismatch.into(),
+                None => return Ok(opened),
+            },
+            Err(err @ crate::Error::NotFound { .. }) => err,
+            Err(e) => return Err(e),
+        };
+        let replica = match &self.replica {
+            Some(replica) => replica,
+            None => return Err(err),
+        };
+
+        let mirror = join_url(replica, location)?;
+        let mut source = match open_file(&mirror) {
+            Ok((file, _)) => file,
+            // Report the original error if missing from both
+            Err(crate::Error::NotFound { .. }) => return Err(err),
+            Err(e) => return Err(e),
+        };
+        // A corrupt mirror must not replace the primary
+        if let Some(mismatch) = self.checksum_mismatch(&mirror)? {
+            return Err(mismatch.into());
+        }
+
+        self.write_staged(
+            path,
+            path,
+            |staged| io::copy(&mut source, staged).map(|_| ()),
+            |_| self.commit_repaired_sidecars(path, &mirror),
+        )?;
+        open_file(path)
+    }
+
+    /// Writes the sidecars of an object at `path` repaired from `mirror`, carrying over
+    /// the checksum of `mirror` rather than computing one that would conceal its corruption
+    fn commit_repaired_sidecars(
+        &self,
+        path: &std::path::Path,
+        mirror: &std::path::Path,
+    ) -> Result<()> {
+        #[cfg(feature = "fs-sidecar")]
+        if self.sidecar_attributes() {
+            write_sidecar(self, path, &read_sidecar(mirror))?;
+        }
+        if self.checksums {
+            match read_checksum(mirror) {
+                Some(checksum) => {
+                    write_sidecar_file(self, path, CHECKSUM_SUFFIX, checksum.as_bytes())?
+                }
+                None => remove_sidecar(path, CHECKSUM_SUFFIX)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if an object exists at `location`, or in the replica if configured
+    fn exists(&self, location: &Path) -> Result<bool> {
+        let path = self.prefix_to_filesystem(location)?;
+        let exists = |path: &PathBuf| match metadata(path) {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(source) => Err(Error::Metadata {
+                source: source.into(),
+                path: path.to_string_lossy().to_string(),
+            }),
+        };
+
+        match (exists(&path)?, &self.replica) {
+            (false, Some(replica)) => Ok(exists(&join_url(replica, location)?)?),
+            (exists, _) => Ok(exists),
+        }
+    }
+
+    /// Returns the [`ObjectMeta`] for `location` at `path`
+    fn head(&self, location: Path, path: &PathBuf) -> Result<ObjectMeta> {
+        if let Some(marker) = self.marker_at(&location, path)? {
+            return Ok(marker);
+        }
+
+        // Symlinks are only detected without races by opening the file
+        if self.nfs_consistency || !self.follow_symlinks {
+            let (file, metadata) = self.open(&location, path)?;
+            return self.convert_metadata(path, metadata, Some(&file), location);
+        }
+
+        // Avoid opening the file, which may block, e.g. for a FIFO, or be expensive
+        match metadata(path) {
+            Ok(metadata) if metadata.is_dir() => {
+                let e = Error::IsADirectory { path: path.clone() };
+                Err(self.directory_not_found(e.into()))
+            }
+            Ok(metadata) => self.convert_metadata(path, metadata, None, location),
+            Err(source) if source.kind() == ErrorKind::NotFound && self.replica.is_none() => {
+                let path = path.clone();
+                Err(Error::NotFound { path, source }.into())
+            }
+            // Defer to open for consistent error reporting and read-repair
+            _ => {
+                let (file, metadata) = self.open(&location, path)?;
+                self.convert_metadata(path, metadata, Some(&file), location)
+            }
+        }
+    }
+
+    /// Opens the file for `location` at `path` for reads of byte ranges, reusing an open
+    /// file if [`LocalFileSystem::with_fd_cache`] unless the file has since changed
+    fn open_for_ranges(&self, location: &Path, path: &PathBuf) -> Result<(RangeReader, Metadata)> {
+        match &self.fd_cache {
+            Some(cache) => {
+                let (file, metadata) = cache.get(path, || self.open(location, path))?;
+                Ok((RangeReader::Cached(file, Arc::clone(cache)), metadata))
+            }
+            None => {
+                let (file, metadata) = self.open(location, path)?;
+                Ok((RangeReader::Owned(file), metadata))
+            }
+        }
+    }
+
+    /// Reads `range` from the file opened by [`Self::open_for_ranges`], memory mapping it
+    /// if configured to do so
+    fn read_range(
+        &self,
+        mut reader: RangeReader,
+        metadata: &Metadata,
+        path: &PathBuf,
+        range: Range<u64>,
+    ) -> Result<Bytes> {
+        if let Some(bytes) = self.mmap_large_range(reader.file(), path, metadata, &range)? {
+            return Ok(bytes);
+        }
+        reader.read_range(path, range)
+    }
+
+    /// Memory maps `range` of `file` if larger than [`LocalFileSystem::with_mmap_threshold`],
+    /// see also [`LocalFileSystem::with_mmap`]
+    ///
+    /// Returns `None` if the range should instead be read into a buffer
+    fn mmap_large_range(
+        &self,
+        file: &File,
+        path: &std::path::Path,
+        metadata: &Metadata,
+        range: &Range<u64>,
+    ) -> Result<Option<Bytes>> {
+        let threshold = match self.mmap_threshold {
+            Some(threshold) if metadata.is_file() => threshold as u64,
+            _ => return Ok(None),
+        };
+        let to_read = range_to_read(file, path, range)?;
+        if to_read <= threshold {
+            return Ok(None);
+        }
+        let end = range.start + to_read;
+        Ok(mmap_range(file, end, range.start..end))
+    }
+
+    /// Returns `None` if `res` is an error reading a directory due to insufficient
+    /// permissions and [`LocalFileSystem::with_skip_unreadable`]
+    fn skip_unreadable(&self, res: walkdir::Result<DirEntry>) -> Option<walkdir::Result<DirEntry>> {
+        let denied = match &res {
+            Err(e) if self.skip_unreadable => {
+                e.io_error().map(|e| e.kind()) == Some(ErrorKind::PermissionDenied)
+            }
+            _ => false,
+        };
+        if !denied {
+            return Some(res);
+        }
+        if let Err(e) = res {
+            warn!("Skipping unreadable directory: {}", e);
+        }
+        self.skipped_unreadable.0.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// If [`LocalFileSystem::with_read_rate_limit`], waits until `bytes` may be read
+    async fn throttle(&self, bytes: u64) {
+        if let Some(limiter) = &self.read_rate_limit {
+            limiter.acquire(bytes).await
+        }
+    }
+
+    /// Opens the file at `path`, returning an error if it is a symlink, unless
+    /// [`LocalFileSystem::with_follow_symlinks`]
+    ///
+    /// On unix, each directory below the root is opened in turn with `O_NOFOLLOW`, such
+    /// that neither the file nor its parent directories can be swapped for a symlink
+    /// after being checked. Elsewhere only the final component is checked before opening.
+    fn open_file(&self, path: &PathBuf) -> Result<(File, Metadata)> {
+        if self.follow_symlinks {
+            return open_file(path);
+        }
+
+        #[cfg(unix)]
+        {
+            let root = self.root.to_file_path().map_err(|_| Error::InvalidUrl {
+                url: self.root.clone(),
+            })?;
+            let file = open_no_follow(&root, path).map_err(|source| match source.kind() {
+                ErrorKind::NotFound => Error::NotFound {
+                    path: path.clone(),
+                    source,
+                },
+                _ if source.raw_os_error() == Some(rustix::io::Errno::LOOP.raw_os_error()) => {
+                    Error::Symlink { path: path.clone() }
+                }
+                _ => Error::UnableToOpenFile {
+                    path: path.clone(),
+                    source,
+                },
+            })?;
+            let metadata = file.metadata().map_err(|source| Error::UnableToOpenFile {
+                path: path.clone(),
+                source,
+            })?;
+            match metadata.is_dir() {
+                true => Err(Error::IsADirectory { path: path.clone() }.into()),
+                false => Ok((file, metadata)),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            match symlink_metadata(path) {
+                Ok(metadata) if metadata.is_symlink() => {
+                    Err(Error::Symlink { path: path.clone() }.into())
+                }
+                _ => open_file(path),
+            }
+        }
+    }
+
+    /// Returns false if `entry` is a symlink resolving outside `sandbox_root`, see
+    /// [`Self::sandbox_root`]
+    fn in_sandbox(entry: &DirEntry, sandbox_root: Option<&PathBuf>) -> bool {
+        match sandbox_root {
+            Some(root) if entry.path_is_symlink() => std::fs::canonicalize(entry.path())
+                // Broken symlinks are ignored by convert_walkdir_result
+                .map_or(true, |path| path.starts_with(root)),
+            _ => true,
+        }
+    }
+
+    /// Converts `res` with [`convert_walkdir_result`], additionally skipping symlinks
+    /// unless [`LocalFileSystem::with_follow_symlinks`]
+    fn convert_walkdir_result(&self, res: walkdir::Result<DirEntry>) -> Result<Option<DirEntry>> {
+        match res {
+            Ok(entry) if !self.follow_symlinks && entry.path_is_symlink() => Ok(None),
+            res => convert_walkdir_result(res),
+        }
+    }
+
+    /// If [`LocalFileSystem::with_restrict_symlinks_to_root`], returns an error if the
+    /// parent directory of `path` resolves outside the root
+    fn check_within_root(&self, path: &std::path::Path) -> Result<()> {
+        match self.restrict_symlinks_to_root {
+            // Parent directories are created as necessary, and so resolve the closest ancestor
+            true => self.check_resolves_within_root(path, path.parent()),
+            false => Ok(()),
+        }
+    }
+
+    /// If [`LocalFileSystem::with_sandbox`], returns an error if `path`, or its closest
+    /// existing ancestor, resolves outside the root
+    fn check_sandbox(&self, path: &std::path::Path) -> Result<()> {
+        match self.sandbox {
+            true => self.check_resolves_within_root(path, Some(path)),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns the canonicalized root, if [`LocalFileSystem::with_sandbox`]
+    fn sandbox_root(&self) -> Result<Option<PathBuf>> {
+        match self.sandbox {
+            true => self.canonical_root().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Returns the root of this store with all symlinks resolved
+    fn canonical_root(&self) -> Result<PathBuf> {
+        let root = self.root.to_file_path().map_err(|_| Error::InvalidUrl {
+            url: self.root.clone(),
+        })?;
+        Ok(std::fs::canonicalize(&root).map_err(|source| {
+            let path = root.clone();
+            Error::UnableToCanonicalize { source, path }
+        })?)
+    }
+
+    /// Returns [`Error::PathEscapesRoot`] for `path` if the first existing path of `ancestor`
+    /// and its ancestors resolves outside the root
+    fn check_resolves_within_root(
+        &self,
+        path: &std::path::Path,
+        mut ancestor: Option<&std::path::Path>,
+    ) -> Result<()> {
+        let canonicalize = |path: &std::path::Path| {
+            std::fs::canonicalize(path).map_err(|source| {
+                let path = path.into();
+                Error::UnableToCanonicalize { source, path }
+            })
+        };
+        let root = self.canonical_root()?;
+
+        while let Some(dir) = ancestor {
+            match symlink_metadata(dir) {
+                Ok(_) => {
+                    if !canonicalize(dir)?.starts_with(&root) {
+                        let path = path.into();
+                        return Err(Error::PathEscapesRoot { path, root }.into());
+                    }
+                    return Ok(());
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => ancestor = dir.parent(),
+                Err(source) => {
+                    let path = dir.to_string_lossy().to_string();
+                    return Err(Error::Metadata {
+                        source: source.into(),
+                        path,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies `from` to a new staged upload for `to`, see [`stage_copy`], preserving its
+    /// metadata if [`LocalFileSystem::with_preserve_metadata`]
+    fn stage_copy(&self, from: &std::path::Path, to: &std::path::Path) -> Result<PathBuf> {
+        let staged = stage_copy(self, from, to)?;
+        self.preserve_metadata(from, &staged)?;
+        Ok(staged)
+    }
+
+    /// Clones `from` to a new staged upload for `to`, see [`stage_reflink`], preserving
+    /// its metadata if [`LocalFileSystem::with_preserve_metadata`]
+    fn stage_reflink(&self, from: &std::path::Path, to: &std::path::Path) -> Option<PathBuf> {
+        let staged = stage_reflink(self, from, to)?;
+        // Falls back to a hard link, which shares the metadata of `from`
+        self.preserve_metadata(from, &staged).ok()?;
+        Some(staged)
+    }
+
+    /// Copies the permissions and modification time of `from` to the file `staged` if
+    /// [`LocalFileSystem::with_preserve_metadata`], removing `staged` on error
+    fn preserve_metadata(&self, from: &std::path::Path, staged: &std::path::Path) -> Result<()> {
+        if !self.preserve_metadata {
+            return Ok(());
+        }
+        copy_metadata(from, staged).map_err(|source| {
+            let _ = std::fs::remove_file(staged); // Attempt to clean up
+            let (from, to) = (from.into(), staged.into());
+            Error::UnableToCopyFile { from, to, source }.into()
+        })
+    }
+
+    /// Removes the empty parent directories of `path` up to the root, see
+    /// [`LocalFileSystem::with_automatic_cleanup`]
+    fn remove_empty_parents(&self, path: &std::path::Path) -> Result<()> {
+        let root = self.root.to_file_path().map_err(|_| Error::InvalidUrl {
+            url: self.root.clone(),
+        })?;
+
+        // here we will try to traverse up and delete an empty dir if possible until we reach the root or get an error,
+        // such as a directory that is no longer empty because of a concurrent write
+        let mut parent = path.parent();
+
+        while let Some(loc) = parent {
+            if loc != root && std::fs::remove_dir(loc).is_ok() {
+                parent = loc.parent();
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the ETag of the file at `path` with `metadata`, see [`EtagStrategy`]
+    ///
+    /// `file` is a handle to the file if already open, from which the inode is obtained
+    /// on platforms where it is not part of the [`Metadata`]
+    fn etag(
+        &self,
+        path: &std::path::Path,
+        metadata: &Metadata,
+        file: Option<&File>,
+    ) -> Result<String> {
+        let etag = self.maybe_etag(path, metadata, file, true)?;
+        Ok(etag.expect("computed ETag"))
+    }
+
+    /// Returns the ETag of the file at `path` as [`Self::etag`], unless it would need to be
+    /// computed from the file contents and `compute` is false, as is the case for listings
+    fn maybe_etag(
+        &self,
+        path: &std::path::Path,
+        metadata: &Metadata,
+        file: Option<&File>,
+        compute: bool,
+    ) -> Result<Option<String>> {
+        let validator = || {
+            get_etag(path, metadata, file).map_err(|source| Error::Metadata {
+                source: source.into(),
+                path: path.to_string_lossy().to_string(),
+            })
+        };
+        // `s3` is set for S3-compatible ETags, along with the part sizes of objects
+        // written by multipart uploads, which alone have their ETag computed per part
+        let (kind, s3): (_, Option<Option<Vec<u64>>>) = match self.etag_strategy {
+            EtagStrategy::InodeSizeMtime => return Ok(Some(validator()?)),
+            EtagStrategy::SizeMtime => return Ok(Some(size_mtime_etag(metadata))),
+            EtagStrategy::ContentSha256 => ("sha256", None),
+            #[cfg(feature = "fs-s3-etag")]
+            EtagStrategy::S3Compatible => match multipart_part_sizes(path) {
+                Some(part_sizes) => ("s3-multipart", Some(Some(part_sizes))),
+                None => ("s3", Some(None)),
+            },
+        };
+
+        // Content-derived ETags are cached alongside the metadata ETag of the file,
+        // and the strategy, they were computed for
+        let prefix = format!("{} {kind} ", validator()?);
+        let cached = read_xattr(path, ETAG_XATTR);
+        if let Some(etag) = cached.as_deref().and_then(|v| v.strip_prefix(&prefix)) {
+            return Ok(Some(etag.to_string()));
+        }
+        if !compute {
+            return Ok(None);
+        }
+
+        let etag = content_etag(path, s3.as_ref().map(Option::as_deref))?;
+        let _ = write_xattr(path, ETAG_XATTR, &format!("{prefix}{etag}")); // Best effort
+        Ok(Some(etag))
+    }
+
+    /// Returns the [`ObjectMeta`] for `location` of the file at `path` with `metadata`
+    fn convert_metadata(
+        &self,
+        path: &std::path::Path,
+        metadata: Metadata,
+        file: Option<&File>,
+        location: Path,
+    ) -> Result<ObjectMeta> {
+        Ok(ObjectMeta {
+            e_tag: Some(self.etag(path, &metadata, file)?),
+            last_modified: last_modified(&metadata),
+            size: metadata.len(),
+            location,
+            version: None,
+        })
+    }
+
+    /// Returns the zero-byte marker object for the listed directory `entry` if it is empty,
+    /// see [`LocalFileSystem::with_directory_markers`]
+    fn directory_marker(&self, entry: &DirEntry) -> Result<Option<ObjectMeta>> {
+        let location = self.filesystem_to_path(entry.path())?;
+        match entry.metadata() {
+            Ok(metadata) => marker_meta(location, entry.path(), &metadata),
+            Err(e) if e.io_error().map(|e| e.kind()) == Some(ErrorKind::NotFound) => Ok(None),
+            Err(e) => Err(Error::Metadata {
+                source: e.into(),
+                path: location.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Returns the zero-byte marker object for `location` if [`LocalFileSystem::with_directory_markers`]
+    /// and `path` is an empty directory, as would be returned by [`ObjectStore::list`]
+    fn marker_at(&self, location: &Path, path: &std::path::Path) -> Result<Option<ObjectMeta>> {
+        if !self.directory_markers {
+            return Ok(None);
+        }
+        let metadata = match self.follow_symlinks {
+            true => metadata(path),
+            false => symlink_metadata(path),
+        };
+        match metadata {
+            Ok(metadata) if metadata.is_dir() => marker_meta(location.clone(), path, &metadata),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the [`ObjectMeta`] for `location` of the listed `entry`, without its ETag if
+    /// this would require reading the file, see [`EtagStrategy`]
+    fn convert_entry(&self, entry: DirEntry, location: Path) -> Result<Option<ObjectMeta>> {
+        match entry.metadata() {
+            Ok(metadata) => Ok(Some(ObjectMeta {
+                e_tag: self.maybe_etag(entry.path(), &metadata, None, false)?,
+                last_modified: last_modified(&metadata),
+                size: metadata.len(),
+                location,
+                version: None,
+            })),
+            Err(e) => {
+                if let Some(io_err) = e.io_error() {
+                    if io_err.kind() == ErrorKind::NotFound {
+                        return Ok(None);
+                    }
+                }
+                Err(Error::Metadata {
+                    source: e.into(),
+                    path: location.to_string(),
+                })?
+            }
+        }
+    }
+
+    /// Persists the `attributes` of an object staged at `staging`, see
+    /// [`LocalFileSystem::with_attribute_storage`]
+    fn stage_attributes(&self, staging: &std::path::Path, attributes: &Attributes) -> Result<()> {
+        match self.attributes {
+            AttributeStorage::ExtendedAttributes => write_attributes(staging, attributes),
+            AttributeStorage::Disabled => Ok(()),
+            #[cfg(feature = "fs-sidecar")]
+            AttributeStorage::Sidecar => Ok(()),
+        }
+    }
+
+    /// Returns true if attributes are stored in sidecar files, see
+    /// [`AttributeStorage::Sidecar`]
+    fn sidecar_attributes(&self) -> bool {
+        match self.attributes {
+            #[cfg(feature = "fs-sidecar")]
+            AttributeStorage::Sidecar => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the suffixes of the sidecar files stored next to objects, see
+    /// [`AttributeStorage::Sidecar`] and [`LocalFileSystem::with_checksums`]
+    fn sidecar_suffixes(&self) -> impl Iterator<Item = &'static str> {
+        [
+            (self.sidecar_attributes(), SIDECAR_SUFFIX),
+            (self.checksums, CHECKSUM_SUFFIX),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, suffix)| suffix)
+    }
+
+    /// Writes the sidecars of an object about to be moved from `staged` to `path`, i.e.
+    /// its checksum and the `attributes` not stored by [`Self::stage_attributes`]
+    fn commit_sidecars(
+        &self,
+        path: &std::path::Path,
+        staged: &std::path::Path,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        if self.sidecar_attributes() {
+            write_sidecar(self, path, attributes)?;
+        }
+        if self.checksums {
+            let checksum = sha256_hex(staged)?;
+            write_sidecar_file(self, path, CHECKSUM_SUFFIX, checksum.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the sidecars of `to` with those of `from`
+    fn copy_sidecars(&self, from: &std::path::Path, to: &std::path::Path) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+        for suffix in self.sidecar_suffixes() {
+            let path = sidecar_path(from, suffix);
+            match std::fs::read(&path) {
+                Ok(contents) => write_sidecar_file(self, to, suffix, &contents)?,
+                Err(e) if e.kind() == ErrorKind::NotFound => remove_sidecar(to, suffix)?,
+                Err(source) => return Err(Error::UnableToReadBytes { source, path }.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the sidecars of `path`, if any
+    fn remove_sidecars(&self, path: &std::path::Path) -> Result<()> {
+        self.sidecar_suffixes()
+            .try_for_each(|suffix| remove_sidecar(path, suffix))
+    }
+
+    /// Returns true if `path` may be that of an object, i.e. is neither a staged upload,
+    /// nor a sidecar, see [`Self::sidecar_suffixes`]
+    fn is_valid_file_path(&self, path: &Path) -> bool {
+        match path.filename() {
+            Some(p) if is_staging_file_name(p) => false,
+            Some(p) => !self
+                .sidecar_suffixes()
+                .any(|suffix| is_sidecar_file_name(p, suffix)),
+            None => false,
+        }
+    }
+
+    /// Returns [`Error::ChecksumMismatch`] if the contents of the file at `path` do not
+    /// match its recorded checksum, see [`LocalFileSystem::with_checksums`]
+    fn checksum_mismatch(&self, path: &std::path::Path) -> Result<Option<Error>> {
+        let expected = match self.checksums {
+            true => read_checksum(path),
+            false => None,
+        };
+        let expected = match expected {
+            Some(expected) => expected,
+            None => return Ok(None),
+        };
+        let actual = sha256_hex(path)?;
+        Ok((actual != expected).then(|| Error::ChecksumMismatch {
+            path: path.into(),
+            expected,
+            actual,
+        }))
+    }
+
+    /// Creates a new staged upload for `path`, within [`LocalFileSystem::with_staging_dir`]
+    /// if configured, otherwise alongside `path`
+    fn staged_upload(&self, path: &std::path::Path) -> Result<(File, PathBuf)> {
+        let (base, parent) = match (self.staging_base(path), path.parent()) {
+            (Some(base), Some(parent)) => (base, parent),
+            _ => return new_staged_upload_with_mode(path, self.file_mode, self.dir_mode),
+        };
+        // The parent directories must exist for the staged file to be moved into place
+        create_dirs(parent, self.dir_mode)?;
+        new_staged_upload_with_mode(&base, self.file_mode, self.dir_mode)
+    }
+
+    /// Returns the path of the staging file `upload_id` for an upload to `path`, see
+    /// [`Self::staged_upload`]
+    fn staging_path(&self, path: &std::path::Path, upload_id: &str) -> PathBuf {
+        match self.staging_base(path) {
+            Some(base) => staged_upload_path(&base, upload_id),
+            None => staged_upload_path(path, upload_id),
+        }
+    }
+
+    /// Returns the path within [`LocalFileSystem::with_staging_dir`], if configured, to
+    /// which the `#` suffix of staged uploads to `path` is appended
+    ///
+    /// This is named after a digest of the full path, as uploads to different locations
+    /// with the same file name must not share staging files.
+    fn staging_base(&self, path: &std::path::Path) -> Option<PathBuf> {
+        let staging_dir = self.staging_dir.as_ref()?;
+        let digest = Sha256::digest(path.to_string_lossy().as_bytes());
+        Some(staging_dir.join(hex_encode(&digest)))
+    }
+
+    /// Atomically replaces `dest` with the contents written by `write` to a new staged upload
+    /// for `path`, see [`Self::staged_upload`], calling `commit` with the path of the staged
+    /// file before it is moved into place
+    ///
+    /// The staged file and the directory containing `dest` are synced to disk if
+    /// [`LocalFileSystem::with_durable_writes`]
+    fn write_staged(
+        &self,
+        path: &std::path::Path,
+        dest: &std::path::Path,
+        write: impl FnOnce(&mut File) -> io::Result<()>,
+        commit: impl FnOnce(&std::path::Path) -> Result<()>,
+    ) -> Result<()> {
+        let (mut file, staging_path) = self.staged_upload(path)?;
+        let err = match write(&mut file).and_then(|_| self.sync_staged(&file)) {
+            Ok(_) => {
+                // For some fuse types of file systems, the file must be closed first
+                // to trigger the upload operation, and then renamed, such as Blobfuse
+                std::mem::drop(file);
+                commit(&staging_path)
+                    .and_then(|_| {
+                        std::fs::rename(&staging_path, dest)
+                            .map_err(|source| Error::UnableToRenameFile { source }.into())
+                    })
+                    .err()
+            }
+            Err(source) => Some(Error::UnableToCopyDataToFile { source }.into()),
+        };
+
+        if let Some(err) = err {
+            let _ = std::fs::remove_file(&staging_path); // Attempt to cleanup
+            return Err(err);
+        }
+        self.sync_parent(dest)
+    }
+
+    /// Syncs the contents of the staged `file` to disk, if
+    /// [`LocalFileSystem::with_durable_writes`]
+    fn sync_staged(&self, file: &File) -> io::Result<()> {
+        match self.durable_writes {
+            true => self.sync_all(file),
+            false => Ok(()),
+        }
+    }
+
+    /// Syncs the directory containing `path` to disk, if
+    /// [`LocalFileSystem::with_durable_writes`], making a file moved to `path` durable
+    fn sync_parent(&self, path: &std::path::Path) -> Result<()> {
+        let parent = match (self.durable_writes, path.parent()) {
+            (true, Some(parent)) => parent,
+            _ => return Ok(()),
+        };
+        let synced = match open_dir(parent) {
+            Ok(Some(dir)) => self.sync_all(&dir),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        synced.map_err(|source| {
+            let path = parent.into();
+            Error::UnableToSync { source, path }.into()
+        })
+    }
+
+    /// Syncs the directories containing `from` and `to` to disk, if
+    /// [`LocalFileSystem::with_durable_writes`], making a move from `from` to `to` durable
+    fn sync_parents(&self, from: &std::path::Path, to: &std::path::Path) -> Result<()> {
+        self.sync_parent(to)?;
+        match from.parent() != to.parent() {
+            true => self.sync_parent(from),
+            false => Ok(()),
+        }
+    }
+
+    /// Syncs the contents and metadata of `file` to disk
+    fn sync_all(&self, file: &File) -> io::Result<()> {
+        #[cfg(test)]
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        file.sync_all()
+    }
+
+    /// Returns the [`Attributes`] of the file at `path` with `metadata`
+    fn attributes(&self, path: &std::path::Path, metadata: &Metadata) -> Attributes {
+        let storage_class =
+            read_xattr(path, STORAGE_CLASS_XATTR).unwrap_or_else(|| self.storage_class.clone());
+
+        let mut attributes = match self.attributes {
+            AttributeStorage::Disabled => Attributes::new(),
+            AttributeStorage::ExtendedAttributes => read_attributes(path),
+            #[cfg(feature = "fs-sidecar")]
+            AttributeStorage::Sidecar => read_sidecar(path),
+        };
+        attributes.insert(
+            Attribute::Metadata(STORAGE_CLASS_ATTRIBUTE.into()),
+            storage_class.into(),
+        );
+        if let Some(allocated) = allocated_size(metadata).filter(|_| self.allocated_size_attribute)
+        {
+            attributes.insert(
+                Attribute::Metadata(ALLOCATED_SIZE_ATTRIBUTE.into()),
+                allocated.to_string().into(),
+            );
+        }
+        if let Some(links) = link_count(metadata).filter(|_| self.link_count_attribute) {
+            attributes.insert(
+                Attribute::Metadata(LINK_COUNT_ATTRIBUTE.into()),
+                links.to_string().into(),
+            );
+        }
+        if self.derived_attributes {
+            attributes.insert(
+                Attribute::Metadata(CONTENT_LENGTH_ATTRIBUTE.into()),
+                metadata.len().to_string().into(),
+            );
+            let last_modified = last_modified(metadata).format("%a, %d %b %Y %H:%M:%S GMT");
+            attributes.insert(
+                Attribute::Metadata(LAST_MODIFIED_ATTRIBUTE.into()),
+                last_modified.to_string().into(),
+            );
+        }
+        let infer_content_type = self.derived_attributes || self.content_type_from_extension;
+        if infer_content_type && attributes.get(&Attribute::ContentType).is_none() {
+            if let Some(content_type) = content_type_from_extension(path) {
+                attributes.insert(Attribute::ContentType, content_type.into());
+            }
+        }
+        attributes
+    }
+
+    /// Resolves the provided absolute filesystem path to a [`Path`] prefix
+    fn filesystem_to_path(&self, location: &std::path::Path) -> Result<Path> {
+        let path = Path::from_absolute_path_with_base(location, Some(&self.root))?;
+        if !self.decode_percent_filenames || !path.as_ref().contains('%') {
+            return Ok(path);
+        }
+        let parts: Vec<_> = path
+            .parts()
+            .map(|part| decode_filename(part.as_ref()))
+            .collect();
+        Ok(Path::parse(parts.join(DELIMITER))?)
+    }
+}
+
+/// Decodes the `%3A` and `%25` sequences of a filename, leaving any others unchanged, see
+/// [`LocalFileSystem::with_decode_percent_filenames`]
+fn decode_filename(name: &str) -> String {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(idx) = rest.find('%') {
+        decoded.push_str(&rest[..idx]);
+        let (c, len) = match rest.get(idx..idx + 3) {
+            Some("%3A") | Some("%3a") => (':', 3),
+            Some("%25") => ('%', 3),
+            _ => ('%', 1),
+        };
+        decoded.push(c);
+        rest = &rest[idx + len..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Return an absolute filesystem path of `location` relative to `root`
+///
+/// Each part of `location` is percent-encoded as a URL segment, including any `%` it
+/// contains, and so decodes back to exactly one path component regardless of how
+/// `root` itself is encoded. Returns [`Error::InvalidUrl`] if the result is not a
+/// valid filesystem path.
+fn join_url(root: &Url, location: &Path) -> Result<PathBuf> {
+    let mut url = root.clone();
+    url.path_segments_mut()
+        .expect("url path")
+        // technically not necessary as Path ignores empty segments
+        // but avoids creating paths with "//" which look odd in error messages.
+        .pop_if_empty()
+        .extend(location.parts());
+
+    url.to_file_path()
+        .map_err(|_| Error::InvalidUrl { url }.into())
+}
+
+/// Returns an error if `location` contains a segment with a trailing `.` or space, which
+/// Windows would otherwise silently strip, or refuse to create as a directory
+fn check_windows_segments(location: &Path) -> Result<()> {
+    for part in location.parts() {
+        let segment = part.as_ref();
+        if let Some(illegal) = segment.chars().last().filter(|c| matches!(c, '.' | ' ')) {
+            let path = location.to_string();
+            let source = InvalidPart::new(segment, illegal);
+            let source = crate::path::Error::BadSegment { path, source };
+            return Err(crate::Error::InvalidPath { source });
+        }
+    }
+    Ok(())
+}
+
+/// Returns [`Error::IsADirectory`] if `path` is a directory, which cannot be copied
+fn check_not_directory(path: &PathBuf) -> Result<()> {
+    match metadata(path) {
+        Ok(m) if m.is_dir() => Err(Error::IsADirectory { path: path.clone() }.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the content type of files with the extension of `path`, if known
+fn content_type_from_extension(path: &std::path::Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    CONTENT_TYPE_EXTENSIONS
+        .iter()
+        .find(|(e, _)| *e == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// Returns true if `name` is that of a staged upload, i.e. ends with `#` and only digits
+fn is_staging_file_name(name: &str) -> bool {
+    match name.split_once('#') {
+        Some((_, suffix)) if !suffix.is_empty() => {
+            suffix.as_bytes().iter().all(|x| x.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if `name` is that of a sidecar with `suffix`, see [`sidecar_path`]
+fn is_sidecar_file_name(name: &str, suffix: &str) -> bool {
+    name.rsplit_once('#').map_or(false, |(_, s)| s == suffix)
+}
+
+/// Removes staged uploads under `root` last modified at least `max_age` ago, returning
+/// the number removed
+fn remove_stale_staging(root: &std::path::Path, max_age: Duration) -> usize {
+    let now = SystemTime::now();
+    WalkDir::new(root)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .map_or(false, is_staging_file_name)
+        })
+        .filter(|entry| {
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            let age = modified.and_then(|m| now.duration_since(m).ok());
+            age.unwrap_or_default() >= max_age
+        })
+        .filter(|entry| std::fs::remove_file(entry.path()).is_ok())
+        .count()
+}
+
+#[async_trait]
+impl ObjectStore for LocalFileSystem {
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        let location = location.clone();
+        let path = self.path_to_filesystem(&location)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            config.head(location, &path)
+        })
+        .await
+    }
+
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        if !opts.attributes.is_empty() && self.config.attributes == AttributeStorage::Disabled {
+            return Err(crate::Error::NotImplemented);
+        }
+
+        let path = self.path_to_filesystem(location)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            config.check_within_root(&path)?;
+            let (mut file, staging_path) = config.staged_upload(&path)?;
+            let mut e_tag = None;
+
+            let staged = config
+                .stage_attributes(&staging_path, &opts.attributes)
+                .and_then(|_| stage_expiry(&staging_path, opts.extensions.get()));
+            if let Err(e) = staged {
+                let _ = std::fs::remove_file(&staging_path); // Attempt to cleanup
+                return Err(e);
+            }
+
+            let written = payload
+                .iter()
+                .try_for_each(|x| file.write_all(x))
+                .and_then(|_| config.sync_staged(&file));
+            let err = match written {
+                Ok(_) => {
+                    let metadata = file.metadata().map_err(|e| Error::Metadata {
+                        source: e.into(),
+                        path: path.to_string_lossy().to_string(),
+                    })?;
+                    e_tag = Some(config.etag(&staging_path, &metadata, Some(&file))?);
+                    match opts.mode {
+                        PutMode::Overwrite => {
+                            // For some fuse types of file systems, the file must be closed first
+                            // to trigger the upload operation, and then renamed, such as Blobfuse
+                            std::mem::drop(file);
+                            config
+                                .commit_sidecars(&path, &staging_path, &opts.attributes)
+                                .and_then(|_| {
+                                    std::fs::rename(&staging_path, &path).map_err(|source| {
+                                        Error::UnableToRenameFile { source }.into()
+                                    })
+                                })
+                                .err()
+                        }
+                        PutMode::Create => match hard_link(&staging_path, &path) {
+                            Ok(_) => {
+                                let _ = std::fs::remove_file(&staging_path); // Attempt to cleanup
+
+                                // The sidecar of an existing object must not be replaced, and so
+                                // is written once created, removing the object should that fail
+                                match config.commit_sidecars(&path, &path, &opts.attributes) {
+                                    Ok(_) => None,
+                                    Err(e) => {
+                                        let _ = std::fs::remove_file(&path); // Attempt to cleanup
+                                        Some(e)
+                                    }
+                                }
+                            }
+                            Err(source) => match source.kind() {
+                                ErrorKind::AlreadyExists => Some(
+                                    Error::AlreadyExists {
+                                        path: path.to_str().unwrap().to_string(),
+                                        source,
+                                    }
+                                    .into(),
+                                ),
+                                _ => Some(Error::UnableToRenameFile { source }.into()),
+                            },
+                        },
+                        PutMode::Update(version) => {
+                            std::mem::drop(file);
+                            let attributes = &opts.attributes;
+                            rename_if_matches(&config, &staging_path, &path, version, attributes)
+                                .err()
+                        }
+                    }
+                }
+                Err(source) => Some(Error::UnableToCopyDataToFile { source }.into()),
+            };
+
+            if let Some(err) = err {
+                let _ = std::fs::remove_file(&staging_path); // Attempt to cleanup
+                return Err(err);
+            }
+            config.sync_parent(&path)?;
+
+            Ok(PutResult {
+                e_tag,
+                version: None,
+            })
+        })
+        .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOptions,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        if !opts.attributes.is_empty() && self.config.attributes == AttributeStorage::Disabled {
+            return Err(crate::Error::NotImplemented);
+        }
+
+        let dest = self.path_to_filesystem(location)?;
+        self.config.check_within_root(&dest)?;
+        let (file, src) = self.config.staged_upload(&dest)?;
+        let staged = lock_staged(&file, &src)
+            .and_then(|_| self.config.stage_attributes(&src, &opts.attributes));
+        if let Err(e) = staged {
+            let _ = std::fs::remove_file(&src); // Attempt to cleanup
+            return Err(e);
+        }
+        if let Some(SizeHint(size)) = opts.extensions.get().filter(|s: &&SizeHint| s.0 > 0) {
+            let _ = preallocate(&file, *size); // Purely a hint, ignore unsupported filesystems
+        }
+        let config = Arc::clone(&self.config);
+        let upload = LocalUpload::new(config, src, dest, file, opts.attributes);
+        Ok(Box::new(upload))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let location = location.clone();
+        let path = self.path_to_filesystem(&location)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            let conditional = options.if_match.is_some()
+                || options.if_none_match.is_some()
+                || options.if_modified_since.is_some()
+                || options.if_unmodified_since.is_some();
+
+            if let Some(meta) = config.marker_at(&location, &path)? {
+                options.check_preconditions(&meta)?;
+                let range = match options.range {
+                    Some(r) => r
+                        .as_range(0)
+                        .map_err(|source| Error::InvalidRange { source })?,
+                    None => 0..0,
+                };
+                return Ok(GetResult {
+                    payload: GetResultPayload::Stream(futures::stream::empty().boxed()),
+                    attributes: Attributes::new(),
+                    range,
+                    meta,
+                });
+            }
+
+            // Check preconditions prior to opening the file, avoiding doing so if not modified,
+            // they are checked again below in case the file is concurrently replaced
+            if conditional {
+                options.check_preconditions(&config.head(location.clone(), &path)?)?;
+            }
+
+            let (file, metadata) = config.open(&location, &path)?;
+            let attributes = config.attributes(&path, &metadata);
+            let opened = config.detect_replacement.then(|| metadata.clone());
+            let meta = config.convert_metadata(&path, metadata, Some(&file), location)?;
+            options.check_preconditions(&meta)?;
+
+            let range = match options.range {
+                Some(r) => r
+                    .as_range(meta.size)
+                    .map_err(|source| Error::InvalidRange { source })?,
+                None => 0..meta.size,
+            };
+
+            let chunk_size = config.read_chunk_size;
+            let payload = match (opened, &config.read_rate_limit) {
+                (None, None) if chunk_size == CHUNK_SIZE => GetResultPayload::File(file, path),
+                (opened, limiter) => {
+                    let stream = chunked_stream(
+                        file,
+                        path.clone(),
+                        range.clone(),
+                        chunk_size,
+                        limiter.clone(),
+                    );
+                    match opened {
+                        Some(opened) => {
+                            GetResultPayload::Stream(detect_replacement(stream, path, opened))
+                        }
+                        None => GetResultPayload::Stream(stream),
+                    }
+                }
+            };
+
+            Ok(GetResult {
+                payload,
+                attributes,
+                range,
+                meta,
+            })
+        })
+        .await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<u64>) -> Result<Bytes> {
+        let location = location.clone();
+        let path = self.path_to_filesystem(&location)?;
+        let config = Arc::clone(&self.config);
+        let bytes = spawn_blocking(self.config.io_limiter.clone(), move || {
+            let (reader, metadata) = config.open_for_ranges(&location, &path)?;
+            config.read_range(reader, &metadata, &path, range)
+        })
+        .await?;
+        self.config.throttle(bytes.len() as u64).await;
+        Ok(bytes)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<u64>]) -> Result<Vec<Bytes>> {
+        let location = location.clone();
+        let path = self.path_to_filesystem(&location)?;
+        let config = Arc::clone(&self.config);
+        let ranges = ranges.to_vec();
+        let (ranges, read) = spawn_blocking(self.config.io_limiter.clone(), move || {
+            let (reader, metadata) = config.open_for_ranges(&location, &path)?;
+            let len = metadata.len();
+            if let Some(r) = ranges.iter().find(|r| r.start >= len) {
+                let source = InvalidGetRange::StartTooLarge {
+                    requested: r.start,
+                    length: len,
+                };
+                return Err(Error::InvalidRange { source }.into());
+            }
+
+            // Read nearby and overlapping ranges with a single positioned read each,
+            // avoiding both seeking and reading the same bytes twice
+            let (mut file, cache) = reader.into_shared();
+            let opened = Arc::clone(&file);
+            let fetch_ranges = merge_ranges(&ranges, config.range_coalesce_gap as u64);
+            let fetched = fetch_ranges
+                .iter()
+                .map(|r| {
+                    if let Some(bytes) = config.mmap_large_range(&file, &path, &metadata, r)? {
+                        return Ok(bytes);
+                    }
+                    let mut buf = Vec::new();
+                    read_range_at(&mut file, &path, r.clone(), &mut buf)?;
+                    Ok(buf.into())
+                })
+                .collect::<Result<Vec<Bytes>>>();
+            if let Some(cache) = cache {
+                cache.replace(&path, &opened, &file);
+            }
+            let fetched = fetched?;
+            let read = fetched.iter().map(|b| b.len() as u64).sum::<u64>();
+            Ok((split_ranges(&ranges, &fetch_ranges, &fetched), read))
+        })
+        .await?;
+        self.config.throttle(read).await;
+        Ok(ranges)
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        let config = Arc::clone(&self.config);
+        let path = self.path_to_filesystem(location)?;
+        let automactic_cleanup = self.automatic_cleanup;
+        let location = location.clone();
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            if config.marker_at(&location, &path)?.is_some() {
+                std::fs::remove_dir(&path).map_err(|source| match source.kind() {
+                    ErrorKind::NotFound => Error::NotFound {
+                        path: path.clone(),
+                        source,
+                    },
+                    _ => Error::UnableToDeleteFile {
+                        path: path.clone(),
+                        source,
+                    },
+                })?;
+                if automactic_cleanup {
+                    config.remove_empty_parents(&path)?;
+                }
+                return Ok(());
+            }
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                return Err(match e.kind() {
+                    ErrorKind::NotFound => Error::NotFound { path, source: e }.into(),
+                    _ => Error::UnableToDeleteFile { path, source: e }.into(),
+                });
+            }
+
+            config.remove_sidecars(&path)?;
+
+            if automactic_cleanup {
+                config.remove_empty_parents(&path)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, Result<ObjectMeta>> {
+        self.list_with_maybe_offset(prefix, None, None)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'static, Result<ObjectMeta>> {
+        self.list_with_maybe_offset(prefix, Some(offset), None)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        let config = Arc::clone(&self.config);
+
+        let prefix = prefix.cloned().unwrap_or_default();
+        let resolved_prefix = config.prefix_to_filesystem(&prefix)?;
+
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            config.check_sandbox(&resolved_prefix)?;
+            let sandbox_root = config.sandbox_root()?;
+            let walkdir = WalkDir::new(&resolved_prefix)
+                .min_depth(1)
+                .max_depth(1)
+                .follow_links(config.follow_symlinks)
+                .sort_by(walk_order);
+
+            let mut common_prefixes = BTreeSet::new();
+            let mut objects = Vec::new();
+
+            let entries = walkdir
+                .into_iter()
+                .filter_entry(|e| Config::in_sandbox(e, sandbox_root.as_ref()))
+                .filter_map(|r| config.skip_unreadable(r));
+            for entry_res in entries.map(|r| config.convert_walkdir_result(r)) {
+                if let Some(entry) = entry_res? {
+                    let is_directory = entry.file_type().is_dir();
+                    let entry_location = config.filesystem_to_path(entry.path())?;
+                    if !is_directory && !config.is_valid_file_path(&entry_location) {
+                        continue;
+                    }
+
+                    let mut parts = match entry_location.prefix_match(&prefix) {
+                        Some(parts) => parts,
+                        None => continue,
+                    };
+
+                    let common_prefix = match parts.next() {
+                        Some(p) => p,
+                        None => continue,
+                    };
+
+                    drop(parts);
+
+                    if is_directory {
+                        common_prefixes.insert(prefix.child(common_prefix));
+                    } else if let Some(metadata) = config.convert_entry(entry, entry_location)? {
+                        objects.push(metadata);
+                    }
+                }
+            }
+
+            Ok(ListResult {
+                common_prefixes: common_prefixes.into_iter().collect(),
+                objects,
+            })
+        })
+        .await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = self.path_to_filesystem(from)?;
+        let to = self.path_to_filesystem(to)?;
+        let config = Arc::clone(&self.config);
+        let mut id = 0;
+        // In order to make this atomic we:
+        //
+        // - hard link to a hidden temporary file
+        // - atomically rename this temporary file into place
+        //
+        // This is necessary because hard_link returns an error if the destination already exists
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            check_not_directory(&from)?;
+            if from == to {
+                return check_exists(from);
+            }
+            if from.is_file() {
+                config.copy_sidecars(&from, &to)?;
+            }
+
+            let rename_staged = |staged: PathBuf, from: PathBuf, to: PathBuf| {
+                if let Err(source) = std::fs::rename(&staged, &to) {
+                    let _ = std::fs::remove_file(&staged); // Attempt to clean up
+                    return Err(Error::UnableToCopyFile { from, to, source }.into());
+                }
+                config.sync_parent(&to)
+            };
+
+            if let Some(staged) = config.stage_reflink(&from, &to) {
+                return rename_staged(staged, from, to);
+            }
+
+            loop {
+                let staged = staged_upload_path(&to, &id.to_string());
+                match hard_link(&from, &staged) {
+                    Ok(_) => return rename_staged(staged, from, to),
+                    Err(source) if is_cross_device(&source) => {
+                        let staged = config.stage_copy(&from, &to)?;
+                        return rename_staged(staged, from, to);
+                    }
+                    Err(source) => match source.kind() {
+                        ErrorKind::AlreadyExists => id += 1,
+                        ErrorKind::NotFound => match from.exists() {
+                            true => create_parent_dirs(&to, source, config.dir_mode)?,
+                            false => return Err(Error::NotFound { path: from, source }.into()),
+                        },
+                        _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
+                    },
+                }
+            }
+        })
+        .await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = self.path_to_filesystem(from)?;
+        let to = self.path_to_filesystem(to)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            if from == to {
+                return check_exists(from);
+            }
+            if from.is_file() {
+                config.copy_sidecars(&from, &to)?;
+            }
+
+            loop {
+                match std::fs::rename(&from, &to) {
+                    Ok(_) => break,
+                    Err(source) if is_cross_device(&source) => {
+                        let staged = config.stage_copy(&from, &to)?;
+                        if let Err(source) = std::fs::rename(&staged, &to) {
+                            let _ = std::fs::remove_file(&staged); // Attempt to clean up
+                            return Err(Error::UnableToCopyFile { from, to, source }.into());
+                        }
+                        std::fs::remove_file(&from).map_err(|source| {
+                            Error::UnableToDeleteFile {
+                                source,
+                                path: from.clone(),
+                            }
+                        })?;
+                        break;
+                    }
+                    Err(source) => match source.kind() {
+                        ErrorKind::NotFound => match from.exists() {
+                            true => create_parent_dirs(&to, source, config.dir_mode)?,
+                            false => return Err(Error::NotFound { path: from, source }.into()),
+                        },
+                        _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
+                    },
+                }
+            }
+            let _ = config.remove_sidecars(&from); // Attempt to clean up
+            config.sync_parents(&from, &to)
+        })
+        .await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = self.path_to_filesystem(from)?;
+        let to = self.path_to_filesystem(to)?;
+        let config = Arc::clone(&self.config);
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            copy_if_absent(&config, from.clone(), to.clone())?;
+            // The sidecar of an existing object must not be replaced, and so is copied once
+            // created, removing the copy should that fail
+            if let Err(e) = config.copy_sidecars(&from, &to) {
+                let _ = std::fs::remove_file(&to); // Attempt to clean up
+                return Err(e);
+            }
+            config.sync_parent(&to)
+        })
+        .await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        let from = self.path_to_filesystem(from)?;
+        let to = self.path_to_filesystem(to)?;
+        let config = Arc::clone(&self.config);
+
+        spawn_blocking(self.config.io_limiter.clone(), move || {
+            check_not_directory(&from)?;
+            if from == to {
+                return copy_if_absent(&config, from, to);
+            }
+
+            let copied = loop {
+                match rename_no_replace(&from, &to) {
+                    Ok(_) => break false,
+                    Err(source) if is_cross_device(&source) || is_rename_unsupported(&source) => {
+                        // Link or copy into place, only removing the source once successful
+                        copy_if_absent(&config, from.clone(), to.clone())?;
+                        break true;
+                    }
+                    Err(source) => match source.kind() {
+                        ErrorKind::AlreadyExists => {
+                            let path = to.to_string_lossy().to_string();
+                            return Err(Error::AlreadyExists { path, source }.into());
+                        }
+                        ErrorKind::NotFound => match from.exists() {
+                            true => create_parent_dirs(&to, source, config.dir_mode)?,
+                            false => return Err(Error::NotFound { path: from, source }.into()),
+                        },
+                        _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
+                    },
+                }
+            };
+
+            // The sidecar of an existing object must not be replaced, and so is copied once
+            // moved into place, moving the object back should that fail
+            if let Err(e) = config.copy_sidecars(&from, &to) {
+                let _ = match copied {
+                    true => std::fs::remove_file(&to),
+                    false => rename_no_replace(&to, &from),
+                }; // Attempt to clean up
+                return Err(e);
+            }
+            if copied {
+                std::fs::remove_file(&from).map_err(|source| Error::UnableToDeleteFile {
+                    source,
+                    path: from.clone(),
+                })?;
+            }
+            let _ = config.remove_sidecars(&from); // Attempt to clean up
+            config.sync_parents(&from, &to)
+        })
+        .await
+    }
+}
+
+impl LocalFileSystem {
+    /// List the objects with the given prefix, descending at most `depth` directories below it
+    ///
+    /// Objects directly within `prefix` have a depth of `1`, and so a `depth` of `0` yields
+    /// no objects. Directories beyond `depth` are not traversed, making this considerably
+    /// cheaper than filtering the output of [`ObjectStore::list`] for deep hierarchies.
+    pub fn list_max_depth(
+        &self,
+        prefix: Option<&Path>,
+        depth: usize,
+    ) -> BoxStream<'static, Result<ObjectMeta>> {
+        // WalkDir would otherwise lower min_depth to yield the root itself
+        if depth == 0 {
+            return futures::stream::empty().boxed();
+        }
+        self.list_with_maybe_offset(prefix, None, Some(depth))
+    }
+
+    fn list_with_maybe_offset(
+        &self,
+        prefix: Option<&Path>,
+        maybe_offset: Option<&Path>,
+        max_depth: Option<usize>,
+    ) -> BoxStream<'static, Result<ObjectMeta>> {
+        let config = Arc::clone(&self.config);
+
+        let root_path = match prefix {
+            Some(prefix) => match config.prefix_to_filesystem(prefix) {
+                Ok(path) => path,
+                Err(e) => return futures::future::ready(Err(e)).into_stream().boxed(),
+            },
+            None => config.root.to_file_path().unwrap(),
+        };
+        let sandbox_root = match config
+            .check_sandbox(&root_path)
+            .and_then(|_| config.sandbox_root())
+        {
+            Ok(sandbox_root) => sandbox_root,
+            Err(e) => return futures::future::ready(Err(e)).into_stream().boxed(),
+        };
+
+        let maybe_offset = maybe_offset.cloned();
+        match config.list_concurrency {
+            0 | 1 => walk_objects(config, root_path, max_depth, maybe_offset, sandbox_root),
+            concurrency => {
+                let top_level = walk_objects(
+                    Arc::clone(&config),
+                    root_path.clone(),
+                    Some(1),
+                    maybe_offset.clone(),
+                    sandbox_root.clone(),
+                );
+                let subdirs = match max_depth.map(|d| d.saturating_sub(1)) {
+                    Some(0) => return top_level,
+                    subdir_depth => subdir_depth,
+                };
+
+                // Walk each top-level directory separately, along with the top-level files
+                let walks = async move {
+                    let dirs = {
+                        let config = Arc::clone(&config);
+                        let sandbox_root = sandbox_root.clone();
+                        spawn_blocking(config.io_limiter.clone(), move || {
+                            top_level_dirs(&config, &root_path, sandbox_root.as_ref())
+                        })
+                        .await?
+                    };
+                    let walks = dirs.into_iter().map(move |dir| {
+                        let config = Arc::clone(&config);
+                        let (offset, sandbox_root) = (maybe_offset.clone(), sandbox_root.clone());
+                        walk_objects(config, dir, subdirs, offset, sandbox_root)
+                    });
+                    let walks = std::iter::once(top_level).chain(walks);
+                    Ok::<_, crate::Error>(
+                        futures::stream::iter(walks).flatten_unordered(concurrency),
+                    )
+                };
+                futures::stream::once(walks).try_flatten().boxed()
+            }
+        }
+    }
+}
+
+/// Returns the directories directly within `root`, following symlinks as configured and
+/// skipping those outside the `sandbox_root`, see [`Config::sandbox_root`]
+fn top_level_dirs(
+    config: &Config,
+    root: &std::path::Path,
+    sandbox_root: Option<&PathBuf>,
+) -> Result<Vec<PathBuf>> {
+    let walkdir = WalkDir::new(root)
+        .min_depth(1)
+        .max_depth(1)
+        .follow_links(config.follow_symlinks)
+        .sort_by(walk_order);
+
+    let entries = walkdir
+        .into_iter()
+        .filter_entry(|e| Config::in_sandbox(e, sandbox_root))
+        .filter_map(|r| config.skip_unreadable(r));
+
+    let mut dirs = vec![];
+    for entry in entries.map(|r| config.convert_walkdir_result(r)) {
+        match entry? {
+            Some(entry) if entry.file_type().is_dir() => dirs.push(entry.into_path()),
+            _ => {}
+        }
+    }
+    Ok(dirs)
+}
+
+/// Walks the files under `root`, descending at most `max_depth` directories, returning the
+/// [`ObjectMeta`] of those after `maybe_offset`, see [`LocalFileSystem::list_with_offset`]
+fn walk_objects(
+    config: Arc<Config>,
+    root: PathBuf,
+    max_depth: Option<usize>,
+    maybe_offset: Option<Path>,
+    sandbox_root: Option<PathBuf>,
+) -> BoxStream<'static, Result<ObjectMeta>> {
+    let io_limiter = config.io_limiter.clone();
+    let mut walkdir = WalkDir::new(root)
+        // Don't include the root directory itself
+        .min_depth(1)
+        .follow_links(config.follow_symlinks)
+        // Yield objects in lexicographic order of their location
+        .sort_by(walk_order);
+
+    if let Some(max_depth) = max_depth {
+        walkdir = walkdir.max_depth(max_depth);
+    }
+
+    let s = walkdir
+        .into_iter()
+        .filter_entry(move |e| Config::in_sandbox(e, sandbox_root.as_ref()))
+        .flat_map(move |result_dir_entry| {
+            let result_dir_entry = config.skip_unreadable(result_dir_entry)?;
+
+            // Apply offset filter before proceeding, to reduce statx file system calls
+            // This matters for NFS mounts
+            if let (Some(offset), Ok(entry)) = (maybe_offset.as_ref(), result_dir_entry.as_ref()) {
+                let location = config.filesystem_to_path(entry.path());
+                match location {
+                    Ok(path) if path <= *offset => return None,
+                    Err(e) => return Some(Err(e)),
+                    _ => {}
+                }
+            }
+
+            let entry = match config
+                .convert_walkdir_result(result_dir_entry)
+                .transpose()?
+            {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if config.directory_markers && entry.file_type().is_dir() {
+                return config.directory_marker(&entry).transpose();
+            }
+
+            if !entry.path().is_file() {
+                return None;
+            }
+
+            match config.filesystem_to_path(entry.path()) {
+                Ok(path) => match config.is_valid_file_path(&path) {
+                    true => config.convert_entry(entry, path).transpose(),
+                    false => None,
+                },
+                Err(e) => Some(Err(e)),
+            }
+        });
+
+    // If no tokio context, return iterator directly as no
+    // need to perform chunked spawn_blocking reads
+    if tokio::runtime::Handle::try_current().is_err() {
+        return futures::stream::iter(s).boxed();
+    }
+
+    // Otherwise list in batches of CHUNK_SIZE
+    const CHUNK_SIZE: usize = 1024;
+
+    let buffer = VecDeque::with_capacity(CHUNK_SIZE);
+    futures::stream::try_unfold((s, buffer), move |(mut s, mut buffer)| {
+        let io_limiter = io_limiter.clone();
+        async move {
+            if buffer.is_empty() {
+                (s, buffer) = spawn_blocking(io_limiter, move || {
+                    for _ in 0..CHUNK_SIZE {
+                        match s.next() {
+                            Some(r) => buffer.push_back(r),
+                            None => break,
+                        }
+                    }
+                    Ok((s, buffer))
+                })
+                .await?;
+            }
+
+            match buffer.pop_front() {
+                Some(Err(e)) => Err(e),
+                Some(Ok(meta)) => Ok(Some((meta, (s, buffer)))),
+                None => Ok(None),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Orders the entries of a directory such that a depth-first walk visits files in
+/// lexicographic order of their path
+///
+/// A directory sorts as if its name had a trailing delimiter, as the files it contains
+/// do, e.g. `a.txt` before `a/b` as `.` precedes `/`
+fn walk_order(a: &DirEntry, b: &DirEntry) -> CmpOrdering {
+    let key = |e: &DirEntry| {
+        let mut name = e.file_name().to_string_lossy().into_owned();
+        if e.file_type().is_dir() {
+            name.push_str(DELIMITER);
+        }
+        name
+    };
+    key(a).cmp(&key(b))
+}
+
+/// Returns [`Error::NotFound`] if there is no file at `path`
+fn check_exists(path: PathBuf) -> Result<()> {
+    match metadata(&path) {
+        Ok(_) => Ok(()),
+        Err(source) => Err(match source.kind() {
+            ErrorKind::NotFound => Error::NotFound { path, source },
+            _ => Error::Metadata {
+                source: source.into(),
+                path: path.to_string_lossy().to_string(),
+            },
+        }
+        .into()),
+    }
+}
+
+/// Creates the parent directories of `path` or returns an error based on `source` if no parent
+fn create_parent_dirs(path: &std::path::Path, source: io::Error, mode: Option<u32>) -> Result<()> {
+    let parent = path.parent().ok_or_else(|| {
+        let path = path.to_path_buf();
+        Error::UnableToCreateFile { path, source }
+    })?;
+    create_dirs(parent, mode)
+}
+
+/// Creates the directory `dir` and any missing ancestors, with permissions `mode` if
+/// provided, see [`LocalFileSystem::with_dir_mode`]
+fn create_dirs(dir: &std::path::Path, mode: Option<u32>) -> Result<()> {
+    // The directories to be created, whose permissions would otherwise be masked by the umask
+    let missing: Vec<_> = match mode {
+        Some(_) => dir.ancestors().take_while(|d| !d.exists()).collect(),
+        None => vec![],
+    };
+
+    // A concurrent delete with automatic cleanup may remove an ancestor between
+    // `create_dir_all` creating it and creating its children
+    let mut retries = 0;
+    loop {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode);
+        }
+        match builder.create(dir) {
+            Ok(()) => break,
+            Err(e) if retries < CREATE_DIR_RETRIES && is_concurrently_removed(&e) => {
+                retries += 1;
+            }
+            Err(source) => {
+                let path = dir.into();
+                return Err(Error::UnableToCreateDir { source, path }.into());
+            }
+        }
+    }
+
+    if let Some(mode) = mode {
+        for dir in missing {
+            set_mode(dir, mode).map_err(|source| {
+                let path = dir.into();
+                Error::UnableToCreateDir { source, path }
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+/// Sets the permissions of `path` to `mode`, regardless of the umask
+fn set_mode(path: &std::path::Path, mode: u32) -> io::Result<()> {
+    let permissions = std::os::unix::fs::PermissionsExt::from_mode(mode);
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+/// Permission modes are only supported on unix platforms
+fn set_mode(_path: &std::path::Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns true if `e` indicates a directory was removed whilst `create_dir_all` was
+/// creating it or its children, which reports `AlreadyExists` if the directory it failed
+/// to create was removed before it could confirm it exists
+fn is_concurrently_removed(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::NotFound | ErrorKind::AlreadyExists)
+}
+
+/// Generates a unique file path `{base}#{suffix}`, returning the opened `File` and `path`
+///
+/// Creates any directories if necessary
+fn new_staged_upload(base: &std::path::Path) -> Result<(File, PathBuf)> {
+    new_staged_upload_with_mode(base, None, None)
+}
+
+/// Creates a new staged upload as [`new_staged_upload`], with the permissions `file_mode`
+/// and those of any directories created `dir_mode`, if provided
+fn new_staged_upload_with_mode(
+    base: &std::path::Path,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+) -> Result<(File, PathBuf)> {
+    let mut multipart_id = 1;
+    loop {
+        let suffix = multipart_id.to_string();
+        let path = staged_upload_path(base, &suffix);
+        let mut options = OpenOptions::new();
+        #[cfg(unix)]
+        if let Some(mode) = file_mode {
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+        }
+        match options.read(true).write(true).create_new(true).open(&path) {
+            Ok(f) => {
+                if let Some(mode) = file_mode {
+                    // The mode passed to open is masked by the umask
+                    if let Err(source) = set_mode(&path, mode) {
+                        let _ = std::fs::remove_file(&path); // Attempt to clean up
+                        return Err(Error::UnableToCreateFile { source, path }.into());
+                    }
+                }
+                return Ok((f, path));
+            }
+            Err(source) => match source.kind() {
+                ErrorKind::AlreadyExists => multipart_id += 1,
+                ErrorKind::NotFound => create_parent_dirs(&path, source, dir_mode)?,
+                _ => return Err(Error::UnableToOpenFile { source, path }.into()),
+            },
+        }
+    }
+}
+
+/// Opens the file at `path`, creating it if necessary and `create`, and takes an exclusive
+/// advisory lock on it
+///
+/// As writes replace files by renaming a staged file into place, the lock is only
+/// returned once it is confirmed to be held on the file currently at `path`
+fn open_locked(path: &PathBuf, create: bool) -> Result<File> {
+    loop {
+        let mut options = OpenOptions::new();
+        let file = match options.read(true).write(true).create(create).open(path) {
+            Ok(file) => file,
+            Err(source) => match source.kind() {
+                ErrorKind::NotFound if create => {
+                    create_parent_dirs(path, source, None)?;
+                    continue;
+                }
+                ErrorKind::NotFound => {
+                    let path = path.clone();
+                    return Err(Error::NotFound { path, source }.into());
+                }
+                _ => {
+                    let path = path.clone();
+                    return Err(Error::UnableToOpenFile { source, path }.into());
+                }
+            },
+        };
+
+        lock_exclusive(&file).map_err(|source| {
+            let path = path.clone();
+            Error::UnableToLockFile { source, path }
+        })?;
+
+        match is_file_at(&file, path) {
+            Ok(true) => return Ok(file),
+            // Replaced or removed whilst waiting for the lock
+            Ok(false) => continue,
+            Err(e) if e.kind() == ErrorKind::NotFound => match create {
+                true => continue,
+                false => {
+                    let path = path.clone();
+                    return Err(Error::NotFound { path, source: e }.into());
+                }
+            },
+            Err(e) => {
+                return Err(Error::Metadata {
+                    source: e.into(),
+                    path: path.to_string_lossy().to_string(),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+/// Renames `staging_path` to `path` if the ETag of the file at `path` matches `version`,
+/// returning [`crate::Error::Precondition`] otherwise
+///
+/// An advisory lock is held on the file at `path` until the rename completes, so that
+/// concurrent conditional updates cannot both succeed
+fn rename_if_matches(
+    config: &Config,
+    staging_path: &PathBuf,
+    path: &PathBuf,
+    version: UpdateVersion,
+    attributes: &Attributes,
+) -> Result<()> {
+    if let Some(version) = version.version {
+        return Err(Error::UnsupportedVersion { version }.into());
+    }
+    let expected = version.e_tag.ok_or(Error::MissingETag)?;
+    let precondition = |source: String| crate::Error::Precondition {
+        path: path.to_string_lossy().to_string(),
+        source: source.into(),
+    };
+
+    let file = match open_locked(path, false) {
+        Ok(file) => file,
+        Err(crate::Error::NotFound { .. }) => {
+            return Err(precondition(format!("{} not found", path.display())))
+        }
+        Err(e) => return Err(e),
+    };
+    let metadata = file.metadata().map_err(|e| Error::Metadata {
+        source: e.into(),
+        path: path.to_string_lossy().to_string(),
+    })?;
+
+    let existing = config.etag(path, &metadata, Some(&file))?;
+    if existing != expected {
+        return Err(precondition(format!(
+            "{existing} does not match {expected}"
+        )));
+    }
+
+    config.commit_sidecars(path, staging_path, attributes)?;
+    std::fs::rename(staging_path, path).map_err(|source| Error::UnableToRenameFile { source })?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn lock_exclusive(file: &File) -> io::Result<()> {
+    use rustix::fs::{flock, FlockOperation};
+    Ok(flock(file, FlockOperation::LockExclusive)?)
+}
+
+#[cfg(windows)]
+fn lock_exclusive(file: &File) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    // SAFETY: the handle is valid for the lifetime of `file` and `overlapped` is zeroed
+    let ret = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        LockFileEx(
+            file.as_raw_handle() as _,
+            LOCKFILE_EXCLUSIVE_LOCK,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    match ret {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock_exclusive(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "file locking is not supported on this platform",
+    ))
+}
+
+/// Takes an exclusive advisory lock on `file` without blocking, returning `false` if it
+/// is held by another open file
+#[cfg(unix)]
+fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    use rustix::fs::{flock, FlockOperation};
+    match flock(file, FlockOperation::NonBlockingLockExclusive) {
+        Ok(_) => Ok(true),
+        Err(e) if e == rustix::io::Errno::WOULDBLOCK => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Takes an exclusive lock on `file` without blocking, returning `false` if it is held
+/// by another open file
+#[cfg(windows)]
+fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    // SAFETY: the handle is valid for the lifetime of `file` and `overlapped` is zeroed
+    let ret = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        LockFileEx(
+            file.as_raw_handle() as _,
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    match ret {
+        0 => match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) => Ok(false),
+            e => Err(e),
+        },
+        _ => Ok(true),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    lock_exclusive(file).map(|_| true)
+}
+
+/// Locks the staging `file` at `path` for the lifetime of an upload, returning
+/// [`Error::UploadInProgress`] if it is held by another upload
+fn lock_staged(file: &File, path: &std::path::Path) -> Result<()> {
+    match try_lock_exclusive(file) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::UploadInProgress { path: path.into() }.into()),
+        Err(source) => {
+            let path = path.into();
+            Err(Error::UnableToLockFile { source, path }.into())
+        }
+    }
+}
+
+/// Records the expiry of an object staged at `staging`, see [`LocalFileSystem::put_with_ttl`]
+fn stage_expiry(staging: &std::path::Path, expires_at: Option<&ExpiresAt>) -> Result<()> {
+    match expires_at {
+        Some(ExpiresAt(expires_at)) => {
+            write_xattr(staging, EXPIRES_AT_XATTR, &expires_at.to_string()).map_err(|source| {
+                let path = staging.into();
+                Error::UnableToSetXattr { source, path }.into()
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Removes the object at `path` along with its sidecars if it is still `file`, returning
+/// `false` if it has since been replaced or removed
+///
+/// The file is first moved aside, and restored should it turn out to have been replaced,
+/// such that an object concurrently written to `path` is never removed
+fn remove_if_unchanged(config: &Config, path: &std::path::Path, file: &File) -> Result<bool> {
+    let opened = file.metadata().map_err(|source| Error::Metadata {
+        source: source.into(),
+        path: path.to_string_lossy().to_string(),
+    })?;
+
+    let (_, aside) = new_staged_upload(path)?;
+    if let Err(source) = std::fs::rename(path, &aside) {
+        let _ = std::fs::remove_file(&aside); // Attempt to cleanup
+        return match source.kind() {
+            // Concurrently deleted
+            ErrorKind::NotFound => Ok(false),
+            _ => Err(Error::UnableToRenameFile { source }.into()),
+        };
+    }
+
+    let unchanged = metadata(&aside).map_or(false, |m| is_same_file(&m, &opened));
+    if !unchanged {
+        // Fails if yet another object has since been written, which supersedes this one
+        let _ = hard_link(&aside, path);
+    }
+    std::fs::remove_file(&aside).map_err(|source| {
+        let path = aside.clone();
+        Error::UnableToDeleteFile { source, path }
+    })?;
+    if unchanged {
+        config.remove_sidecars(path)?;
+    }
+    Ok(unchanged)
+}
+
+/// Returns the unique upload for the given path and suffix
+fn staged_upload_path(dest: &std::path::Path, suffix: &str) -> PathBuf {
+    let mut staging_path = dest.as_os_str().to_owned();
+    staging_path.push("#");
+    staging_path.push(suffix);
+    staging_path.into()
+}
+
+#[derive(Debug)]
+struct LocalUpload {
+    /// The upload state
+    state: Arc<UploadState>,
+    /// The location of the temporary file
+    src: Option<PathBuf>,
+    /// The next offset to write into the file
+    offset: u64,
+    /// The sizes of the parts written so far
+    part_sizes: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct UploadState {
+    config: Arc<Config>,
+    dest: PathBuf,
+    file: Mutex<File>,
+    /// The attributes to persist on completion, see [`Config::commit_sidecars`]
+    attributes: Attributes,
+}
+
+impl LocalUpload {
+    fn new(
+        config: Arc<Config>,
+        src: PathBuf,
+        dest: PathBuf,
+        file: File,
+        attributes: Attributes,
+    ) -> Self {
+        Self {
+            state: Arc::new(UploadState {
+                config,
+                dest,
+                file: Mutex::new(file),
+                attributes,
+            }),
+            src: Some(src),
+            offset: 0,
+            part_sizes: vec![],
+        }
+    }
+
+    /// Records parts of `sizes` about to be written, returning an error if
+    /// [`LocalFileSystem::with_enforce_s3_part_sizes`] and a part that is no longer the
+    /// last is too small
+    fn record_parts(&mut self, sizes: impl IntoIterator<Item = usize>) -> Result<()> {
+        let enforce = self.state.config.enforce_s3_part_sizes;
+        let recorded = self.part_sizes.len();
+        for size in sizes {
+            match self.part_sizes.last() {
+                Some(&last) if enforce && last < S3_MIN_PART_SIZE as u64 => {
+                    let part = self.part_sizes.len();
+                    self.part_sizes.truncate(recorded);
+                    return Err(Error::PartTooSmall {
+                        part,
+                        size: last as usize,
+                        min: S3_MIN_PART_SIZE,
+                    }
+                    .into());
+                }
+                _ => self.part_sizes.push(size as u64),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MultipartUpload for LocalUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        if let Err(e) = self.record_parts([data.content_length()]) {
+            return futures::future::ready(Err(e)).boxed();
+        }
+        let offset = self.offset;
+        self.offset += data.content_length() as u64;
+
+        let s = Arc::clone(&self.state);
+        spawn_blocking(self.state.config.io_limiter.clone(), move || {
+            let mut file = s.file.lock();
+            file.seek(SeekFrom::Start(offset)).map_err(|source| {
+                let path = s.dest.clone();
+                Error::Seek { source, path }
+            })?;
+
+            data.iter()
+                .try_for_each(|x| file.write_all(x))
+                .map_err(|source| Error::UnableToCopyDataToFile { source })?;
+
+            Ok(())
+        })
+        .boxed()
+    }
+
+    fn put_parts(&mut self, parts: Vec<PutPayload>) -> UploadPart {
+        if let Err(e) = self.record_parts(parts.iter().map(|p| p.content_length())) {
+            return futures::future::ready(Err(e)).boxed();
+        }
+        let offset = self.offset;
+        self.offset += parts.iter().map(|p| p.content_length() as u64).sum::<u64>();
+
+        // Parts are contiguous and so can be written sequentially from `offset`
+        let s = Arc::clone(&self.state);
+        spawn_blocking(self.state.config.io_limiter.clone(), move || {
+            let mut file = s.file.lock();
+            file.seek(SeekFrom::Start(offset)).map_err(|source| {
+                let path = s.dest.clone();
+                Error::Seek { source, path }
+            })?;
+
+            parts
+                .iter()
+                .flat_map(|data| data.iter())
+                .try_for_each(|x| file.write_all(x))
+                .map_err(|source| Error::UnableToCopyDataToFile { source })?;
+
+            Ok(())
+        })
+        .boxed()
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let src = self.src.take().ok_or(Error::Aborted)?;
+        #[cfg(feature = "fs-s3-etag")]
+        let part_sizes = std::mem::take(&mut self.part_sizes);
+        let s = Arc::clone(&self.state);
+        spawn_blocking(self.state.config.io_limiter.clone(), move || {
+            // Ensure no inflight writes
+            let file = s.file.lock();
+            s.config.sync_staged(&file).map_err(|source| {
+                let path = src.clone();
+                Error::UnableToSync { source, path }
+            })?;
+            #[cfg(feature = "fs-s3-etag")]
+            if s.config.etag_strategy == EtagStrategy::S3Compatible && !part_sizes.is_empty() {
+                let part_sizes = encode_part_sizes(&part_sizes);
+                let _ = write_xattr(&src, MULTIPART_XATTR, &part_sizes); // Best effort
+            }
+            s.config.commit_sidecars(&s.dest, &src, &s.attributes)?;
+            std::fs::rename(&src, &s.dest)
+                .map_err(|source| Error::UnableToRenameFile { source })?;
+            s.config.sync_parent(&s.dest)?;
+            let metadata = file.metadata().map_err(|e| Error::Metadata {
+                source: e.into(),
+                path: src.to_string_lossy().to_string(),
+            })?;
+
+            Ok(PutResult {
+                e_tag: Some(s.config.etag(&s.dest, &metadata, Some(&file))?),
+                version: None,
+            })
+        })
+        .await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        let src = self.src.take().ok_or(Error::Aborted)?;
+        spawn_blocking(self.state.config.io_limiter.clone(), move || {
+            std::fs::remove_file(&src)
+                .map_err(|source| Error::UnableToDeleteFile { source, path: src })?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl Drop for LocalUpload {
+    fn drop(&mut self) {
+        if let Some(src) = self.src.take() {
+            // Try to clean up intermediate file ignoring any error
+            match tokio::runtime::Handle::try_current() {
+                Ok(r) => drop(r.spawn_blocking(move || std::fs::remove_file(src))),
+                Err(_) => drop(std::fs::remove_file(src)),
+            };
+        }
+    }
+}
+
+pub(crate) fn chunked_stream(
+    mut file: File,
+    path: PathBuf,
+    range: Range<u64>,
+    chunk_size: usize,
+    limiter: Option<Arc<RateLimiter>>,
+) -> BoxStream<'static, Result<Bytes, super::Error>> {
+    if chunk_size == 0 {
+        // Would otherwise never make progress
+        let error = Err(Error::ZeroChunkSize.into());
+        return futures::stream::once(futures::future::ready(error)).boxed();
+    }
+
+    futures::stream::once(async move {
+        let (file, path, opened) = maybe_spawn_blocking(move || {
+            file.seek(SeekFrom::Start(range.start as _))
+                .map_err(|source| Error::Seek {
+                    source,
+                    path: path.clone(),
+                })?;
+            let opened = file.metadata().ok().map(Arc::new);
+            Ok((file, path, opened))
+        })
+        .await?;
+
+        let stream = futures::stream::try_unfold(
+            (file, path, range.end - range.start),
+            move |(mut file, path, remaining)| {
+                let opened = opened.clone();
+                let limiter = limiter.clone();
+                let to_read = remaining.min(chunk_size as u64);
+                async move {
+                    if let Some(limiter) = &limiter {
+                        limiter.acquire(to_read).await;
+                    }
+                    maybe_spawn_blocking(move || {
+                        if remaining == 0 {
+                            return Ok(None);
+                        }
+
+                        let cap = usize::try_from(to_read).map_err(|_e| Error::InvalidRange {
+                            source: InvalidGetRange::TooLarge {
+                                requested: to_read,
+                                max: usize::MAX as u64,
+                            },
+                        })?;
+                        let mut buffer = Vec::with_capacity(cap);
+                        let offset = range.end - remaining;
+                        // Once data has been returned, it must not be followed by that of a
+                        // replacement file
+                        let expected = opened.as_deref().filter(|_| offset != range.start);
+                        let reopen = || reopen_at(&path, offset, expected);
+                        let read = retry_stale(&mut file, reopen, |file| {
+                            buffer.clear();
+                            file.take(to_read).read_to_end(&mut buffer)
+                        })
+                        .map_err(|e| Error::UnableToReadBytes {
+                            source: e,
+                            path: path.clone(),
+                        })?;
+
+                        Ok(Some((buffer.into(), (file, path, remaining - read as u64))))
+                    })
+                    .await
+                }
+            },
+        );
+        Ok::<_, super::Error>(stream)
+    })
+    .try_flatten()
+    .boxed()
+}
+
+/// The number of directories skipped by the listing operations of a [`LocalFileSystem`],
+/// see [`LocalFileSystem::skipped_unreadable`]
+///
+/// Unlike the rest of [`Config`] this is not carried over to a copy, so that each store
+/// derived from another by its builder methods counts only its own skipped directories
+#[derive(Debug, Default)]
+struct SkippedCounter(AtomicUsize);
+
+impl Clone for SkippedCounter {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// The open files reused by reads of byte ranges, see [`LocalFileSystem::with_fd_cache`]
+#[derive(Debug)]
+pub(crate) struct FdCache {
+    capacity: usize,
+    /// The open files and their metadata when opened, the most recently used last
+    entries: Mutex<VecDeque<(PathBuf, Arc<File>, Metadata)>>,
+    /// The number of files opened by this cache
+    opened: AtomicUsize,
+}
+
+impl FdCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            opened: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the open file at `path` and its current metadata, calling `open` if it is
+    /// not cached or has changed since it was opened
+    fn get(
+        &self,
+        path: &PathBuf,
+        open: impl FnOnce() -> Result<(File, Metadata)>,
+    ) -> Result<(Arc<File>, Metadata)> {
+        let current = metadata(path).ok();
+        let mut entries = self.entries.lock();
+        if let Some(idx) = entries.iter().position(|(p, _, _)| p == path) {
+            let entry = entries.remove(idx).unwrap();
+            let unchanged = current
+                .as_ref()
+                .map_or(false, |current| is_unmodified(&entry.2, current));
+            if let (true, Some(current)) = (unchanged, current) {
+                let file = Arc::clone(&entry.1);
+                entries.push_back(entry);
+                return Ok((file, current));
+            }
+        }
+        drop(entries);
+
+        let (file, metadata) = open()?;
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        let file = Arc::new(file);
+
+        let mut entries = self.entries.lock();
+        entries.retain(|(p, _, _)| p != path);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((path.clone(), Arc::clone(&file), metadata.clone()));
+        Ok((file, metadata))
+    }
+
+    /// Replaces the cached `stale` file at `path` with `file`, which reopened `path` after
+    /// a read of `stale` failed with a stale file handle
+    ///
+    /// Does nothing if `file` is `stale`, or `stale` has since been evicted or replaced
+    fn replace(&self, path: &PathBuf, stale: &Arc<File>, file: &Arc<File>) {
+        if Arc::ptr_eq(stale, file) {
+            return;
+        }
+        let mut entries = self.entries.lock();
+        let idx = entries
+            .iter()
+            .position(|(p, f, _)| p == path && Arc::ptr_eq(f, stale));
+        if let Some(idx) = idx {
+            match file.metadata() {
+                Ok(metadata) => entries[idx] = (path.clone(), Arc::clone(file), metadata),
+                Err(_) => drop(entries.remove(idx)),
+            }
+        }
+    }
+}
+
+/// A file opened for reads of byte ranges, see [`Config::open_for_ranges`]
+#[derive(Debug)]
+enum RangeReader {
+    /// A file owned by this reader, read by seeking
+    Owned(File),
+    /// A file shared with the [`FdCache`], read with positioned reads
+    Cached(Arc<File>, Arc<FdCache>),
+}
+
+impl RangeReader {
+    fn file(&self) -> &File {
+        match self {
+            Self::Owned(file) => file,
+            Self::Cached(file, _) => file,
+        }
+    }
+
+    /// Returns the file of this reader, for use with positioned reads, along with the
+    /// [`FdCache`] it is shared with, if any
+    fn into_shared(self) -> (Arc<File>, Option<Arc<FdCache>>) {
+        match self {
+            Self::Owned(file) => (Arc::new(file), None),
+            Self::Cached(file, cache) => (file, Some(cache)),
+        }
+    }
+
+    fn read_range(&mut self, path: &PathBuf, range: Range<u64>) -> Result<Bytes> {
+        let mut buf = Vec::new();
+        self.read_range_into(path, range, &mut buf)?;
+        Ok(buf.into())
+    }
+
+    /// Appends `range` of the file at `path` to `buf`, returning the number of bytes read
+    ///
+    /// Leaves `buf` unchanged on error
+    fn read_range_into(
+        &mut self,
+        path: &PathBuf,
+        range: Range<u64>,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        match self {
+            Self::Owned(file) => read_range_into(file, path, range, buf),
+            Self::Cached(file, cache) => {
+                let opened = Arc::clone(file);
+                let result = read_range_at(file, path, range, buf);
+                cache.replace(path, &opened, file);
+                result
+            }
+        }
+    }
+}
+
+/// A limiter shared by the reads of a [`LocalFileSystem`], see
+/// [`LocalFileSystem::with_read_rate_limit`]
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    bytes_per_sec: u64,
+    /// The time by which the bytes acquired so far will have been paid for
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until `bytes` may be read without exceeding the limit
+    ///
+    /// This is called outside the blocking thread pool, so that waiting does not occupy
+    /// its threads
+    async fn acquire(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        let start = {
+            let mut next = self.next.lock();
+            let start = (*next).max(Instant::now());
+            *next = start + cost;
+            start
+        };
+        tokio::time::sleep_until(start.into()).await
+    }
+}
+
+/// A limit on the blocking operations of a [`LocalFileSystem`] in progress at once, see
+/// [`LocalFileSystem::with_io_concurrency_limit`]
+#[derive(Debug)]
+pub(crate) struct IoLimiter {
+    limit: usize,
+    semaphore: tokio::sync::Semaphore,
+}
+
+impl IoLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            semaphore: tokio::sync::Semaphore::new(limit),
+        }
+    }
+
+    /// Waits until an operation may start, returning a permit that allows another to
+    /// start once dropped
+    async fn acquire(&self) -> tokio::sync::SemaphorePermit<'_> {
+        // The semaphore is never closed
+        self.semaphore.acquire().await.unwrap()
+    }
+}
+
+/// Runs `f` on the blocking thread pool if available, first waiting for a permit from
+/// `limiter` if any
+async fn spawn_blocking<F, T>(limiter: Option<Arc<IoLimiter>>, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let limiter = match &limiter {
+        Some(limiter) => limiter,
+        None => return maybe_spawn_blocking(f).await,
+    };
+    let _permit = limiter.acquire().await;
+    maybe_spawn_blocking(f).await
+}
+
+/// Splits the chunks of a file into lines, see [`LocalFileSystem::lines`]
+#[derive(Debug)]
+struct LineSplitter {
+    path: PathBuf,
+    max: Option<usize>,
+    /// The bytes of the current line read so far
+    pending: Vec<u8>,
+    /// The number of lines returned so far
+    line: usize,
+    /// Whether an error has been returned, after which no further lines are returned
+    failed: bool,
+}
+
+impl LineSplitter {
+    /// Returns the lines completed by `bytes`
+    fn push(&mut self, bytes: &[u8]) -> Vec<Result<String>> {
+        let mut lines = vec![];
+        let mut remaining = bytes;
+        while let Some(idx) = remaining.iter().position(|b| *b == b'\n') {
+            if self.failed {
+                return lines;
+            }
+            self.pending.extend_from_slice(&remaining[..idx]);
+            remaining = &remaining[idx + 1..];
+            lines.push(self.take_line());
+        }
+
+        if !self.failed {
+            self.pending.extend_from_slice(remaining);
+            if let Err(e) = self.check_length() {
+                self.failed = true;
+                lines.push(Err(e));
+            }
+        }
+        lines
+    }
+
+    /// Returns the final line, if not terminated by a newline
+    fn finish(&mut self) -> Vec<Result<String>> {
+        match self.failed || self.pending.is_empty() {
+            true => vec![],
+            false => vec![self.take_line()],
+        }
+    }
+
+    fn take_line(&mut self) -> Result<String> {
+        let r = self.next_line();
+        self.failed = r.is_err();
+        r
+    }
+
+    fn next_line(&mut self) -> Result<String> {
+        self.check_length()?;
+        let mut line = std::mem::take(&mut self.pending);
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        self.line += 1;
+        String::from_utf8(line).map_err(|source| {
+            let (path, line) = (self.path.clone(), self.line);
+            Error::InvalidUtf8 { path, line, source }.into()
+        })
+    }
+
+    fn check_length(&self) -> Result<()> {
+        match self.max {
+            Some(max) if self.pending.len() > max => Err(Error::LineTooLong {
+                path: self.path.clone(),
+                line: self.line + 1,
+                max,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Appends a check to `stream` returning [`Error::Replaced`] if, once `stream` has been
+/// read to completion, `path` no longer refers to the file described by `opened`
+fn detect_replacement(
+    stream: BoxStream<'static, Result<Bytes>>,
+    path: PathBuf,
+    opened: Metadata,
+) -> BoxStream<'static, Result<Bytes>> {
+    let check = maybe_spawn_blocking(move || match metadata(&path) {
+        Ok(current) if is_same_file(&current, &opened) => Ok(()),
+        Ok(_) => Err(Error::Replaced { path }.into()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(Error::Replaced { path }.into()),
+        Err(source) => Err(Error::Metadata {
+            source: source.into(),
+            path: path.to_string_lossy().to_string(),
+        }
+        .into()),
+    });
+
+    let check =
+        futures::stream::once(check).filter_map(|r| futures::future::ready(r.err().map(Err)));
+    stream.chain(check).boxed()
+}
+
+/// Appends `range` of `file` to `buf`, returning the number of bytes read
+///
+/// Leaves `buf` unchanged on error
+fn read_range_into(
+    file: &mut File,
+    path: &PathBuf,
+    range: Range<u64>,
+    buf: &mut Vec<u8>,
+) -> Result<usize> {
+    let to_read = range_to_read(file, path, &range)?;
+
+    file.seek(SeekFrom::Start(range.start)).map_err(|source| {
+        let path = path.into();
+        Error::Seek { source, path }
+    })?;
+
+    let len = buf.len();
+    buf.reserve(to_read as usize);
+    let reopen = || {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        Ok(file)
+    };
+    let read = retry_stale(file, reopen, |file| {
+        buf.truncate(len);
+        file.take(to_read).read_to_end(buf)
+    });
+    let read = match read {
+        Ok(read) => read as u64,
+        Err(source) => {
+            buf.truncate(len);
+            let path = path.into();
+            return Err(Error::UnableToReadBytes { source, path }.into());
+        }
+    };
+
+    if read != to_read {
+        buf.truncate(len);
+        let error = Error::OutOfRange {
+            path: path.into(),
+            expected: to_read,
+            actual: read,
+        };
+
+        return Err(error.into());
+    }
+
+    Ok(read as usize)
+}
+
+/// Appends `range` of `file` to `buf` using positioned reads, returning the number of
+/// bytes read
+///
+/// Unlike [`read_range_into`] this does not modify the offset of `file`, and so may be
+/// used concurrently. Leaves `buf` unchanged on error.
+fn read_range_at(
+    file: &mut Arc<File>,
+    path: &PathBuf,
+    range: Range<u64>,
+    buf: &mut Vec<u8>,
+) -> Result<usize> {
+    let to_read = range_to_read(file, path, &range)?;
+
+    let len = buf.len();
+    let end = len + to_read as usize;
+    let reopen = || File::open(path).map(Arc::new);
+    let read = retry_stale(file, reopen, |file| {
+        buf.resize(end, 0);
+        let mut read = 0;
+        while len + read < end {
+            match read_at(file, &mut buf[len + read..], range.start + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    });
+    let read = match read {
+        Ok(read) => read as u64,
+        Err(source) => {
+            buf.truncate(len);
+            let path = path.into();
+            return Err(Error::UnableToReadBytes { source, path }.into());
+        }
+    };
+
+    if read != to_read {
+        buf.truncate(len);
+        let error = Error::OutOfRange {
+            path: path.into(),
+            expected: to_read,
+            actual: read,
+        };
+
+        return Err(error.into());
+    }
+
+    Ok(read as usize)
+}
+
+/// Returns the number of bytes of `range` of `file` to read, clamped to the file length
+fn range_to_read(file: &File, path: &std::path::Path, range: &Range<u64>) -> Result<u64> {
+    let file_metadata = file.metadata().map_err(|e| Error::Metadata {
+        source: e.into(),
+        path: path.to_string_lossy().to_string(),
+    })?;
+
+    // If none of the range is satisfiable we should error, e.g. if the start offset is beyond the
+    // extents of the file
+    let file_len = file_metadata.len();
+    if range.start >= file_len {
+        return Err(Error::InvalidRange {
+            source: InvalidGetRange::StartTooLarge {
+                requested: range.start,
+                length: file_len,
+            },
+        }
+        .into());
+    }
+
+    // Don't read past end of file
+    Ok(range.end.min(file_len) - range.start)
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Reopens the file at `path` positioned at `offset`, returning an error if `expected` is
+/// provided and the file is no longer the same, unmodified file
+fn reopen_at(path: &PathBuf, offset: u64, expected: Option<&Metadata>) -> io::Result<File> {
+    let mut file = File::open(path)?;
+    if let Some(expected) = expected {
+        if !is_unmodified(expected, &file.metadata()?) {
+            let msg = format!("{} was replaced during read", path.display());
+            return Err(io::Error::new(ErrorKind::Other, msg));
+        }
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file)
+}
+
+/// Performs `op` on `file`, retrying it up to [`STALE_HANDLE_RETRIES`] times if the file
+/// handle has gone stale, replacing `file` with one obtained by `reopen`
+///
+/// An NFS file handle becomes stale if the file is replaced by another client, which
+/// reopening the file by path resolves
+fn retry_stale<F, T>(
+    file: &mut F,
+    mut reopen: impl FnMut() -> io::Result<F>,
+    mut op: impl FnMut(&mut F) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut retries = 0;
+    loop {
+        match op(file) {
+            Err(e) if retries < STALE_HANDLE_RETRIES && is_stale(&e) => {
+                retries += 1;
+                *file = reopen()?;
+            }
+            r => return r,
+        }
+    }
+}
+
+/// Copies `from` to `to`, failing if `to` already exists, see
+/// [`LocalFileSystem::copy_if_not_exists`]
+fn copy_if_absent(config: &Config, from: PathBuf, to: PathBuf) -> Result<()> {
+    check_not_directory(&from)?;
+    if from == to {
+        check_exists(from)?;
+        let source = io::Error::new(
+            ErrorKind::AlreadyExists,
+            "source and destination are the same",
+        );
+        let path = to.to_string_lossy().to_string();
+        return Err(Error::AlreadyExists { path, source }.into());
+    }
+
+    if let Some(staged) = config.stage_reflink(&from, &to) {
+        return link_staged(staged, from, to);
+    }
+
+    loop {
+        match hard_link(&from, &to) {
+            Ok(_) => return Ok(()),
+            Err(source) if is_cross_device(&source) => {
+                let staged = config.stage_copy(&from, &to)?;
+                return link_staged(staged, from, to);
+            }
+            Err(source) => match source.kind() {
+                ErrorKind::AlreadyExists => {
+                    return Err(Error::AlreadyExists {
+                        path: to.to_str().unwrap().to_string(),
+                        source,
+                    }
+                    .into())
+                }
+                ErrorKind::NotFound => match from.exists() {
+                    true => create_parent_dirs(&to, source, config.dir_mode)?,
+                    false => return Err(Error::NotFound { path: from, source }.into()),
+                },
+                _ => return Err(Error::UnableToCopyFile { from, to, source }.into()),
+            },
+        }
+    }
+}
+
+/// Renames `from` to `to` with `renameat2(RENAME_NOREPLACE)`, failing if `to` already exists
+#[cfg(target_os = "linux")]
+fn rename_no_replace(from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+    use rustix::fs::{renameat_with, RenameFlags, CWD};
+    Ok(renameat_with(CWD, from, CWD, to, RenameFlags::NOREPLACE)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_no_replace(_from: &std::path::Path, _to: &std::path::Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "renaming without replacement is only supported on Linux",
+    ))
+}
+
+/// Returns true if `e` indicates the filesystem does not support [`rename_no_replace`]
+#[cfg(target_os = "linux")]
+fn is_rename_unsupported(e: &io::Error) -> bool {
+    use rustix::io::Errno;
+    let code = e.raw_os_error();
+    code == Some(Errno::INVAL.raw_os_error()) || code == Some(Errno::NOSYS.raw_os_error())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_rename_unsupported(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::Unsupported
+}
+
+/// Creates `to` as a hard link to `from`, failing if `to` already exists
+///
+/// On Windows, if the filesystem does not support hard links, this instead copies `from`
+/// to `to`, see [`copy_file_no_replace`]
+fn hard_link(from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+    match std::fs::hard_link(from, to) {
+        Err(e) if is_link_unsupported(&e) => copy_file_no_replace(from, to),
+        r => r,
+    }
+}
+
+/// Returns true if `e` indicates the filesystem does not support hard links
+#[cfg(windows)]
+fn is_link_unsupported(e: &io::Error) -> bool {
+    use windows_sys::Win32::Foundation::{ERROR_INVALID_FUNCTION, ERROR_NOT_SUPPORTED};
+    let code = e.raw_os_error();
+    code == Some(ERROR_INVALID_FUNCTION as i32) || code == Some(ERROR_NOT_SUPPORTED as i32)
+}
+
+#[cfg(not(windows))]
+fn is_link_unsupported(_e: &io::Error) -> bool {
+    false
+}
+
+/// Copies `from` to `to` with `CopyFileExW`, failing if `to` already exists
+#[cfg(windows)]
+fn copy_file_no_replace(from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::CopyFileExW;
+    use windows_sys::Win32::System::WindowsProgramming::COPY_FILE_FAIL_IF_EXISTS;
+
+    let wide = |p: &std::path::Path| -> Vec<u16> {
+        p.as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let (from, to) = (wide(from), wide(to));
+
+    // SAFETY: both paths are NUL-terminated, and no progress routine is provided
+    let ret = unsafe {
+        CopyFileExW(
+            from.as_ptr(),
+            to.as_ptr(),
+            None,
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            COPY_FILE_FAIL_IF_EXISTS,
+        )
+    };
+    match ret {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(windows))]
+fn copy_file_no_replace(_from: &std::path::Path, _to: &std::path::Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "copying without replacement is only supported on Windows",
+    ))
+}
+
+/// Returns true if `e` indicates an operation failed as it would cross filesystem
+/// boundaries, see [`stage_copy`]
+#[cfg(unix)]
+fn is_cross_device(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(rustix::io::Errno::XDEV.raw_os_error())
+}
+
+#[cfg(windows)]
+fn is_cross_device(e: &io::Error) -> bool {
+    use windows_sys::Win32::Foundation::ERROR_NOT_SAME_DEVICE;
+    e.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE as i32)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_cross_device(_e: &io::Error) -> bool {
+    false
+}
+
+/// Copies the contents of `from` to a new staged upload for `to`, see
+/// [`Config::staged_upload`], for use where `from` cannot be hard linked or renamed to
+/// `to` as they are on different filesystems
+///
+/// Returns the path of the staged file once its contents have been synced to disk
+fn stage_copy(config: &Config, from: &std::path::Path, to: &std::path::Path) -> Result<PathBuf> {
+    let mut source = File::open(from).map_err(|source| {
+        let path = from.into();
+        match source.kind() {
+            ErrorKind::NotFound => Error::NotFound { path, source },
+            _ => Error::UnableToOpenFile { path, source },
+        }
+    })?;
+
+    let (mut file, staged) = config.staged_upload(to)?;
+    if let Err(source) = io::copy(&mut source, &mut file).and_then(|_| file.sync_all()) {
+        let _ = std::fs::remove_file(&staged); // Attempt to clean up
+        let (from, to) = (from.into(), to.into());
+        return Err(Error::UnableToCopyFile { from, to, source }.into());
+    }
+    Ok(staged)
+}
+
+/// Sets the permissions and modification time of `to` to those of `from`
+fn copy_metadata(from: &std::path::Path, to: &std::path::Path) -> io::Result<()> {
+    let metadata = std::fs::metadata(from)?;
+    std::fs::set_permissions(to, metadata.permissions())?;
+    set_mtime(to, &metadata)
+}
+
+#[cfg(unix)]
+/// Sets the modification time of `path` to that of `metadata`, leaving its access time
+fn set_mtime(path: &std::path::Path, metadata: &Metadata) -> io::Result<()> {
+    use rustix::fs::{utimensat, AtFlags, Timespec, Timestamps, CWD, UTIME_OMIT};
+    use std::os::unix::fs::MetadataExt;
+
+    let times = Timestamps {
+        last_access: Timespec {
+            tv_sec: 0,
+            tv_nsec: UTIME_OMIT,
+        },
+        last_modification: Timespec {
+            tv_sec: metadata.mtime(),
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    };
+    Ok(utimensat(CWD, path, &times, AtFlags::empty())?)
+}
+
+#[cfg(windows)]
+/// Sets the modification time of `path` to that of `metadata`, leaving its access time
+fn set_mtime(path: &std::path::Path, metadata: &Metadata) -> io::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::FILETIME;
+    use windows_sys::Win32::Storage::FileSystem::SetFileTime;
+
+    let file = OpenOptions::new().write(true).open(path)?;
+    let mtime = metadata.last_write_time();
+    let mtime = FILETIME {
+        dwLowDateTime: mtime as u32,
+        dwHighDateTime: (mtime >> 32) as u32,
+    };
+    // SAFETY: the handle is valid for the lifetime of `file`, and null times are unchanged
+    let ret = unsafe {
+        SetFileTime(
+            file.as_raw_handle() as _,
+            std::ptr::null(),
+            std::ptr::null(),
+            &mtime,
+        )
+    };
+    match ret {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_mtime(_path: &std::path::Path, _metadata: &Metadata) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "setting modification times is not supported on this platform",
+    ))
+}
+
+/// Hard links the file `staged` for `to` by [`stage_copy`] or [`stage_reflink`] into place,
+/// failing if `to` already exists, and then removes `staged`
+///
+/// As `staged` is on the same filesystem as `to`, this preserves the atomicity of
+/// [`LocalFileSystem::copy_if_not_exists`]
+fn link_staged(staged: PathBuf, from: PathBuf, to: PathBuf) -> Result<()> {
+    let result = hard_link(&staged, &to);
+    let _ = std::fs::remove_file(&staged); // Attempt to clean up
+    result.map_err(|source| match source.kind() {
+        ErrorKind::AlreadyExists => Error::AlreadyExists {
+            path: to.to_str().unwrap().to_string(),
+            source,
+        }
+        .into(),
+        _ => Error::UnableToCopyFile { from, to, source }.into(),
+    })
+}
+
+/// Clones `from` to a new staged upload for `to`, see [`Config::staged_upload`], with a
+/// copy-on-write reflink if [`LocalFileSystem::with_copy_strategy`] is
+/// [`CopyStrategy::Reflink`], returning the path of the staged file
+///
+/// Returns `None` if the clone could not be made for any reason, including the filesystem
+/// not supporting reflinks, in which case the caller falls back to a hard link
+fn stage_reflink(config: &Config, from: &std::path::Path, to: &std::path::Path) -> Option<PathBuf> {
+    if config.copy_strategy != CopyStrategy::Reflink {
+        return None;
+    }
+    let source = File::open(from).ok()?;
+    let (file, staged) = config.staged_upload(to).ok()?;
+    match reflink(&source, &file) {
+        Ok(()) => Some(staged),
+        Err(_) => {
+            let _ = std::fs::remove_file(&staged); // Attempt to clean up
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Shares the contents of `source` with the empty file `dest` with `ioctl(FICLONE)`
+fn reflink(source: &File, dest: &File) -> io::Result<()> {
+    Ok(rustix::fs::ioctl_ficlone(dest, source)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Reflinks are not supported on this platform
+fn reflink(_source: &File, _dest: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "reflinks are not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+/// Reserves `len` bytes of disk space for `file` with `fallocate`, without changing its size
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    let flags = rustix::fs::FallocateFlags::KEEP_SIZE;
+    Ok(rustix::fs::fallocate(file, flags, 0, len)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+/// Preallocation is not supported on this platform
+fn preallocate(_file: &File, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+/// Opens the directory at `path`, to be synced making the creation of its entries durable
+fn open_dir(path: &std::path::Path) -> io::Result<Option<File>> {
+    File::open(path).map(Some)
+}
+
+#[cfg(not(unix))]
+/// Directories cannot be opened to be synced on this platform
+fn open_dir(_path: &std::path::Path) -> io::Result<Option<File>> {
+    Ok(None)
+}
+
+/// Returns true if `e` indicates a stale NFS file handle
+#[cfg(unix)]
+fn is_stale(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(rustix::io::Errno::STALE.raw_os_error())
+}
+
+#[cfg(not(unix))]
+fn is_stale(_e: &io::Error) -> bool {
+    false
+}
+
+/// Removes the contents of the directory `dir`, except for `keep` and its ancestors
+///
+/// Symlinks are removed rather than followed
+fn remove_dir_contents(dir: &std::path::Path, keep: Option<&std::path::Path>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        let removed = match keep {
+            Some(keep) if keep == path => continue,
+            Some(keep) if is_dir && keep.starts_with(&path) => {
+                remove_dir_contents(&path, Some(keep))?;
+                continue;
+            }
+            _ if is_dir => std::fs::remove_dir_all(&path),
+            _ => std::fs::remove_file(&path),
+        };
+        match removed {
+            // Concurrently deleted
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            r => r?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "fs-mmap")]
+/// Memory maps `range` of `file`, clamped to the file length `len`
+///
+/// Returns `None` if the file could not be mapped
+fn mmap_range(file: &File, len: u64, range: Range<u64>) -> Option<Bytes> {
+    use memmap2::MmapOptions;
+
+    let start = range.start.min(len);
+    let end = range.end.clamp(start, len);
+    if start == end {
+        return Some(Bytes::new());
+    }
+
+    let map_len = usize::try_from(end - start).ok()?;
+    // SAFETY: the mapping is read-only, and files are replaced atomically by renames
+    // rather than being modified in place, see LocalFileSystem::with_mmap. A file truncated
+    // by another process whilst mapped raises SIGBUS on access beyond its new length,
+    // which cannot be guarded against here and is documented on LocalFileSystem::with_mmap
+    let mmap = unsafe { MmapOptions::new().offset(start).len(map_len).map(file) }.ok()?;
+    Some(Bytes::from_owner(mmap))
+}
+
+#[cfg(not(feature = "fs-mmap"))]
+/// Memory mapping requires the `fs-mmap` feature
+fn mmap_range(_file: &File, _len: u64, _range: Range<u64>) -> Option<Bytes> {
+    None
+}
+
+fn open_file(path: &PathBuf) -> Result<(File, Metadata)> {
+    let ret = match File::open(path).and_then(|f| Ok((f.metadata()?, f))) {
+        Err(e) => Err(match e.kind() {
+            ErrorKind::NotFound => Error::NotFound {
+                path: path.clone(),
+                source: e,
+            },
+            _ => Error::UnableToOpenFile {
+                path: path.clone(),
+                source: e,
+            },
+        }),
+        Ok((metadata, file)) => match !metadata.is_dir() {
+            true => Ok((file, metadata)),
+            false => Err(Error::IsADirectory { path: path.clone() }),
+        },
+    }?;
+    Ok(ret)
+}
+
+/// Opens the file at `path` without following symlinks in `path` below `root`
+///
+/// Each directory is opened relative to its parent with `O_NOFOLLOW`, failing with
+/// `ELOOP` if it, or the file itself, is a symlink, as reported by `O_NOFOLLOW` for the
+/// file. For a directory, combined with `O_DIRECTORY`, a symlink is instead reported as
+/// `ENOTDIR`, and so is distinguished from a file by `fstatat`. The file is opened with `O_NONBLOCK`
+/// to avoid blocking on a FIFO, which has no effect on reads of regular files.
+#[cfg(unix)]
+fn open_no_follow(root: &std::path::Path, path: &std::path::Path) -> io::Result<File> {
+    use rustix::fs::{open, openat, statat, AtFlags, FileType, Mode, OFlags};
+    use rustix::io::Errno;
+
+    let relative = path.strip_prefix(root).map_err(|_| {
+        let msg = format!("{} is not within {}", path.display(), root.display());
+        io::Error::new(ErrorKind::InvalidInput, msg)
+    })?;
+
+    let flags = OFlags::RDONLY | OFlags::CLOEXEC;
+    let mut fd = open(root, flags | OFlags::DIRECTORY, Mode::empty())?;
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        let name = component.as_os_str();
+        fd = match components.peek() {
+            Some(_) => match openat(
+                &fd,
+                name,
+                flags | OFlags::NOFOLLOW | OFlags::DIRECTORY,
+                Mode::empty(),
+            ) {
+                Err(Errno::NOTDIR) => {
+                    let stat = statat(&fd, name, AtFlags::SYMLINK_NOFOLLOW)?;
+                    match FileType::from_raw_mode(stat.st_mode as _) {
+                        FileType::Symlink => return Err(Errno::LOOP.into()),
+                        _ => return Err(Errno::NOTDIR.into()),
+                    }
+                }
+                r => r?,
+            },
+            None => openat(
+                &fd,
+                name,
+                flags | OFlags::NOFOLLOW | OFlags::NONBLOCK,
+                Mode::empty(),
+            )?,
+        };
+    }
+    Ok(fd.into())
+}
+
+/// Ensures `opened`, the file and metadata obtained by opening `path`, is not stale
+///
+/// Opening a file on NFS revalidates its cached attributes, however, the file may have
+/// since been replaced by another client. This therefore checks `path` still refers to
+/// the opened file, reopening it if not, and returns freshly obtained metadata.
+fn revalidate(
+    path: &PathBuf,
+    opened: (File, Metadata),
+    open: impl Fn(&PathBuf) -> Result<(File, Metadata)>,
+) -> Result<(File, Metadata)> {
+    const MAX_ATTEMPTS: usize = 3;
+
+    let (mut file, _) = opened;
+    for _ in 0..MAX_ATTEMPTS {
+        let current = file.metadata().map_err(|source| Error::Metadata {
+            source: source.into(),
+            path: path.to_string_lossy().to_string(),
+        })?;
+
+        match metadata(path) {
+            Ok(m) if is_same_file(&m, &current) => return Ok((file, current)),
+            // The file has been replaced or removed since it was opened
+            Ok(_) | Err(_) => file = open(path)?.0,
+        }
+    }
+
+    let metadata = file.metadata().map_err(|source| Error::Metadata {
+        source: source.into(),
+        path: path.to_string_lossy().to_string(),
+    })?;
+    Ok((file, metadata))
+}
+
+#[cfg(unix)]
+/// Returns the number of bytes allocated on disk for the file, which `st_blocks`
+/// reports in 512 byte units regardless of the filesystem block size
+fn allocated_size(metadata: &Metadata) -> Option<u64> {
+    Some(std::os::unix::fs::MetadataExt::blocks(metadata) * 512)
+}
+
+#[cfg(not(unix))]
+fn allocated_size(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+/// Returns the capacity of the filesystem containing `path` with `statvfs`
+fn disk_usage(path: &std::path::Path) -> io::Result<DiskUsage> {
+    let stat = rustix::fs::statvfs(path)?;
+    Ok(DiskUsage {
+        total: stat.f_blocks * stat.f_frsize,
+        available: stat.f_bavail * stat.f_frsize,
+        used: (stat.f_blocks - stat.f_bfree) * stat.f_frsize,
+    })
+}
+
+#[cfg(windows)]
+/// Returns the capacity of the filesystem containing `path` with `GetDiskFreeSpaceExW`
+fn disk_usage(path: &std::path::Path) -> io::Result<DiskUsage> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let (mut available, mut total, mut free) = (0, 0, 0);
+
+    // SAFETY: the path is NUL-terminated, and the out pointers are valid for writes
+    let ret = unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, &mut free) };
+    match ret {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(DiskUsage {
+            total,
+            available,
+            used: total - free,
+        }),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn disk_usage(_path: &std::path::Path) -> io::Result<DiskUsage> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "disk usage is not supported on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn link_count(metadata: &Metadata) -> Option<u64> {
+    Some(std::os::unix::fs::MetadataExt::nlink(metadata))
+}
+
+#[cfg(not(unix))]
+fn link_count(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+fn last_modified(metadata: &Metadata) -> DateTime<Utc> {
+    metadata
+        .modified()
+        .expect("Modified file time should be supported on this platform")
+        .into()
+}
+
+fn get_etag(
+    path: &std::path::Path,
+    metadata: &Metadata,
+    file: Option<&File>,
+) -> io::Result<String> {
+    let inode = get_inode(path, metadata, file)?;
+
+    // Use an ETag scheme based on that used by many popular HTTP servers
+    // <https://httpd.apache.org/docs/2.2/mod/core.html#fileetag>
+    // <https://stackoverflow.com/questions/47512043/how-etags-are-generated-and-configured>
+    Ok(format!("{inode:x}-{}", size_mtime_etag(metadata)))
+}
+
+/// Returns the ETag for [`EtagStrategy::SizeMtime`]
+fn size_mtime_etag(metadata: &Metadata) -> String {
+    let size = metadata.len();
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_micros();
+    format!("{mtime:x}-{size:x}")
+}
+
+/// Computes the ETag for [`EtagStrategy::ContentSha256`] of the file at `path`
+fn sha256_etag(path: &std::path::Path) -> Result<String> {
+    Ok(format!("\"{}\"", sha256_hex(path)?))
+}
+
+/// Computes the hex-encoded SHA-256 digest of the file at `path`
+fn sha256_hex(path: &std::path::Path) -> Result<String> {
+    let mut file = File::open(path).map_err(|source| {
+        let path = path.into();
+        Error::UnableToOpenFile { source, path }
+    })?;
+
+    let mut buf = vec![0; 64 * 1024];
+    let mut sha256 = Sha256::new();
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => sha256.update(&buf[..read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(source) => {
+                let path = path.into();
+                return Err(Error::UnableToReadBytes { source, path }.into());
+            }
+        }
+    }
+    Ok(hex_encode(&sha256.finalize()))
+}
+
+#[cfg(feature = "fs-s3-etag")]
+/// Computes the content-derived ETag of the file at `path`, that of [`s3_etag`] if `s3`
+/// provides the part sizes of its multipart upload, if any
+fn content_etag(path: &std::path::Path, s3: Option<Option<&[u64]>>) -> Result<String> {
+    match s3 {
+        Some(part_sizes) => s3_etag(path, part_sizes),
+        None => sha256_etag(path),
+    }
+}
+
+#[cfg(not(feature = "fs-s3-etag"))]
+/// S3-compatible ETags require the `fs-s3-etag` feature
+fn content_etag(path: &std::path::Path, _s3: Option<Option<&[u64]>>) -> Result<String> {
+    sha256_etag(path)
+}
+
+#[cfg(feature = "fs-s3-etag")]
+/// Computes the ETag S3 would report for the file at `path` if uploaded in parts of
+/// `part_sizes`, or with a single request if `None`
+///
+/// <https://docs.aws.amazon.com/AmazonS3/latest/userguide/checking-object-integrity.html#large-object-checksums>
+fn s3_etag(path: &std::path::Path, part_sizes: Option<&[u64]>) -> Result<String> {
+    let mut file = File::open(path).map_err(|source| {
+        let path = path.into();
+        Error::UnableToOpenFile { source, path }
+    })?;
+
+    // Returns the MD5 of the next `len` bytes of the file, along with the number read
+    let mut buf = vec![0; 64 * 1024];
+    let mut read_part = |len: u64| {
+        let mut md5 = Md5::new();
+        let mut remaining = len;
+        while remaining > 0 {
+            let to_read = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+            match file.read(&mut buf[..to_read]) {
+                Ok(0) => break,
+                Ok(read) => {
+                    md5.update(&buf[..read]);
+                    remaining -= read as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(source) => {
+                    let path = path.into();
+                    return Err(Error::UnableToReadBytes { source, path });
+                }
+            }
+        }
+        Ok((md5.finalize(), len - remaining))
+    };
+
+    let part_sizes = match part_sizes {
+        Some(part_sizes) => part_sizes,
+        None => return Ok(format!("\"{}\"", hex_encode(&read_part(u64::MAX)?.0))),
+    };
+    let mut parts = Vec::with_capacity(part_sizes.len());
+    for size in part_sizes {
+        parts.push(read_part(*size)?.0);
+    }
+    // Any bytes beyond the recorded parts form a final part
+    let (rest, read) = read_part(u64::MAX)?;
+    if read > 0 {
+        parts.push(rest);
+    }
+
+    let mut md5 = Md5::new();
+    parts.iter().for_each(|part| md5.update(part));
+    Ok(format!(
+        "\"{}-{}\"",
+        hex_encode(&md5.finalize()),
+        parts.len()
+    ))
+}
+
+#[cfg(feature = "fs-s3-etag")]
+/// Encodes the `part_sizes` of a multipart upload for [`MULTIPART_XATTR`] as runs of
+/// equal sizes, e.g. `6*2,1*1` for two parts of 6 bytes followed by one of 1 byte
+fn encode_part_sizes(part_sizes: &[u64]) -> String {
+    let mut runs: Vec<(u64, usize)> = vec![];
+    for size in part_sizes {
+        match runs.last_mut() {
+            Some((last, count)) if last == size => *count += 1,
+            _ => runs.push((*size, 1)),
+        }
+    }
+    let runs: Vec<_> = runs
+        .into_iter()
+        .map(|(size, count)| format!("{size}*{count}"))
+        .collect();
+    runs.join(",")
+}
+
+#[cfg(feature = "fs-s3-etag")]
+/// Returns the part sizes recorded by [`encode_part_sizes`] for the file at `path`, or
+/// `None` if it was not written by a multipart upload
+fn multipart_part_sizes(path: &std::path::Path) -> Option<Vec<u64>> {
+    let value = read_xattr(path, MULTIPART_XATTR)?;
+    let mut part_sizes = vec![];
+    for run in value.split(',') {
+        let (size, count) = run.split_once('*')?;
+        let (size, count): (u64, usize) = (size.parse().ok()?, count.parse().ok()?);
+        part_sizes.extend(std::iter::repeat(size).take(count));
+    }
+    Some(part_sizes)
+}
+
+#[cfg(unix)]
+/// We include the inode when available to yield an ETag more resistant to collisions
+/// and as used by popular web servers such as [Apache](https://httpd.apache.org/docs/2.2/mod/core.html#fileetag)
+fn get_inode(
+    _path: &std::path::Path,
+    metadata: &Metadata,
+    _file: Option<&File>,
+) -> io::Result<u64> {
+    Ok(std::os::unix::fs::MetadataExt::ino(metadata))
+}
+
+/// On Windows the file ID, the equivalent of an inode, is only available from a handle
+/// to the file, and so this opens `path` unless `file` is already open
+#[cfg(windows)]
+fn get_inode(path: &std::path::Path, _metadata: &Metadata, file: Option<&File>) -> io::Result<u64> {
+    let (_, id) = match file {
+        Some(file) => file_id(file)?,
+        None => file_id(&open_for_info(path)?)?,
+    };
+    Ok(id)
+}
+
+#[cfg(windows)]
+/// Opens the file or directory at `path` to query its information
+fn open_for_info(path: &std::path::Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_FLAG_BACKUP_SEMANTICS;
+
+    // Directories can only be opened with FILE_FLAG_BACKUP_SEMANTICS
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
+
+#[cfg(windows)]
+/// Returns the volume serial number and file ID of the open `file`, which together
+/// uniquely identify it
+fn file_id(file: &File) -> io::Result<(u32, u64)> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    // SAFETY: the handle is valid for the lifetime of `file` and `info` is zeroed
+    let (ret, info) = unsafe {
+        let mut info: BY_HANDLE_FILE_INFORMATION = std::mem::zeroed();
+        let ret = GetFileInformationByHandle(file.as_raw_handle() as _, &mut info);
+        (ret, info)
+    };
+    match ret {
+        0 => Err(io::Error::last_os_error()),
+        _ => {
+            let id = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+            Ok((info.dwVolumeSerialNumber, id))
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+/// On platforms where an inode isn't available, fallback to just relying on size and mtime
+fn get_inode(
+    _path: &std::path::Path,
+    _metadata: &Metadata,
+    _file: Option<&File>,
+) -> io::Result<u64> {
+    Ok(0)
+}
+
+#[cfg(unix)]
+/// Returns the UTF-8 value of the extended attribute `name` of `path`, if any
+fn read_xattr(path: &std::path::Path, name: &str) -> Option<String> {
+    let value = xattr::get(path, name).ok()??;
+    String::from_utf8(value).ok()
+}
+
+#[cfg(not(unix))]
+/// Extended attributes are not supported on this platform
+fn read_xattr(_path: &std::path::Path, _name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+/// Returns the UTF-8 value of the extended attribute `name` of the open `file`, if any
+fn read_file_xattr(file: &File, name: &str) -> Option<String> {
+    let value = xattr::FileExt::get_xattr(file, name).ok()??;
+    String::from_utf8(value).ok()
+}
+
+#[cfg(not(unix))]
+/// Extended attributes are not supported on this platform
+fn read_file_xattr(_file: &File, _name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+/// Returns the names of the extended attributes of `path`
+fn list_xattrs(path: &std::path::Path) -> Vec<String> {
+    match xattr::list(path) {
+        Ok(names) => names.filter_map(|n| n.into_string().ok()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+#[cfg(not(unix))]
+/// Extended attributes are not supported on this platform
+fn list_xattrs(_path: &std::path::Path) -> Vec<String> {
+    vec![]
+}
+
+#[cfg(unix)]
+/// Sets the extended attribute `name` of `path` to `value`
+fn write_xattr(path: &std::path::Path, name: &str, value: &str) -> io::Result<()> {
+    xattr::set(path, name, value.as_bytes())
+}
+
+#[cfg(not(unix))]
+/// Extended attributes are not supported on this platform
+fn write_xattr(_path: &std::path::Path, _name: &str, _value: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    ))
+}
+
+#[cfg(unix)]
+/// Returns the ID of the device containing the file described by `metadata`
+fn device_id(metadata: &Metadata) -> Option<u64> {
+    Some(std::os::unix::fs::MetadataExt::dev(metadata))
+}
+
+#[cfg(not(unix))]
+/// The device ID is not available from [`Metadata`] on this platform
+fn device_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+/// Returns true if `a` and `b` describe the same underlying file
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+/// Returns true if `a` and `b` describe the same underlying file
+///
+/// The file ID is not available from [`Metadata`] on this platform, and so this instead
+/// compares the size and modification time
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+#[cfg(not(windows))]
+/// Returns true if `path` refers to the open `file`
+fn is_file_at(file: &File, path: &std::path::Path) -> io::Result<bool> {
+    Ok(is_same_file(&file.metadata()?, &metadata(path)?))
+}
+
+#[cfg(windows)]
+/// Returns true if `path` refers to the open `file`
+///
+/// Unlike [`is_same_file`] this compares the volume serial number and file ID, which are
+/// only available from a handle to the file
+fn is_file_at(file: &File, path: &std::path::Path) -> io::Result<bool> {
+    Ok(file_id(file)? == file_id(&open_for_info(path)?)?)
+}
+
+/// Returns the zero-byte marker object at `location` for the directory at `path` with
+/// `metadata`, or `None` if it is not empty, see [`LocalFileSystem::with_directory_markers`]
+fn marker_meta(
+    location: Path,
+    path: &std::path::Path,
+    metadata: &Metadata,
+) -> Result<Option<ObjectMeta>> {
+    let empty = std::fs::read_dir(path).and_then(|mut d| d.next().transpose());
+    match empty {
+        Ok(None) => {}
+        Ok(Some(_)) => return Ok(None),
+        // Removed since it was found
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(Error::Metadata {
+                source: e.into(),
+                path: path.to_string_lossy().to_string(),
+            }
+            .into())
+        }
+    }
+    Ok(Some(ObjectMeta {
+        location,
+        last_modified: last_modified(metadata),
+        size: 0,
+        e_tag: None,
+        version: None,
+    }))
+}
+
+/// Returns true if `a` and `b` describe the same underlying file, with the same size and
+/// modification time
+fn is_unmodified(a: &Metadata, b: &Metadata) -> bool {
+    is_same_file(a, b) && a.len() == b.len() && a.modified().ok() == b.modified().ok()
+}
+
+/// Returns the name under which `attribute` is persisted
+fn attribute_name(attribute: &Attribute) -> String {
+    let name = match attribute {
+        Attribute::ContentDisposition => "content-disposition",
+        Attribute::ContentEncoding => "content-encoding",
+        Attribute::ContentLanguage => "content-language",
+        Attribute::ContentType => "content-type",
+        Attribute::CacheControl => "cache-control",
+        Attribute::Metadata(key) => return format!("metadata.{key}"),
+    };
+    name.to_string()
+}
+
+/// Returns the [`Attribute`] persisted under `name`, see [`attribute_name`]
+fn parse_attribute_name(name: &str) -> Option<Attribute> {
+    Some(match name {
+        "content-disposition" => Attribute::ContentDisposition,
+        "content-encoding" => Attribute::ContentEncoding,
+        "content-language" => Attribute::ContentLanguage,
+        "content-type" => Attribute::ContentType,
+        "cache-control" => Attribute::CacheControl,
+        n => Attribute::Metadata(n.strip_prefix("metadata.")?.to_string().into()),
+    })
+}
+
+/// Returns the name of the extended attribute storing `attribute`
+fn attribute_xattr(attribute: &Attribute) -> String {
+    format!("{ATTRIBUTE_XATTR_PREFIX}{}", attribute_name(attribute))
+}
+
+/// Stores `attributes` in extended attributes of `path`, returning
+/// [`crate::Error::NotImplemented`] if these are not supported
+fn write_attributes(path: &std::path::Path, attributes: &Attributes) -> Result<()> {
+    for (attribute, value) in attributes {
+        if let Err(source) = write_xattr(path, &attribute_xattr(attribute), value.as_ref()) {
+            return Err(match is_unsupported(&source) {
+                true => crate::Error::NotImplemented,
+                false => Error::UnableToSetXattr {
+                    source,
+                    path: path.into(),
+                }
+                .into(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the [`Attributes`] stored in extended attributes of `path`
+fn read_attributes(path: &std::path::Path) -> Attributes {
+    let mut attributes = Attributes::new();
+    for name in list_xattrs(path) {
+        let attribute = name
+            .strip_prefix(ATTRIBUTE_XATTR_PREFIX)
+            .and_then(parse_attribute_name);
+        let attribute = match attribute {
+            Some(attribute) => attribute,
+            None => continue,
+        };
+        if let Some(value) = read_xattr(path, &name) {
+            attributes.insert(attribute, value.into());
+        }
+    }
+    attributes
+}
+
+/// Returns the path of the sidecar with `suffix` of the object at `path`, see
+/// [`AttributeStorage::Sidecar`] and [`LocalFileSystem::with_checksums`]
+fn sidecar_path(path: &std::path::Path, suffix: &str) -> PathBuf {
+    staged_upload_path(path, suffix)
+}
+
+/// Atomically replaces the sidecar with `suffix` of the object at `path` with `contents`
+fn write_sidecar_file(
+    config: &Config,
+    path: &std::path::Path,
+    suffix: &str,
+    contents: &[u8],
+) -> Result<()> {
+    // Staged as an upload to the object so as to be recognised by `is_staging_file_name`
+    let sidecar = sidecar_path(path, suffix);
+    config.write_staged(path, &sidecar, |file| file.write_all(contents), |_| Ok(()))
+}
+
+#[cfg(feature = "fs-sidecar")]
+/// Atomically replaces the attributes sidecar of the object at `path` with one storing
+/// `attributes`, removing it if there are none
+fn write_sidecar(config: &Config, path: &std::path::Path, attributes: &Attributes) -> Result<()> {
+    if attributes.is_empty() {
+        return remove_sidecar(path, SIDECAR_SUFFIX);
+    }
+
+    let json: serde_json::Map<String, serde_json::Value> = attributes
+        .iter()
+        .map(|(attribute, value)| (attribute_name(attribute), value.as_ref().into()))
+        .collect();
+    let json = serde_json::to_vec(&json)
+        .map_err(|e| Error::UnableToCopyDataToFile { source: e.into() })?;
+    write_sidecar_file(config, path, SIDECAR_SUFFIX, &json)
+}
+
+#[cfg(not(feature = "fs-sidecar"))]
+/// Attribute sidecars require the `fs-sidecar` feature
+fn write_sidecar(
+    _config: &Config,
+    _path: &std::path::Path,
+    _attributes: &Attributes,
+) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(feature = "fs-sidecar")]
+/// Returns the [`Attributes`] stored in the sidecar of the object at `path`, if any
+fn read_sidecar(path: &std::path::Path) -> Attributes {
+    let json = std::fs::read(sidecar_path(path, SIDECAR_SUFFIX))
+        .ok()
+        .and_then(|b| serde_json::from_slice::<serde_json::Map<_, _>>(&b).ok())
+        .unwrap_or_default();
+
+    json.into_iter()
+        .filter_map(|(name, value)| match value {
+            serde_json::Value::String(value) => Some((parse_attribute_name(&name)?, value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the checksum recorded in the sidecar of the object at `path`, if any
+fn read_checksum(path: &std::path::Path) -> Option<String> {
+    let checksum = std::fs::read_to_string(sidecar_path(path, CHECKSUM_SUFFIX)).ok()?;
+    Some(checksum.trim().to_string())
+}
+
+/// Removes the sidecar with `suffix` of the object at `path`, if any
+fn remove_sidecar(path: &std::path::Path, suffix: &str) -> Result<()> {
+    let path = sidecar_path(path, suffix);
+    match std::fs::remove_file(&path) {
+        Err(source) if source.kind() != ErrorKind::NotFound => {
+            Err(Error::UnableToDeleteFile { source, path }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns true if `e` indicates an operation is not supported by the filesystem
+fn is_unsupported(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(rustix::io::Errno::NOTSUP.raw_os_error()) {
+        return true;
+    }
+    e.kind() == ErrorKind::Unsupported
+}
+
+/// Convert walkdir results and converts not-found errors into `None`.
+/// Convert broken symlinks to `None`.
+fn convert_walkdir_result(
+    res: std::result::Result<DirEntry, walkdir::Error>,
+) -> Result<Option<DirEntry>> {
+    match res {
+        Ok(entry) => {
+            // To check for broken symlink: call symlink_metadata() - it does not traverse symlinks);
+            // if ok: check if entry is symlink; and try to read it by calling metadata().
+            match symlink_metadata(entry.path()) {
+                Ok(attr) => {
+                    if attr.is_symlink() {
+                        let target_metadata = metadata(entry.path());
+                        match target_metadata {
+                            Ok(_) => {
+                                // symlink is valid
+                                Ok(Some(entry))
+                            }
+                            Err(_) => {
+                                // this is a broken symlink, return None
+                                Ok(None)
+                            }
+                        }
+                    } else {
+                        Ok(Some(entry))
+                    }
+                }
+                Err(_) => Ok(None),
+            }
+        }
+
+        Err(walkdir_err) => match walkdir_err.io_error() {
+            Some(io_err) => match io_err.kind() {
+                ErrorKind::NotFound => Ok(None),
+                _ => Err(Error::UnableToWalkDir {
+                    source: walkdir_err,
+                }
+                .into()),
+            },
+            None => Err(Error::UnableToWalkDir {
+                source: walkdir_err,
+            }
+            .into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::TryStreamExt;
+    use tempfile::TempDir;
+
+    #[cfg(target_family = "unix")]
+    use tempfile::NamedTempFile;
+
+    use crate::integration::*;
+
+    use super::*;
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn file_test() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        put_get_delete_list(&integration).await;
+        get_opts(&integration).await;
+        list_uses_directories_correctly(&integration).await;
+        list_with_delimiter(&integration).await;
+        rename_and_copy(&integration).await;
+        copy_if_not_exists(&integration).await;
+        copy_rename_nonexistent_object(&integration).await;
+        stream_get(&integration).await;
+        put_opts(&integration, true).await;
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_non_tokio() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        futures::executor::block_on(async move {
+            put_get_delete_list(&integration).await;
+            list_uses_directories_correctly(&integration).await;
+            list_with_delimiter(&integration).await;
+
+            // Can't use stream_get test as WriteMultipart uses a tokio JoinSet
+            let p = Path::from("manual_upload");
+            let mut upload = integration.put_multipart(&p).await.unwrap();
+            upload.put_part("123".into()).await.unwrap();
+            upload.put_part("45678".into()).await.unwrap();
+            let r = upload.complete().await.unwrap();
+
+            let get = integration.get(&p).await.unwrap();
+            assert_eq!(get.meta.e_tag.as_ref().unwrap(), r.e_tag.as_ref().unwrap());
+            let actual = get.bytes().await.unwrap();
+            assert_eq!(actual.as_ref(), b"12345678");
+        });
+    }
+
+    #[tokio::test]
+    async fn creates_dir_if_not_present() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let location = Path::from("nested/file/test_file");
+
+        let data = Bytes::from("arbitrary data");
+
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        let read_data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(&*read_data, data);
+    }
+
+    #[tokio::test]
+    async fn unknown_length() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let location = Path::from("some_file");
+
+        let data = Bytes::from("arbitrary data");
+
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        let read_data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(&*read_data, data);
+    }
+
+    #[tokio::test]
+    async fn range_request_start_beyond_end_of_file() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let location = Path::from("some_file");
+
+        let data = Bytes::from("arbitrary data");
+
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        integration
+            .get_range(&location, 100..200)
+            .await
+            .expect_err("Should error with start range beyond end of file");
+    }
+
+    #[tokio::test]
+    async fn range_request_beyond_end_of_file() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let location = Path::from("some_file");
+
+        let data = Bytes::from("arbitrary data");
+
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        let read_data = integration.get_range(&location, 0..100).await.unwrap();
+        assert_eq!(&*read_data, data);
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    // Fails on github actions runner (which runs the tests as root)
+    #[ignore]
+    async fn bubble_up_io_errors() {
+        use std::{fs::set_permissions, os::unix::prelude::PermissionsExt};
+
+        let root = TempDir::new().unwrap();
+
+        // make non-readable
+        let metadata = root.path().metadata().unwrap();
+        let mut permissions = metadata.permissions();
+        permissions.set_mode(0o000);
+        set_permissions(root.path(), permissions).unwrap();
+
+        let store = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let mut stream = store.list(None);
+        let mut any_err = false;
+        while let Some(res) = stream.next().await {
+            if res.is_err() {
+                any_err = true;
+            }
+        }
+        assert!(any_err);
+
+        // `list_with_delimiter
+        assert!(store.list_with_delimiter(None).await.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn skip_unreadable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_skip_unreadable(true);
+        assert!(integration.config().skip_unreadable);
+
+        integration
+            .put(&Path::from("a/1"), "data".into())
+            .await
+            .unwrap();
+        integration
+            .put(&Path::from("b/2"), "data".into())
+            .await
+            .unwrap();
+        let unreadable = root.path().join("b");
+        std::fs::set_permissions(&unreadable, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        /// Restores the permissions of the directory, allowing the [`TempDir`] to remove it
+        struct Restore(PathBuf);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let _ = std::fs::set_permissions(&self.0, std::fs::Permissions::from_mode(0o755));
+            }
+        }
+        let _restore = Restore(unreadable.clone());
+
+        if std::fs::read_dir(&unreadable).is_ok() {
+            eprintln!("Skipping check - directory can be read without permission");
+            return;
+        }
+
+        let list = flatten_list_stream(&integration, None).await.unwrap();
+        assert_eq!(list, vec![Path::from("a/1")]);
+        assert_eq!(integration.skipped_unreadable(), 1);
+
+        let prefix = Path::from("b");
+        let r = integration
+            .list_with_delimiter(Some(&prefix))
+            .await
+            .unwrap();
+        assert!(r.objects.is_empty());
+        assert_eq!(integration.skipped_unreadable(), 2);
+
+        // A copy of the configuration does not share the count
+        let config = Config::clone(&integration.config);
+        assert_eq!(config.skipped_unreadable.0.load(Ordering::Relaxed), 0);
+        assert_eq!(integration.skipped_unreadable(), 2);
+
+        // Errors are returned by default
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        assert!(flatten_list_stream(&integration, None).await.is_err());
+        assert_eq!(integration.skipped_unreadable(), 0);
+    }
+
+    const NON_EXISTENT_NAME: &str = "nonexistentname";
+
+    #[tokio::test]
+    async fn get_nonexistent_location() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let location = Path::from(NON_EXISTENT_NAME);
+
+        let err = get_nonexistent_object(&integration, Some(location))
+            .await
+            .unwrap_err();
+        if let crate::Error::NotFound { path, source } = err {
+            let source_variant = source.downcast_ref::<std::io::Error>();
+            assert!(
+                matches!(source_variant, Some(std::io::Error { .. }),),
+                "got: {source_variant:?}"
+            );
+            assert!(path.ends_with(NON_EXISTENT_NAME), "{}", path);
+        } else {
+            panic!("unexpected error type: {err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn root() {
+        let integration = LocalFileSystem::new();
+
+        let canonical = std::path::Path::new("Cargo.toml").canonicalize().unwrap();
+        let url = Url::from_directory_path(&canonical).unwrap();
+        let path = Path::parse(url.path()).unwrap();
+
+        let roundtrip = integration.path_to_filesystem(&path).unwrap();
+
+        // Needed as on Windows canonicalize returns extended length path syntax
+        // C:\Users\circleci -> \\?\C:\Users\circleci
+        let roundtrip = roundtrip.canonicalize().unwrap();
+
+        assert_eq!(roundtrip, canonical);
+
+        integration.head(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "windows")]
+    async fn test_list_root() {
+        let fs = LocalFileSystem::new();
+        let r = fs.list_with_delimiter(None).await.unwrap_err().to_string();
+
+        assert!(
+            r.contains("Unable to convert URL \"file:///\" to filesystem path"),
+            "{}",
+            r
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_list_root() {
+        let fs = LocalFileSystem::new();
+        fs.list_with_delimiter(None).await.unwrap();
+    }
+
+    #[cfg(target_family = "unix")]
+    async fn check_list(integration: &LocalFileSystem, prefix: Option<&Path>, expected: &[&str]) {
+        let result: Vec<_> = integration.list(prefix).try_collect().await.unwrap();
+
+        let mut strings: Vec<_> = result.iter().map(|x| x.location.as_ref()).collect();
+        strings.sort_unstable();
+        assert_eq!(&strings, expected)
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn test_symlink() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let subdir = root.path().join("a");
+        std::fs::create_dir(&subdir).unwrap();
+        let file = subdir.join("file.parquet");
+        std::fs::write(file, "test").unwrap();
+
+        check_list(&integration, None, &["a/file.parquet"]).await;
+        integration
+            .head(&Path::from("a/file.parquet"))
+            .await
+            .unwrap();
+
+        // Follow out of tree symlink
+        let other = NamedTempFile::new().unwrap();
+        std::os::unix::fs::symlink(other.path(), root.path().join("test.parquet")).unwrap();
+
+        // Should return test.parquet even though out of tree
+        check_list(&integration, None, &["a/file.parquet", "test.parquet"]).await;
+
+        // Can fetch test.parquet
+        integration.head(&Path::from("test.parquet")).await.unwrap();
+
+        // Follow in tree symlink
+        std::os::unix::fs::symlink(&subdir, root.path().join("b")).unwrap();
+        check_list(
+            &integration,
+            None,
+            &["a/file.parquet", "b/file.parquet", "test.parquet"],
+        )
+        .await;
+        check_list(&integration, Some(&Path::from("b")), &["b/file.parquet"]).await;
+
+        // Can fetch through symlink
+        integration
+            .head(&Path::from("b/file.parquet"))
+            .await
+            .unwrap();
+
+        // Ignore broken symlink
+        std::os::unix::fs::symlink(root.path().join("foo.parquet"), root.path().join("c")).unwrap();
+
+        check_list(
+            &integration,
+            None,
+            &["a/file.parquet", "b/file.parquet", "test.parquet"],
+        )
+        .await;
+
+        let mut r = integration.list_with_delimiter(None).await.unwrap();
+        r.common_prefixes.sort_unstable();
+        assert_eq!(r.common_prefixes.len(), 2);
+        assert_eq!(r.common_prefixes[0].as_ref(), "a");
+        assert_eq!(r.common_prefixes[1].as_ref(), "b");
+        assert_eq!(r.objects.len(), 1);
+        assert_eq!(r.objects[0].location.as_ref(), "test.parquet");
+
+        let r = integration
+            .list_with_delimiter(Some(&Path::from("a")))
+            .await
+            .unwrap();
+        assert_eq!(r.common_prefixes.len(), 0);
+        assert_eq!(r.objects.len(), 1);
+        assert_eq!(r.objects[0].location.as_ref(), "a/file.parquet");
+
+        // Deleting a symlink doesn't delete the source file
+        integration
+            .delete(&Path::from("test.parquet"))
+            .await
+            .unwrap();
+        assert!(other.path().exists());
+
+        check_list(&integration, None, &["a/file.parquet", "b/file.parquet"]).await;
+
+        // Deleting through a symlink deletes both files
+        integration
+            .delete(&Path::from("b/file.parquet"))
+            .await
+            .unwrap();
+
+        check_list(&integration, None, &[]).await;
+
+        // Adding a file through a symlink creates in both paths
+        integration
+            .put(&Path::from("b/file.parquet"), vec![0, 1, 2].into())
+            .await
+            .unwrap();
+
+        check_list(&integration, None, &["a/file.parquet", "b/file.parquet"]).await;
+    }
+
+    #[tokio::test]
+    async fn invalid_path() {
+        let root = TempDir::new().unwrap();
+        let root = root.path().join("🙀");
+        std::fs::create_dir(root.clone()).unwrap();
+
+        // Invalid paths supported above root of store
+        let integration = LocalFileSystem::new_with_prefix(root.clone()).unwrap();
+
+        let directory = Path::from("directory");
+        let object = directory.child("child.txt");
+        let data = Bytes::from("arbitrary");
+        integration.put(&object, data.clone().into()).await.unwrap();
+        integration.head(&object).await.unwrap();
+        let result = integration.get(&object).await.unwrap();
+        assert_eq!(result.bytes().await.unwrap(), data);
+
+        flatten_list_stream(&integration, None).await.unwrap();
+        flatten_list_stream(&integration, Some(&directory))
+            .await
+            .unwrap();
+
+        let result = integration
+            .list_with_delimiter(Some(&directory))
+            .await
+            .unwrap();
+        assert_eq!(result.objects.len(), 1);
+        assert!(result.common_prefixes.is_empty());
+        assert_eq!(result.objects[0].location, object);
+
+        let emoji = root.join("💀");
+        std::fs::write(emoji, "foo").unwrap();
+
+        // Can list illegal file
+        let mut paths = flatten_list_stream(&integration, None).await.unwrap();
+        paths.sort_unstable();
+
+        assert_eq!(
+            paths,
+            vec![
+                Path::parse("directory/child.txt").unwrap(),
+                Path::parse("💀").unwrap()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_hides_incomplete_uploads() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("some_file");
+
+        let data = PutPayload::from("arbitrary data");
+        let mut u1 = integration.put_multipart(&location).await.unwrap();
+        u1.put_part(data.clone()).await.unwrap();
+
+        let mut u2 = integration.put_multipart(&location).await.unwrap();
+        u2.put_part(data).await.unwrap();
+
+        let list = flatten_list_stream(&integration, None).await.unwrap();
+        assert_eq!(list.len(), 0);
+
+        assert_eq!(
+            integration
+                .list_with_delimiter(None)
+                .await
+                .unwrap()
+                .objects
+                .len(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_with_offset() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let root_path = root.path();
+        for i in 0..5 {
+            let filename = format!("test{i}.parquet");
+            let file = root_path.join(filename);
+            std::fs::write(file, "test").unwrap();
+        }
+        let filter_str = "test";
+        let filter = String::from(filter_str);
+        let offset_str = filter + "1";
+        let offset = Path::from(offset_str.clone());
+
+        // Use list_with_offset to retrieve files
+        let res = integration.list_with_offset(None, &offset);
+        let offset_paths: Vec<_> = res.map_ok(|x| x.location).try_collect().await.unwrap();
+        let mut offset_files: Vec<_> = offset_paths
+            .iter()
+            .map(|x| String::from(x.filename().unwrap()))
+            .collect();
+
+        // Check result with direct filesystem read
+        let files = fs::read_dir(root_path).unwrap();
+        let filtered_files = files
+            .filter_map(Result::ok)
+            .filter_map(|d| {
+                d.file_name().to_str().and_then(|f| {
+                    if f.contains(filter_str) {
+                        Some(String::from(f))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut expected_offset_files: Vec<_> = filtered_files
+            .iter()
+            .filter(|s| **s > offset_str)
+            .cloned()
+            .collect();
+
+        fn do_vecs_match<T: PartialEq>(a: &[T], b: &[T]) -> bool {
+            let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
+            matching == a.len() && matching == b.len()
+        }
+
+        offset_files.sort();
+        expected_offset_files.sort();
+
+        // println!("Expected Offset Files: {:?}", expected_offset_files);
+        // println!("Actual Offset Files: {:?}", offset_files);
+
+        assert_eq!(offset_files.len(), expected_offset_files.len());
+        assert!(do_vecs_match(&expected_offset_files, &offset_files));
+    }
+
+    #[tokio::test]
+    async fn filesystem_filename_with_percent() {
+        let temp_dir = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(temp_dir.path()).unwrap();
+        let filename = "L%3ABC.parquet";
+
+        std::fs::write(temp_dir.path().join(filename), "foo").unwrap();
+
+        let res: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].location.as_ref(), filename);
+
+        let res = integration.list_with_delimiter(None).await.unwrap();
+        assert_eq!(res.objects.len(), 1);
+        assert_eq!(res.objects[0].location.as_ref(), filename);
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn decode_percent_filenames() {
+        let temp_dir = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(temp_dir.path())
+            .unwrap()
+            .with_decode_percent_filenames(true);
+        assert!(integration.config().decode_percent_filenames);
+
+        std::fs::write(temp_dir.path().join("L%3ABC.parquet"), "foo").unwrap();
+        std::fs::write(temp_dir.path().join("a%2Fb"), "bar").unwrap();
+
+        let mut res: Vec<_> = flatten_list_stream(&integration, None).await.unwrap();
+        res.sort();
+        // Only `%3A` and `%25` are decoded
+        assert_eq!(
+            res,
+            vec![Path::from("L:BC.parquet"), Path::parse("a%2Fb").unwrap()]
+        );
+
+        let location = Path::from("L:BC.parquet");
+        let read = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(read.as_ref(), b"foo");
+
+        // Colons are encoded when writing, as on Windows
+        let location = Path::from("x:y");
+        integration.put(&location, "baz".into()).await.unwrap();
+        assert_eq!(
+            std::fs::read(temp_dir.path().join("x%3Ay")).unwrap(),
+            b"baz"
+        );
+        let res = integration.list_with_delimiter(None).await.unwrap();
+        assert!(res.objects.iter().any(|o| o.location == location));
+
+        // Every location written lists unchanged
+        let cases = [
+            "p%3Aq", "p%3aq", "p%25q", "p%q", "p%2Fq", "p%20q:%", "100%", "%",
+        ];
+        for case in cases {
+            let location = Path::parse(format!("round/{case}")).unwrap();
+            integration.put(&location, case.into()).await.unwrap();
+            let read = integration.get(&location).await.unwrap().bytes().await;
+            assert_eq!(read.unwrap(), case.as_bytes());
+        }
+        let round = Path::from("round");
+        let mut listed: Vec<_> = integration
+            .list(Some(&round))
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect()
+            .await
+            .unwrap();
+        listed.sort();
+        let mut expected: Vec<_> = cases.iter().map(|c| format!("round/{c}")).collect();
+        expected.sort();
+        assert_eq!(listed, expected);
+    }
+
+    #[tokio::test]
+    async fn join_tricky_roots() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["with space", "per%cent", "%20literal", "a%2Fb", "ünïcödé"] {
+            let root = temp_dir.path().join(name);
+            std::fs::create_dir(&root).unwrap();
+            let integration = LocalFileSystem::new_with_prefix(&root).unwrap();
+
+            let location = Path::from("sub/dir/file.txt");
+            let path = integration.path_to_filesystem(&location).unwrap();
+            assert_eq!(path, root.join("sub").join("dir").join("file.txt"));
+
+            // Parts are joined verbatim in their encoded form
+            let location = Path::parse("a b/c%25d/e%2Ff/ü").unwrap();
+            let path = integration.path_to_filesystem(&location).unwrap();
+            assert_eq!(path, root.join("a b").join("c%25d").join("e%2Ff").join("ü"));
+
+            integration.put(&location, "data".into()).await.unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), b"data");
+            let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+            assert_eq!(listed.len(), 1);
+            assert_eq!(listed[0].location, location);
+
+            // An unprefixed store joins the same paths
+            let absolute = Path::from_absolute_path(&path).unwrap();
+            let path = LocalFileSystem::new()
+                .path_to_filesystem(&absolute)
+                .unwrap();
+            assert_eq!(path, root.join("a b").join("c%25d").join("e%2Ff").join("ü"));
+        }
+    }
+
+    #[tokio::test]
+    async fn relative_paths() {
+        LocalFileSystem::new_with_prefix(".").unwrap();
+        LocalFileSystem::new_with_prefix("..").unwrap();
+        LocalFileSystem::new_with_prefix("../..").unwrap();
+
+        let integration = LocalFileSystem::new();
+        let path = Path::from_filesystem_path(".").unwrap();
+        integration.list_with_delimiter(Some(&path)).await.unwrap();
+    }
+
+    #[test]
+    fn test_valid_path() {
+        let cases = [
+            ("foo#123/test.txt", true),
+            ("foo#123/test#23.txt", true),
+            ("foo#123/test#34", false),
+            ("foo😁/test#34", false),
+            ("foo/test#😁34", true),
+            // Only the suffix following the first `#` is considered
+            ("foo/test#bar#34", true),
+            ("foo/test#34#bar", true),
+            ("foo/test#attrs", true),
+        ];
+
+        let integration = LocalFileSystem::new();
+        for (case, expected) in cases {
+            let path = Path::parse(case).unwrap();
+            assert_eq!(integration.config.is_valid_file_path(&path), expected);
+        }
+
+        // Sidecars are only reserved when in use
+        #[cfg(feature = "fs-sidecar")]
+        {
+            let integration = integration.with_attribute_storage(AttributeStorage::Sidecar);
+            let cases = [
+                ("foo/test#34", false),
+                ("foo/test#attrs", false),
+                ("foo/test#attrs.txt", true),
+                ("foo/test.attrs", true),
+            ];
+            for (case, expected) in cases {
+                let path = Path::parse(case).unwrap();
+                assert_eq!(integration.config.is_valid_file_path(&path), expected);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_intermediate_files() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let a = Path::parse("foo#123/test.txt").unwrap();
+        integration.put(&a, "test".into()).await.unwrap();
+
+        let list = flatten_list_stream(&integration, None).await.unwrap();
+        assert_eq!(list, vec![a.clone()]);
+
+        std::fs::write(root.path().join("bar#123"), "test").unwrap();
+
+        // Should ignore file
+        let list = flatten_list_stream(&integration, None).await.unwrap();
+        assert_eq!(list, vec![a.clone()]);
+
+        let b = Path::parse("bar#123").unwrap();
+        let err = integration.get(&b).await.unwrap_err().to_string();
+        assert_eq!(err, "Generic LocalFileSystem error: Filenames containing trailing '/#\\d+/', '#attrs' or '#sha256' are not supported: bar#123");
+
+        let c = Path::parse("foo#123.txt").unwrap();
+        integration.put(&c, "test".into()).await.unwrap();
+
+        let mut list = flatten_list_stream(&integration, None).await.unwrap();
+        list.sort_unstable();
+        assert_eq!(list, vec![c, a]);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "windows")]
+    async fn filesystem_filename_with_colon() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let path = Path::parse("file%3Aname.parquet").unwrap();
+        let location = Path::parse("file:name.parquet").unwrap();
+
+        integration.put(&location, "test".into()).await.unwrap();
+        let list = flatten_list_stream(&integration, None).await.unwrap();
+        assert_eq!(list, vec![path.clone()]);
+
+        let result = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(result, Bytes::from("test"));
+    }
+
+    #[tokio::test]
+    async fn delete_dirs_automatically() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_automatic_cleanup(true);
+        let location = Path::from("nested/file/test_file");
+        let data = Bytes::from("arbitrary data");
+
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        let read_data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+
+        assert_eq!(&*read_data, data);
+        assert!(fs::read_dir(root.path()).unwrap().count() > 0);
+        integration.delete(&location).await.unwrap();
+        assert!(fs::read_dir(root.path()).unwrap().count() == 0);
+    }
+
+    #[tokio::test]
+    async fn delete_dirs_automatically_concurrent_put() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_automatic_cleanup(true);
+
+        // Deleting the only file in a directory removes the directories above it, which
+        // races with writes creating a file in those same directories
+        let worker = |name: &'static str| {
+            let integration = &integration;
+            async move {
+                let location = Path::from_iter(["a", "b", "c", "d", name]);
+                for _ in 0..200 {
+                    integration.put(&location, "data".into()).await?;
+                    integration.delete(&location).await?;
+                }
+                Ok::<_, crate::Error>(())
+            }
+        };
+        let (a, b) = futures::join!(worker("a"), worker("b"));
+        a.unwrap();
+        b.unwrap();
+        assert!(!root.path().join("a").exists());
+    }
+
+    #[tokio::test]
+    async fn increment_counter() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("counters/sequence");
+
+        assert_eq!(integration.increment(&location, 5).await.unwrap(), 5);
+        assert_eq!(integration.increment(&location, 2).await.unwrap(), 7);
+
+        let data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"7");
+
+        integration.put(&location, "foo".into()).await.unwrap();
+        let err = integration.increment(&location, 1).await.unwrap_err();
+        assert!(err.to_string().contains("valid integer"), "{err}");
+
+        integration
+            .put(&location, u64::MAX.to_string().into())
+            .await
+            .unwrap();
+        let err = integration.increment(&location, 1).await.unwrap_err();
+        assert!(err.to_string().contains("overflow"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn storage_class() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("file");
+        let key = Attribute::Metadata(STORAGE_CLASS_ATTRIBUTE.into());
+
+        integration.put(&location, "data".into()).await.unwrap();
+        let result = integration.get(&location).await.unwrap();
+        assert_eq!(result.attributes.get(&key).unwrap().as_ref(), "STANDARD");
+
+        let integration = integration.with_storage_class("GLACIER");
+        let options = GetOptions {
+            head: true,
+            ..Default::default()
+        };
+        let result = integration.get_opts(&location, options).await.unwrap();
+        assert_eq!(result.attributes.get(&key).unwrap().as_ref(), "GLACIER");
+        let result = integration.get(&location).await.unwrap();
+        assert_eq!(result.attributes.get(&key).unwrap().as_ref(), "GLACIER");
+
+        #[cfg(target_os = "linux")]
+        {
+            let path = integration.path_to_filesystem(&location).unwrap();
+            xattr::set(&path, STORAGE_CLASS_XATTR, b"DEEP_ARCHIVE").unwrap();
+            let result = integration.get(&location).await.unwrap();
+            assert_eq!(
+                result.attributes.get(&key).unwrap().as_ref(),
+                "DEEP_ARCHIVE"
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn attributes() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let attributes = Attributes::from_iter([
+            (Attribute::CacheControl, "max-age=604800"),
+            (
+                Attribute::ContentDisposition,
+                r#"attachment; filename="test.html""#,
+            ),
+            (Attribute::ContentEncoding, "gzip"),
+            (Attribute::ContentLanguage, "en-US"),
+            (Attribute::ContentType, "text/html; charset=utf-8"),
+            (Attribute::Metadata("test_key".into()), "test_value"),
+        ]);
+
+        let location = Path::from("attributes");
+        let err = integration
+            .put_opts(&location, "foo".into(), attributes.clone().into())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotImplemented), "{err}");
+
+        let integration = integration.with_attributes(true);
+        let storage = integration.config().attributes;
+        assert_eq!(storage, AttributeStorage::ExtendedAttributes);
+        let assert_attributes = |r: &GetResult| {
+            for (attribute, value) in &attributes {
+                assert_eq!(r.attributes.get(attribute), Some(value), "{attribute:?}");
+            }
+        };
+
+        integration
+            .put_opts(&location, "foo".into(), attributes.clone().into())
+            .await
+            .unwrap();
+        assert_attributes(&integration.get(&location).await.unwrap());
+
+        // Attributes are replaced by subsequent writes
+        integration.put(&location, "bar".into()).await.unwrap();
+        let r = integration.get(&location).await.unwrap();
+        assert!(r.attributes.get(&Attribute::ContentType).is_none());
+
+        let opts = attributes.clone().into();
+        let mut upload = integration
+            .put_multipart_opts(&location, opts)
+            .await
+            .unwrap();
+        upload.put_part("foo".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert_attributes(&integration.get(&location).await.unwrap());
+
+        // Attributes are preserved by copies
+        let copy = Path::from("copy");
+        integration.copy(&location, &copy).await.unwrap();
+        assert_attributes(&integration.get(&copy).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn derived_attributes() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("data.JSON");
+        integration.put(&location, "{}".into()).await.unwrap();
+
+        let content_length = Attribute::Metadata(CONTENT_LENGTH_ATTRIBUTE.into());
+        let last_modified = Attribute::Metadata(LAST_MODIFIED_ATTRIBUTE.into());
+        let r = integration.get(&location).await.unwrap();
+        assert!(r.attributes.get(&content_length).is_none());
+        assert!(r.attributes.get(&last_modified).is_none());
+        assert!(r.attributes.get(&Attribute::ContentType).is_none());
+
+        let integration = integration.with_derived_attributes(true);
+        assert!(integration.config().derived_attributes);
+
+        // Derived from the whole object, regardless of the range requested
+        let opts = GetOptions {
+            range: Some((0..1).into()),
+            ..Default::default()
+        };
+        let r = integration.get_opts(&location, opts).await.unwrap();
+        assert_eq!(r.attributes.get(&content_length).unwrap().as_ref(), "2");
+        let value = r.attributes.get(&last_modified).unwrap();
+        assert!(value.ends_with(" GMT"), "{value:?}");
+        let parsed = DateTime::parse_from_rfc2822(value).unwrap();
+        assert_eq!(parsed.timestamp(), r.meta.last_modified.timestamp());
+        let content_type = r.attributes.get(&Attribute::ContentType).unwrap();
+        assert_eq!(content_type.as_ref(), "application/json");
+
+        let unknown = Path::from("data.unknown");
+        integration.put(&unknown, "{}".into()).await.unwrap();
+        let r = integration.get(&unknown).await.unwrap();
+        assert!(r.attributes.get(&Attribute::ContentType).is_none());
+        assert!(r.attributes.get(&content_length).is_some());
+
+        // Stored attributes take precedence
+        #[cfg(feature = "fs-sidecar")]
+        {
+            let integration = integration.with_attribute_storage(AttributeStorage::Sidecar);
+            let attributes = Attributes::from_iter([(Attribute::ContentType, "text/plain")]);
+            integration
+                .put_opts(&location, "{}".into(), attributes.into())
+                .await
+                .unwrap();
+            let r = integration.get(&location).await.unwrap();
+            let content_type = r.attributes.get(&Attribute::ContentType).unwrap();
+            assert_eq!(content_type.as_ref(), "text/plain");
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "fs-sidecar")]
+    async fn content_type_from_extension() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_content_type_from_extension(true)
+            .with_attribute_storage(AttributeStorage::Sidecar);
+        assert!(integration.config().content_type_from_extension);
+
+        let location = Path::from("foo.json");
+        integration.put(&location, "{}".into()).await.unwrap();
+        let r = integration.get(&location).await.unwrap();
+        let content_type = r.attributes.get(&Attribute::ContentType).unwrap();
+        assert_eq!(content_type.as_ref(), "application/json");
+        // Only the content type is inferred
+        let content_length = Attribute::Metadata(CONTENT_LENGTH_ATTRIBUTE.into());
+        assert!(r.attributes.get(&content_length).is_none());
+
+        // Stored attributes take precedence
+        let location = Path::from("bar.json");
+        let attributes = Attributes::from_iter([(Attribute::ContentType, "text/plain")]);
+        integration
+            .put_opts(&location, "{}".into(), attributes.into())
+            .await
+            .unwrap();
+        let r = integration.get(&location).await.unwrap();
+        let content_type = r.attributes.get(&Attribute::ContentType).unwrap();
+        assert_eq!(content_type.as_ref(), "text/plain");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "fs-sidecar")]
+    async fn sidecar_attributes() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_attribute_storage(AttributeStorage::Sidecar);
+        let attributes = Attributes::from_iter([
+            (Attribute::ContentType, "text/html; charset=utf-8"),
+            (Attribute::Metadata("test_key".into()), "test_value"),
+        ]);
+        let assert_attributes = |r: &GetResult| {
+            for (attribute, value) in &attributes {
+                assert_eq!(r.attributes.get(attribute), Some(value), "{attribute:?}");
+            }
+        };
+
+        let location = Path::from("dir/file.parquet");
+        let sidecar = root.path().join("dir/file.parquet#attrs");
+        integration
+            .put_opts(&location, "foo".into(), attributes.clone().into())
+            .await
+            .unwrap();
+        assert!(sidecar.exists());
+        assert_attributes(&integration.get(&location).await.unwrap());
+
+        // The sidecar is not visible to listing, nor can it be addressed directly
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].location, location);
+
+        let dir = Path::from("dir");
+        let listed = integration.list_with_delimiter(Some(&dir)).await.unwrap();
+        assert_eq!(listed.objects.len(), 1);
+        assert_eq!(listed.objects[0].location, location);
+        assert!(listed.common_prefixes.is_empty());
+
+        let err = integration
+            .get(&Path::parse("dir/file.parquet#attrs").unwrap())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("are not supported"), "{err}");
+
+        // Attributes are replaced by subsequent writes
+        integration.put(&location, "bar".into()).await.unwrap();
+        let r = integration.get(&location).await.unwrap();
+        assert!(r.attributes.get(&Attribute::ContentType).is_none());
+        assert!(!sidecar.exists());
+
+        let opts = attributes.clone().into();
+        let mut upload = integration
+            .put_multipart_opts(&location, opts)
+            .await
+            .unwrap();
+        upload.put_part("foo".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert_attributes(&integration.get(&location).await.unwrap());
+
+        // The sidecar is replaced before the object
+        std::fs::remove_file(&sidecar).unwrap();
+        std::fs::create_dir(&sidecar).unwrap();
+        let err = integration.put(&location, "baz".into()).await.unwrap_err();
+        assert!(matches!(err, crate::Error::Generic { .. }), "{err}");
+        let r = integration.get(&location).await.unwrap();
+        assert_eq!(r.bytes().await.unwrap().as_ref(), b"foo");
+        std::fs::remove_dir(&sidecar).unwrap();
+        let opts = attributes.clone().into();
+        integration
+            .put_opts(&location, "foo".into(), opts)
+            .await
+            .unwrap();
+
+        // Attributes of an existing object are retained by a failed create
+        let opts = PutOptions::from(PutMode::Create);
+        let err = integration.put_opts(&location, "baz".into(), opts).await;
+        assert!(matches!(err, Err(crate::Error::AlreadyExists { .. })));
+        assert_attributes(&integration.get(&location).await.unwrap());
+
+        // Sidecars are carried over by copies and renames
+        let copied = Path::from("copied");
+        integration.copy(&location, &copied).await.unwrap();
+        assert_attributes(&integration.get(&copied).await.unwrap());
+        integration.delete(&copied).await.unwrap();
+        integration
+            .copy_if_not_exists(&location, &copied)
+            .await
+            .unwrap();
+        assert_attributes(&integration.get(&copied).await.unwrap());
+        integration.delete(&copied).await.unwrap();
+
+        let renamed = Path::from("renamed");
+        integration.rename(&location, &renamed).await.unwrap();
+        assert_attributes(&integration.get(&renamed).await.unwrap());
+        assert!(!sidecar.exists());
+        integration
+            .rename_if_not_exists(&renamed, &location)
+            .await
+            .unwrap();
+        assert_attributes(&integration.get(&location).await.unwrap());
+        assert!(!root.path().join("renamed#attrs").exists());
+
+        // Copying an object without attributes removes those of the destination
+        integration.put(&copied, "bar".into()).await.unwrap();
+        integration.copy(&copied, &location).await.unwrap();
+        let r = integration.get(&location).await.unwrap();
+        assert!(r.attributes.get(&Attribute::ContentType).is_none());
+        assert!(!sidecar.exists());
+        integration.delete(&copied).await.unwrap();
+
+        // Deleting the object removes its sidecar
+        let opts = attributes.clone().into();
+        integration
+            .put_opts(&location, "foo".into(), opts)
+            .await
+            .unwrap();
+        assert!(sidecar.exists());
+        integration.delete(&location).await.unwrap();
+        assert!(!sidecar.exists());
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert!(listed.is_empty());
+
+        // Without sidecars, files named like them are ordinary objects
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::parse("dir/file.parquet#attrs").unwrap();
+        integration.put(&location, "data".into()).await.unwrap();
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].location, location);
+    }
+
+    #[tokio::test]
+    async fn resume_multipart() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("a/file");
+
+        // Uploads that are in progress cannot be resumed
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload.put_part("foo".into()).await.unwrap();
+        let err = integration
+            .resume_multipart(&location, "1")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("in progress"), "{err}");
+        upload.abort().await.unwrap();
+
+        // Simulate a crash, which does not clean up the staging file
+        std::fs::write(root.path().join("a/file#1"), "foobar").unwrap();
+
+        let report = integration.verify(None).await.unwrap();
+        assert_eq!(report.staging_files, [root.path().join("a/file#1")]);
+        assert!(integration.head(&location).await.is_err());
+
+        let mut upload = integration.resume_multipart(&location, "1").await.unwrap();
+        upload.put_part("baz".into()).await.unwrap();
+        let err = integration
+            .resume_multipart(&location, "1")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("in progress"), "{err}");
+        upload.complete().await.unwrap();
+
+        let data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"foobarbaz");
+        assert!(integration.verify(None).await.unwrap().is_empty());
+
+        let err = integration
+            .resume_multipart(&location, "1")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+        let err = integration
+            .resume_multipart(&location, "../x")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not supported"), "{err}");
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn multipart_size_hint() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("file");
+
+        let mut opts = PutMultipartOptions::default();
+        opts.extensions.insert(SizeHint(1 << 20));
+        let mut upload = integration
+            .put_multipart_opts(&location, opts)
+            .await
+            .unwrap();
+        upload.put_part(vec![1; 1000].into()).await.unwrap();
+        upload.put_part(vec![2; 24].into()).await.unwrap();
+
+        // The space is reserved without changing the size of the staged file
+        let staged = std::fs::metadata(root.path().join("file#1")).unwrap();
+        assert_eq!(staged.len(), 1024);
+        assert!(staged.blocks() * 512 >= 1 << 20, "{}", staged.blocks());
+        upload.complete().await.unwrap();
+
+        let meta = integration.head(&location).await.unwrap();
+        assert_eq!(meta.size, 1024);
+        let data = integration.get(&location).await.unwrap().bytes().await;
+        let data = data.unwrap();
+        assert_eq!(&data[..1000], &[1; 1000]);
+        assert_eq!(&data[1000..], &[2; 24]);
+    }
+
+    #[tokio::test]
+    async fn new_with_prefix_create() {
+        let root = TempDir::new().unwrap();
+        let prefix = root.path().join("a/b/c");
+        assert!(LocalFileSystem::new_with_prefix(&prefix).is_err());
+
+        let integration = LocalFileSystem::new_with_prefix_create(&prefix).unwrap();
+        assert!(prefix.is_dir());
+        let location = Path::from("file");
+        integration.put(&location, "data".into()).await.unwrap();
+        assert!(prefix.join("file").is_file());
+
+        // Existing directories are reused
+        LocalFileSystem::new_with_prefix_create(&prefix).unwrap();
+        assert!(prefix.join("file").is_file());
+
+        let err = LocalFileSystem::new_with_prefix_create(prefix.join("file")).unwrap_err();
+        assert!(err.to_string().contains("Unable to create dir"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn delete_prefix() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        for location in ["data/a", "data/b/c", "data/b/d/e", "other/a"] {
+            let location = Path::from(location);
+            integration.put(&location, "foo".into()).await.unwrap();
+        }
+
+        let mut inside = integration
+            .put_multipart(&Path::from("data/b/f"))
+            .await
+            .unwrap();
+        inside.put_part("foo".into()).await.unwrap();
+        let mut outside = integration
+            .put_multipart(&Path::from("other/b"))
+            .await
+            .unwrap();
+        outside.put_part("foo".into()).await.unwrap();
+
+        // Uploads staged within the prefix, but to other locations, are unaffected
+        let staging = root.path().join("data/staging");
+        std::fs::create_dir(&staging).unwrap();
+        let staged = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_staging_dir(&staging)
+            .unwrap();
+        let mut other = staged.put_multipart(&Path::from("other/c")).await.unwrap();
+        other.put_part("foo".into()).await.unwrap();
+
+        staged.delete_prefix(&Path::from("data")).await.unwrap();
+        assert!(!root.path().join("data/b").exists());
+        assert!(inside.complete().await.is_err());
+        outside.complete().await.unwrap();
+        other.complete().await.unwrap();
+
+        let listed: BTreeSet<_> = integration
+            .list(None)
+            .map_ok(|m| m.location.to_string())
+            .try_collect()
+            .await
+            .unwrap();
+        let expected = ["other/a", "other/b", "other/c"].map(String::from);
+        assert_eq!(listed, BTreeSet::from(expected));
+
+        // Deleting a prefix that does not exist succeeds
+        integration
+            .delete_prefix(&Path::from("data/b"))
+            .await
+            .unwrap();
+        integration
+            .delete_prefix(&Path::from("missing"))
+            .await
+            .unwrap();
+
+        let err = integration
+            .delete_prefix(&Path::from("other/a"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Not a directory"), "{err}");
+
+        // The whole store cannot be deleted
+        let err = integration
+            .delete_prefix(&Path::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("empty prefix"), "{err}");
+        assert!(root.path().join("other/a").exists());
+
+        // Nor the whole filesystem
+        let err = LocalFileSystem::new()
+            .delete_prefix(&Path::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("empty prefix"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn staging_dir() {
+        let root = TempDir::new().unwrap();
+        let staging = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_staging_dir(staging.path())
+            .unwrap();
+        let staging_dir = std::fs::canonicalize(staging.path()).unwrap();
+        assert_eq!(integration.config().staging_dir, Some(staging_dir));
+        let staged = || std::fs::read_dir(staging.path()).unwrap().count();
+
+        let location = Path::from("a/b/object");
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload.put_part("foo".into()).await.unwrap();
+
+        // The part is staged within the staging directory, and not visible at the destination
+        let entries = || {
+            let mut entries: Vec<_> = std::fs::read_dir(staging.path())
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect();
+            entries.sort();
+            entries
+        };
+        let path = integration.path_to_filesystem(&location).unwrap();
+        let staging_path = integration.config.staging_path(&path, "1");
+        let staging_dir = integration.config().staging_dir;
+        assert_eq!(staging_path.parent(), staging_dir.as_deref());
+        let staged_name = staging_path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(entries(), [staged_name]);
+        assert!(!root.path().join("a/b/object").exists());
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert!(listed.is_empty());
+
+        // Uploads to other locations with the same file name are staged separately
+        let other = Path::from("c/object");
+        let mut other_upload = integration.put_multipart(&other).await.unwrap();
+        other_upload.put_part("other".into()).await.unwrap();
+        assert_eq!(entries().len(), 2);
+        other_upload.abort().await.unwrap();
+        let other_path = integration.path_to_filesystem(&other).unwrap();
+        std::fs::write(integration.config.staging_path(&other_path, "1"), "other").unwrap();
+        let mut other_upload = integration.resume_multipart(&other, "1").await.unwrap();
+        other_upload.complete().await.unwrap();
+        let data = integration.get(&other).await.unwrap().bytes().await;
+        assert_eq!(data.unwrap().as_ref(), b"other");
+        assert_eq!(entries(), [staged_name]);
+
+        upload.complete().await.unwrap();
+        assert_eq!(staged(), 0);
+        let data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"foo");
+
+        integration.put(&location, "bar".into()).await.unwrap();
+        let opts = PutMode::Create.into();
+        let err = integration.put_opts(&location, "baz".into(), opts).await;
+        assert!(matches!(err, Err(crate::Error::AlreadyExists { .. })));
+        assert_eq!(staged(), 0);
+        let data = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"bar");
+
+        let err = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_staging_dir(root.path().join("missing"))
+            .unwrap_err();
+        assert!(err.to_string().contains("Unable to canonicalize"), "{err}");
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn staging_dir_different_filesystem() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = TempDir::new().unwrap();
+        let staging = match TempDir::new_in("/dev/shm") {
+            Ok(staging) => staging,
+            Err(_) => return,
+        };
+        let dev = |p: &std::path::Path| std::fs::metadata(p).unwrap().dev();
+        if dev(root.path()) == dev(staging.path()) {
+            return;
+        }
+
+        let err = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_staging_dir(staging.path())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("not on the same filesystem"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn durable_writes() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let syncs = || integration.config.syncs.load(Ordering::Relaxed);
+        let location = Path::from("dir/wal");
+
+        integration.put(&location, "data".into()).await.unwrap();
+        assert_eq!(syncs(), 0);
+
+        let integration = integration.with_durable_writes(true);
+        assert!(integration.config().durable_writes);
+        let syncs = || integration.config.syncs.load(Ordering::Relaxed);
+        let before = syncs();
+
+        // Both the staged file and the directory it is moved into are synced
+        integration.put(&location, "data".into()).await.unwrap();
+        assert_eq!(syncs(), before + 2);
+
+        let opts = PutMode::Create.into();
+        integration
+            .put_opts(&Path::from("dir/created"), "data".into(), opts)
+            .await
+            .unwrap();
+        assert_eq!(syncs(), before + 4);
+
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload.put_part("data".into()).await.unwrap();
+        assert_eq!(syncs(), before + 4);
+        upload.complete().await.unwrap();
+        assert_eq!(syncs(), before + 6);
+
+        // A failed write is not synced into place
+        let opts = PutMode::Create.into();
+        let err = integration.put_opts(&location, "data".into(), opts).await;
+        assert!(matches!(err, Err(crate::Error::AlreadyExists { .. })));
+        assert_eq!(syncs(), before + 7);
+
+        // Copies sync the directory they are linked into
+        let copied = Path::from("dir/copied");
+        integration.copy(&location, &copied).await.unwrap();
+        assert_eq!(syncs(), before + 8);
+        let created = Path::from("dir/created_copy");
+        integration
+            .copy_if_not_exists(&location, &created)
+            .await
+            .unwrap();
+        assert_eq!(syncs(), before + 9);
+
+        // Renames also sync the directory moved from, if different
+        let renamed = Path::from("other/renamed");
+        integration.rename(&copied, &renamed).await.unwrap();
+        assert_eq!(syncs(), before + 11);
+        let moved = Path::from("other/moved");
+        integration
+            .rename_if_not_exists(&renamed, &moved)
+            .await
+            .unwrap();
+        assert_eq!(syncs(), before + 12);
+
+        // As are counters, and the checksum sidecars written alongside objects
+        integration
+            .increment(&Path::from("counter"), 1)
+            .await
+            .unwrap();
+        assert_eq!(syncs(), before + 14);
+        let integration = integration.with_checksums(true);
+        let syncs = || integration.config.syncs.load(Ordering::Relaxed);
+        integration.put(&location, "data".into()).await.unwrap();
+        assert_eq!(syncs(), before + 18);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn copy_strategy() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        assert_eq!(integration.config().copy_strategy, CopyStrategy::HardLink);
+        let integration = integration.with_copy_strategy(CopyStrategy::Reflink);
+        assert_eq!(integration.config().copy_strategy, CopyStrategy::Reflink);
+
+        let (src, copy, other) = (Path::from("src"), Path::from("copy"), Path::from("other"));
+        integration.put(&src, "data".into()).await.unwrap();
+        integration.copy(&src, &copy).await.unwrap();
+        integration.copy_if_not_exists(&src, &other).await.unwrap();
+        let err = integration.copy_if_not_exists(&src, &other).await;
+        assert!(matches!(err, Err(crate::Error::AlreadyExists { .. })));
+
+        integration.put(&src, "updated".into()).await.unwrap();
+        for location in [&copy, &other] {
+            let data = integration.get(location).await.unwrap().bytes().await;
+            assert_eq!(data.unwrap().as_ref(), b"data");
+        }
+
+        // Determine whether the filesystem of the store supports reflinks
+        let probe = root.path().join("probe");
+        std::fs::write(&probe, "probe").unwrap();
+        let cloned = root.path().join("cloned");
+        let supported = reflink(
+            &File::open(&probe).unwrap(),
+            &File::create(&cloned).unwrap(),
+        );
+        let supported = supported.is_ok();
+        std::fs::remove_file(probe).unwrap();
+        std::fs::remove_file(cloned).unwrap();
+
+        integration.copy(&src, &copy).await.unwrap();
+        let path = integration.path_to_filesystem(&copy).unwrap();
+        let src_path = integration.path_to_filesystem(&src).unwrap();
+        let nlink = std::fs::metadata(&path).unwrap().nlink();
+        let mut file = OpenOptions::new().write(true).open(src_path).unwrap();
+        file.write_all(b"UPDATED").unwrap();
+        let data = integration.get(&copy).await.unwrap().bytes().await;
+        match supported {
+            // A clone is unaffected by in-place modification
+            true => {
+                assert_eq!(nlink, 1);
+                assert_eq!(data.unwrap().as_ref(), b"updated");
+            }
+            // Otherwise this falls back to a hard link, sharing the modification
+            false => {
+                assert_eq!(nlink, 2);
+                assert_eq!(data.unwrap().as_ref(), b"UPDATED");
+            }
+        }
+
+        // No staging files are left behind
+        let entries = std::fs::read_dir(root.path()).unwrap().count();
+        assert_eq!(entries, 3);
+    }
+
+    #[test]
+    fn stage_copy() {
+        let root = TempDir::new().unwrap();
+        let from = root.path().join("from");
+        std::fs::write(&from, "data").unwrap();
+
+        let to = root.path().join("a/b/to");
+        let config = &LocalFileSystem::new().config;
+        let staged = super::stage_copy(config, &from, &to).unwrap();
+        let name = staged.file_name().unwrap().to_str().unwrap();
+        assert!(is_staging_file_name(name), "{name}");
+        assert_eq!(staged.parent(), to.parent());
+        assert_eq!(std::fs::read(&staged).unwrap(), b"data");
+
+        // Staged within the staging directory, if configured
+        let staging = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new()
+            .with_staging_dir(staging.path())
+            .unwrap();
+        let staged = super::stage_copy(&integration.config, &from, &to).unwrap();
+        let staging_dir = std::fs::canonicalize(staging.path()).unwrap();
+        assert_eq!(staged.parent(), Some(staging_dir.as_path()));
+        assert_eq!(std::fs::read(&staged).unwrap(), b"data");
+
+        let err = super::stage_copy(config, &root.path().join("missing"), &to).unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+
+        #[cfg(unix)]
+        {
+            let exdev = rustix::io::Errno::XDEV.raw_os_error();
+            assert!(is_cross_device(&io::Error::from_raw_os_error(exdev)));
+            assert!(!is_cross_device(&io::Error::from(ErrorKind::NotFound)));
+        }
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn copy_file_no_replace() {
+        let root = TempDir::new().unwrap();
+        let to = root.path().join("to");
+
+        // Concurrent copies to the same destination, as used by `copy_if_not_exists` and
+        // `PutMode::Create` where hard links are unsupported, succeed exactly once
+        let sources: Vec<_> = (0..8)
+            .map(|i| {
+                let from = root.path().join(format!("from{i}"));
+                std::fs::write(&from, i.to_string()).unwrap();
+                from
+            })
+            .collect();
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = sources
+                .iter()
+                .map(|from| s.spawn(|| super::copy_file_no_replace(from, &to)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let winner = results.iter().position(|r| r.is_ok()).unwrap();
+        for (i, result) in results.iter().enumerate().filter(|(i, _)| *i != winner) {
+            let err = result.as_ref().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::AlreadyExists, "{i}: {err}");
+        }
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), winner.to_string());
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn file_and_dir_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new().unwrap();
+        // Modes that a typical umask of 0o022 would otherwise mask
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_file_mode(0o660)
+            .with_dir_mode(0o770);
+        assert_eq!(integration.config().file_mode, Some(0o660));
+        assert_eq!(integration.config().dir_mode, Some(0o770));
+        let mode = |path: &str| {
+            let metadata = std::fs::metadata(root.path().join(path)).unwrap();
+            metadata.permissions().mode() & 0o777
+        };
+
+        let location = Path::from("a/b/file");
+        integration.put(&location, "data".into()).await.unwrap();
+        assert_eq!(mode("a/b/file"), 0o660);
+        assert_eq!(mode("a/b"), 0o770);
+        assert_eq!(mode("a"), 0o770);
+
+        let mut upload = integration
+            .put_multipart(&Path::from("a/c/file"))
+            .await
+            .unwrap();
+        upload.put_part("data".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert_eq!(mode("a/c/file"), 0o660);
+        assert_eq!(mode("a/c"), 0o770);
+
+        integration
+            .copy(&location, &Path::from("d/copy"))
+            .await
+            .unwrap();
+        assert_eq!(mode("d"), 0o770);
+
+        // Existing directories are unchanged
+        std::fs::create_dir(root.path().join("e")).unwrap();
+        std::fs::set_permissions(root.path().join("e"), PermissionsExt::from_mode(0o700)).unwrap();
+        integration
+            .put(&Path::from("e/file"), "data".into())
+            .await
+            .unwrap();
+        assert_eq!(mode("e"), 0o700);
+        assert_eq!(mode("e/file"), 0o660);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn preserve_metadata() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let root = TempDir::new().unwrap();
+        let from = root.path().join("from");
+        std::fs::write(&from, "data").unwrap();
+        std::fs::set_permissions(&from, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let time = nix::sys::time::TimeVal::new(1_000_000, 0);
+        nix::sys::stat::utimes(&from, &time, &time).unwrap();
+
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let to = root.path().join("to");
+        let staged = integration.config.stage_copy(&from, &to).unwrap();
+        let metadata = std::fs::metadata(&staged).unwrap();
+        assert_ne!(metadata.mtime(), 1_000_000);
+
+        let integration = integration.with_preserve_metadata(true);
+        assert!(integration.config().preserve_metadata);
+        let staged = integration.config.stage_copy(&from, &to).unwrap();
+        let metadata = std::fs::metadata(&staged).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        assert_eq!(metadata.mtime(), 1_000_000);
+        assert_eq!(std::fs::read(&staged).unwrap(), b"data");
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn cross_filesystem_copy() {
+        use std::os::unix::fs::MetadataExt;
+
+        // /dev/shm is typically a tmpfs mount distinct from the temporary directory
+        let src = TempDir::new().unwrap();
+        let dst = match TempDir::new_in("/dev/shm") {
+            Ok(dst) => dst,
+            Err(_) => return,
+        };
+        let dev = |p: &std::path::Path| std::fs::metadata(p).unwrap().dev();
+        if dev(src.path()) == dev(dst.path()) {
+            return;
+        }
+
+        let integration = LocalFileSystem::new();
+        let location =
+            |root: &TempDir, name: &str| Path::from_absolute_path(root.path().join(name)).unwrap();
+        let (from, to) = (location(&src, "from"), location(&dst, "dir/to"));
+        integration.put(&from, "data".into()).await.unwrap();
+
+        integration.copy(&from, &to).await.unwrap();
+        let data = integration.get(&to).await.unwrap().bytes().await.unwrap();
+        assert_eq!(data.as_ref(), b"data");
+
+        let err = integration
+            .copy_if_not_exists(&from, &to)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::AlreadyExists { .. }), "{err}");
+
+        let other = location(&dst, "other");
+        integration.copy_if_not_exists(&from, &other).await.unwrap();
+        let data = integration
+            .get(&other)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"data");
+
+        let renamed = location(&dst, "renamed");
+        integration.rename(&from, &renamed).await.unwrap();
+        let data = integration
+            .get(&renamed)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"data");
+        let err = integration.head(&from).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+
+        // No staging files are left behind
+        let mut names: Vec<_> = WalkDir::new(dst.path())
+            .min_depth(1)
+            .into_iter()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["dir", "other", "renamed", "to"]);
+
+        // The source mode and modification time are preserved if requested
+        let integration = integration.with_preserve_metadata(true);
+        let (from, to) = (location(&src, "private"), location(&dst, "private"));
+        let from_path = src.path().join("private");
+        std::fs::write(&from_path, "data").unwrap();
+        let permissions = std::os::unix::fs::PermissionsExt::from_mode(0o600);
+        std::fs::set_permissions(&from_path, permissions).unwrap();
+        let time = nix::sys::time::TimeVal::new(1_000_000, 0);
+        nix::sys::stat::utimes(&from_path, &time, &time).unwrap();
+
+        integration.copy(&from, &to).await.unwrap();
+        let metadata = std::fs::metadata(dst.path().join("private")).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        assert_eq!(metadata.mtime(), 1_000_000);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "fs-mmap")]
+    async fn mmap_get_range() {
+        let root = TempDir::new().unwrap();
+        let buffered = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let mapped = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_mmap(true);
+
+        let location = Path::from("data");
+        let data: Vec<u8> = (0..10_000u32).map(|x| x as u8).collect();
+        buffered.put(&location, data.into()).await.unwrap();
+
+        for range in [0..10_000, 1..2, 4095..4097, 5000..9999] {
+            let expected = buffered.get_range(&location, range.clone()).await.unwrap();
+            let actual = mapped.get_range(&location, range).await.unwrap();
+            assert_eq!(actual, expected);
+        }
+
+        // Over-long ranges are clamped to the file length, as for buffered reads
+        let expected = buffered.get_range(&location, 9000..10_000).await.unwrap();
+        let actual = mapped.get_range(&location, 9000..20_000).await.unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(mapped.config().mmap_threshold, Some(0));
+
+        let err = mapped
+            .get_range(&location, 20_000..30_000)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("range was invalid"), "{err}");
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "fs-mmap")]
+    async fn mmap_threshold() {
+        let root = TempDir::new().unwrap();
+        let buffered = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let mapped = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_mmap_threshold(1024);
+        assert_eq!(mapped.config().mmap_threshold, Some(1024));
+
+        let location = Path::from("data");
+        let data: Vec<u8> = (0..3 * 1024 * 1024u32).map(|x| (x % 251) as u8).collect();
+        buffered.put(&location, data.clone().into()).await.unwrap();
+
+        let len = data.len() as u64;
+        for range in [
+            100..len - 5,
+            0..len,
+            5..10,
+            4095..8193,
+            len - 2000..len + 2000,
+        ] {
+            let end = range.end.min(len) as usize;
+            let expected = &data[range.start as usize..end];
+            let actual = mapped.get_range(&location, range.clone()).await.unwrap();
+            assert_eq!(actual.as_ref(), expected, "{range:?}");
+        }
+
+        let ranges = [0..2048, 1024..4096, 10..20, 1_000_000..1_002_000];
+        let actual = mapped.get_ranges(&location, &ranges).await.unwrap();
+        let expected = buffered.get_ranges(&location, &ranges).await.unwrap();
+        assert_eq!(actual, expected);
+
+        let err = mapped
+            .get_range(&location, len..len + 2000)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("range was invalid"), "{err}");
+    }
+
+    #[test]
+    #[cfg(all(target_family = "unix", feature = "fs-mmap"))]
+    fn mmap_fifo() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().join("fifo");
+        nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU).unwrap();
+
+        // Opening for both reading and writing does not block on a writer
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        assert!(mmap_range(&file, 10, 0..10).is_none());
+
+        let config = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_mmap_threshold(0)
+            .config;
+        let metadata = file.metadata().unwrap();
+        let mapped = config.mmap_large_range(&file, &path, &metadata, &(0..10));
+        assert!(mapped.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rename_if_not_exists() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let from = Path::from("from");
+        let to = Path::from("a/b/to");
+        integration.put(&from, "data".into()).await.unwrap();
+
+        integration.rename_if_not_exists(&from, &to).await.unwrap();
+        assert!(!integration.exists(&from).await.unwrap());
+        let read = integration.get(&to).await.unwrap().bytes().await.unwrap();
+        assert_eq!(read.as_ref(), b"data");
+
+        integration.put(&from, "other".into()).await.unwrap();
+        let err = integration
+            .rename_if_not_exists(&from, &to)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::AlreadyExists { .. }), "{err}");
+        // Neither file is modified
+        let read = integration.get(&to).await.unwrap().bytes().await.unwrap();
+        assert_eq!(read.as_ref(), b"data");
+        assert!(integration.exists(&from).await.unwrap());
+
+        let err = integration
+            .rename_if_not_exists(&Path::from("missing"), &Path::from("other"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+        assert!(!integration.exists(&Path::from("other")).await.unwrap());
+
+        let err = integration
+            .rename_if_not_exists(&from, &from)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::AlreadyExists { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn copy_onto_self() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("file");
+        let data = Bytes::from("arbitrary data");
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        integration.copy(&location, &location).await.unwrap();
+        integration.rename(&location, &location).await.unwrap();
+        let err = integration
+            .copy_if_not_exists(&location, &location)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::AlreadyExists { .. }), "{err}");
+
+        let read = integration
+            .get(&location)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(read, data);
+        // No staging files are left behind
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+
+        let missing = Path::from("missing");
+        let err = integration.copy(&missing, &missing).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn read_repair_from_replica() {
+        let root = TempDir::new().unwrap();
+        let mirror = TempDir::new().unwrap();
+        let primary = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_replica(mirror.path())
+            .unwrap();
+        let replica = LocalFileSystem::new_with_prefix(mirror.path())
+            .unwrap()
+            .with_checksums(true);
+
+        // Primary present, mirror is not read
+        let present = Path::from("present");
+        primary.put(&present, "primary".into()).await.unwrap();
+        replica.put(&present, "mirror".into()).await.unwrap();
+        let read = primary.get(&present).await.unwrap().bytes().await.unwrap();
+        assert_eq!(read.as_ref(), b"primary");
+
+        // Primary missing, served from mirror and repaired
+        let missing = Path::from("nested/missing");
+        replica.put(&missing, "mirror".into()).await.unwrap();
+        let read = primary.get(&missing).await.unwrap().bytes().await.unwrap();
+        assert_eq!(read.as_ref(), b"mirror");
+        let repaired = std::fs::read(root.path().join("nested/missing")).unwrap();
+        assert_eq!(repaired, b"mirror");
+
+        let range = primary.get_range(&missing, 1..3).await.unwrap();
+        assert_eq!(range.as_ref(), b"ir");
+
+        // Missing from both
+        let err = primary.get(&Path::from("absent")).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+
+        // Primary corrupt, served from mirror and repaired
+        let primary = primary.with_checksums(true);
+        let corrupt = Path::from("corrupt");
+        primary.put(&corrupt, "mirror".into()).await.unwrap();
+        replica.put(&corrupt, "mirror".into()).await.unwrap();
+        std::fs::write(root.path().join("corrupt"), "mirrxr").unwrap();
+        let read = primary.get(&corrupt).await.unwrap().bytes().await.unwrap();
+        assert_eq!(read.as_ref(), b"mirror");
+        let repaired = std::fs::read(root.path().join("corrupt")).unwrap();
+        assert_eq!(repaired, b"mirror");
+
+        // Primary and mirror corrupt, the mirror's checksum is not replaced
+        std::fs::write(root.path().join("corrupt"), "mirrxr").unwrap();
+        std::fs::write(mirror.path().join("corrupt"), "mxrror").unwrap();
+        let err = primary.get(&corrupt).await.unwrap_err();
+        let mirror_path = mirror.path().join("corrupt");
+        assert!(
+            err.to_string().contains(&mirror_path.display().to_string()),
+            "{err}"
+        );
+        let primary_contents = std::fs::read(root.path().join("corrupt")).unwrap();
+        assert_eq!(primary_contents, b"mirrxr");
+        let expected = std::fs::read_to_string(mirror.path().join("corrupt#sha256")).unwrap();
+        let actual = std::fs::read_to_string(root.path().join("corrupt#sha256")).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn checksums() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_checksums(true);
+        assert!(integration.config().checksums);
 
-        let stream = futures::stream::try_unfold(
-            (file, path, range.end - range.start),
-            move |(mut file, path, remaining)| {
-                maybe_spawn_blocking(move || {
-                    if remaining == 0 {
-                        return Ok(None);
-                    }
+        let location = Path::from("dir/file");
+        let sidecar = root.path().join("dir/file#sha256");
+        integration.put(&location, "data".into()).await.unwrap();
+        let checksum = std::fs::read_to_string(&sidecar).unwrap();
+        assert_eq!(checksum, sha256_hex(&root.path().join("dir/file")).unwrap());
 
-                    let to_read = remaining.min(chunk_size as u64);
-                    let cap = usize::try_from(to_read).map_err(|_e| Error::InvalidRange {
-                        source: InvalidGetRange::TooLarge {
-                            requested: to_read,
-                            max: usize::MAX as u64,
-                        },
-                    })?;
-                    let mut buffer = Vec::with_capacity(cap);
-                    let read = (&mut file)
-                        .take(to_read)
-                        .read_to_end(&mut buffer)
-                        .map_err(|e| Error::UnableToReadBytes {
-                            source: e,
-                            path: path.clone(),
-                        })?;
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload.put_part("other".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        let read = integration.get(&location).await.unwrap().bytes().await;
+        assert_eq!(read.unwrap().as_ref(), b"other");
+
+        // The sidecar is not visible to listing, and is carried over by renames
+        let renamed = Path::from("renamed");
+        integration.rename(&location, &renamed).await.unwrap();
+        assert!(!sidecar.exists());
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].location, renamed);
+        assert!(root.path().join("renamed#sha256").exists());
+
+        // Corruption is detected
+        std::fs::write(root.path().join("renamed"), "othex").unwrap();
+        let err = integration.get(&renamed).await.unwrap_err().to_string();
+        assert!(err.contains("Checksum mismatch"), "{err}");
+
+        // Objects written without checksums are not verified
+        std::fs::write(root.path().join("plain"), "data").unwrap();
+        let read = integration.get(&Path::from("plain")).await.unwrap();
+        assert_eq!(read.bytes().await.unwrap().as_ref(), b"data");
+
+        integration.delete(&renamed).await.unwrap();
+        assert!(!root.path().join("renamed#sha256").exists());
+    }
 
-                    Ok(Some((buffer.into(), (file, path, remaining - read as u64))))
-                })
-            },
-        );
-        Ok::<_, super::Error>(stream)
-    })
-    .try_flatten()
-    .boxed()
-}
+    #[tokio::test]
+    async fn get_directory() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("prefix");
+        integration
+            .put(&location.child("object"), "data".into())
+            .await
+            .unwrap();
 
-pub(crate) fn read_range(file: &mut File, path: &PathBuf, range: Range<u64>) -> Result<Bytes> {
-    let file_metadata = file.metadata().map_err(|e| Error::Metadata {
-        source: e.into(),
-        path: path.to_string_lossy().to_string(),
-    })?;
+        let err = integration.get(&location).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
 
-    // If none of the range is satisfiable we should error, e.g. if the start offset is beyond the
-    // extents of the file
-    let file_len = file_metadata.len();
-    if range.start >= file_len {
-        return Err(Error::InvalidRange {
-            source: InvalidGetRange::StartTooLarge {
-                requested: range.start,
-                length: file_len,
-            },
-        }
-        .into());
+        let integration = integration.with_directory_error(true);
+        let err = integration.get(&location).await.unwrap_err();
+        assert!(matches!(err, crate::Error::IsADirectory { .. }), "{err}");
+        let err = integration.head(&location).await.unwrap_err();
+        assert!(matches!(err, crate::Error::IsADirectory { .. }), "{err}");
+
+        let err = integration.get(&Path::from("missing")).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
     }
 
-    // Don't read past end of file
-    let to_read = range.end.min(file_len) - range.start;
+    #[tokio::test]
+    async fn directory_markers() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let empty = Path::from("a/empty");
+        integration.create_dir(&empty).await.unwrap();
+        integration.create_dir(&empty).await.unwrap();
+        assert!(root.path().join("a/empty").is_dir());
+        integration
+            .put(&Path::from("a/file"), "data".into())
+            .await
+            .unwrap();
 
-    file.seek(SeekFrom::Start(range.start)).map_err(|source| {
-        let path = path.into();
-        Error::Seek { source, path }
-    })?;
+        let r = integration
+            .list_with_delimiter(Some(&Path::from("a")))
+            .await
+            .unwrap();
+        assert_eq!(r.common_prefixes, vec![empty.clone()]);
+        assert_eq!(r.objects.len(), 1);
 
-    let mut buf = Vec::with_capacity(to_read as usize);
-    let read = file.take(to_read).read_to_end(&mut buf).map_err(|source| {
-        let path = path.into();
-        Error::UnableToReadBytes { source, path }
-    })? as u64;
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        let locations: Vec<_> = listed.iter().map(|m| m.location.as_ref()).collect();
+        assert_eq!(locations, ["a/file"]);
 
-    if read != to_read {
-        let error = Error::OutOfRange {
-            path: path.into(),
-            expected: to_read,
-            actual: read,
-        };
+        let integration = integration.with_directory_markers(true);
+        assert!(integration.config().directory_markers);
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        let locations: Vec<_> = listed.iter().map(|m| m.location.as_ref()).collect();
+        assert_eq!(locations, ["a/empty", "a/file"]);
+        assert_eq!(listed[0].size, 0);
 
-        return Err(error.into());
+        // Directories with contents are not markers
+        integration
+            .put(&Path::from("a/empty/file"), "data".into())
+            .await
+            .unwrap();
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        let locations: Vec<_> = listed.iter().map(|m| m.location.as_ref()).collect();
+        assert_eq!(locations, ["a/empty/file", "a/file"]);
+        let err = integration.head(&empty).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+        integration.delete(&empty).await.unwrap_err();
+        assert!(root.path().join("a/empty/file").is_file());
+
+        // Listed markers can be read and deleted as objects
+        integration
+            .delete(&Path::from("a/empty/file"))
+            .await
+            .unwrap();
+        let meta = integration.head(&empty).await.unwrap();
+        assert_eq!(meta.location, empty);
+        assert_eq!(meta.size, 0);
+        let r = integration.get(&empty).await.unwrap();
+        assert_eq!(r.meta, meta);
+        assert!(r.bytes().await.unwrap().is_empty());
+
+        integration.delete(&empty).await.unwrap();
+        assert!(!root.path().join("a/empty").exists());
+        let err = integration.head(&empty).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
     }
 
-    Ok(buf.into())
-}
+    #[tokio::test]
+    async fn list_sorted() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let locations = [
+            "b", "a/c", "c/d/e", "a.txt", "a/b", "a-b", "a0", "c/d.txt", "a/b.c",
+        ];
+        for location in locations {
+            let location = Path::from(location);
+            integration.put(&location, "data".into()).await.unwrap();
+        }
+        let mut expected = locations.map(String::from).to_vec();
+        expected.sort_unstable();
 
-fn open_file(path: &PathBuf) -> Result<(File, Metadata)> {
-    let ret = match File::open(path).and_then(|f| Ok((f.metadata()?, f))) {
-        Err(e) => Err(match e.kind() {
-            ErrorKind::NotFound => Error::NotFound {
-                path: path.clone(),
-                source: e,
-            },
-            _ => Error::UnableToOpenFile {
-                path: path.clone(),
-                source: e,
-            },
-        }),
-        Ok((metadata, file)) => match !metadata.is_dir() {
-            true => Ok((file, metadata)),
-            false => Err(Error::NotFound {
-                path: path.clone(),
-                source: io::Error::new(ErrorKind::NotFound, "is directory"),
-            }),
-        },
-    }?;
-    Ok(ret)
-}
+        let listed: Vec<_> = integration
+            .list(None)
+            .map_ok(|m| m.location.to_string())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(listed, expected);
 
-fn convert_entry(entry: DirEntry, location: Path) -> Result<Option<ObjectMeta>> {
-    match entry.metadata() {
-        Ok(metadata) => Ok(Some(convert_metadata(metadata, location))),
-        Err(e) => {
-            if let Some(io_err) = e.io_error() {
-                if io_err.kind() == ErrorKind::NotFound {
-                    return Ok(None);
+        for offset in ["a/b", "a0", "b", "c/d"] {
+            let listed: Vec<_> = integration
+                .list_with_offset(None, &Path::from(offset))
+                .map_ok(|m| m.location.to_string())
+                .try_collect()
+                .await
+                .unwrap();
+            let expected: Vec<_> = expected
+                .iter()
+                .filter(|l| l.as_str() > offset)
+                .cloned()
+                .collect();
+            assert_eq!(listed, expected, "{offset}");
+        }
+
+        let listed = integration.list_with_delimiter(None).await.unwrap();
+        let objects: Vec<_> = listed.objects.iter().map(|m| m.location.as_ref()).collect();
+        assert_eq!(objects, ["a-b", "a.txt", "a0", "b"]);
+    }
+
+    #[tokio::test]
+    async fn list_concurrency() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_list_concurrency(4);
+        assert_eq!(integration.config().list_concurrency, 4);
+
+        let mut expected = vec![];
+        for i in 0..20 {
+            expected.push(format!("file{i}"));
+            for j in 0..5 {
+                for k in 0..3 {
+                    expected.push(format!("dir{i}/sub{j}/file{k}"));
                 }
             }
-            Err(Error::Metadata {
-                source: e.into(),
-                path: location.to_string(),
-            })?
         }
-    }
-}
+        for location in &expected {
+            let location = Path::from(location.as_str());
+            integration.put(&location, "data".into()).await.unwrap();
+        }
+        expected.sort_unstable();
 
-fn last_modified(metadata: &Metadata) -> DateTime<Utc> {
-    metadata
-        .modified()
-        .expect("Modified file time should be supported on this platform")
-        .into()
-}
+        // Staging files are excluded
+        std::fs::write(root.path().join("dir0/sub0/file0#1"), "staged").unwrap();
 
-fn get_etag(metadata: &Metadata) -> String {
-    let inode = get_inode(metadata);
-    let size = metadata.len();
-    let mtime = metadata
-        .modified()
-        .ok()
-        .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
-        .unwrap_or_default()
-        .as_micros();
+        let list = |stream: BoxStream<'static, Result<ObjectMeta>>| async move {
+            let mut listed: Vec<_> = stream
+                .map_ok(|m| m.location.to_string())
+                .try_collect()
+                .await
+                .unwrap();
+            listed.sort_unstable();
+            listed
+        };
 
-    // Use an ETag scheme based on that used by many popular HTTP servers
-    // <https://httpd.apache.org/docs/2.2/mod/core.html#fileetag>
-    // <https://stackoverflow.com/questions/47512043/how-etags-are-generated-and-configured>
-    format!("{inode:x}-{mtime:x}-{size:x}")
-}
+        // Every object is listed exactly once
+        assert_eq!(list(integration.list(None)).await, expected);
+
+        let prefix = Path::from("dir3");
+        let prefixed: Vec<_> = expected
+            .iter()
+            .filter(|l| l.starts_with("dir3/"))
+            .cloned()
+            .collect();
+        assert_eq!(list(integration.list(Some(&prefix))).await, prefixed);
 
-fn convert_metadata(metadata: Metadata, location: Path) -> ObjectMeta {
-    let last_modified = last_modified(&metadata);
+        let offset = Path::from("dir3/sub2/file1");
+        let after: Vec<_> = expected
+            .iter()
+            .filter(|l| l.as_str() > "dir3/sub2/file1")
+            .cloned()
+            .collect();
+        assert_eq!(
+            list(integration.list_with_offset(None, &offset)).await,
+            after
+        );
 
-    ObjectMeta {
-        location,
-        last_modified,
-        size: metadata.len(),
-        e_tag: Some(get_etag(&metadata)),
-        version: None,
+        let shallow: Vec<_> = expected
+            .iter()
+            .filter(|l| l.starts_with("file"))
+            .cloned()
+            .collect();
+        assert_eq!(list(integration.list_max_depth(None, 1)).await, shallow);
+        assert_eq!(list(integration.list_max_depth(None, 3)).await, expected);
+        let listed = list(integration.list_max_depth(None, 2)).await;
+        assert_eq!(listed, shallow);
     }
-}
 
-#[cfg(unix)]
-/// We include the inode when available to yield an ETag more resistant to collisions
-/// and as used by popular web servers such as [Apache](https://httpd.apache.org/docs/2.2/mod/core.html#fileetag)
-fn get_inode(metadata: &Metadata) -> u64 {
-    std::os::unix::fs::MetadataExt::ino(metadata)
-}
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn list_sorted_by_mtime() {
+        use nix::sys::time::TimeVal;
 
-#[cfg(not(unix))]
-/// On platforms where an inode isn't available, fallback to just relying on size and mtime
-fn get_inode(_metadata: &Metadata) -> u64 {
-    0
-}
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-/// Convert walkdir results and converts not-found errors into `None`.
-/// Convert broken symlinks to `None`.
-fn convert_walkdir_result(
-    res: std::result::Result<DirEntry, walkdir::Error>,
-) -> Result<Option<DirEntry>> {
-    match res {
-        Ok(entry) => {
-            // To check for broken symlink: call symlink_metadata() - it does not traverse symlinks);
-            // if ok: check if entry is symlink; and try to read it by calling metadata().
-            match symlink_metadata(entry.path()) {
-                Ok(attr) => {
-                    if attr.is_symlink() {
-                        let target_metadata = metadata(entry.path());
-                        match target_metadata {
-                            Ok(_) => {
-                                // symlink is valid
-                                Ok(Some(entry))
-                            }
-                            Err(_) => {
-                                // this is a broken symlink, return None
-                                Ok(None)
-                            }
-                        }
-                    } else {
-                        Ok(Some(entry))
-                    }
-                }
-                Err(_) => Ok(None),
-            }
+        // Created out of order of their modification times, with `b` and `e` tied
+        let mtimes = [
+            ("a", 40),
+            ("b", 20),
+            ("c", 50),
+            ("d", 10),
+            ("e", 20),
+            ("f", 30),
+        ];
+        for (name, mtime) in mtimes {
+            let location = Path::from_iter(["prefix", name]);
+            integration.put(&location, "data".into()).await.unwrap();
+            let path = integration.path_to_filesystem(&location).unwrap();
+            let time = TimeVal::new(1_700_000_000 + mtime, 0);
+            nix::sys::stat::utimes(&path, &time, &time).unwrap();
         }
+        integration
+            .put(&Path::from("other"), "data".into())
+            .await
+            .unwrap();
 
-        Err(walkdir_err) => match walkdir_err.io_error() {
-            Some(io_err) => match io_err.kind() {
-                ErrorKind::NotFound => Ok(None),
-                _ => Err(Error::UnableToWalkDir {
-                    source: walkdir_err,
-                }
-                .into()),
-            },
-            None => Err(Error::UnableToWalkDir {
-                source: walkdir_err,
-            }
-            .into()),
-        },
+        let names = |objects: Vec<ObjectMeta>| -> Vec<String> {
+            let names = objects.iter().map(|o| o.location.filename().unwrap());
+            names.map(ToString::to_string).collect()
+        };
+        let prefix = Path::from("prefix");
+
+        let oldest = integration
+            .list_sorted_by_mtime(Some(&prefix), MtimeOrder::OldestFirst, Some(3))
+            .await
+            .unwrap();
+        assert_eq!(names(oldest), ["d", "b", "e"]);
+
+        let newest = integration
+            .list_sorted_by_mtime(Some(&prefix), MtimeOrder::NewestFirst, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(names(newest), ["c", "a"]);
+
+        let all = integration
+            .list_sorted_by_mtime(Some(&prefix), MtimeOrder::OldestFirst, None)
+            .await
+            .unwrap();
+        assert_eq!(names(all), ["d", "b", "e", "f", "a", "c"]);
+
+        let none = integration
+            .list_sorted_by_mtime(None, MtimeOrder::NewestFirst, Some(0))
+            .await
+            .unwrap();
+        assert!(none.is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::fs;
+    #[tokio::test]
+    async fn disk_usage() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        let usage = integration.disk_usage().await.unwrap();
+        assert!(usage.total > 0, "{usage:?}");
+        assert!(usage.available > 0, "{usage:?}");
+        assert!(usage.available <= usage.total, "{usage:?}");
+        assert!(usage.used <= usage.total, "{usage:?}");
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn prefix_allocated_size() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        std::fs::create_dir(root.path().join("prefix")).unwrap();
+        let file = File::create(root.path().join("prefix/sparse")).unwrap();
+        file.set_len(10 * 1024 * 1024).unwrap();
+        drop(file);
 
-    use futures::TryStreamExt;
-    use tempfile::TempDir;
+        let location = Path::from("prefix/sparse");
+        let key = Attribute::Metadata(ALLOCATED_SIZE_ATTRIBUTE.into());
+        let result = integration.get(&location).await.unwrap();
+        assert!(result.attributes.get(&key).is_none());
 
-    #[cfg(target_family = "unix")]
-    use tempfile::NamedTempFile;
+        let integration = integration.with_allocated_size_attribute(true);
+        assert!(integration.config().allocated_size_attribute);
+        let result = integration.get(&location).await.unwrap();
+        let allocated = result.attributes.get(&key).unwrap().parse::<u64>().unwrap();
+        assert_eq!(result.meta.size, 10 * 1024 * 1024);
+        assert!(allocated < result.meta.size);
 
-    use crate::integration::*;
+        integration
+            .put(&Path::from("prefix/dense"), vec![1; 8192].into())
+            .await
+            .unwrap();
+        integration
+            .put(&Path::from("other"), vec![1; 8192].into())
+            .await
+            .unwrap();
+        let total = integration
+            .prefix_allocated_size(Some(&Path::from("prefix")))
+            .await
+            .unwrap();
+        assert!(total >= 8192, "{total}");
+        assert!(total < 10 * 1024 * 1024, "{total}");
 
-    use super::*;
+        let all = integration.prefix_allocated_size(None).await.unwrap();
+        assert!(all >= total + 8192, "{all}");
+    }
 
     #[tokio::test]
-    #[cfg(target_family = "unix")]
-    async fn file_test() {
+    #[cfg(unix)]
+    async fn verify() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        integration
+            .put(&Path::from("a/b"), "data".into())
+            .await
+            .unwrap();
+        assert!(integration.verify(None).await.unwrap().is_empty());
+
+        let staging = root.path().join("a/c#1");
+        let empty_staging = root.path().join("a/d#2");
+        let broken = root.path().join("broken");
+        std::fs::write(&staging, "partial").unwrap();
+        std::fs::write(&empty_staging, "").unwrap();
+        std::os::unix::fs::symlink(root.path().join("missing"), &broken).unwrap();
+
+        let report = integration.verify(None).await.unwrap();
+        assert_eq!(report.staging_files, vec![staging]);
+        assert_eq!(report.empty_staging_files, vec![empty_staging]);
+        assert_eq!(report.broken_symlinks, vec![broken]);
+        assert!(report.unreadable.is_empty());
+
+        let report = integration.verify(Some(&Path::from("a"))).await.unwrap();
+        assert!(report.broken_symlinks.is_empty());
+        assert_eq!(report.staging_files.len(), 1);
+
+        // A missing prefix has no anomalies
+        let missing = Path::from("missing");
+        assert!(integration.verify(Some(&missing)).await.unwrap().is_empty());
+
+        // Broken symlinks are reported without following symlinks
+        let integration = integration.with_follow_symlinks(false);
+        let report = integration.verify(None).await.unwrap();
+        assert_eq!(report.broken_symlinks, vec![root.path().join("broken")]);
+        assert!(report.unreadable.is_empty());
+    }
 
-        put_get_delete_list(&integration).await;
-        get_opts(&integration).await;
-        list_uses_directories_correctly(&integration).await;
-        list_with_delimiter(&integration).await;
-        rename_and_copy(&integration).await;
-        copy_if_not_exists(&integration).await;
-        copy_rename_nonexistent_object(&integration).await;
-        stream_get(&integration).await;
-        put_opts(&integration, false).await;
+    #[tokio::test]
+    async fn verify_checksums() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_checksums(true);
+
+        let location = Path::from("a/b");
+        integration.put(&location, "data".into()).await.unwrap();
+        assert!(integration.verify(None).await.unwrap().is_empty());
+
+        // Corrupt the contents without updating the checksum
+        let path = root.path().join("a").join("b");
+        std::fs::write(&path, "corrupt").unwrap();
+        let report = integration.verify(None).await.unwrap();
+        assert_eq!(report.checksum_mismatches, vec![path]);
+        assert!(!report.is_empty());
+
+        // Checksums are not verified unless enabled
+        let integration = integration.with_checksums(false);
+        assert!(integration.verify(None).await.unwrap().is_empty());
     }
 
-    #[test]
-    #[cfg(target_family = "unix")]
-    fn test_non_tokio() {
+    #[tokio::test]
+    async fn list_max_depth() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
-        futures::executor::block_on(async move {
-            put_get_delete_list(&integration).await;
-            list_uses_directories_correctly(&integration).await;
-            list_with_delimiter(&integration).await;
 
-            // Can't use stream_get test as WriteMultipart uses a tokio JoinSet
-            let p = Path::from("manual_upload");
-            let mut upload = integration.put_multipart(&p).await.unwrap();
-            upload.put_part("123".into()).await.unwrap();
-            upload.put_part("45678".into()).await.unwrap();
-            let r = upload.complete().await.unwrap();
+        for location in ["a", "b/c", "b/d/e", "b/d/f/g", "h/i/j/k"] {
+            let location = Path::from(location);
+            integration.put(&location, "data".into()).await.unwrap();
+        }
 
-            let get = integration.get(&p).await.unwrap();
-            assert_eq!(get.meta.e_tag.as_ref().unwrap(), r.e_tag.as_ref().unwrap());
-            let actual = get.bytes().await.unwrap();
-            assert_eq!(actual.as_ref(), b"12345678");
-        });
+        async fn list(
+            integration: &LocalFileSystem,
+            prefix: Option<&str>,
+            depth: usize,
+        ) -> Vec<String> {
+            let prefix = prefix.map(Path::from);
+            let mut list: Vec<_> = integration
+                .list_max_depth(prefix.as_ref(), depth)
+                .map_ok(|meta| meta.location.to_string())
+                .try_collect()
+                .await
+                .unwrap();
+            list.sort_unstable();
+            list
+        }
+
+        assert_eq!(list(&integration, None, 2).await, vec!["a", "b/c"]);
+        assert!(list(&integration, None, 0).await.is_empty());
+        assert_eq!(list(&integration, Some("b"), 2).await, vec!["b/c", "b/d/e"]);
+        assert_eq!(list(&integration, None, 4).await.len(), 5);
     }
 
     #[tokio::test]
-    async fn creates_dir_if_not_present() {
+    async fn head_many() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-        let location = Path::from("nested/file/test_file");
+        let locations: Vec<_> = (0..100).map(|i| Path::from(format!("file_{i}"))).collect();
+        for location in locations.iter().step_by(7) {
+            integration.put(location, "data".into()).await.unwrap();
+        }
 
-        let data = Bytes::from("arbitrary data");
+        let results = integration.head_many(locations.clone()).await;
+        assert_eq!(results.len(), 100);
+        for (i, (location, result)) in locations.iter().zip(results).enumerate() {
+            match result {
+                Ok(meta) => {
+                    assert_eq!(i % 7, 0);
+                    assert_eq!(&meta.location, location);
+                    assert_eq!(meta.size, 4);
+                }
+                Err(crate::Error::NotFound { .. }) => assert_ne!(i % 7, 0),
+                Err(e) => panic!("{e}"),
+            }
+        }
+
+        assert!(integration.head_many(vec![]).await.is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn put_with_ttl() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
+        let expiring = Path::from("cache/expiring");
+        let permanent = Path::from("cache/permanent");
+        let unexpired = Path::from("cache/unexpired");
+        let ttl = Duration::from_secs(3600);
         integration
-            .put(&location, data.clone().into())
+            .put_with_ttl(&unexpired, "data".into(), ttl)
             .await
             .unwrap();
+        integration.put(&permanent, "data".into()).await.unwrap();
 
-        let read_data = integration
-            .get(&location)
+        assert_eq!(integration.purge_expired(None).await.unwrap(), 0);
+        integration.head(&unexpired).await.unwrap();
+
+        // Expires immediately
+        integration
+            .put_with_ttl(&expiring, "data".into(), Duration::ZERO)
             .await
+            .unwrap();
+        let path = integration.path_to_filesystem(&expiring).unwrap();
+        let expires_at: u128 = read_xattr(&path, EXPIRES_AT_XATTR)
             .unwrap()
-            .bytes()
+            .parse()
+            .unwrap();
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+        assert!(expires_at <= now.unwrap().as_millis());
+
+        // An expired object replaced after being opened by a purge is retained
+        let file = File::open(&path).unwrap();
+        integration.put(&expiring, "new".into()).await.unwrap();
+        assert!(!remove_if_unchanged(&integration.config, &path, &file).unwrap());
+        let data = integration.get(&expiring).await.unwrap().bytes().await;
+        assert_eq!(data.unwrap().as_ref(), b"new");
+        integration
+            .put_with_ttl(&expiring, "data".into(), Duration::ZERO)
             .await
             .unwrap();
-        assert_eq!(&*read_data, data);
+
+        let prefix = Path::from("cache");
+        assert_eq!(integration.purge_expired(Some(&prefix)).await.unwrap(), 1);
+        let err = integration.head(&expiring).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+        integration.head(&permanent).await.unwrap();
+        integration.head(&unexpired).await.unwrap();
+
+        // No staged files are left behind
+        let mut names: Vec<_> = std::fs::read_dir(root.path().join("cache"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["permanent", "unexpired"]);
     }
 
     #[tokio::test]
-    async fn unknown_length() {
+    #[cfg(target_os = "windows")]
+    async fn normalize_backslashes() {
         let root = TempDir::new().unwrap();
-        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
-
-        let location = Path::from("some_file");
-
-        let data = Bytes::from("arbitrary data");
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_normalize_backslashes(true);
 
-        integration
-            .put(&location, data.clone().into())
-            .await
-            .unwrap();
+        let native = Path::parse(r"a\b\c").unwrap();
+        let location = Path::from("a/b/c");
+        integration.put(&native, "data".into()).await.unwrap();
 
-        let read_data = integration
+        let read = integration
             .get(&location)
             .await
             .unwrap()
             .bytes()
             .await
             .unwrap();
-        assert_eq!(&*read_data, data);
+        assert_eq!(read.as_ref(), b"data");
+        assert_eq!(
+            integration.path_to_filesystem(&native).unwrap(),
+            integration.path_to_filesystem(&location).unwrap()
+        );
+
+        let list = flatten_list_stream(&integration, Some(&Path::parse(r"a\b").unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(list, vec![location]);
     }
 
     #[tokio::test]
-    async fn range_request_start_beyond_end_of_file() {
+    async fn get_ranges_merged() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("a");
+        let data: Vec<u8> = (0..100).collect();
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
 
-        let location = Path::from("some_file");
+        let ranges = [
+            50..60,
+            0..10,
+            5..15,
+            10..20,
+            55..58,
+            90..200,
+            95..96,
+            30..31,
+        ];
+        let r = integration.get_ranges(&location, &ranges).await.unwrap();
+        assert_eq!(r.len(), ranges.len());
+        for (range, bytes) in ranges.iter().zip(&r) {
+            let end = (range.end as usize).min(data.len());
+            assert_eq!(
+                bytes.as_ref(),
+                &data[range.start as usize..end],
+                "{range:?}"
+            );
+        }
 
-        let data = Bytes::from("arbitrary data");
+        assert!(integration
+            .get_ranges(&location, &[])
+            .await
+            .unwrap()
+            .is_empty());
+        let err = integration
+            .get_ranges(&location, &[90..200, 100..110])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("range was invalid"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn get_ranges_coalesce_gap() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_range_coalesce_gap(8);
+        assert_eq!(integration.config().range_coalesce_gap, 8);
+
+        let location = Path::from("a");
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        integration.put(&location, data.into()).await.unwrap();
+
+        let ranges = [
+            // Contiguous
+            0..10,
+            10..20,
+            // Small gap
+            25..30,
+            // Large gap
+            100..120,
+            // Overlapping
+            110..130,
+            115..118,
+            // Unordered
+            50..60,
+            40..45,
+            990..1100,
+        ];
+        let r = integration.get_ranges(&location, &ranges).await.unwrap();
+        assert_eq!(r.len(), ranges.len());
+        for (range, bytes) in ranges.iter().zip(&r) {
+            let expected = integration
+                .get_range(&location, range.clone())
+                .await
+                .unwrap();
+            assert_eq!(bytes, &expected, "{range:?}");
+        }
+    }
 
+    #[tokio::test]
+    async fn get_range_opts() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("a");
+        let data: Vec<u8> = (0..100).collect();
         integration
             .put(&location, data.clone().into())
             .await
             .unwrap();
 
+        let cases = [
+            (GetRange::Bounded(10..20), &data[10..20]),
+            (GetRange::Bounded(90..200), &data[90..]),
+            (GetRange::Offset(40), &data[40..]),
+            (GetRange::Offset(100), &[]),
+            (GetRange::Suffix(10), &data[90..]),
+            (GetRange::Suffix(1000), &data[..]),
+        ];
+        for (range, expected) in cases {
+            let bytes = integration
+                .get_range_opts(&location, range.clone())
+                .await
+                .unwrap();
+            assert_eq!(bytes.as_ref(), expected, "{range}");
+        }
+
+        for range in [
+            GetRange::Bounded(100..110),
+            GetRange::Bounded(20..20),
+            GetRange::Offset(101),
+        ] {
+            let err = integration
+                .get_range_opts(&location, range.clone())
+                .await
+                .unwrap_err();
+            assert!(
+                err.to_string().contains("range was invalid"),
+                "{range}: {err}"
+            );
+        }
+
+        let empty = Path::from("empty");
         integration
-            .get_range(&location, 100..200)
+            .put(&empty, PutPayload::default())
             .await
-            .expect_err("Should error with start range beyond end of file");
+            .unwrap();
+        for range in [GetRange::Offset(0), GetRange::Suffix(10)] {
+            let bytes = integration.get_range_opts(&empty, range).await.unwrap();
+            assert!(bytes.is_empty());
+        }
     }
 
     #[tokio::test]
-    async fn range_request_beyond_end_of_file() {
+    async fn read_range_into() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("file");
+        integration
+            .put(&location, "0123456789".into())
+            .await
+            .unwrap();
 
-        let location = Path::from("some_file");
-
-        let data = Bytes::from("arbitrary data");
+        let mut buf = Vec::with_capacity(16);
+        let ptr = buf.as_ptr();
+        for (range, expected) in [(0..4, "0123"), (2..10, "23456789"), (8..20, "89")] {
+            let read = integration
+                .read_range_into(&location, range, &mut buf, false)
+                .await
+                .unwrap();
+            assert_eq!(read, expected.len());
+            assert_eq!(buf, expected.as_bytes());
+            // Buffer is reused rather than reallocated
+            assert_eq!(buf.as_ptr(), ptr);
+        }
 
-        integration
-            .put(&location, data.clone().into())
+        let read = integration
+            .read_range_into(&location, 0..2, &mut buf, true)
             .await
             .unwrap();
+        assert_eq!(read, 2);
+        assert_eq!(buf, b"8901");
 
-        let read_data = integration.get_range(&location, 0..100).await.unwrap();
-        assert_eq!(&*read_data, data);
+        let err = integration
+            .read_range_into(&location, 20..30, &mut buf, false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::Generic { .. }), "{err}");
+        assert!(buf.is_empty());
+
+        let missing = Path::from("missing");
+        let err = integration
+            .read_range_into(&missing, 0..1, &mut buf, true)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
     }
 
     #[tokio::test]
-    #[cfg(target_family = "unix")]
-    // Fails on github actions runner (which runs the tests as root)
-    #[ignore]
-    async fn bubble_up_io_errors() {
-        use std::{fs::set_permissions, os::unix::prelude::PermissionsExt};
-
+    async fn fd_cache() {
         let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_fd_cache(2);
+        assert_eq!(integration.config().fd_cache, Some(2));
+        let cache = integration.config.fd_cache.clone().unwrap();
+        let opened = || cache.opened.load(Ordering::Relaxed);
 
-        // make non-readable
-        let metadata = root.path().metadata().unwrap();
-        let mut permissions = metadata.permissions();
-        permissions.set_mode(0o000);
-        set_permissions(root.path(), permissions).unwrap();
-
-        let store = LocalFileSystem::new_with_prefix(root.path()).unwrap();
-
-        let mut stream = store.list(None);
-        let mut any_err = false;
-        while let Some(res) = stream.next().await {
-            if res.is_err() {
-                any_err = true;
-            }
+        let (a, b, c) = (Path::from("a"), Path::from("b"), Path::from("c"));
+        for location in [&a, &b, &c] {
+            integration
+                .put(location, "0123456789".into())
+                .await
+                .unwrap();
         }
-        assert!(any_err);
 
-        // `list_with_delimiter
-        assert!(store.list_with_delimiter(None).await.is_err());
+        // Repeated reads reuse the open file
+        for _ in 0..3 {
+            let r = integration.get_range(&a, 2..5).await.unwrap();
+            assert_eq!(r.as_ref(), b"234");
+        }
+        let r = integration.get_ranges(&a, &[0..1, 8..20]).await.unwrap();
+        assert_eq!(r, vec![Bytes::from("0"), Bytes::from("89")]);
+        let mut buf = vec![];
+        integration
+            .read_range_into(&a, 0..3, &mut buf, false)
+            .await
+            .unwrap();
+        assert_eq!(buf, b"012");
+        assert_eq!(opened(), 1);
+
+        // A replaced file is reopened
+        integration.put(&a, "abcdefghij".into()).await.unwrap();
+        let r = integration.get_range(&a, 2..5).await.unwrap();
+        assert_eq!(r.as_ref(), b"cde");
+        assert_eq!(opened(), 2);
+
+        // The least recently used file is closed once at capacity
+        integration.get_range(&b, 0..1).await.unwrap();
+        integration.get_range(&c, 0..1).await.unwrap();
+        assert_eq!(opened(), 4);
+        integration.get_range(&c, 0..1).await.unwrap();
+        integration.get_range(&a, 0..1).await.unwrap();
+        assert_eq!(opened(), 5);
+
+        // Errors are unchanged
+        let err = integration.get_range(&a, 20..30).await.unwrap_err();
+        assert!(
+            err.to_string().contains("Requested range was invalid"),
+            "{err}"
+        );
+        integration.delete(&a).await.unwrap();
+        let err = integration.get_range(&a, 0..1).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+
+        // A file reopened after a stale handle replaces the cached file
+        let path = root.path().join("b");
+        let open = || open_file(&path);
+        let (stale, _) = cache.get(&path, open).unwrap();
+        let reopened = Arc::new(File::open(&path).unwrap());
+        cache.replace(&path, &stale, &reopened);
+        let (cached, _) = cache.get(&path, open).unwrap();
+        assert!(Arc::ptr_eq(&cached, &reopened));
+
+        // Unless the cached file has since changed
+        let other = Arc::new(File::open(&path).unwrap());
+        cache.replace(&path, &stale, &other);
+        let (cached, _) = cache.get(&path, open).unwrap();
+        assert!(Arc::ptr_eq(&cached, &reopened));
     }
 
-    const NON_EXISTENT_NAME: &str = "nonexistentname";
-
     #[tokio::test]
-    async fn get_nonexistent_location() {
+    #[cfg(target_family = "unix")]
+    async fn follow_symlinks() {
         let root = TempDir::new().unwrap();
-        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let outside = TempDir::new().unwrap();
+        std::fs::write(outside.path().join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("dir")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), root.path().join("file"))
+            .unwrap();
 
-        let location = Path::from(NON_EXISTENT_NAME);
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        integration
+            .put(&Path::from("a/b"), "data".into())
+            .await
+            .unwrap();
 
-        let err = get_nonexistent_object(&integration, Some(location))
+        let list = |integration: &LocalFileSystem| {
+            let list = integration.list(None).map_ok(|m| m.location.to_string());
+            list.try_collect::<BTreeSet<_>>()
+        };
+        let expected = BTreeSet::from(["a/b".into(), "dir/secret".into(), "file".into()]);
+        assert_eq!(list(&integration).await.unwrap(), expected);
+
+        let integration = integration.with_follow_symlinks(false);
+        assert!(!integration.config().follow_symlinks);
+
+        let expected = BTreeSet::from(["a/b".to_string()]);
+        assert_eq!(list(&integration).await.unwrap(), expected);
+        let offset = Path::from("a");
+        let listed: Vec<_> = integration
+            .list_with_offset(None, &offset)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+
+        let listed = integration.list_with_delimiter(None).await.unwrap();
+        assert_eq!(listed.common_prefixes, [Path::from("a")]);
+        assert!(listed.objects.is_empty());
+
+        let file = Path::from("file");
+        let err = integration.head(&file).await.unwrap_err();
+        assert!(err.to_string().contains("is a symlink"), "{err}");
+        let err = integration.get(&file).await.unwrap_err();
+        assert!(err.to_string().contains("is a symlink"), "{err}");
+
+        // Symlinked parent directories are not followed either
+        let nested = Path::from("dir/secret");
+        let err = integration.head(&nested).await.unwrap_err();
+        assert!(err.to_string().contains("is a symlink"), "{err}");
+        let err = integration.get(&nested).await.unwrap_err();
+        assert!(err.to_string().contains("is a symlink"), "{err}");
+        let err = integration
+            .head(&Path::from("a/missing"))
             .await
             .unwrap_err();
-        if let crate::Error::NotFound { path, source } = err {
-            let source_variant = source.downcast_ref::<std::io::Error>();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+
+        let data = integration.get(&Path::from("a/b")).await.unwrap();
+        assert_eq!(data.bytes().await.unwrap().as_ref(), b"data");
+    }
+
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn restrict_symlinks_to_root() {
+        let root = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("out")).unwrap();
+        std::fs::create_dir(root.path().join("dir")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("dir"), root.path().join("in")).unwrap();
+
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_restrict_symlinks_to_root(true);
+        assert!(integration.config().restrict_symlinks_to_root);
+
+        for location in ["out/a", "out/nested/a"] {
+            let location = Path::from(location);
+            let err = integration.put(&location, "data".into()).await.unwrap_err();
             assert!(
-                matches!(source_variant, Some(std::io::Error { .. }),),
-                "got: {source_variant:?}"
+                err.to_string().contains("resolves outside the root"),
+                "{err}"
+            );
+            let err = integration.put_multipart(&location).await.unwrap_err();
+            assert!(
+                err.to_string().contains("resolves outside the root"),
+                "{err}"
             );
-            assert!(path.ends_with(NON_EXISTENT_NAME), "{}", path);
-        } else {
-            panic!("unexpected error type: {err:?}");
         }
+        assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 0);
+
+        // Symlinks within the root, and new directories, are permitted
+        integration
+            .put(&Path::from("in/a"), "data".into())
+            .await
+            .unwrap();
+        integration
+            .put(&Path::from("new/a"), "data".into())
+            .await
+            .unwrap();
+        assert!(root.path().join("dir/a").exists());
+
+        let integration = integration.with_restrict_symlinks_to_root(false);
+        integration
+            .put(&Path::from("out/a"), "data".into())
+            .await
+            .unwrap();
+        assert!(outside.path().join("a").exists());
     }
 
     #[tokio::test]
-    async fn root() {
-        let integration = LocalFileSystem::new();
+    #[cfg(target_family = "unix")]
+    async fn sandbox() {
+        let root = TempDir::new().unwrap();
+        let outside = NamedTempFile::new().unwrap();
+        std::fs::write(outside.path(), "secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("test.parquet")).unwrap();
+        let outside_dir = TempDir::new().unwrap();
+        std::fs::write(outside_dir.path().join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(outside_dir.path(), root.path().join("out")).unwrap();
+        std::fs::create_dir(root.path().join("a")).unwrap();
+        std::fs::write(root.path().join("a/file"), "data").unwrap();
+        std::os::unix::fs::symlink(root.path().join("a"), root.path().join("b")).unwrap();
 
-        let canonical = std::path::Path::new("Cargo.toml").canonicalize().unwrap();
-        let url = Url::from_directory_path(&canonical).unwrap();
-        let path = Path::parse(url.path()).unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let parquet = Path::from("test.parquet");
+        integration.head(&parquet).await.unwrap();
 
-        let roundtrip = integration.path_to_filesystem(&path).unwrap();
+        let integration = integration.with_sandbox(true);
+        assert!(integration.config().sandbox);
 
-        // Needed as on Windows canonicalize returns extended length path syntax
-        // C:\Users\circleci -> \\?\C:\Users\circleci
-        let roundtrip = roundtrip.canonicalize().unwrap();
+        for location in ["test.parquet", "out/secret"] {
+            let location = Path::from(location);
+            let err = integration.head(&location).await.unwrap_err();
+            assert!(
+                err.to_string().contains("resolves outside the root"),
+                "{err}"
+            );
+            let err = integration.get(&location).await.unwrap_err();
+            assert!(
+                err.to_string().contains("resolves outside the root"),
+                "{err}"
+            );
+        }
 
-        assert_eq!(roundtrip, canonical);
+        // Symlinks within the root are permitted
+        let data = integration.get(&Path::from("b/file")).await.unwrap();
+        assert_eq!(data.bytes().await.unwrap().as_ref(), b"data");
 
-        integration.head(&path).await.unwrap();
-    }
+        let list = integration.list(None).map_ok(|m| m.location.to_string());
+        let list: BTreeSet<_> = list.try_collect().await.unwrap();
+        assert_eq!(list, BTreeSet::from(["a/file".into(), "b/file".into()]));
 
-    #[tokio::test]
-    #[cfg(target_family = "windows")]
-    async fn test_list_root() {
-        let fs = LocalFileSystem::new();
-        let r = fs.list_with_delimiter(None).await.unwrap_err().to_string();
+        let listed = integration.list_with_delimiter(None).await.unwrap();
+        assert_eq!(listed.common_prefixes, [Path::from("a"), Path::from("b")]);
+        assert!(listed.objects.is_empty());
 
+        let err = integration
+            .list_with_delimiter(Some(&Path::from("out")))
+            .await
+            .unwrap_err();
         assert!(
-            r.contains("Unable to convert URL \"file:///\" to filesystem path"),
-            "{}",
-            r
+            err.to_string().contains("resolves outside the root"),
+            "{err}"
         );
     }
 
     #[tokio::test]
-    #[cfg(target_os = "linux")]
-    async fn test_list_root() {
-        let fs = LocalFileSystem::new();
-        fs.list_with_delimiter(None).await.unwrap();
-    }
+    async fn exists_many() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-    #[cfg(target_family = "unix")]
-    async fn check_list(integration: &LocalFileSystem, prefix: Option<&Path>, expected: &[&str]) {
-        let result: Vec<_> = integration.list(prefix).try_collect().await.unwrap();
+        let locations: Vec<_> = (0..200).map(|i| Path::from(format!("p={i}/f"))).collect();
+        for location in locations.iter().step_by(3) {
+            integration.put(location, "data".into()).await.unwrap();
+        }
+        // Directories are not objects
+        std::fs::create_dir_all(root.path().join("p=1/f")).unwrap();
 
-        let mut strings: Vec<_> = result.iter().map(|x| x.location.as_ref()).collect();
-        strings.sort_unstable();
-        assert_eq!(&strings, expected)
+        let exists = integration.exists_many(&locations).await.unwrap();
+        let expected: Vec<_> = (0..200).map(|i| i % 3 == 0).collect();
+        assert_eq!(exists, expected);
+
+        assert!(integration.exists_many(&[]).await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    #[cfg(target_family = "unix")]
-    async fn test_symlink() {
+    async fn exists() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-        let subdir = root.path().join("a");
-        std::fs::create_dir(&subdir).unwrap();
-        let file = subdir.join("file.parquet");
-        std::fs::write(file, "test").unwrap();
+        let location = Path::from("a/file");
+        assert!(!integration.exists(&location).await.unwrap());
+        integration.put(&location, "data".into()).await.unwrap();
+        assert!(integration.exists(&location).await.unwrap());
 
-        check_list(&integration, None, &["a/file.parquet"]).await;
-        integration
-            .head(&Path::from("a/file.parquet"))
-            .await
-            .unwrap();
+        // Directories are not objects
+        assert!(!integration.exists(&Path::from("a")).await.unwrap());
 
-        // Follow out of tree symlink
-        let other = NamedTempFile::new().unwrap();
-        std::os::unix::fs::symlink(other.path(), root.path().join("test.parquet")).unwrap();
+        let err = integration
+            .exists(&Path::parse("a/file#123").unwrap())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not supported"), "{err}");
+    }
 
-        // Should return test.parquet even though out of tree
-        check_list(&integration, None, &["a/file.parquet", "test.parquet"]).await;
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn get_not_modified() {
+        use std::os::unix::fs::PermissionsExt;
 
-        // Can fetch test.parquet
-        integration.head(&Path::from("test.parquet")).await.unwrap();
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("file");
+        let r = integration.put(&location, "data".into()).await.unwrap();
+        let e_tag = r.e_tag.unwrap();
 
-        // Follow in tree symlink
-        std::os::unix::fs::symlink(&subdir, root.path().join("b")).unwrap();
-        check_list(
-            &integration,
-            None,
-            &["a/file.parquet", "b/file.parquet", "test.parquet"],
-        )
-        .await;
-        check_list(&integration, Some(&Path::from("b")), &["b/file.parquet"]).await;
+        let options = GetOptions {
+            if_none_match: Some(e_tag.clone()),
+            ..Default::default()
+        };
+        let err = integration
+            .get_opts(&location, options.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotModified { .. }), "{err}");
 
-        // Can fetch through symlink
-        integration
-            .head(&Path::from("b/file.parquet"))
+        let stale = GetOptions {
+            if_none_match: Some("stale".to_string()),
+            ..Default::default()
+        };
+        let r = integration
+            .get_opts(&location, stale.clone())
             .await
             .unwrap();
+        assert_eq!(r.bytes().await.unwrap().as_ref(), b"data");
+
+        // The file is not opened if not modified
+        let path = root.path().join("file");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if File::open(&path).is_ok() {
+            eprintln!("Skipping check - file can be opened without permission");
+            return;
+        }
+        let err = integration.get_opts(&location, options).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotModified { .. }), "{err}");
+        let err = integration.get_opts(&location, stale).await.unwrap_err();
+        assert!(err.to_string().contains("Unable to open file"), "{err}");
+    }
 
-        // Ignore broken symlink
-        std::os::unix::fs::symlink(root.path().join("foo.parquet"), root.path().join("c")).unwrap();
+    #[tokio::test]
+    async fn cleanup_staging() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir(root.path().join("dir")).unwrap();
+        let staged = ["file#1", "dir/file#2", "dir/other#12"];
+        for name in staged.iter().chain(&["file", "dir/data#x"]) {
+            std::fs::write(root.path().join(name), "data").unwrap();
+        }
 
-        check_list(
-            &integration,
-            None,
-            &["a/file.parquet", "b/file.parquet", "test.parquet"],
-        )
-        .await;
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let removed = integration.cleanup_staging(Duration::ZERO).await.unwrap();
+        assert_eq!(removed, 3);
+        for name in staged {
+            assert!(!root.path().join(name).exists());
+        }
+        assert!(root.path().join("file").exists());
+        assert!(root.path().join("dir/data#x").exists());
+
+        // Recent staged uploads are retained
+        std::fs::write(root.path().join("file#1"), "data").unwrap();
+        let removed = integration.cleanup_staging(Duration::from_secs(3600));
+        assert_eq!(removed.await.unwrap(), 0);
+        let removed = integration.cleanup_staging(Duration::ZERO).await.unwrap();
+        assert_eq!(removed, 1);
+
+        // The filesystem root is never scanned
+        let err = LocalFileSystem::new()
+            .cleanup_staging(Duration::from_secs(3600))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("filesystem root"), "{}", err);
+    }
 
-        let mut r = integration.list_with_delimiter(None).await.unwrap();
-        r.common_prefixes.sort_unstable();
-        assert_eq!(r.common_prefixes.len(), 2);
-        assert_eq!(r.common_prefixes[0].as_ref(), "a");
-        assert_eq!(r.common_prefixes[1].as_ref(), "b");
-        assert_eq!(r.objects.len(), 1);
-        assert_eq!(r.objects[0].location.as_ref(), "test.parquet");
+    #[tokio::test]
+    async fn get_negotiated() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("data/file.json");
+        integration.put(&location, "plain".into()).await.unwrap();
+        let gzip = Path::from("data/file.json.gz");
+        integration.put(&gzip, "gzipped".into()).await.unwrap();
 
         let r = integration
-            .list_with_delimiter(Some(&Path::from("a")))
+            .get_negotiated(&location, &["br", "gzip"])
             .await
             .unwrap();
-        assert_eq!(r.common_prefixes.len(), 0);
-        assert_eq!(r.objects.len(), 1);
-        assert_eq!(r.objects[0].location.as_ref(), "a/file.parquet");
+        assert_eq!(r.meta.location, gzip);
+        let encoding = r.attributes.get(&Attribute::ContentEncoding).unwrap();
+        assert_eq!(encoding.as_ref(), "gzip");
+        assert_eq!(r.bytes().await.unwrap().as_ref(), b"gzipped");
+
+        for accept in [&[][..], &["identity"], &["br"], &["gzip;q=0"]] {
+            let r = integration.get_negotiated(&location, accept).await.unwrap();
+            assert_eq!(r.meta.location, location);
+            assert!(r.attributes.get(&Attribute::ContentEncoding).is_none());
+            assert_eq!(r.bytes().await.unwrap().as_ref(), b"plain");
+        }
 
-        // Deleting a symlink doesn't delete the source file
-        integration
-            .delete(&Path::from("test.parquet"))
+        let missing = Path::from("missing");
+        let err = integration
+            .get_negotiated(&missing, &["gzip"])
             .await
-            .unwrap();
-        assert!(other.path().exists());
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+    }
 
-        check_list(&integration, None, &["a/file.parquet", "b/file.parquet"]).await;
+    #[tokio::test]
+    async fn put_parts() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-        // Deleting through a symlink deletes both files
-        integration
-            .delete(&Path::from("b/file.parquet"))
+        let buffer = Bytes::from((0..1000).map(|x| x as u8).collect::<Vec<_>>());
+        let boundaries = [0, 7, 100, 101, 250, 500, 501, 640, 800, 999, 1000];
+        let parts: Vec<_> = boundaries
+            .windows(2)
+            .map(|w| PutPayload::from(buffer.slice(w[0]..w[1])))
+            .collect();
+        assert_eq!(parts.len(), 10);
+
+        let batched = Path::from("batched");
+        let mut upload = integration.put_multipart(&batched).await.unwrap();
+        upload.put_parts(parts.clone()).await.unwrap();
+        upload.complete().await.unwrap();
+
+        let single = Path::from("single");
+        let mut upload = integration.put_multipart(&single).await.unwrap();
+        for part in parts.clone() {
+            upload.put_part(part).await.unwrap();
+        }
+        upload.complete().await.unwrap();
+
+        let batched = integration
+            .get(&batched)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let single = integration
+            .get(&single)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(batched, buffer);
+        assert_eq!(single, buffer);
+
+        // Offsets are tracked across interleaved calls
+        let mixed = Path::from("mixed");
+        let mut upload = integration.put_multipart(&mixed).await.unwrap();
+        let mut parts = parts.into_iter();
+        let first = upload.put_part(parts.next().unwrap());
+        let middle = upload.put_parts(parts.by_ref().take(8).collect());
+        let last = upload.put_part(parts.next().unwrap());
+        futures::future::try_join3(last, middle, first)
+            .await
+            .unwrap();
+        upload.complete().await.unwrap();
+        let mixed = integration
+            .get(&mixed)
+            .await
+            .unwrap()
+            .bytes()
             .await
             .unwrap();
+        assert_eq!(mixed, buffer);
+    }
 
-        check_list(&integration, None, &[]).await;
+    #[tokio::test]
+    async fn enforce_s3_part_sizes() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_enforce_s3_part_sizes(true);
+        assert!(integration.config().enforce_s3_part_sizes);
 
-        // Adding a file through a symlink creates in both paths
-        integration
-            .put(&Path::from("b/file.parquet"), vec![0, 1, 2].into())
+        // Only the last part may be smaller than the minimum
+        let location = Path::from("valid");
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload
+            .put_part(vec![0; S3_MIN_PART_SIZE].into())
             .await
             .unwrap();
+        upload.put_part(vec![1; 10].into()).await.unwrap();
+        upload.complete().await.unwrap();
+        let meta = integration.head(&location).await.unwrap();
+        assert_eq!(meta.size, S3_MIN_PART_SIZE as u64 + 10);
 
-        check_list(&integration, None, &["a/file.parquet", "b/file.parquet"]).await;
+        let location = Path::from("invalid");
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload.put_part(vec![0; 10].into()).await.unwrap();
+        let err = upload.put_part(vec![1; 10].into()).await.unwrap_err();
+        assert!(err.to_string().contains("Part 1 of 10 bytes"), "{err}");
+        let err = upload
+            .put_parts(vec![vec![2; 10].into(), vec![3; 10].into()])
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("smaller than the minimum"),
+            "{err}"
+        );
+        upload.abort().await.unwrap();
+
+        // Parts of any size are accepted by default
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let mut upload = integration.put_multipart(&location).await.unwrap();
+        upload.put_part(vec![0; 10].into()).await.unwrap();
+        upload.put_part(vec![1; 10].into()).await.unwrap();
+        upload.complete().await.unwrap();
     }
 
     #[tokio::test]
-    async fn invalid_path() {
+    #[cfg(unix)]
+    async fn link_count_attribute() {
         let root = TempDir::new().unwrap();
-        let root = root.path().join("🙀");
-        std::fs::create_dir(root.clone()).unwrap();
-
-        // Invalid paths supported above root of store
-        let integration = LocalFileSystem::new_with_prefix(root.clone()).unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_link_count_attribute(true);
+        assert!(integration.config().link_count_attribute);
+
+        let a = Path::from("a");
+        let b = Path::from("b");
+        let key = Attribute::Metadata(LINK_COUNT_ATTRIBUTE.into());
+        integration.put(&a, "data".into()).await.unwrap();
+        let r = integration.get(&a).await.unwrap();
+        assert_eq!(r.attributes.get(&key).unwrap().as_ref(), "1");
+
+        // Both locations of a copy share an inode
+        integration.copy(&a, &b).await.unwrap();
+        for location in [&a, &b] {
+            let r = integration.get(location).await.unwrap();
+            assert_eq!(r.attributes.get(&key).unwrap().as_ref(), "2");
+        }
 
-        let directory = Path::from("directory");
-        let object = directory.child("child.txt");
-        let data = Bytes::from("arbitrary");
-        integration.put(&object, data.clone().into()).await.unwrap();
-        integration.head(&object).await.unwrap();
-        let result = integration.get(&object).await.unwrap();
-        assert_eq!(result.bytes().await.unwrap(), data);
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let r = integration.get(&a).await.unwrap();
+        assert!(r.attributes.get(&key).is_none());
+    }
 
-        flatten_list_stream(&integration, None).await.unwrap();
-        flatten_list_stream(&integration, Some(&directory))
-            .await
-            .unwrap();
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn detect_replacement() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_detect_replacement(true);
+        assert!(integration.config().detect_replacement);
 
-        let result = integration
-            .list_with_delimiter(Some(&directory))
+        let location = Path::from("file");
+        let data = vec![1; CHUNK_SIZE * 4];
+        integration
+            .put(&location, data.clone().into())
             .await
             .unwrap();
-        assert_eq!(result.objects.len(), 1);
-        assert!(result.common_prefixes.is_empty());
-        assert_eq!(result.objects[0].location, object);
 
-        let emoji = root.join("💀");
-        std::fs::write(emoji, "foo").unwrap();
+        // Unmodified file is read successfully
+        let r = integration.get(&location).await.unwrap();
+        assert!(matches!(r.payload, GetResultPayload::Stream(_)));
+        assert_eq!(r.bytes().await.unwrap().as_ref(), data);
+
+        // Replace the file once the first chunk has been read
+        let mut stream = integration.get(&location).await.unwrap().into_stream();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.len(), CHUNK_SIZE);
+        integration.put(&location, "new".into()).await.unwrap();
+
+        let mut read = first.len();
+        let err = loop {
+            match stream.next().await.unwrap() {
+                Ok(chunk) => read += chunk.len(),
+                Err(e) => break e,
+            }
+        };
+        // The original version is read in its entirety
+        assert_eq!(read, data.len());
+        assert!(err.to_string().contains("was replaced"), "{err}");
+        assert!(stream.next().await.is_none());
 
-        // Can list illegal file
-        let mut paths = flatten_list_stream(&integration, None).await.unwrap();
-        paths.sort_unstable();
+        // Deletion is also detected
+        let mut stream = integration.get(&location).await.unwrap().into_stream();
+        integration.delete(&location).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().as_ref(), b"new");
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("was replaced"), "{err}");
+    }
 
-        assert_eq!(
-            paths,
-            vec![
-                Path::parse("directory/child.txt").unwrap(),
-                Path::parse("💀").unwrap()
-            ]
-        );
+    #[tokio::test]
+    async fn read_chunk_size() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_read_chunk_size(3);
+        assert_eq!(integration.config().read_chunk_size, 3);
+
+        let location = Path::from("file");
+        integration.put(&location, "abcdefgh".into()).await.unwrap();
+
+        let r = integration.get(&location).await.unwrap();
+        assert!(matches!(r.payload, GetResultPayload::Stream(_)));
+        let chunks: Vec<_> = r.into_stream().try_collect().await.unwrap();
+        assert_eq!(chunks, ["abc", "def", "gh"]);
+
+        let lines: Vec<_> = integration.lines(&location).try_collect().await.unwrap();
+        assert_eq!(lines, ["abcdefgh"]);
+
+        // A chunk size larger than the file yields a single chunk
+        let integration = integration.with_read_chunk_size(1024);
+        let r = integration.get(&location).await.unwrap();
+        let chunks: Vec<_> = r.into_stream().try_collect().await.unwrap();
+        assert_eq!(chunks, ["abcdefgh"]);
+
+        // A zero-length range yields no chunks
+        let options = GetOptions {
+            range: Some(crate::GetRange::Suffix(0)),
+            ..Default::default()
+        };
+        let r = integration.get_opts(&location, options).await.unwrap();
+        assert_eq!(r.range, 8..8);
+        let chunks: Vec<Bytes> = r.into_stream().try_collect().await.unwrap();
+        assert!(chunks.is_empty());
+
+        // A zero chunk size errors rather than never completing
+        let integration = integration.with_read_chunk_size(0);
+        let r = integration.get(&location).await.unwrap();
+        let err = r.bytes().await.unwrap_err();
+        assert!(err.to_string().contains("greater than zero"), "{err}");
+        let err = integration.lines(&location).next().await.unwrap();
+        assert!(err.unwrap_err().to_string().contains("greater than zero"));
+
+        // The default returns a file
+        let integration = integration.with_read_chunk_size(CHUNK_SIZE);
+        let r = integration.get(&location).await.unwrap();
+        assert!(matches!(r.payload, GetResultPayload::File(_, _)));
     }
 
     #[tokio::test]
-    async fn list_hides_incomplete_uploads() {
+    async fn io_concurrency_limit() {
         let root = TempDir::new().unwrap();
-        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
-        let location = Path::from("some_file");
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_io_concurrency_limit(2);
+        assert_eq!(integration.config().io_concurrency_limit, Some(2));
 
-        let data = PutPayload::from("arbitrary data");
-        let mut u1 = integration.put_multipart(&location).await.unwrap();
-        u1.put_part(data.clone()).await.unwrap();
+        let puts = (0..16).map(|i| {
+            let location = Path::from(format!("file_{i}"));
+            let integration = &integration;
+            async move { integration.put(&location, "data".into()).await }
+        });
+        futures::future::try_join_all(puts).await.unwrap();
+
+        let limiter = integration.config.io_limiter.as_ref().unwrap();
+        assert_eq!(limiter.semaphore.available_permits(), 2);
+
+        // Record the operations in progress at once
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let ops = (0..16).map(|_| {
+            let (active, max_active) = (Arc::clone(&active), Arc::clone(&max_active));
+            spawn_blocking(Some(Arc::clone(limiter)), move || {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                max_active.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(10));
+                active.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        });
+        futures::future::try_join_all(ops).await.unwrap();
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+        assert_eq!(limiter.semaphore.available_permits(), 2);
 
-        let mut u2 = integration.put_multipart(&location).await.unwrap();
-        u2.put_part(data).await.unwrap();
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert_eq!(listed.len(), 16);
+    }
 
-        let list = flatten_list_stream(&integration, None).await.unwrap();
-        assert_eq!(list.len(), 0);
+    #[tokio::test]
+    async fn read_rate_limit() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_read_rate_limit(128 * 1024);
+        assert_eq!(integration.config().read_rate_limit, Some(128 * 1024));
 
-        assert_eq!(
-            integration
-                .list_with_delimiter(None)
-                .await
-                .unwrap()
-                .objects
-                .len(),
-            0
+        let location = Path::from("file");
+        let data = vec![1; CHUNK_SIZE * 8];
+        integration
+            .put(&location, data.clone().into())
+            .await
+            .unwrap();
+
+        // The limit is shared between concurrent reads
+        let start = Instant::now();
+        let (r, range) = futures::join!(
+            integration.get(&location),
+            integration.get_range(&location, 0..CHUNK_SIZE as u64 * 4)
         );
+        let r = r.unwrap();
+        assert!(matches!(r.payload, GetResultPayload::Stream(_)));
+        assert_eq!(r.bytes().await.unwrap().as_ref(), data);
+        assert_eq!(range.unwrap().len(), CHUNK_SIZE * 4);
+
+        // 96 KiB at 128 KiB/s, less the first chunk which is read immediately
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(680), "{elapsed:?}");
+
+        // Waiting does not hold the only permit for blocking operations
+        let integration = integration.with_io_concurrency_limit(1);
+        let read = integration.get_range(&location, 0..CHUNK_SIZE as u64 * 4);
+        let head = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let start = Instant::now();
+            integration.head(&location).await.unwrap();
+            start.elapsed()
+        };
+        let (read, head) = futures::join!(read, head);
+        assert_eq!(read.unwrap().len(), CHUNK_SIZE * 4);
+        assert!(head < Duration::from_millis(200), "{head:?}");
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "windows")]
+    async fn windows_trailing_dot() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+
+        for location in ["foo./bar", "foo/bar.", "foo /bar"] {
+            let location = Path::from(location);
+            let err = integration.put(&location, "data".into()).await.unwrap_err();
+            assert!(matches!(err, crate::Error::InvalidPath { .. }), "{err}");
+            let err = integration.path_to_filesystem(&location).unwrap_err();
+            assert!(
+                err.to_string().contains("whilst parsing path segment"),
+                "{err}"
+            );
+        }
+        assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 0);
+
+        integration
+            .put(&Path::from("foo.d/.bar"), "data".into())
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
-    async fn test_path_with_offset() {
+    #[cfg(target_os = "windows")]
+    async fn windows_file_id_etag() {
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-        let root_path = root.path();
-        for i in 0..5 {
-            let filename = format!("test{i}.parquet");
-            let file = root_path.join(filename);
-            std::fs::write(file, "test").unwrap();
-        }
-        let filter_str = "test";
-        let filter = String::from(filter_str);
-        let offset_str = filter + "1";
-        let offset = Path::from(offset_str.clone());
+        let a = integration.put(&Path::from("a"), "data".into()).await;
+        let b = integration.put(&Path::from("b"), "data".into()).await;
+        let (a, b) = (a.unwrap().e_tag.unwrap(), b.unwrap().e_tag.unwrap());
 
-        // Use list_with_offset to retrieve files
-        let res = integration.list_with_offset(None, &offset);
-        let offset_paths: Vec<_> = res.map_ok(|x| x.location).try_collect().await.unwrap();
-        let mut offset_files: Vec<_> = offset_paths
-            .iter()
-            .map(|x| String::from(x.filename().unwrap()))
-            .collect();
+        // The file ID distinguishes files of the same size and modification time
+        let (a_id, _) = a.split_once('-').unwrap();
+        let (b_id, _) = b.split_once('-').unwrap();
+        assert_ne!(a_id, "0");
+        assert_ne!(a_id, b_id);
+        assert_ne!(a, b);
+    }
 
-        // Check result with direct filesystem read
-        let files = fs::read_dir(root_path).unwrap();
-        let filtered_files = files
-            .filter_map(Result::ok)
-            .filter_map(|d| {
-                d.file_name().to_str().and_then(|f| {
-                    if f.contains(filter_str) {
-                        Some(String::from(f))
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn lines() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-        let mut expected_offset_files: Vec<_> = filtered_files
-            .iter()
-            .filter(|s| **s > offset_str)
-            .cloned()
-            .collect();
+        let location = Path::from("multi");
+        let data = "first\r\nsecond\n\nlast";
+        integration.put(&location, data.into()).await.unwrap();
+        let lines: Vec<_> = integration.lines(&location).try_collect().await.unwrap();
+        assert_eq!(lines, vec!["first", "second", "", "last"]);
+
+        // Line spanning a chunk boundary
+        let long = "x".repeat(CHUNK_SIZE + 10);
+        let data = format!("short\n{long}\nend\n");
+        integration.put(&location, data.into()).await.unwrap();
+        let lines: Vec<_> = integration.lines(&location).try_collect().await.unwrap();
+        assert_eq!(lines, vec!["short", &long, "end"]);
+
+        let err = integration
+            .lines_with_max_length(&location, CHUNK_SIZE)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Line 2"), "{err}");
+        let lines = integration.lines_with_max_length(&location, CHUNK_SIZE + 10);
+        assert_eq!(lines.try_collect::<Vec<_>>().await.unwrap().len(), 3);
 
-        fn do_vecs_match<T: PartialEq>(a: &[T], b: &[T]) -> bool {
-            let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
-            matching == a.len() && matching == b.len()
-        }
+        let empty = Path::from("empty");
+        integration.put(&empty, "".into()).await.unwrap();
+        let lines: Vec<_> = integration.lines(&empty).try_collect().await.unwrap();
+        assert!(lines.is_empty());
 
-        offset_files.sort();
-        expected_offset_files.sort();
+        let invalid = Path::from("invalid");
+        integration
+            .put(&invalid, b"ok\n\xff\n"[..].into())
+            .await
+            .unwrap();
+        let mut lines = integration.lines(&invalid);
+        assert_eq!(lines.next().await.unwrap().unwrap(), "ok");
+        let err = lines.next().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("Line 2"), "{err}");
+        assert!(err.to_string().contains("not valid UTF-8"), "{err}");
+
+        let missing = integration.lines(&Path::from("missing")).next().await;
+        assert!(matches!(missing, Some(Err(crate::Error::NotFound { .. }))));
+    }
 
-        // println!("Expected Offset Files: {:?}", expected_offset_files);
-        // println!("Actual Offset Files: {:?}", offset_files);
+    #[tokio::test]
+    async fn copy_directory() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        integration
+            .put(&Path::from("dir/file"), "data".into())
+            .await
+            .unwrap();
 
-        assert_eq!(offset_files.len(), expected_offset_files.len());
-        assert!(do_vecs_match(&expected_offset_files, &offset_files));
+        let (dir, to) = (Path::from("dir"), Path::from("copy"));
+        let err = integration.copy(&dir, &to).await.unwrap_err();
+        assert!(matches!(err, crate::Error::IsADirectory { .. }), "{err}");
+        let err = integration.copy(&dir, &dir).await.unwrap_err();
+        assert!(matches!(err, crate::Error::IsADirectory { .. }), "{err}");
+        let err = integration.copy_if_not_exists(&dir, &to).await.unwrap_err();
+        assert!(matches!(err, crate::Error::IsADirectory { .. }), "{err}");
+        assert!(!root.path().join("copy").exists());
     }
 
     #[tokio::test]
-    async fn filesystem_filename_with_percent() {
-        let temp_dir = TempDir::new().unwrap();
-        let integration = LocalFileSystem::new_with_prefix(temp_dir.path()).unwrap();
-        let filename = "L%3ABC.parquet";
+    async fn strict_path_segments() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
 
-        std::fs::write(temp_dir.path().join(filename), "foo").unwrap();
+        // Path::from removes empty segments, whereas Path::child does not
+        assert_eq!(Path::from("a//b").as_ref(), "a/b");
+        let location = Path::from("a").child("").child("b");
+        assert_eq!(location.as_ref(), "a//b");
+
+        integration.put(&location, "data".into()).await.unwrap();
+        let meta = integration.head(&Path::from("a/b")).await.unwrap();
+        assert_eq!(meta.size, 4);
+
+        let integration = integration.with_strict_path_segments(true);
+        assert!(integration.config().strict_path_segments);
+        let err = integration.head(&location).await.unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidPath { .. }), "{err}");
+        let err = integration.path_to_filesystem(&location).unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidPath { .. }), "{err}");
+        integration.head(&Path::from("a/b")).await.unwrap();
+    }
 
-        let res: Vec<_> = integration.list(None).try_collect().await.unwrap();
-        assert_eq!(res.len(), 1);
-        assert_eq!(res[0].location.as_ref(), filename);
+    #[test]
+    fn effective_config() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_automatic_cleanup(true);
 
-        let res = integration.list_with_delimiter(None).await.unwrap();
-        assert_eq!(res.objects.len(), 1);
-        assert_eq!(res.objects[0].location.as_ref(), filename);
+        let config = integration.config();
+        let expected = absolute_path_to_url(std::fs::canonicalize(root.path()).unwrap()).unwrap();
+        assert_eq!(config.root, expected);
+        assert!(config.automatic_cleanup);
+        assert_eq!(config.storage_class, "STANDARD");
+        assert_eq!(config.mmap_threshold, None);
+        assert_eq!(config.replica, None);
+
+        let config = LocalFileSystem::new().config();
+        assert_eq!(config.root.as_str(), "file:///");
+        assert!(!config.automatic_cleanup);
     }
 
     #[tokio::test]
-    async fn relative_paths() {
-        LocalFileSystem::new_with_prefix(".").unwrap();
-        LocalFileSystem::new_with_prefix("..").unwrap();
-        LocalFileSystem::new_with_prefix("../..").unwrap();
-
-        let integration = LocalFileSystem::new();
-        let path = Path::from_filesystem_path(".").unwrap();
-        integration.list_with_delimiter(Some(&path)).await.unwrap();
+    #[cfg(all(target_family = "unix", feature = "fs-s3-etag"))]
+    async fn s3_compatible_etag() {
+        let root = TempDir::new().unwrap();
+        let strategy = EtagStrategy::S3Compatible;
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_etag_strategy(strategy);
+        assert_eq!(integration.config().etag_strategy, strategy);
+
+        let single = Path::from("single");
+        let r = integration.put(&single, "hello".into()).await.unwrap();
+        let expected = "\"5d41402abc4b2a76b9719d911017c592\"";
+        assert_eq!(r.e_tag.unwrap(), expected);
+        let meta = integration.head(&single).await.unwrap();
+        assert_eq!(meta.e_tag.unwrap(), expected);
+
+        // Objects written with a single request have the MD5 of their contents as their
+        // ETag, regardless of their size
+        let multi = Path::from("multi");
+        let r = integration
+            .put(&multi, "hello world!".into())
+            .await
+            .unwrap();
+        let expected = "\"fc3ff98e8c6a0d3087d515c0473f8677\"";
+        assert_eq!(r.e_tag.unwrap(), expected);
+        let meta = integration.head(&multi).await.unwrap();
+        assert_eq!(meta.e_tag.unwrap(), expected);
+
+        // Known ETag of "hello world!" uploaded to S3 in two 6 byte parts
+        let mut upload = integration.put_multipart(&multi).await.unwrap();
+        upload.put_part("hello ".into()).await.unwrap();
+        upload.put_part("world!".into()).await.unwrap();
+        let r = upload.complete().await.unwrap();
+        let expected = "\"8ae2532e3bf1d4178d0aa9ca8a20f149-2\"";
+        assert_eq!(r.e_tag.unwrap(), expected);
+        let meta = integration.head(&multi).await.unwrap();
+        assert_eq!(meta.e_tag.unwrap(), expected);
+
+        // Multipart uploads smaller than a part still have a part count
+        let small = Path::from("small");
+        let mut upload = integration.put_multipart(&small).await.unwrap();
+        upload.put_part("hi".into()).await.unwrap();
+        let r = upload.complete().await.unwrap();
+        assert_eq!(r.e_tag.unwrap(), "\"8d355a2cf80658161c294a33878f9085-1\"");
+
+        // Listing returns cached ETags, but does not read files to compute them
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        assert!(listed.iter().all(|m| m.e_tag.is_some()));
+        let path = integration.path_to_filesystem(&multi).unwrap();
+        xattr::remove(&path, ETAG_XATTR).unwrap();
+        let listed: Vec<_> = integration.list(None).try_collect().await.unwrap();
+        let listed = listed.iter().find(|m| m.location == multi).unwrap();
+        assert_eq!(listed.e_tag, None);
+        let meta = integration.head(&multi).await.unwrap();
+        assert_eq!(meta.e_tag.unwrap(), expected);
+
+        // Parts of differing sizes are hashed at their own boundaries
+        let mut upload = integration.put_multipart(&multi).await.unwrap();
+        upload
+            .put_parts(vec!["hello ".into(), "hello ".into(), "!".into()])
+            .await
+            .unwrap();
+        let r = upload.complete().await.unwrap();
+        assert_eq!(r.e_tag.unwrap(), "\"97a9b6fc1188cba3d2a99679807a17ae-3\"");
+        assert_eq!(read_xattr(&path, MULTIPART_XATTR).unwrap(), "6*2,1*1");
+
+        // Other strategies do not record multipart uploads
+        let other = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let mut upload = other.put_multipart(&multi).await.unwrap();
+        upload.put_part("hello ".into()).await.unwrap();
+        upload.complete().await.unwrap();
+        assert!(read_xattr(&path, MULTIPART_XATTR).is_none());
+        let meta = integration.head(&multi).await.unwrap();
+        assert_eq!(meta.e_tag.unwrap(), "\"f814893777bcc2295fff05f00e508da6\"");
+
+        let empty = Path::from("empty");
+        let r = integration
+            .put(&empty, PutPayload::default())
+            .await
+            .unwrap();
+        assert_eq!(r.e_tag.unwrap(), "\"d41d8cd98f00b204e9800998ecf8427e\"");
     }
 
-    #[test]
-    fn test_valid_path() {
-        let cases = [
-            ("foo#123/test.txt", true),
-            ("foo#123/test#23.txt", true),
-            ("foo#123/test#34", false),
-            ("foo😁/test#34", false),
-            ("foo/test#😁34", true),
-        ];
+    #[tokio::test]
+    async fn content_sha256_etag() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_etag_strategy(EtagStrategy::ContentSha256);
+
+        let expected = "\"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\"";
+        let a = Path::from("a");
+        let r = integration.put(&a, "hello".into()).await.unwrap();
+        assert_eq!(r.e_tag.unwrap(), expected);
+        let meta = integration.head(&a).await.unwrap();
+        assert_eq!(meta.e_tag.unwrap(), expected);
+
+        let mut upload = integration.put_multipart(&Path::from("b")).await.unwrap();
+        upload.put_part("hel".into()).await.unwrap();
+        upload.put_part("lo".into()).await.unwrap();
+        let r = upload.complete().await.unwrap();
+        assert_eq!(r.e_tag.unwrap(), expected);
+
+        let r = integration.put(&a, "world".into()).await.unwrap();
+        assert_ne!(r.e_tag.unwrap(), expected);
+    }
 
-        for (case, expected) in cases {
-            let path = Path::parse(case).unwrap();
-            assert_eq!(is_valid_file_path(&path), expected);
+    #[tokio::test]
+    #[cfg(target_family = "unix")]
+    async fn size_mtime_etag() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_etag_strategy(EtagStrategy::SizeMtime);
+
+        let (a, b) = (Path::from("a"), Path::from("b"));
+        let r = integration.put(&a, "data".into()).await.unwrap();
+        let meta = integration.head(&a).await.unwrap();
+        assert_eq!(r.e_tag, meta.e_tag);
+        integration.put(&b, "data".into()).await.unwrap();
+
+        // Byte-identical files with the same modification time but different inodes
+        let time = nix::sys::time::TimeVal::new(1_000_000, 0);
+        for name in ["a", "b"] {
+            nix::sys::stat::utimes(&root.path().join(name), &time, &time).unwrap();
         }
+        let meta_a = integration.head(&a).await.unwrap();
+        let meta_b = integration.head(&b).await.unwrap();
+        assert_eq!(meta_a.e_tag, meta_b.e_tag);
+
+        let integration = integration.with_etag_strategy(EtagStrategy::InodeSizeMtime);
+        let meta_a = integration.head(&a).await.unwrap();
+        let meta_b = integration.head(&b).await.unwrap();
+        assert_ne!(meta_a.e_tag, meta_b.e_tag);
     }
 
-    #[tokio::test]
-    async fn test_intermediate_files() {
+    #[test]
+    fn revalidate_replaced() {
         let root = TempDir::new().unwrap();
-        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let path = root.path().join("file");
+        std::fs::write(&path, "old").unwrap();
+        let opened = open_file(&path).unwrap();
+
+        // Replace the file as another NFS client would
+        let staging = root.path().join("staging");
+        std::fs::write(&staging, "updated").unwrap();
+        std::fs::rename(&staging, &path).unwrap();
+
+        let (mut file, metadata) = revalidate(&path, opened, open_file).unwrap();
+        assert_eq!(metadata.len(), 7);
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "updated");
+
+        std::fs::remove_file(&path).unwrap();
+        let opened = (file, metadata);
+        let err = revalidate(&path, opened, open_file).unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+    }
 
-        let a = Path::parse("foo#123/test.txt").unwrap();
-        integration.put(&a, "test".into()).await.unwrap();
+    #[test]
+    fn reopen_replaced() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().join("file");
+        std::fs::write(&path, "data").unwrap();
+        let opened = metadata(&path).unwrap();
+
+        let mut file = reopen_at(&path, 1, Some(&opened)).unwrap();
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "ata");
+
+        // A replacement is only accepted if nothing has been read
+        let staged = root.path().join("staged");
+        std::fs::write(&staged, "other").unwrap();
+        std::fs::rename(&staged, &path).unwrap();
+        let err = reopen_at(&path, 1, Some(&opened)).unwrap_err();
+        assert!(err.to_string().contains("replaced during read"), "{err}");
+        reopen_at(&path, 0, None).unwrap();
+    }
 
-        let list = flatten_list_stream(&integration, None).await.unwrap();
-        assert_eq!(list, vec![a.clone()]);
+    #[test]
+    #[cfg(unix)]
+    fn retry_stale_handle() {
+        /// A file handle that has gone stale if `stale`
+        struct Handle {
+            stale: bool,
+            data: &'static [u8],
+        }
 
-        std::fs::write(root.path().join("bar#123"), "test").unwrap();
+        let estale = || io::Error::from_raw_os_error(rustix::io::Errno::STALE.raw_os_error());
+        let read = |h: &mut Handle| match h.stale {
+            true => Err(estale()),
+            false => Ok(h.data.to_vec()),
+        };
 
-        // Should ignore file
-        let list = flatten_list_stream(&integration, None).await.unwrap();
-        assert_eq!(list, vec![a.clone()]);
+        // A stale handle is reopened and the read retried
+        let mut reopened = 0;
+        let mut handle = Handle {
+            stale: true,
+            data: b"",
+        };
+        let reopen = || {
+            reopened += 1;
+            Ok(Handle {
+                stale: false,
+                data: b"data",
+            })
+        };
+        assert_eq!(retry_stale(&mut handle, reopen, read).unwrap(), b"data");
+        assert_eq!(reopened, 1);
+
+        // Retries are bounded
+        handle.stale = true;
+        let mut reopened = 0;
+        let reopen = || {
+            reopened += 1;
+            Ok(Handle {
+                stale: true,
+                data: b"",
+            })
+        };
+        let err = retry_stale(&mut handle, reopen, read).unwrap_err();
+        assert!(is_stale(&err));
+        assert_eq!(reopened, STALE_HANDLE_RETRIES);
+
+        // Other errors are not retried
+        let reopen = || -> io::Result<Handle> { unreachable!() };
+        let err = retry_stale(&mut handle, reopen, |_| -> io::Result<()> {
+            Err(io::Error::new(ErrorKind::Other, "other"))
+        })
+        .unwrap_err();
+        assert!(!is_stale(&err));
+    }
 
-        let b = Path::parse("bar#123").unwrap();
-        let err = integration.get(&b).await.unwrap_err().to_string();
-        assert_eq!(err, "Generic LocalFileSystem error: Filenames containing trailing '/#\\d+/' are not supported: bar#123");
+    #[tokio::test]
+    async fn nfs_consistency() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path())
+            .unwrap()
+            .with_nfs_consistency(true);
+        assert!(integration.config().nfs_consistency);
 
-        let c = Path::parse("foo#123.txt").unwrap();
-        integration.put(&c, "test".into()).await.unwrap();
+        let location = Path::from("file");
+        integration.put(&location, "data".into()).await.unwrap();
+        assert_eq!(integration.head(&location).await.unwrap().size, 4);
+        let r = integration.get(&location).await.unwrap();
+        assert_eq!(r.bytes().await.unwrap().as_ref(), b"data");
 
-        let mut list = flatten_list_stream(&integration, None).await.unwrap();
-        list.sort_unstable();
-        assert_eq!(list, vec![c, a]);
+        let missing = integration.head(&Path::from("missing")).await.unwrap_err();
+        assert!(
+            matches!(missing, crate::Error::NotFound { .. }),
+            "{missing}"
+        );
     }
 
     #[tokio::test]
-    #[cfg(target_os = "windows")]
-    async fn filesystem_filename_with_colon() {
+    async fn copy_prefix_with() {
+        use crate::path::PathPart;
+
         let root = TempDir::new().unwrap();
         let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
-        let path = Path::parse("file%3Aname.parquet").unwrap();
-        let location = Path::parse("file:name.parquet").unwrap();
 
-        integration.put(&location, "test".into()).await.unwrap();
-        let list = flatten_list_stream(&integration, None).await.unwrap();
-        assert_eq!(list, vec![path.clone()]);
+        for location in ["src/2024/01/30/a", "src/2024/01/31/a", "src/2024/01/31/b"] {
+            let location = Path::from(location);
+            let data = location.to_string();
+            integration.put(&location, data.into()).await.unwrap();
+        }
 
-        let result = integration
-            .get(&location)
+        let relayout = |location: &Path| {
+            let parts: Vec<_> = location.parts().collect();
+            let (filename, date) = parts.split_last().unwrap();
+            let date = date.iter().map(|p| p.as_ref()).collect::<Vec<_>>();
+            Path::from_iter([PathPart::from(date.join("-")), filename.clone()])
+        };
+
+        let src = Path::from("src");
+        let dst = Path::from("dst");
+        integration
+            .copy_prefix_with(&src, &dst, relayout)
+            .await
+            .unwrap();
+
+        let mut copied = flatten_list_stream(&integration, Some(&dst)).await.unwrap();
+        copied.sort_unstable();
+        assert_eq!(
+            copied,
+            vec![
+                Path::from("dst/2024-01-30/a"),
+                Path::from("dst/2024-01-31/a"),
+                Path::from("dst/2024-01-31/b"),
+            ]
+        );
+        let read = integration
+            .get(&copied[2])
             .await
             .unwrap()
             .bytes()
             .await
             .unwrap();
-        assert_eq!(result, Bytes::from("test"));
+        assert_eq!(read.as_ref(), b"src/2024/01/31/b");
+
+        // Sources are retained
+        assert_eq!(
+            flatten_list_stream(&integration, Some(&src))
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // Collisions are rejected before copying
+        let err = integration
+            .copy_prefix_with(&src, &Path::from("flat"), |l| {
+                Path::from_iter(l.filename().map(PathPart::from))
+            })
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("would be copied to flat/a"),
+            "{err}"
+        );
+        assert!(flatten_list_stream(&integration, Some(&Path::from("flat")))
+            .await
+            .unwrap()
+            .is_empty());
     }
 
     #[tokio::test]
-    async fn delete_dirs_automatically() {
+    async fn increment_counter_concurrent() {
         let root = TempDir::new().unwrap();
-        let integration = LocalFileSystem::new_with_prefix(root.path())
-            .unwrap()
-            .with_automatic_cleanup(true);
-        let location = Path::from("nested/file/test_file");
-        let data = Bytes::from("arbitrary data");
+        let integration = Arc::new(LocalFileSystem::new_with_prefix(root.path()).unwrap());
+        let location = Path::from("counter");
+
+        let tasks: Vec<_> = (1..=50)
+            .map(|by| {
+                let integration = Arc::clone(&integration);
+                let location = location.clone();
+                tokio::spawn(async move { integration.increment(&location, by).await.unwrap() })
+            })
+            .collect();
 
-        integration
-            .put(&location, data.clone().into())
-            .await
-            .unwrap();
+        for task in tasks {
+            task.await.unwrap();
+        }
 
-        let read_data = integration
+        let data = integration
             .get(&location)
             .await
             .unwrap()
             .bytes()
             .await
             .unwrap();
+        assert_eq!(
+            data.as_ref(),
+            (1..=50u64).sum::<u64>().to_string().as_bytes()
+        );
+        assert_eq!(
+            flatten_list_stream(integration.as_ref(), None)
+                .await
+                .unwrap(),
+            vec![location]
+        );
+    }
 
-        assert_eq!(&*read_data, data);
-        assert!(fs::read_dir(root.path()).unwrap().count() > 0);
-        integration.delete(&location).await.unwrap();
-        assert!(fs::read_dir(root.path()).unwrap().count() == 0);
+    #[tokio::test]
+    async fn put_update_race() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        let location = Path::from("file");
+
+        for _ in 0..20 {
+            let v = integration.put(&location, "base".into()).await.unwrap();
+            let update = |data: &'static str| {
+                let mode = PutMode::Update(v.clone().into());
+                integration.put_opts(&location, data.into(), mode.into())
+            };
+
+            // Exactly one of the updaters wins, with the other's ETag then stale
+            let (a, b) = futures::join!(update("a"), update("b"));
+            let (winner, err) = match (a, b) {
+                (Ok(_), Err(e)) => ("a", e),
+                (Err(e), Ok(_)) => ("b", e),
+                (a, b) => panic!("expected exactly one update to succeed: {a:?} {b:?}"),
+            };
+            assert!(matches!(err, crate::Error::Precondition { .. }), "{err}");
+
+            let read = integration.get(&location).await.unwrap().bytes().await;
+            assert_eq!(read.unwrap().as_ref(), winner.as_bytes());
+        }
+
+        let version = UpdateVersion {
+            e_tag: None,
+            version: None,
+        };
+        let mode = PutMode::Update(version);
+        let err = integration
+            .put_opts(&location, "c".into(), mode.into())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ETag required"), "{err}");
+
+        let version = UpdateVersion {
+            e_tag: integration.head(&location).await.unwrap().e_tag,
+            version: Some("1".to_string()),
+        };
+        let mode = PutMode::Update(version);
+        let err = integration
+            .put_opts(&location, "c".into(), mode.into())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::NotSupported { .. }), "{err}");
     }
 }
 
@@ -1713,6 +10206,7 @@ mod not_wasm_tests {
 #[cfg(test)]
 mod unix_test {
     use std::fs::OpenOptions;
+    use std::time::Duration;
 
     use nix::sys::stat;
     use nix::unistd;
@@ -1739,4 +10233,24 @@ mod unix_test {
 
         spawned.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn head_fifo() {
+        let root = TempDir::new().unwrap();
+        let integration = LocalFileSystem::new_with_prefix(root.path()).unwrap();
+        unistd::mkfifo(&root.path().join("fifo"), stat::Mode::S_IRWXU).unwrap();
+
+        // Opening the FIFO would block without a writer
+        let location = Path::from("fifo");
+        let head = integration.head(&location);
+        let meta = tokio::time::timeout(Duration::from_secs(10), head)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(meta.size, 0);
+
+        std::fs::create_dir(root.path().join("dir")).unwrap();
+        let err = integration.head(&Path::from("dir")).await.unwrap_err();
+        assert!(matches!(err, crate::Error::NotFound { .. }), "{err}");
+    }
 }
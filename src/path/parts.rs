@@ -33,6 +33,17 @@ pub struct InvalidPart {
     illegal: String,
 }
 
+impl InvalidPart {
+    /// Create an [`InvalidPart`] for `segment` containing the `illegal` character sequence
+    #[cfg(all(feature = "fs", not(target_arch = "wasm32")))]
+    pub(crate) fn new(segment: impl Into<String>, illegal: impl Into<String>) -> Self {
+        Self {
+            segment: segment.into(),
+            illegal: illegal.into(),
+        }
+    }
+}
+
 /// The PathPart type exists to validate the directory/file names that form part
 /// of a path.
 ///
@@ -66,6 +66,19 @@ pub trait MultipartUpload: Send + std::fmt::Debug {
     /// [R2]: https://developers.cloudflare.com/r2/objects/multipart-objects/#limitations
     fn put_part(&mut self, data: PutPayload) -> UploadPart;
 
+    /// Upload `parts` as the next parts, in order
+    ///
+    /// Equivalent to calling [`MultipartUpload::put_part`] for each of `parts` and
+    /// polling the returned futures in parallel, however, implementations may upload
+    /// the parts more efficiently, e.g. by writing them all in a single operation.
+    fn put_parts(&mut self, parts: Vec<PutPayload>) -> UploadPart {
+        let parts: Vec<_> = parts.into_iter().map(|data| self.put_part(data)).collect();
+        Box::pin(async move {
+            futures::future::try_join_all(parts).await?;
+            Ok(())
+        })
+    }
+
     /// Complete the multipart upload
     ///
     /// It is implementation defined behaviour if this method is called before polling
@@ -98,6 +111,10 @@ impl<W: MultipartUpload + ?Sized> MultipartUpload for Box<W> {
         (**self).put_part(data)
     }
 
+    fn put_parts(&mut self, parts: Vec<PutPayload>) -> UploadPart {
+        (**self).put_parts(parts)
+    }
+
     async fn complete(&mut self) -> Result<PutResult> {
         (**self).complete().await
     }
@@ -120,7 +120,17 @@ where
         .try_collect()
         .await?;
 
-    Ok(ranges
+    Ok(split_ranges(ranges, &fetch_ranges, &fetched))
+}
+
+/// Slices the bytes of each of `ranges` out of `fetched`, the bytes of the
+/// corresponding `fetch_ranges` returned by [`merge_ranges`]
+pub(crate) fn split_ranges(
+    ranges: &[Range<u64>],
+    fetch_ranges: &[Range<u64>],
+    fetched: &[Bytes],
+) -> Vec<Bytes> {
+    ranges
         .iter()
         .map(|range| {
             let idx = fetch_ranges.partition_point(|v| v.start <= range.start) - 1;
@@ -132,11 +142,11 @@ where
             let range = (start as usize)..(end as usize).min(fetch_bytes.len());
             fetch_bytes.slice(range)
         })
-        .collect())
+        .collect()
 }
 
 /// Returns a sorted list of ranges that cover `ranges`
-fn merge_ranges(ranges: &[Range<u64>], coalesce: u64) -> Vec<Range<u64>> {
+pub(crate) fn merge_ranges(ranges: &[Range<u64>], coalesce: u64) -> Vec<Range<u64>> {
     if ranges.is_empty() {
         return vec![];
     }
@@ -315,7 +325,11 @@ pub(crate) fn hex_digest(bytes: &[u8]) -> String {
 }
 
 /// Returns `bytes` as a lower-case hex encoded string
-#[cfg(any(feature = "aws", feature = "gcp"))]
+#[cfg(any(
+    feature = "aws",
+    feature = "gcp",
+    all(feature = "fs", not(target_arch = "wasm32"))
+))]
 pub(crate) fn hex_encode(bytes: &[u8]) -> String {
     use std::fmt::Write;
     let mut out = String::with_capacity(bytes.len() * 2);